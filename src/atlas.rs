@@ -0,0 +1,374 @@
+//! Packing of rasterized glyphs into texture atlases.
+
+use std::collections::HashMap;
+
+use super::{BitmapBuffer, Error, GlyphFormat, GlyphKey, Rasterize, RasterizedGlyph};
+
+/// Empty pixels kept between glyphs, so texture filtering doesn't sample neighbouring glyphs.
+const PADDING: u32 = 1;
+
+/// Pixel format of an atlas page.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AtlasFormat {
+    /// One byte per pixel which is either `0` or `255`, for monochrome glyphs.
+    Mono,
+
+    /// One byte of coverage per pixel, for grayscale glyphs.
+    Gray,
+
+    /// Three bytes of RGB coverage per pixel, for glyphs with subpixel antialiasing.
+    Subpixel,
+
+    /// Four bytes of RGBA with premultiplied alpha per pixel, for color glyphs.
+    Color,
+}
+
+impl AtlasFormat {
+    /// Number of bytes per pixel.
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            AtlasFormat::Mono | AtlasFormat::Gray => 1,
+            AtlasFormat::Subpixel => 3,
+            AtlasFormat::Color => 4,
+        }
+    }
+
+    /// Format of the page a glyph rendered in `format` is packed into.
+    fn of(glyph: &RasterizedGlyph, format: GlyphFormat) -> Self {
+        match (&glyph.buffer, format) {
+            (BitmapBuffer::Rgba(_), _) | (BitmapBuffer::Bgra(_), _) => AtlasFormat::Color,
+            (BitmapBuffer::GrayF32(_), _) => AtlasFormat::Gray,
+            (BitmapBuffer::Rgb(_), GlyphFormat::Mono) => AtlasFormat::Mono,
+            (BitmapBuffer::Rgb(_), GlyphFormat::Gray8) => AtlasFormat::Gray,
+            (BitmapBuffer::Rgb(_), GlyphFormat::SubpixelRgb)
+            | (BitmapBuffer::Rgb(_), GlyphFormat::ColorRgba) => AtlasFormat::Subpixel,
+        }
+    }
+}
+
+/// Location of a glyph in an atlas.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AtlasRegion {
+    /// Index of the page in `GlyphAtlas::pages`.
+    pub page: usize,
+
+    /// Position of the glyph's top-left corner in the page, in pixels.
+    pub x: u32,
+    pub y: u32,
+
+    /// Size of the glyph's bitmap in pixels.
+    ///
+    /// Empty glyphs like spaces have a zero size and don't need to be drawn.
+    pub width: u32,
+    pub height: u32,
+
+    /// Texture coordinates `[left, top, right, bottom]` of the glyph from `0.0` to `1.0`.
+    pub uv: [f32; 4],
+
+    /// Bearings of the glyph, like `RasterizedGlyph::top` and `RasterizedGlyph::left`.
+    pub top: i32,
+    pub left: i32,
+
+    /// Advance of the glyph, like `RasterizedGlyph::advance`.
+    pub advance: (i32, i32),
+}
+
+/// Row of glyphs within a page.
+#[derive(Debug, Clone)]
+struct Shelf {
+    y: u32,
+    height: u32,
+
+    /// Start of the free space at the end of the shelf.
+    x: u32,
+}
+
+/// Square texture of an atlas with glyphs of one `AtlasFormat`.
+#[derive(Debug, Clone)]
+pub struct AtlasPage {
+    format: AtlasFormat,
+    size: u32,
+    buffer: Vec<u8>,
+    shelves: Vec<Shelf>,
+}
+
+impl AtlasPage {
+    fn new(format: AtlasFormat, size: u32) -> Self {
+        let buffer = vec![0; size as usize * size as usize * format.bytes_per_pixel()];
+        Self { format, size, buffer, shelves: Vec::new() }
+    }
+
+    /// Pixel format of the page.
+    pub fn format(&self) -> AtlasFormat {
+        self.format
+    }
+
+    /// Width and height of the page in pixels, always a power of two.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Pixels of the page in rows from top to bottom.
+    pub fn buffer(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Find space for a rectangle, using the shelf which wastes the least height.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        let padded_width = width + PADDING;
+        let padded_height = height + PADDING;
+
+        let size = self.size;
+        let shelf = self
+            .shelves
+            .iter_mut()
+            .filter(|shelf| shelf.height >= padded_height && shelf.x + padded_width <= size)
+            .min_by_key(|shelf| shelf.height);
+        if let Some(shelf) = shelf {
+            let position = (shelf.x, shelf.y);
+            shelf.x += padded_width;
+            return Some(position);
+        }
+
+        // Open a new shelf below the last one.
+        let y = self.shelves.last().map_or(0, |shelf| shelf.y + shelf.height);
+        if y + padded_height > self.size || padded_width > self.size {
+            return None;
+        }
+
+        self.shelves.push(Shelf { y, height: padded_height, x: padded_width });
+        Some((0, y))
+    }
+
+    /// Copy a glyph's pixels into the page, converting them to the page's format.
+    fn write(&mut self, x: u32, y: u32, glyph: &RasterizedGlyph) {
+        let bytes_per_pixel = self.format.bytes_per_pixel();
+        let width = glyph.width.max(0) as usize;
+
+        for row in 0..glyph.height.max(0) as usize {
+            let start = ((y as usize + row) * self.size as usize + x as usize) * bytes_per_pixel;
+            let target = &mut self.buffer[start..start + width * bytes_per_pixel];
+
+            match (&glyph.buffer, self.format) {
+                (BitmapBuffer::Rgb(pixels), AtlasFormat::Mono | AtlasFormat::Gray) => {
+                    let source = &pixels[row * width * 3..(row + 1) * width * 3];
+                    for (alpha, rgb) in target.iter_mut().zip(source.chunks_exact(3)) {
+                        *alpha = rgb[0];
+                    }
+                },
+                (BitmapBuffer::GrayF32(coverage), _) => {
+                    let source = &coverage[row * width..(row + 1) * width];
+                    for (alpha, &coverage) in target.iter_mut().zip(source) {
+                        *alpha = (coverage * 255.).round() as u8;
+                    }
+                },
+                (BitmapBuffer::Rgb(pixels), _) | (BitmapBuffer::Rgba(pixels), _) => {
+                    let row_bytes = width * bytes_per_pixel;
+                    target.copy_from_slice(&pixels[row * row_bytes..(row + 1) * row_bytes]);
+                },
+                (BitmapBuffer::Bgra(pixels), _) => {
+                    let source = &pixels[row * width * 4..(row + 1) * width * 4];
+                    for (rgba, bgra) in target.chunks_exact_mut(4).zip(source.chunks_exact(4)) {
+                        rgba.copy_from_slice(&[bgra[2], bgra[1], bgra[0], bgra[3]]);
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Texture atlas packing glyphs into power of two pages.
+///
+/// Glyphs are packed into rows of similar height, with separate pages for every `AtlasFormat`.
+/// The format of a glyph's page is chosen from the `GlyphFormat` it was rendered in.
+/// New pages are added when the existing ones are full, glyphs larger than a page get a page of
+/// their own.
+#[derive(Debug, Clone)]
+pub struct GlyphAtlas {
+    page_size: u32,
+    pages: Vec<AtlasPage>,
+    regions: HashMap<GlyphKey, AtlasRegion>,
+}
+
+impl GlyphAtlas {
+    /// Create an empty atlas with pages of `page_size` pixels, rounded up to a power of two.
+    pub fn new(page_size: u32) -> Self {
+        Self {
+            page_size: page_size.max(1).next_power_of_two(),
+            pages: Vec::new(),
+            regions: HashMap::new(),
+        }
+    }
+
+    /// Pages of the atlas, in the order they were added.
+    pub fn pages(&self) -> &[AtlasPage] {
+        &self.pages
+    }
+
+    /// Location of a glyph which was added before.
+    pub fn get(&self, key: &GlyphKey) -> Option<&AtlasRegion> {
+        self.regions.get(key)
+    }
+
+    /// Add a glyph rasterized in `format` to the atlas.
+    ///
+    /// Glyphs which were already added keep their location.
+    pub fn insert(
+        &mut self,
+        key: GlyphKey,
+        glyph: &RasterizedGlyph,
+        format: GlyphFormat,
+    ) -> AtlasRegion {
+        if let Some(region) = self.regions.get(&key) {
+            return *region;
+        }
+
+        let format = AtlasFormat::of(glyph, format);
+        let width = glyph.width.max(0) as u32;
+        let height = glyph.height.max(0) as u32;
+
+        let allocation = self
+            .pages
+            .iter_mut()
+            .enumerate()
+            .filter(|(_, page)| page.format == format)
+            .find_map(|(index, page)| page.allocate(width, height).map(|(x, y)| (index, x, y)));
+        let (page, x, y) = match allocation {
+            Some(allocation) => allocation,
+            None => {
+                let size = self.page_size.max((width.max(height) + PADDING).next_power_of_two());
+                let mut page = AtlasPage::new(format, size);
+                let (x, y) = page.allocate(width, height).unwrap();
+                self.pages.push(page);
+                (self.pages.len() - 1, x, y)
+            },
+        };
+
+        self.pages[page].write(x, y, glyph);
+
+        let size = self.pages[page].size as f32;
+        let region = AtlasRegion {
+            page,
+            x,
+            y,
+            width,
+            height,
+            uv: [
+                x as f32 / size,
+                y as f32 / size,
+                (x + width) as f32 / size,
+                (y + height) as f32 / size,
+            ],
+            top: glyph.top,
+            left: glyph.left,
+            advance: glyph.advance,
+        };
+        self.regions.insert(key, region);
+
+        region
+    }
+
+    /// Get the location of a glyph, rasterizing and adding it if necessary.
+    ///
+    /// Characters without a glyph in the font or its fallbacks use the font's missing glyph.
+    /// Backends which can't report the `GlyphFormat` get subpixel pages for RGB glyphs, which
+    /// keep all of their channels.
+    pub fn get_or_insert<R: Rasterize>(
+        &mut self,
+        rasterizer: &mut R,
+        key: GlyphKey,
+    ) -> Result<AtlasRegion, Error> {
+        if let Some(region) = self.regions.get(&key) {
+            return Ok(*region);
+        }
+
+        let format = match rasterizer.glyph_format(key) {
+            Ok(format) => format,
+            Err(Error::Unsupported { .. }) => GlyphFormat::SubpixelRgb,
+            Err(err) => return Err(err),
+        };
+
+        let glyph = match rasterizer.get_glyph(key) {
+            Ok(glyph) | Err(Error::MissingGlyph(glyph)) => glyph,
+            Err(err) => return Err(err),
+        };
+
+        Ok(self.insert(key, &glyph, format))
+    }
+
+    /// Remove all glyphs and pages.
+    pub fn clear(&mut self) {
+        self.pages.clear();
+        self.regions.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FontKey, Size};
+
+    fn key(character: char) -> GlyphKey {
+        GlyphKey { character, font_key: FontKey::next(), size: Size::new(12.) }
+    }
+
+    fn glyph(width: i32, height: i32, buffer: BitmapBuffer) -> RasterizedGlyph {
+        RasterizedGlyph { width, height, buffer, ..Default::default() }
+    }
+
+    #[test]
+    fn shelf_packing() {
+        let mut atlas = GlyphAtlas::new(60);
+        assert_eq!(atlas.page_size, 64);
+
+        let gray = |width, height| {
+            glyph(width, height, BitmapBuffer::Rgb(vec![255; (width * height * 3) as usize]))
+        };
+        let first = atlas.insert(key('a'), &gray(10, 20), GlyphFormat::Gray8);
+        let second = atlas.insert(key('b'), &gray(10, 18), GlyphFormat::Gray8);
+        let third = atlas.insert(key('c'), &gray(60, 30), GlyphFormat::Gray8);
+
+        assert_eq!((first.x, first.y), (0, 0));
+        assert_eq!((second.x, second.y), (11, 0));
+        assert_eq!((third.x, third.y), (0, 21));
+        assert_eq!(third.uv, [0., 21. / 64., 60. / 64., 51. / 64.]);
+        assert_eq!(atlas.pages().len(), 1);
+        assert_eq!(atlas.pages()[0].buffer()[0], 255);
+
+        // Full pages and larger glyphs get new pages.
+        let fourth = atlas.insert(key('d'), &gray(10, 25), GlyphFormat::Gray8);
+        let large = atlas.insert(key('e'), &gray(100, 10), GlyphFormat::Gray8);
+        assert_eq!(fourth.page, 1);
+        assert_eq!(large.page, 2);
+        assert_eq!(atlas.pages()[2].size(), 128);
+    }
+
+    #[test]
+    fn pages_per_format() {
+        let mut atlas = GlyphAtlas::new(64);
+
+        let subpixel = glyph(1, 1, BitmapBuffer::Rgb(vec![255, 128, 0]));
+        let color = glyph(1, 1, BitmapBuffer::Rgba(vec![255; 4]));
+        let gray = glyph(1, 1, BitmapBuffer::GrayF32(vec![0.5]));
+        let mono = glyph(1, 1, BitmapBuffer::Rgb(vec![255; 3]));
+        let white = glyph(1, 1, BitmapBuffer::Rgb(vec![255; 3]));
+        assert_eq!(atlas.insert(key('a'), &subpixel, GlyphFormat::SubpixelRgb).page, 0);
+        assert_eq!(atlas.insert(key('b'), &color, GlyphFormat::ColorRgba).page, 1);
+        assert_eq!(atlas.insert(key('c'), &gray, GlyphFormat::Gray8).page, 2);
+        assert_eq!(atlas.insert(key('d'), &mono, GlyphFormat::Mono).page, 3);
+
+        // Subpixel glyphs without color fringes still use subpixel pages.
+        assert_eq!(atlas.insert(key('e'), &white, GlyphFormat::SubpixelRgb).page, 0);
+
+        let formats: Vec<_> = atlas.pages().iter().map(|page| page.format()).collect();
+        assert_eq!(formats, [
+            AtlasFormat::Subpixel,
+            AtlasFormat::Color,
+            AtlasFormat::Gray,
+            AtlasFormat::Mono
+        ]);
+        assert_eq!(&atlas.pages()[0].buffer()[..3], [255, 128, 0]);
+        assert_eq!(atlas.pages()[2].buffer()[0], 128);
+        assert_eq!(atlas.pages()[3].buffer()[0], 255);
+    }
+}
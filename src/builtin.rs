@@ -0,0 +1,610 @@
+//! Box drawing, block element and Powerline glyphs drawn to fill the cell exactly.
+//!
+//! Fonts often don't align these glyphs with the line height and advance, which leaves gaps
+//! between lines and blocks drawn from multiple characters. Boxes with the codepoint of missing
+//! characters are drawn here too, since they don't depend on any font.
+
+use crate::{BitmapBuffer, FontKey, Metrics, RasterizedGlyph};
+
+/// Arms of the box drawing lines from U+2500 to U+257F.
+///
+/// Every entry has the weight of the up, right, down and left arm: `L` for light, `H` for heavy
+/// and `D` for double lines. Dashed lines, arcs and diagonals are drawn separately.
+#[rustfmt::skip]
+const LINES: [&[u8; 4]; 128] = [
+    b" L L", b" H H", b"L L ", b"H H ", b"    ", b"    ", b"    ", b"    ", // U+2500
+    b"    ", b"    ", b"    ", b"    ", b" LL ", b" HL ", b" LH ", b" HH ", // U+2508
+    b"  LL", b"  LH", b"  HL", b"  HH", b"LL  ", b"LH  ", b"HL  ", b"HH  ", // U+2510
+    b"L  L", b"L  H", b"H  L", b"H  H", b"LLL ", b"LHL ", b"HLL ", b"LLH ", // U+2518
+    b"HLH ", b"HHL ", b"LHH ", b"HHH ", b"L LL", b"L LH", b"H LL", b"L HL", // U+2520
+    b"H HL", b"H LH", b"L HH", b"H HH", b" LLL", b" LLH", b" HLL", b" HLH", // U+2528
+    b" LHL", b" LHH", b" HHL", b" HHH", b"LL L", b"LL H", b"LH L", b"LH H", // U+2530
+    b"HL L", b"HL H", b"HH L", b"HH H", b"LLLL", b"LLLH", b"LHLL", b"LHLH", // U+2538
+    b"HLLL", b"LLHL", b"HLHL", b"HLLH", b"HHLL", b"LLHH", b"LHHL", b"HHLH", // U+2540
+    b"LHHH", b"HLHH", b"HHHL", b"HHHH", b"    ", b"    ", b"    ", b"    ", // U+2548
+    b" D D", b"D D ", b" DL ", b" LD ", b" DD ", b"  LD", b"  DL", b"  DD", // U+2550
+    b"LD  ", b"DL  ", b"DD  ", b"L  D", b"D  L", b"D  D", b"LDL ", b"DLD ", // U+2558
+    b"DDD ", b"L LD", b"D DL", b"D DD", b" DLD", b" LDL", b" DDD", b"LD D", // U+2560
+    b"DL L", b"DD D", b"LDLD", b"DLDL", b"DDDD", b"    ", b"    ", b"    ", // U+2568
+    b"    ", b"    ", b"    ", b"    ", b"   L", b"L   ", b" L  ", b"  L ", // U+2570
+    b"   H", b"H   ", b" H  ", b"  H ", b" H L", b"L H ", b" L H", b"H L ", // U+2578
+];
+
+/// Samples per pixel axis when drawing filled shapes.
+const SUPERSAMPLING: u8 = 4;
+
+/// Hexadecimal digits in a 3x5 pixel font, every row has the left pixel in its highest bit.
+#[rustfmt::skip]
+const HEX_DIGITS: [[u8; 5]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111], [0b010, 0b110, 0b010, 0b010, 0b111], // 0 1
+    [0b111, 0b001, 0b111, 0b100, 0b111], [0b111, 0b001, 0b111, 0b001, 0b111], // 2 3
+    [0b101, 0b101, 0b111, 0b001, 0b001], [0b111, 0b100, 0b111, 0b001, 0b111], // 4 5
+    [0b111, 0b100, 0b111, 0b101, 0b111], [0b111, 0b001, 0b001, 0b001, 0b001], // 6 7
+    [0b111, 0b101, 0b111, 0b101, 0b111], [0b111, 0b101, 0b111, 0b001, 0b111], // 8 9
+    [0b111, 0b101, 0b111, 0b101, 0b101], [0b110, 0b101, 0b110, 0b101, 0b110], // A B
+    [0b111, 0b100, 0b100, 0b100, 0b111], [0b110, 0b101, 0b101, 0b101, 0b110], // C D
+    [0b111, 0b100, 0b111, 0b100, 0b111], [0b111, 0b100, 0b111, 0b100, 0b100], // E F
+];
+
+/// Check if a character is drawn by the rasterizer itself when builtin box drawing is enabled.
+///
+/// This covers box drawing characters (U+2500 to U+257F) and block elements (U+2580 to U+259F).
+pub fn is_builtin_glyph(character: char) -> bool {
+    matches!(character, '\u{2500}'..='\u{259f}')
+}
+
+/// Check if a character is drawn by the rasterizer itself when builtin Powerline is enabled.
+///
+/// This covers the Powerline triangle and half circle separators (U+E0B0 to U+E0B7).
+pub fn is_powerline_glyph(character: char) -> bool {
+    matches!(character, '\u{e0b0}'..='\u{e0b7}')
+}
+
+/// Character ranges drawn by the rasterizer instead of the font.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct BuiltinGlyphs {
+    pub box_drawing: bool,
+    pub powerline: bool,
+}
+
+impl BuiltinGlyphs {
+    /// Check if the glyph of `character` should be drawn by `builtin_glyph`.
+    pub fn contains(self, character: char) -> bool {
+        (self.box_drawing && is_builtin_glyph(character))
+            || (self.powerline && is_powerline_glyph(character))
+    }
+}
+
+/// Draw a builtin glyph filling a cell of the font's `metrics`.
+///
+/// The glyph uses baseline bearings, with its top edge at the top of the line.
+pub fn builtin_glyph(
+    character: char,
+    metrics: &Metrics,
+    font_key: FontKey,
+    dpr: f32,
+) -> RasterizedGlyph {
+    let width = (metrics.average_advance.round() as i32).max(1);
+    let height = (metrics.line_height.round() as i32).max(1);
+    let thickness = (metrics.underline_thickness.round() as i32).max(1);
+
+    let mut canvas = Canvas::new(width, height, thickness);
+    canvas.draw(character);
+
+    let top = (metrics.line_height + f64::from(metrics.descent)).round() as i32;
+    RasterizedGlyph {
+        character,
+        width,
+        height,
+        top,
+        left: 0,
+        top_f: top as f32,
+        left_f: 0.,
+        advance: (width, 0),
+        advance_f: (width as f32, 0.),
+        native_advance: None,
+        buffer: BitmapBuffer::Rgb(canvas.pixels.iter().flat_map(|&alpha| [alpha; 3]).collect()),
+        font_key,
+        dpr,
+    }
+}
+
+/// Draw a box with the hexadecimal codepoint of `character` filling a cell of `cell` pixels.
+///
+/// The border is a twelfth of the `pixel_size` thick, the digits are split over two rows and
+/// scaled to fit inside it. The glyph uses baseline bearings, with its bottom edge on the
+/// baseline.
+pub fn tofu_glyph(character: char, pixel_size: f32, cell: (u32, u32), dpr: f32) -> RasterizedGlyph {
+    let width = (cell.0 as i32).max(1);
+    let height = (cell.1 as i32).max(1);
+    let thickness = ((pixel_size / 12.).round() as i32).max(1);
+
+    let mut canvas = Canvas::new(width, height, thickness);
+    canvas.tofu(character);
+
+    RasterizedGlyph {
+        character,
+        width,
+        height,
+        top: height,
+        left: 0,
+        top_f: height as f32,
+        left_f: 0.,
+        advance: (width, 0),
+        advance_f: (width as f32, 0.),
+        native_advance: None,
+        buffer: BitmapBuffer::Rgb(canvas.pixels.iter().flat_map(|&alpha| [alpha; 3]).collect()),
+        dpr,
+        // The box doesn't belong to any font.
+        ..Default::default()
+    }
+}
+
+/// Coverage bitmap of a single cell.
+struct Canvas {
+    width: i32,
+    height: i32,
+    /// Thickness of light lines.
+    light: i32,
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: i32, height: i32, light: i32) -> Self {
+        Self { width, height, light, pixels: vec![0; (width * height) as usize] }
+    }
+
+    fn draw(&mut self, character: char) {
+        match character {
+            '\u{2504}'..='\u{250b}' | '\u{254c}'..='\u{254f}' => self.dashed(character),
+            '\u{256d}'..='\u{2570}' => self.arc(character),
+            '\u{2571}'..='\u{2573}' => self.diagonal(character),
+            '\u{2500}'..='\u{257f}' => self.lines(LINES[character as usize - 0x2500]),
+            '\u{2580}'..='\u{259f}' => self.block(character),
+            '\u{e0b0}'..='\u{e0b7}' => self.powerline(character),
+            _ => (),
+        }
+    }
+
+    /// Horizontal center of the cell.
+    fn center_x(&self) -> i32 {
+        self.width / 2
+    }
+
+    /// Vertical center of the cell.
+    fn center_y(&self) -> i32 {
+        self.height / 2
+    }
+
+    /// Offset of double lines from the center.
+    fn double_offset(&self) -> i32 {
+        self.light
+    }
+
+    fn thickness(&self, weight: u8) -> i32 {
+        match weight {
+            b'H' => self.light * 2,
+            _ => self.light,
+        }
+    }
+
+    /// Fill a rectangle, clipped to the cell.
+    fn fill_rect(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, alpha: u8) {
+        for y in y0.max(0)..y1.min(self.height) {
+            for x in x0.max(0)..x1.min(self.width) {
+                let pixel = &mut self.pixels[(y * self.width + x) as usize];
+                *pixel = (*pixel).max(alpha);
+            }
+        }
+    }
+
+    /// Fill every pixel with the coverage at its center.
+    fn fill_coverage(&mut self, coverage: impl Fn(f32, f32) -> f32) {
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let alpha = coverage(x as f32 + 0.5, y as f32 + 0.5).clamp(0., 1.);
+                let pixel = &mut self.pixels[(y * self.width + x) as usize];
+                *pixel = (*pixel).max((alpha * 255.).round() as u8);
+            }
+        }
+    }
+
+    /// Fill a shape, antialiased by sampling multiple points per pixel.
+    fn fill_shape(&mut self, inside: impl Fn(f32, f32) -> bool) {
+        let step = 1. / f32::from(SUPERSAMPLING);
+        self.fill_coverage(|x, y| {
+            let (x, y) = (x - 0.5 + step / 2., y - 0.5 + step / 2.);
+            let samples = (0..SUPERSAMPLING)
+                .flat_map(|row| (0..SUPERSAMPLING).map(move |column| (row, column)))
+                .filter(|&(row, column)| {
+                    inside(x + f32::from(column) * step, y + f32::from(row) * step)
+                })
+                .count();
+            samples as f32 / f32::from(SUPERSAMPLING * SUPERSAMPLING)
+        });
+    }
+
+    /// Draw a stroke of `thickness` along a line through two points.
+    fn stroke_line(&mut self, from: (f32, f32), to: (f32, f32), thickness: f32) {
+        let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+        let length = dx.hypot(dy);
+        self.fill_coverage(|x, y| {
+            let distance = ((x - from.0) * dy - (y - from.1) * dx).abs() / length;
+            thickness / 2. + 0.5 - distance
+        });
+    }
+
+    /// Draw the box drawing line arms of one entry of `LINES`.
+    fn lines(&mut self, arms: &[u8; 4]) {
+        let gap = self.double_offset();
+
+        for (direction, &weight) in arms.iter().enumerate() {
+            if weight == b' ' {
+                continue;
+            }
+
+            // Arms to the negative and positive side of this arm, like left and right of up.
+            let sides = if direction % 2 == 0 { [3, 1] } else { [0, 2] };
+
+            // Direction of the cell edge the arm is drawn to, relative to the center.
+            let edge = match direction {
+                0 | 3 => -1,
+                _ => 1,
+            };
+            let (near, far) = (edge * gap, -edge * gap);
+
+            if weight == b'D' {
+                // Each of the two lines connects to the closest perpendicular line.
+                for (&side, offset) in sides.iter().zip([-gap, gap]) {
+                    let opposite = sides[0] + sides[1] - side;
+                    let reach = match (arms[side], arms[opposite]) {
+                        (b'D', _) => (near, self.light),
+                        (b' ', b' ' | b'D') => (far, self.light),
+                        (b' ', opposite_weight) => (0, self.thickness(opposite_weight)),
+                        (side_weight, _) => (0, self.thickness(side_weight)),
+                    };
+                    self.arm(direction, offset, self.light, reach);
+                }
+                continue;
+            }
+
+            let thickness = self.thickness(weight);
+            let doubles = sides.iter().filter(|&&side| arms[side] == b'D').count();
+            let reach = match doubles {
+                2 => (near, self.light),
+                1 => (far, self.light),
+                _ => {
+                    let side_thickness = sides
+                        .iter()
+                        .filter(|&&side| arms[side] != b' ')
+                        .map(|&side| self.thickness(arms[side]))
+                        .max();
+                    (0, side_thickness.unwrap_or(thickness))
+                },
+            };
+            self.arm(direction, 0, thickness, reach);
+        }
+    }
+
+    /// Draw a line from the edge of the cell in `direction` to the center.
+    ///
+    /// The line is moved sideways by `offset` and ends once it fully covers the perpendicular line
+    /// `reach`, which is a tuple of the line's offset from the center and its thickness.
+    fn arm(&mut self, direction: usize, offset: i32, thickness: i32, reach: (i32, i32)) {
+        let (reach_offset, reach_thickness) = reach;
+        let vertical = direction % 2 == 0;
+
+        let (center, cross_center) = if vertical {
+            (self.center_x(), self.center_y())
+        } else {
+            (self.center_y(), self.center_x())
+        };
+        let start = center + offset - thickness / 2;
+        let reach_start = cross_center + reach_offset - reach_thickness / 2;
+        let reach_end = reach_start + reach_thickness;
+
+        let (cross_start, cross_end) = match direction {
+            0 | 3 => (0, reach_end),
+            _ => (reach_start, i32::MAX),
+        };
+
+        if vertical {
+            self.fill_rect(start, cross_start, start + thickness, cross_end, u8::MAX);
+        } else {
+            self.fill_rect(cross_start, start, cross_end, start + thickness, u8::MAX);
+        }
+    }
+
+    /// Draw dashed lines, with the gap between dashes split between both ends of every dash.
+    fn dashed(&mut self, character: char) {
+        let index = character as u32 - 0x2504;
+        let (dashes, index) = match character {
+            '\u{254c}'..='\u{254f}' => (2, character as u32 - 0x254c),
+            _ => (3 + index / 4, index % 4),
+        };
+        let thickness = if index % 2 == 0 { self.light } else { self.light * 2 };
+        let vertical = index >= 2;
+
+        let length = if vertical { self.height } else { self.width };
+        let segment = length as f32 / dashes as f32;
+        for dash in 0..dashes {
+            let segment_start = (dash as f32 * segment).round() as i32;
+            let segment_end = ((dash + 1) as f32 * segment).round() as i32;
+            let gap = ((segment_end - segment_start) / 2).max(1);
+            let start = segment_start + gap / 2;
+            let end = segment_end - (gap - gap / 2);
+
+            if vertical {
+                let x = self.center_x() - thickness / 2;
+                self.fill_rect(x, start, x + thickness, end, u8::MAX);
+            } else {
+                let y = self.center_y() - thickness / 2;
+                self.fill_rect(start, y, end, y + thickness, u8::MAX);
+            }
+        }
+    }
+
+    /// Draw rounded corners as a quarter circle connecting the light lines of both arms.
+    fn arc(&mut self, character: char) {
+        // Direction of the horizontal and vertical arm.
+        let (dx, dy) = match character {
+            '\u{256d}' => (1., 1.),
+            '\u{256e}' => (-1., 1.),
+            '\u{256f}' => (-1., -1.),
+            _ => (1., -1.),
+        };
+
+        let thickness = self.light as f32;
+        let x = (self.center_x() - self.light / 2) as f32 + thickness / 2.;
+        let y = (self.center_y() - self.light / 2) as f32 + thickness / 2.;
+
+        let (width, height) = (self.width as f32, self.height as f32);
+        let horizontal_space = if dx > 0. { width - x } else { x };
+        let vertical_space = if dy > 0. { height - y } else { y };
+        let radius = horizontal_space.min(vertical_space);
+        let (center_x, center_y) = (x + dx * radius, y + dy * radius);
+
+        self.fill_coverage(|px, py| {
+            if (px - center_x) * dx > 0. || (py - center_y) * dy > 0. {
+                return 0.;
+            }
+
+            let distance = (px - center_x).hypot(py - center_y);
+            thickness / 2. + 0.5 - (distance - radius).abs()
+        });
+
+        // Straight lines from the end of the arc to the edges of the cell.
+        let (line_x, line_y) = (self.center_x() - self.light / 2, self.center_y() - self.light / 2);
+        let (arc_x, arc_y) = (center_x.round() as i32, center_y.round() as i32);
+        if dx > 0. {
+            self.fill_rect(arc_x, line_y, self.width, line_y + self.light, u8::MAX);
+        } else {
+            self.fill_rect(0, line_y, arc_x, line_y + self.light, u8::MAX);
+        }
+        if dy > 0. {
+            self.fill_rect(line_x, arc_y, line_x + self.light, self.height, u8::MAX);
+        } else {
+            self.fill_rect(line_x, 0, line_x + self.light, arc_y, u8::MAX);
+        }
+    }
+
+    /// Draw diagonals from corner to corner.
+    fn diagonal(&mut self, character: char) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let thickness = self.light as f32;
+
+        if character != '\u{2572}' {
+            self.stroke_line((width, 0.), (0., height), thickness);
+        }
+
+        if character != '\u{2571}' {
+            self.stroke_line((0., 0.), (width, height), thickness);
+        }
+    }
+
+    /// Draw block elements, which are made of eighths and quadrants of the cell.
+    fn block(&mut self, character: char) {
+        let (width, height) = (self.width, self.height);
+        let eighths = |length: i32, count: i32| (length * count + 4) / 8;
+        // Eighths grow from the left and bottom edges, halves have to line up with them.
+        let (half_x, half_y) = (eighths(width, 4), height - eighths(height, 4));
+
+        match character {
+            '\u{2580}' => self.fill_rect(0, 0, width, half_y, u8::MAX),
+            '\u{2581}'..='\u{2588}' => {
+                let count = character as i32 - 0x2580;
+                self.fill_rect(0, height - eighths(height, count), width, height, u8::MAX);
+            },
+            '\u{2589}'..='\u{258f}' => {
+                let count = 0x2590 - character as i32;
+                self.fill_rect(0, 0, eighths(width, count), height, u8::MAX);
+            },
+            '\u{2590}' => self.fill_rect(half_x, 0, width, height, u8::MAX),
+            '\u{2591}'..='\u{2593}' => {
+                let alpha = (character as u32 - 0x2590) * 64;
+                self.fill_rect(0, 0, width, height, alpha as u8);
+            },
+            '\u{2594}' => self.fill_rect(0, 0, width, eighths(height, 1), u8::MAX),
+            '\u{2595}' => self.fill_rect(width - eighths(width, 1), 0, width, height, u8::MAX),
+            _ => {
+                // Quadrants as upper left, upper right, lower left and lower right.
+                let quadrants: [bool; 4] = match character {
+                    '\u{2596}' => [false, false, true, false],
+                    '\u{2597}' => [false, false, false, true],
+                    '\u{2598}' => [true, false, false, false],
+                    '\u{2599}' => [true, false, true, true],
+                    '\u{259a}' => [true, false, false, true],
+                    '\u{259b}' => [true, true, true, false],
+                    '\u{259c}' => [true, true, false, true],
+                    '\u{259d}' => [false, true, false, false],
+                    '\u{259e}' => [false, true, true, false],
+                    _ => [false, true, true, true],
+                };
+
+                let columns = [(0, half_x), (half_x, width)];
+                let rows = [(0, half_y), (half_y, height)];
+                for (index, _) in quadrants.iter().enumerate().filter(|(_, &filled)| filled) {
+                    let (x0, x1) = columns[index % 2];
+                    let (y0, y1) = rows[index / 2];
+                    self.fill_rect(x0, y0, x1, y1, u8::MAX);
+                }
+            },
+        }
+    }
+
+    /// Draw a box outline with the hexadecimal digits of the codepoint in two rows.
+    fn tofu(&mut self, character: char) {
+        let border = self.light;
+        self.fill_rect(0, 0, self.width, border, u8::MAX);
+        self.fill_rect(0, self.height - border, self.width, self.height, u8::MAX);
+        self.fill_rect(0, 0, border, self.height, u8::MAX);
+        self.fill_rect(self.width - border, 0, self.width, self.height, u8::MAX);
+
+        let digits = format!("{:04X}", character as u32);
+        let columns = (digits.len() + 1) / 2;
+
+        // Digits are 3x5 pixels with a gap of one pixel, scaled to the space inside the border.
+        let inset = border * 2;
+        let (grid_width, grid_height) = ((columns * 4 - 1) as f32, 11.);
+        let scale = ((self.width - inset * 2) as f32 / grid_width)
+            .min((self.height - inset * 2) as f32 / grid_height)
+            .max(0.);
+        let origin_x = (self.width as f32 - grid_width * scale) / 2.;
+        let origin_y = (self.height as f32 - grid_height * scale) / 2.;
+
+        for (index, digit) in digits.bytes().enumerate() {
+            let value = (digit as char).to_digit(16).unwrap_or_default() as usize;
+            let (row, column) = (index / columns, index % columns);
+            for (y, bits) in HEX_DIGITS[value].iter().enumerate() {
+                for x in (0..3).filter(|x| bits & (0b100 >> x) != 0) {
+                    let grid_x = (column * 4 + x) as f32;
+                    let grid_y = (row * 6 + y) as f32;
+                    let x0 = (origin_x + grid_x * scale).round() as i32;
+                    let y0 = (origin_y + grid_y * scale).round() as i32;
+                    let x1 = (origin_x + (grid_x + 1.) * scale).round() as i32;
+                    let y1 = (origin_y + (grid_y + 1.) * scale).round() as i32;
+                    self.fill_rect(x0, y0, x1, y1, u8::MAX);
+                }
+            }
+        }
+    }
+
+    /// Draw the Powerline triangles, arrows and half circles pointing right or left.
+    fn powerline(&mut self, character: char) {
+        let (width, height) = (self.width as f32, self.height as f32);
+        let thickness = self.light as f32;
+
+        // Mirror the glyphs pointing left.
+        let mirrored = matches!(character, '\u{e0b2}' | '\u{e0b3}' | '\u{e0b6}' | '\u{e0b7}');
+        let flip = move |x: f32| if mirrored { width - x } else { x };
+
+        // Half circles are ellipses centered on the cell's edge, touching the opposite edge.
+        let ellipse = move |x: f32, y: f32| {
+            let (x, y) = (flip(x) / width, (y - height / 2.) / (height / 2.));
+            x * x + y * y - 1.
+        };
+
+        match character {
+            '\u{e0b0}' | '\u{e0b2}' => self.fill_shape(|x, y| {
+                let half = height / 2. * (1. - flip(x) / width);
+                (y - height / 2.).abs() <= half
+            }),
+            '\u{e0b1}' | '\u{e0b3}' => {
+                let (top, tip, bottom) =
+                    ((flip(0.), 0.), (flip(width), height / 2.), (flip(0.), height));
+                self.fill_coverage(|x, y| {
+                    let distance = segment_distance((x, y), top, tip).min(segment_distance(
+                        (x, y),
+                        tip,
+                        bottom,
+                    ));
+                    thickness / 2. + 0.5 - distance
+                });
+            },
+            '\u{e0b4}' | '\u{e0b6}' => self.fill_shape(|x, y| ellipse(x, y) <= 0.),
+            _ => self.fill_coverage(|x, y| {
+                // Approximate the distance to the ellipse using its gradient.
+                let (nx, ny) = (flip(x) / width, (y - height / 2.) / (height / 2.));
+                let gradient = (2. * nx / width).hypot(2. * ny / (height / 2.));
+                let distance = ellipse(x, y).abs() / gradient.max(f32::EPSILON);
+                thickness / 2. + 0.5 - distance
+            }),
+        }
+    }
+}
+
+/// Distance from a point to a line segment.
+fn segment_distance(point: (f32, f32), from: (f32, f32), to: (f32, f32)) -> f32 {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let t = ((point.0 - from.0) * dx + (point.1 - from.1) * dy) / (dx * dx + dy * dy);
+    let t = t.clamp(0., 1.);
+    (point.0 - from.0 - t * dx).hypot(point.1 - from.1 - t * dy)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn draw(character: char) -> Canvas {
+        let mut canvas = Canvas::new(10, 19, 1);
+        canvas.draw(character);
+        canvas
+    }
+
+    #[test]
+    fn blocks_fill_cell() {
+        assert!(draw('█').pixels.iter().all(|&alpha| alpha == u8::MAX));
+
+        let upper = draw('▀');
+        let lower = draw('▄');
+        for (upper, lower) in upper.pixels.iter().zip(&lower.pixels) {
+            assert_ne!(upper, lower);
+        }
+    }
+
+    #[test]
+    fn lines_connect_to_cell_edges() {
+        let canvas = draw('┼');
+        let row = |y: i32| &canvas.pixels[(y * 10) as usize..(y * 10 + 10) as usize];
+        assert!(row(9).iter().all(|&alpha| alpha == u8::MAX));
+        assert_eq!(row(0).iter().filter(|&&alpha| alpha != 0).count(), 1);
+        assert_eq!(row(0), row(18));
+
+        // Double lines leave a gap in their intersection.
+        let canvas = draw('╬');
+        assert_eq!(canvas.pixels[9 * 10 + 5], 0);
+        assert_eq!(canvas.pixels[10 * 10 + 5], 0);
+    }
+
+    #[test]
+    fn powerline_separators_cover_edge() {
+        let right = draw('\u{e0b0}');
+        let left = draw('\u{e0b2}');
+        for y in 0..19 {
+            assert!(right.pixels[y * 10] > 0);
+            assert!(left.pixels[y * 10 + 9] > 0);
+        }
+        assert_eq!(right.pixels[9], 0);
+        assert_eq!(left.pixels[0], 0);
+    }
+
+    #[test]
+    fn tofu_shows_codepoint() {
+        let glyph = tofu_glyph('\u{1f600}', 24., (20, 36), 1.);
+        assert_eq!((glyph.width, glyph.height, glyph.top), (20, 36, 36));
+
+        let mut canvas = Canvas::new(20, 36, 2);
+        canvas.tofu('\u{1f600}');
+        let pixel = |x: i32, y: i32| canvas.pixels[(y * 20 + x) as usize];
+        assert_eq!((pixel(0, 0), pixel(19, 35), pixel(1, 18)), (255, 255, 255));
+        assert_eq!(pixel(3, 18), 0);
+
+        // Five digits use three columns, with the bottom right one left empty.
+        let inner = |x0: i32, x1: i32, y0: i32, y1: i32| {
+            (y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y))).any(|(x, y)| pixel(x, y) != 0)
+        };
+        assert!(inner(4, 16, 4, 18));
+        assert!(inner(4, 10, 18, 32));
+        assert!(!inner(14, 16, 18, 32));
+    }
+}
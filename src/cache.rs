@@ -0,0 +1,803 @@
+//! Glyph caching for any rasterizer.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Debug, Formatter};
+use std::hash::{Hash, Hasher};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+#[cfg(feature = "graphemes")]
+use super::ShapedGrapheme;
+
+use super::{
+    AlphaPrecision, BlendMode, Capabilities, CharacterVariant, CharmapPref, ColorOrder, Error,
+    FaceId, FallbackScaling, FontDesc, FontFormat, FontKey, FontMetadata, GlyphFormat, GlyphKey,
+    GlyphOrigin, Metrics, OutlineKind, Rasterize, RasterizedGlyph, RoundingMode, RunExtents, Size,
+    Slant, Stretch, StrokeStyle, Tag, TtInterpreter, UnicodeBlock, Weight,
+};
+
+/// Default number of subpixel positions cached per glyph and axis.
+const DEFAULT_SUBPIXEL_LEVELS: u8 = 3;
+
+/// Identity of a cached glyph.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub struct CacheKey {
+    pub glyph: GlyphKey,
+
+    /// Quantized horizontal subpixel position.
+    ///
+    /// The glyph is rendered with an offset of `subpixel / subpixel_levels` pixels.
+    pub subpixel: u8,
+
+    /// Quantized vertical subpixel position, like `subpixel`.
+    pub subpixel_y: u8,
+
+    /// Bits of the device pixel ratio the glyph was rasterized for.
+    pub dpr: u32,
+
+    /// Fingerprint of the rasterizer's settings which affect rasterized glyphs.
+    ///
+    /// Rasterizers sharing a cache only share glyphs rasterized with the same settings.
+    pub settings: u64,
+}
+
+/// Callback for glyphs removed from the cache.
+type EvictionCallback = Box<dyn FnMut(GlyphKey) + Send>;
+
+/// Storage for rasterized glyphs.
+///
+/// Without a capacity, glyphs stay in the cache until it is cleared. With a capacity, the least
+/// recently used glyphs are evicted to make room for new ones.
+#[derive(Default)]
+pub struct GlyphCache {
+    /// Cached glyphs with the tick of their last use.
+    glyphs: HashMap<CacheKey, (RasterizedGlyph, u64)>,
+
+    /// Cached glyphs by the tick of their last use, oldest first.
+    usage: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    capacity: Option<usize>,
+
+    /// Eviction callbacks of every rasterizer using the cache, by their ID.
+    on_evict: HashMap<usize, EvictionCallback>,
+}
+
+impl Debug for GlyphCache {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GlyphCache")
+            .field("len", &self.glyphs.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+impl GlyphCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get a glyph from the cache, marking it as recently used.
+    pub fn get(&mut self, key: &CacheKey) -> Option<&RasterizedGlyph> {
+        let tick = self.next_tick();
+        let (glyph, last_use) = self.glyphs.get_mut(key)?;
+        self.usage.remove(last_use);
+        self.usage.insert(tick, *key);
+        *last_use = tick;
+        Some(glyph)
+    }
+
+    /// Add a glyph to the cache, evicting the least recently used glyphs above the capacity.
+    pub fn insert(&mut self, key: CacheKey, glyph: RasterizedGlyph) {
+        let tick = self.next_tick();
+        if let Some((_, last_use)) = self.glyphs.insert(key, (glyph, tick)) {
+            self.usage.remove(&last_use);
+        }
+        self.usage.insert(tick, key);
+        self.evict_to_capacity();
+    }
+
+    /// Limit the number of glyphs in the cache.
+    ///
+    /// Glyphs above the capacity are evicted immediately, least recently used first. `None`
+    /// removes the limit.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.capacity = capacity;
+        self.evict_to_capacity();
+    }
+
+    /// Maximum number of glyphs in the cache.
+    pub fn capacity(&self) -> Option<usize> {
+        self.capacity
+    }
+
+    /// Keys of all glyphs in the cache.
+    pub fn keys(&self) -> impl Iterator<Item = &CacheKey> + '_ {
+        self.glyphs.keys()
+    }
+
+    /// Number of glyphs in the cache.
+    pub fn len(&self) -> usize {
+        self.glyphs.len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.glyphs.is_empty()
+    }
+
+    /// Remove all glyphs from the cache, notifying the eviction callbacks.
+    pub fn clear(&mut self) {
+        for key in self.usage.values() {
+            for on_evict in self.on_evict.values_mut() {
+                on_evict(key.glyph);
+            }
+        }
+
+        self.glyphs.clear();
+        self.usage.clear();
+    }
+
+    fn evict_to_capacity(&mut self) {
+        let capacity = match self.capacity {
+            Some(capacity) => capacity,
+            None => return,
+        };
+
+        while self.glyphs.len() > capacity {
+            let tick = match self.usage.keys().next() {
+                Some(tick) => *tick,
+                None => break,
+            };
+            let key = self.usage.remove(&tick).unwrap();
+            self.glyphs.remove(&key);
+
+            for on_evict in self.on_evict.values_mut() {
+                on_evict(key.glyph);
+            }
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+}
+
+/// Rasterizer which caches all glyphs rendered by the wrapped rasterizer.
+///
+/// The cache can be shared by multiple rasterizers, like one per window, so glyphs are only
+/// rasterized once.
+pub struct CachingRasterizer<R> {
+    rasterizer: R,
+    cache: Arc<Mutex<GlyphCache>>,
+    subpixel_levels: u8,
+    origin: GlyphOrigin,
+
+    /// Hashes of the settings changed through this rasterizer, by the hash of the setting.
+    settings: BTreeMap<u64, u64>,
+
+    /// Hash of all `settings`, used as `CacheKey::settings`.
+    fingerprint: u64,
+
+    /// Identifier of the rasterizer's eviction callback in the shared cache.
+    id: usize,
+}
+
+impl<R: Rasterize> CachingRasterizer<R> {
+    /// Wrap an existing rasterizer.
+    pub fn with_rasterizer(rasterizer: R) -> Self {
+        Self::with_shared_cache(rasterizer, Arc::default())
+    }
+
+    /// Wrap an existing rasterizer, storing its glyphs in a cache shared with other rasterizers.
+    ///
+    /// Glyphs are only shared between rasterizers with the same device pixel ratio, subpixel
+    /// levels and rendering settings, so rasterizers can change their settings independently.
+    /// Only settings changed through the `CachingRasterizer` are considered, so the wrapped
+    /// rasterizers must use the same settings when they are passed in. The `FontKey`s of FreeType
+    /// fonts loaded with `load_font` match across rasterizers, fonts loaded from files or bytes
+    /// are never shared.
+    pub fn with_shared_cache(rasterizer: R, cache: Arc<Mutex<GlyphCache>>) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        Self {
+            rasterizer,
+            cache,
+            subpixel_levels: DEFAULT_SUBPIXEL_LEVELS,
+            origin: GlyphOrigin::default(),
+            settings: BTreeMap::new(),
+            fingerprint: hash(&BTreeMap::<u64, u64>::new()),
+            id,
+        }
+    }
+
+    /// Access the wrapped rasterizer.
+    pub fn rasterizer(&self) -> &R {
+        &self.rasterizer
+    }
+
+    /// Access the glyph cache.
+    ///
+    /// The cache is locked until the guard is dropped, which blocks other rasterizers sharing it.
+    pub fn cache(&self) -> MutexGuard<'_, GlyphCache> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Handle to the glyph cache, for sharing it with `with_shared_cache`.
+    pub fn shared_cache(&self) -> Arc<Mutex<GlyphCache>> {
+        self.cache.clone()
+    }
+
+    /// Set the number of subpixel positions cached per glyph on each axis.
+    ///
+    /// Using a single level disables subpixel positioning.
+    pub fn set_subpixel_levels(&mut self, levels: u8) {
+        self.subpixel_levels = levels.max(1);
+        self.record_setting("subpixel_levels", self.subpixel_levels);
+    }
+
+    /// Register a callback for glyphs removed from the cache.
+    ///
+    /// Glyphs are removed when the cache exceeds its capacity or is cleared. Glyphs rasterized
+    /// with previous settings stay in the cache until they are evicted, since other rasterizers
+    /// sharing the cache might still use them. The callback is called once for every cached
+    /// subpixel position of a glyph and replaces any previously registered callback of this
+    /// rasterizer. Callbacks of all rasterizers sharing the cache are called, while the cache
+    /// is locked.
+    pub fn on_evict(&mut self, f: impl FnMut(GlyphKey) + Send + 'static) {
+        self.cache().on_evict.insert(self.id, Box::new(f));
+    }
+
+    /// Limit the number of glyphs in the cache, evicting the least recently used ones.
+    ///
+    /// The limit applies to the whole cache, including glyphs of other rasterizers sharing it.
+    /// `None` removes the limit, which is the default.
+    pub fn set_capacity(&mut self, capacity: Option<usize>) {
+        self.cache().set_capacity(capacity);
+    }
+
+    /// Number of subpixel positions cached per glyph on each axis.
+    pub fn subpixel_levels(&self) -> u8 {
+        self.subpixel_levels
+    }
+
+    /// Rasterize and cache all glyphs in `ranges` ahead of time.
+    ///
+    /// Characters without a glyph are skipped. Returns the number of glyphs in the cache for the
+    /// requested characters.
+    pub fn warm_cache(
+        &mut self,
+        key: FontKey,
+        size: Size,
+        ranges: &[RangeInclusive<char>],
+    ) -> Result<usize, Error> {
+        let mut cached = 0;
+
+        for character in ranges.iter().cloned().flatten() {
+            let glyph_key = GlyphKey { character, font_key: key, size };
+            match self.cached_glyph(self.cache_key(glyph_key, 0, 0)) {
+                Ok(_) => cached += 1,
+                Err(Error::MissingGlyph(_)) => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(cached)
+    }
+
+    /// Rasterize a glyph without adding it to the cache.
+    ///
+    /// This is meant for transient glyphs, like during animations, which would otherwise fill
+    /// the cache with bitmaps that are never used again. Without a capacity limit, every glyph
+    /// passed to `get_glyph` stays in it until the cache is cleared. The glyph is
+    /// identical to the one `get_glyph` returns and is taken from the cache if it's already in
+    /// there.
+    pub fn get_glyph_uncached(&mut self, glyph_key: GlyphKey) -> Result<RasterizedGlyph, Error> {
+        let key = self.cache_key(glyph_key, 0, 0);
+        let cached = self.cache().get(&key).cloned();
+        match cached {
+            Some(glyph) => Ok(glyph),
+            None => self.rasterizer.get_glyph(glyph_key),
+        }
+    }
+
+    /// Cache key of a glyph rasterized with the current settings.
+    fn cache_key(&self, glyph: GlyphKey, subpixel: u8, subpixel_y: u8) -> CacheKey {
+        CacheKey {
+            glyph,
+            subpixel,
+            subpixel_y,
+            dpr: self.rasterizer.device_pixel_ratio().to_bits(),
+            settings: self.fingerprint,
+        }
+    }
+
+    /// Remember a changed setting, so glyphs are only shared with rasterizers using it too.
+    fn record_setting(&mut self, setting: impl Hash, value: impl Hash) {
+        self.settings.insert(hash(&setting), hash(&value));
+        self.fingerprint = hash(&self.settings);
+    }
+
+    /// Get a glyph from the cache, rasterizing it if necessary.
+    ///
+    /// The cache isn't locked while rasterizing, so other rasterizers sharing it can continue.
+    fn cached_glyph(&mut self, key: CacheKey) -> Result<RasterizedGlyph, Error> {
+        if let Some(glyph) = self.cache().get(&key) {
+            return Ok(glyph.clone());
+        }
+
+        let glyph = if key.dpr != self.rasterizer.device_pixel_ratio().to_bits() {
+            self.rasterizer.get_glyph_dpr(key.glyph, f32::from_bits(key.dpr))?
+        } else if key.subpixel == 0 && key.subpixel_y == 0 {
+            self.rasterizer.get_glyph(key.glyph)?
+        } else {
+            let levels = f32::from(self.subpixel_levels);
+            let x_offset = f32::from(key.subpixel) / levels;
+            let y_offset = f32::from(key.subpixel_y) / levels;
+            self.rasterizer.get_glyph_subpixel(key.glyph, x_offset, y_offset)?
+        };
+        self.cache().insert(key, glyph.clone());
+
+        Ok(glyph)
+    }
+}
+
+/// Hash of a value which is stable for the lifetime of the process.
+fn hash(value: &impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl<R> Drop for CachingRasterizer<R> {
+    fn drop(&mut self) {
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache.on_evict.remove(&self.id);
+    }
+}
+
+impl<R: Rasterize> Rasterize for CachingRasterizer<R> {
+    fn new(device_pixel_ratio: f32) -> Result<Self, Error> {
+        Ok(Self::with_rasterizer(R::new(device_pixel_ratio)?))
+    }
+
+    fn metrics(&self, key: FontKey, size: Size) -> Result<Metrics, Error> {
+        self.rasterizer.metrics(key, size)
+    }
+
+    fn load_font(&mut self, desc: &FontDesc, size: Size) -> Result<FontKey, Error> {
+        self.rasterizer.load_font(desc, size)
+    }
+
+    fn get_glyph(&mut self, glyph_key: GlyphKey) -> Result<RasterizedGlyph, Error> {
+        self.cached_glyph(self.cache_key(glyph_key, 0, 0))
+    }
+
+    /// Rasterize a glyph at a subpixel position.
+    ///
+    /// The offsets are quantized to the nearest of the configured subpixel levels, so only a
+    /// fixed number of bitmaps is cached per glyph. Offsets of a pixel or more are applied to the
+    /// glyph's `left` and `top` bearings instead of the bitmap.
+    fn get_glyph_subpixel(
+        &mut self,
+        glyph_key: GlyphKey,
+        x_offset: f32,
+        y_offset: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        let levels = i32::from(self.subpixel_levels);
+        let steps = (x_offset * levels as f32).round() as i32;
+        let steps_y = (y_offset * levels as f32).round() as i32;
+        let subpixel = steps.rem_euclid(levels) as u8;
+        let subpixel_y = steps_y.rem_euclid(levels) as u8;
+
+        let key = self.cache_key(glyph_key, subpixel, subpixel_y);
+        let mut glyph = self.cached_glyph(key)?;
+        glyph.left += steps.div_euclid(levels);
+        glyph.left_f += steps.div_euclid(levels) as f32;
+
+        // Moving down lowers the top above the baseline, but raises the distance from a cell's top.
+        let down = match self.origin {
+            GlyphOrigin::Baseline => -steps_y.div_euclid(levels),
+            GlyphOrigin::TopLeft { .. } => steps_y.div_euclid(levels),
+        };
+        glyph.top += down;
+        glyph.top_f += down as f32;
+
+        Ok(glyph)
+    }
+
+    fn metrics_px(&self, key: FontKey, pixel_size: f32) -> Result<Metrics, Error> {
+        self.rasterizer.metrics_px(key, pixel_size)
+    }
+
+    fn family_styles(&self, family: &str) -> Result<Vec<(Weight, Slant, Stretch)>, Error> {
+        self.rasterizer.family_styles(family)
+    }
+
+    fn advances(&self, key: FontKey, chars: &[char], size: Size) -> Result<Vec<f32>, Error> {
+        self.rasterizer.advances(key, chars, size)
+    }
+
+    fn metrics_dpr(&self, key: FontKey, size: Size, dpr: f32) -> Result<Metrics, Error> {
+        self.rasterizer.metrics_dpr(key, size, dpr)
+    }
+
+    /// Rasterize a glyph for a device pixel ratio other than the rasterizer's.
+    ///
+    /// Glyphs are cached separately for every device pixel ratio, so multiple ratios can be used
+    /// at the same time without clearing the cache.
+    fn get_glyph_dpr(&mut self, glyph_key: GlyphKey, dpr: f32) -> Result<RasterizedGlyph, Error> {
+        let key = CacheKey { dpr: dpr.to_bits(), ..self.cache_key(glyph_key, 0, 0) };
+        self.cached_glyph(key)
+    }
+
+    fn get_glyph_sdf(
+        &mut self,
+        glyph_key: GlyphKey,
+        spread: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_sdf(glyph_key, spread)
+    }
+
+    fn get_glyph_msdf(
+        &mut self,
+        glyph_key: GlyphKey,
+        range: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_msdf(glyph_key, range)
+    }
+
+    fn compare_hinting(
+        &mut self,
+        glyph_key: GlyphKey,
+    ) -> Result<(RasterizedGlyph, RasterizedGlyph), Error> {
+        self.rasterizer.compare_hinting(glyph_key)
+    }
+
+    fn get_glyph_stroked(
+        &mut self,
+        glyph_key: GlyphKey,
+        stroke_width: f32,
+        style: StrokeStyle,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_stroked(glyph_key, stroke_width, style)
+    }
+
+    fn get_glyph_transformed(
+        &mut self,
+        glyph_key: GlyphKey,
+        matrix: [f32; 4],
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_transformed(glyph_key, matrix)
+    }
+
+    fn get_glyph_on_background(
+        &mut self,
+        glyph_key: GlyphKey,
+        fg: [u8; 4],
+        bg: [u8; 4],
+        cell: (u32, u32),
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_on_background(glyph_key, fg, bg, cell)
+    }
+
+    fn tofu_glyph(&mut self, character: char, size: Size, cell: (u32, u32)) -> RasterizedGlyph {
+        self.rasterizer.tofu_glyph(character, size, cell)
+    }
+
+    /// Rasterize a glyph at a size in pixels.
+    ///
+    /// Glyphs rendered at pixel sizes are not cached.
+    fn get_glyph_px(
+        &mut self,
+        font: FontKey,
+        character: char,
+        pixel_size: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_px(font, character, pixel_size)
+    }
+
+    fn get_glyph_with_fallback(
+        &mut self,
+        base: FontKey,
+        character: char,
+        size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_with_fallback(base, character, size)
+    }
+
+    /// Rasterize a glyph variant.
+    ///
+    /// Variation sequences are rare, so their glyphs are not cached.
+    fn get_glyph_variant(
+        &mut self,
+        font: FontKey,
+        base: char,
+        selector: char,
+        size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_variant(font, base, selector, size)
+    }
+
+    fn glyph_format(&mut self, glyph_key: GlyphKey) -> Result<GlyphFormat, Error> {
+        self.rasterizer.glyph_format(glyph_key)
+    }
+
+    fn get_glyph_by_index(
+        &mut self,
+        font: FontKey,
+        glyph_index: u32,
+        size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterizer.get_glyph_by_index(font, glyph_index, size)
+    }
+
+    fn update_dpr(&mut self, device_pixel_ratio: f32) {
+        self.rasterizer.update_dpr(device_pixel_ratio);
+    }
+
+    fn device_pixel_ratio(&self) -> f32 {
+        self.rasterizer.device_pixel_ratio()
+    }
+
+    fn kerning(&mut self, left: GlyphKey, right: GlyphKey) -> Result<(f32, f32), Error> {
+        self.rasterizer.kerning(left, right)
+    }
+
+    fn set_origin_convention(&mut self, origin: GlyphOrigin) {
+        self.rasterizer.set_origin_convention(origin);
+        self.origin = origin;
+        self.record_setting("origin_convention", origin);
+    }
+
+    fn has_glyph(&self, key: FontKey, character: char) -> bool {
+        self.rasterizer.has_glyph(key, character)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.rasterizer.capabilities()
+    }
+
+    fn describe_key(&self, key: FontKey) -> Option<String> {
+        self.rasterizer.describe_key(key)
+    }
+
+    fn face_id(&self, key: FontKey) -> Result<FaceId, Error> {
+        self.rasterizer.face_id(key)
+    }
+
+    fn glyph_count(&self, key: FontKey) -> Result<u32, Error> {
+        self.rasterizer.glyph_count(key)
+    }
+
+    fn codepoints_for_glyph(&self, key: FontKey, glyph_index: u32) -> Result<Vec<char>, Error> {
+        self.rasterizer.codepoints_for_glyph(key, glyph_index)
+    }
+
+    fn private_use_characters(&self, key: FontKey) -> Result<Vec<char>, Error> {
+        self.rasterizer.private_use_characters(key)
+    }
+
+    fn set_codepoint_remap(&mut self, map: HashMap<char, char>) -> Result<(), Error> {
+        let mut remap: Vec<(char, char)> = map.iter().map(|(&from, &to)| (from, to)).collect();
+        remap.sort_unstable();
+        self.rasterizer.set_codepoint_remap(map)?;
+        self.record_setting("codepoint_remap", remap);
+        Ok(())
+    }
+
+    fn has_aat_layout(&self, key: FontKey) -> Result<bool, Error> {
+        self.rasterizer.has_aat_layout(key)
+    }
+
+    fn synthetic_oblique_angle(&self, key: FontKey) -> Result<Option<f32>, Error> {
+        self.rasterizer.synthetic_oblique_angle(key)
+    }
+
+    fn outline_kind(&self, key: FontKey) -> Result<OutlineKind, Error> {
+        self.rasterizer.outline_kind(key)
+    }
+
+    fn set_fallback_order(&mut self, fonts: Vec<FontDesc>) -> Result<(), Error> {
+        let order = hash(&fonts);
+        self.rasterizer.set_fallback_order(fonts)?;
+        self.record_setting("fallback_order", order);
+        Ok(())
+    }
+
+    fn route_block(&mut self, block: UnicodeBlock, font: FontKey) -> Result<(), Error> {
+        self.rasterizer.route_block(block, font)?;
+        self.record_setting(("route_block", block), font);
+        Ok(())
+    }
+
+    fn set_emoji_font(&mut self, key: FontKey) -> Result<(), Error> {
+        self.rasterizer.set_emoji_font(key)?;
+        self.record_setting("emoji_font", key);
+        Ok(())
+    }
+
+    fn set_tracking(&mut self, key: FontKey, px: f32) -> Result<(), Error> {
+        self.rasterizer.set_tracking(key, px)?;
+        self.record_setting(("tracking", key), px.to_bits());
+        Ok(())
+    }
+
+    fn set_auto_optical_size(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_auto_optical_size(enabled)?;
+        self.record_setting("auto_optical_size", enabled);
+        Ok(())
+    }
+
+    fn set_alpha_precision(&mut self, precision: AlphaPrecision) -> Result<(), Error> {
+        self.rasterizer.set_alpha_precision(precision)?;
+        self.record_setting("alpha_precision", precision);
+        Ok(())
+    }
+
+    fn set_color_order(&mut self, order: ColorOrder) -> Result<(), Error> {
+        self.rasterizer.set_color_order(order)?;
+        self.record_setting("color_order", order);
+        Ok(())
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        self.rasterizer.set_blend_mode(mode)?;
+        let blend_mode = match mode {
+            BlendMode::Srgb => (0, 0),
+            BlendMode::Linear => (1, 0),
+            BlendMode::GammaCorrected(gamma) => (2, gamma.to_bits()),
+        };
+        self.record_setting("blend_mode", blend_mode);
+        Ok(())
+    }
+
+    fn set_charmap_preference(&mut self, pref: CharmapPref) -> Result<(), Error> {
+        self.rasterizer.set_charmap_preference(pref)?;
+        self.record_setting("charmap_preference", pref);
+        Ok(())
+    }
+
+    fn set_rounding(&mut self, mode: RoundingMode) -> Result<(), Error> {
+        self.rasterizer.set_rounding(mode)?;
+        self.record_setting("rounding", mode);
+        Ok(())
+    }
+
+    fn set_builtin_box_drawing(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_builtin_box_drawing(enabled)?;
+        self.record_setting("builtin_box_drawing", enabled);
+        Ok(())
+    }
+
+    fn set_builtin_powerline(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_builtin_powerline(enabled)?;
+        self.record_setting("builtin_powerline", enabled);
+        Ok(())
+    }
+
+    fn set_bitmap_scaling(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_bitmap_scaling(enabled)?;
+        self.record_setting("bitmap_scaling", enabled);
+        Ok(())
+    }
+
+    fn set_vertical(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_vertical(enabled)?;
+        self.record_setting("vertical", enabled);
+        Ok(())
+    }
+
+    fn set_tt_interpreter_version(&mut self, version: TtInterpreter) -> Result<(), Error> {
+        self.rasterizer.set_tt_interpreter_version(version)?;
+        self.record_setting("tt_interpreter_version", version);
+        Ok(())
+    }
+
+    fn tt_interpreter_version(&self) -> Result<TtInterpreter, Error> {
+        self.rasterizer.tt_interpreter_version()
+    }
+
+    fn set_stem_darkening(
+        &mut self,
+        enabled: bool,
+        params: Option<[(u32, u32); 4]>,
+    ) -> Result<(), Error> {
+        self.rasterizer.set_stem_darkening(enabled, params)?;
+        self.record_setting("stem_darkening", (enabled, params));
+        Ok(())
+    }
+
+    fn set_strict(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_strict(enabled)?;
+        self.record_setting("strict", enabled);
+        Ok(())
+    }
+
+    fn set_fallback_scaling(&mut self, mode: FallbackScaling) -> Result<(), Error> {
+        self.rasterizer.set_fallback_scaling(mode)?;
+        self.record_setting("fallback_scaling", mode);
+        Ok(())
+    }
+
+    fn set_fallback_cell_advance(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_fallback_cell_advance(enabled)?;
+        self.record_setting("fallback_cell_advance", enabled);
+        Ok(())
+    }
+
+    fn set_ligatures(&mut self, enabled: bool) -> Result<(), Error> {
+        self.rasterizer.set_ligatures(enabled)
+    }
+
+    fn set_max_open_faces(&mut self, max: usize) -> Result<(), Error> {
+        self.rasterizer.set_max_open_faces(max)
+    }
+
+    fn load_font_from_bytes(
+        &mut self,
+        data: Vec<u8>,
+        face_index: usize,
+        format: Option<FontFormat>,
+    ) -> Result<FontKey, Error> {
+        self.rasterizer.load_font_from_bytes(data, face_index, format)
+    }
+
+    fn load_font_file(&mut self, path: &Path, face_index: usize) -> Result<FontKey, Error> {
+        self.rasterizer.load_font_file(path, face_index)
+    }
+
+    fn string_advance(&mut self, text: &str, font_key: FontKey, size: Size) -> Result<f32, Error> {
+        self.rasterizer.string_advance(text, font_key, size)
+    }
+
+    fn digit_width(&mut self, key: FontKey, size: Size) -> Result<f32, Error> {
+        self.rasterizer.digit_width(key, size)
+    }
+
+    fn shape_extents(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        size: Size,
+    ) -> Result<RunExtents, Error> {
+        self.rasterizer.shape_extents(text, font_key, size)
+    }
+
+    #[cfg(feature = "graphemes")]
+    fn shape_graphemes(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        size: Size,
+    ) -> Result<Vec<ShapedGrapheme>, Error> {
+        self.rasterizer.shape_graphemes(text, font_key, size)
+    }
+
+    fn tab_width(&self, key: FontKey, size: Size, spaces: u32) -> Result<f32, Error> {
+        self.rasterizer.tab_width(key, size, spaces)
+    }
+
+    fn supported_scripts(&self, key: FontKey) -> Result<Vec<Tag>, Error> {
+        self.rasterizer.supported_scripts(key)
+    }
+
+    fn supported_languages(&self, key: FontKey, script: Tag) -> Result<Vec<Tag>, Error> {
+        self.rasterizer.supported_languages(key, script)
+    }
+
+    fn character_variants(&self, key: FontKey) -> Result<Vec<CharacterVariant>, Error> {
+        self.rasterizer.character_variants(key)
+    }
+
+    fn font_metadata(&self, key: FontKey) -> Result<FontMetadata, Error> {
+        self.rasterizer.font_metadata(key)
+    }
+
+    fn refresh_font_sources(&mut self) -> Result<(), Error> {
+        self.rasterizer.refresh_font_sources()
+    }
+}
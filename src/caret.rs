@@ -0,0 +1,41 @@
+//! Caret positioning within shaped text.
+
+/// Horizontal offset of a caret within a shaped cluster, like a ligature.
+///
+/// Shaping can combine multiple characters into a single glyph, like `=>` in fonts with
+/// programming ligatures. The cluster's `advance` is divided evenly among its characters, so the
+/// caret in the middle of a two character ligature is at half of its advance.
+///
+/// `cluster` is the source text of the cluster and `byte_offset` the caret position within it.
+/// Offsets within a character are moved to its start, offsets past the end of the cluster to its
+/// end.
+pub fn ligature_caret_offset(cluster: &str, advance: f32, byte_offset: usize) -> f32 {
+    let count = cluster.chars().count();
+    if count == 0 {
+        return 0.;
+    }
+
+    let preceding = cluster
+        .char_indices()
+        .take_while(|(index, character)| index + character.len_utf8() <= byte_offset)
+        .count();
+
+    advance * preceding as f32 / count as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divides_advance_evenly() {
+        assert_eq!(ligature_caret_offset("=>", 20., 0), 0.);
+        assert_eq!(ligature_caret_offset("=>", 20., 1), 10.);
+        assert_eq!(ligature_caret_offset("=>", 20., 2), 20.);
+        assert_eq!(ligature_caret_offset("===", 30., 2), 20.);
+
+        // Offsets within a multi-byte character snap to its start.
+        assert_eq!(ligature_caret_offset("a→", 20., 2), 10.);
+        assert_eq!(ligature_caret_offset("a→", 20., 10), 20.);
+    }
+}
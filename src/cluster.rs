@@ -0,0 +1,167 @@
+//! Mapping between characters and glyphs of shaped text.
+
+#[cfg(feature = "graphemes")]
+use std::ops::Range;
+
+#[cfg(feature = "graphemes")]
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::Info;
+
+/// Characters and glyphs of a single cluster in shaped text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Cluster {
+    /// Byte offset of the cluster's first character in the shaped text.
+    pub start: usize,
+
+    /// Byte offset after the cluster's last character in the shaped text.
+    pub end: usize,
+
+    /// Number of characters in the cluster.
+    pub char_count: usize,
+
+    /// Number of glyphs the cluster was shaped into.
+    pub glyph_count: usize,
+}
+
+impl Cluster {
+    /// Check if every character of the cluster was shaped into its own glyph.
+    ///
+    /// This is `false` for ligatures combining multiple characters into one glyph, for
+    /// decompositions of a character into multiple glyphs and for clusters of characters like
+    /// combining marks which can't be rendered separately.
+    pub fn is_one_to_one(&self) -> bool {
+        self.char_count == 1 && self.glyph_count == 1
+    }
+}
+
+/// Group the glyphs of shaped text into clusters.
+///
+/// The `cluster` of every glyph must be the byte offset of its first character in `text`, like
+/// HarfBuzz reports for UTF-8 input. Clusters are returned in text order, even for glyphs of
+/// right-to-left text.
+pub fn shaped_clusters(text: &str, glyphs: &[Info]) -> Vec<Cluster> {
+    let mut starts: Vec<usize> = glyphs.iter().map(|glyph| glyph.cluster as usize).collect();
+    starts.sort_unstable();
+
+    let mut clusters: Vec<Cluster> = Vec::new();
+    for start in starts {
+        match clusters.last_mut() {
+            Some(cluster) if cluster.start == start => cluster.glyph_count += 1,
+            _ => clusters.push(Cluster { start, end: text.len(), char_count: 0, glyph_count: 1 }),
+        }
+    }
+
+    for index in 0..clusters.len() {
+        if let Some(next) = clusters.get(index + 1).map(|cluster| cluster.start) {
+            clusters[index].end = next;
+        }
+
+        let cluster = &mut clusters[index];
+        cluster.char_count = text.get(cluster.start..cluster.end).map_or(0, |s| s.chars().count());
+    }
+
+    clusters
+}
+
+/// Byte ranges of the extended grapheme clusters in `text`.
+///
+/// Graphemes are the characters users perceive, so they are the unit for cursor movement and
+/// selection.
+#[cfg(feature = "graphemes")]
+pub fn graphemes(text: &str) -> Vec<Range<usize>> {
+    text.grapheme_indices(true).map(|(start, grapheme)| start..start + grapheme.len()).collect()
+}
+
+/// Grapheme of shaped text with its horizontal advance.
+#[cfg(feature = "graphemes")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapedGrapheme {
+    /// Byte range of the grapheme in the shaped text.
+    pub range: Range<usize>,
+
+    /// Total advance of the grapheme's glyphs in pixels.
+    pub advance: f32,
+}
+
+/// Distribute the advances of shaped clusters over the graphemes of `text`.
+///
+/// Every cluster is given as the byte offset of its first character and its advance. Clusters
+/// spanning multiple graphemes, like ligatures, share their advance evenly between them.
+#[cfg(all(feature = "graphemes", not(any(target_os = "macos", windows))))]
+pub(crate) fn grapheme_advances(text: &str, clusters: &[(usize, f32)]) -> Vec<ShapedGrapheme> {
+    let mut graphemes: Vec<ShapedGrapheme> =
+        graphemes(text).into_iter().map(|range| ShapedGrapheme { range, advance: 0. }).collect();
+
+    let mut clusters = clusters.to_vec();
+    clusters.sort_by_key(|&(start, _)| start);
+
+    for (index, &(start, advance)) in clusters.iter().enumerate() {
+        let end = clusters.get(index + 1).map_or(text.len(), |&(next, _)| next);
+        let first = graphemes.partition_point(|grapheme| grapheme.range.start < start);
+        let last = graphemes.partition_point(|grapheme| grapheme.range.start < end);
+
+        if first < last {
+            let share = advance / (last - first) as f32;
+            for grapheme in &mut graphemes[first..last] {
+                grapheme.advance += share;
+            }
+        } else if first > 0 {
+            // The cluster starts within a grapheme.
+            graphemes[first - 1].advance += advance;
+        }
+    }
+
+    graphemes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info(clusters: &[u32]) -> Vec<Info> {
+        clusters.iter().map(|&cluster| Info { codepoint: 0, cluster }).collect()
+    }
+
+    #[test]
+    fn ligatures_and_decompositions() {
+        // `=>` shaped as a ligature, followed by `é` decomposed into two glyphs.
+        let clusters = shaped_clusters("a=>é", &info(&[0, 1, 3, 3]));
+
+        assert_eq!(clusters, [
+            Cluster { start: 0, end: 1, char_count: 1, glyph_count: 1 },
+            Cluster { start: 1, end: 3, char_count: 2, glyph_count: 1 },
+            Cluster { start: 3, end: 5, char_count: 1, glyph_count: 2 },
+        ]);
+        assert!(clusters[0].is_one_to_one());
+        assert!(!clusters[1].is_one_to_one());
+        assert!(!clusters[2].is_one_to_one());
+    }
+
+    #[test]
+    #[cfg(feature = "graphemes")]
+    fn grapheme_ranges() {
+        // `e` with a combining acute accent and a family emoji joined by zero width joiners.
+        let text = "ae\u{301}\u{1f468}\u{200d}\u{1f469}";
+        assert_eq!(graphemes(text), [0..1, 1..4, 4..15]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "graphemes", not(any(target_os = "macos", windows))))]
+    fn ligature_advances() {
+        // `=>` shaped as one ligature and `e` with its accent shaped into two clusters.
+        let advances = grapheme_advances("=>e\u{301}", &[(0, 16.), (2, 8.), (3, 0.)]);
+        assert_eq!(advances, [
+            ShapedGrapheme { range: 0..1, advance: 8. },
+            ShapedGrapheme { range: 1..2, advance: 8. },
+            ShapedGrapheme { range: 2..5, advance: 8. },
+        ]);
+    }
+
+    #[test]
+    fn right_to_left() {
+        let clusters = shaped_clusters("abc", &info(&[2, 1, 0]));
+        assert!(clusters.iter().all(Cluster::is_one_to_one));
+        assert_eq!(clusters.iter().map(|cluster| cluster.start).collect::<Vec<_>>(), [0, 1, 2]);
+    }
+}
@@ -1,5 +1,6 @@
 //! Font rendering based on CoreText.
 
+use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ffi::CStr;
 use std::iter;
@@ -11,8 +12,8 @@ use cocoa::foundation::{NSString, NSUserDefaults};
 
 use core_foundation::array::{CFArray, CFIndex};
 use core_foundation::attributed_string::CFAttributedStringCreate;
-use core_foundation::dictionary::CFDictionary;
 use core_foundation::base::{kCFAllocatorDefault, CFType, ItemRef, TCFType};
+use core_foundation::dictionary::CFDictionary;
 use core_foundation::number::{CFNumber, CFNumberRef};
 use core_foundation::string::CFString;
 use core_graphics::base::kCGImageAlphaPremultipliedFirst;
@@ -51,10 +52,14 @@ pub mod byte_order;
 use byte_order::kCGBitmapByteOrder32Host;
 
 use super::{
-    BitmapBuffer, Error, FontDesc, FontKey, GlyphKey, Info, KeyType, Metrics, Rasterize,
-    RasterizeExt, RasterizedGlyph, Size, Slant, Style, Weight,
+    overline_metrics, shaping_features, BitmapBuffer, BlendMode, Capabilities, CharacterVariant,
+    ColorOrder, Error, FaceId, FontDesc, FontKey, FontMetadata, GlyphKey, GlyphOrigin, Info,
+    KeyType, Metrics, Rasterize, RasterizeExt, RasterizedGlyph, Size, Slant, Style, Tag, Weight,
 };
 
+use crate::builtin::{builtin_glyph, tofu_glyph, BuiltinGlyphs};
+use crate::sfnt::{self, LineMetrics};
+
 /// According to the documentation, the index of 0 must be a missing glyph character:
 /// https://developer.apple.com/fonts/TrueType-Reference-Manual/RM07/appendixB.html
 const MISSING_GLYPH_INDEX: u32 = 0;
@@ -81,6 +86,8 @@ impl Descriptor {
 
     /// Create a Font from this descriptor.
     fn to_font(&self, size: f64, load_fallbacks: bool) -> Font {
+        profile_span!("open");
+
         let ct_font = ct_new_from_descriptor(&self.ct_descriptor, size);
 
         let fallbacks = if load_fallbacks {
@@ -115,18 +122,45 @@ impl Descriptor {
 /// Given a fontdesc, can rasterize fonts.
 pub struct CoreTextRasterizer {
     fonts: HashMap<FontKey, Font>,
-    keys: HashMap<(FontDesc, Size), FontKey>,
+    keys: HashMap<FontDesc, FontKey>,
+
+    /// Copies of the loaded fonts at other sizes, by the bits of their point size.
+    sized_fonts: RefCell<HashMap<(FontKey, u64), Font>>,
     device_pixel_ratio: f32,
+    origin: GlyphOrigin,
+    blend_mode: BlendMode,
+    color_order: ColorOrder,
+    builtin: BuiltinGlyphs,
 }
 
 impl RasterizeExt for Rasterizer {
-    fn shape(&mut self, text: &str, font_key: FontKey) -> Vec<Info> {
+    fn shape_with_features(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        features: &[(Tag, u32)],
+    ) -> Vec<Info> {
+        profile_span!("shape");
+
+        // CoreText only distinguishes required, standard and all ligatures.
+        let features = shaping_features(features);
+        let enabled = |tag: &[u8; 4]| {
+            features.iter().any(|(feature, value)| feature.0 == *tag && *value != 0)
+        };
+        let ligatures = if enabled(b"dlig") {
+            2
+        } else if enabled(b"liga") {
+            1
+        } else {
+            0
+        };
+
         let font = self.fonts.get(&font_key).unwrap();
         let dic_imm: CFDictionary<CFString, _> = unsafe {
             CFDictionary::from_CFType_pairs(&[
                 (
                     TCFType::wrap_under_get_rule(kCTLigatureAttributeName),
-                    CFNumber::from(2).as_CFType(),
+                    CFNumber::from(ligatures).as_CFType(),
                 ),
                 (TCFType::wrap_under_get_rule(kCTFontAttributeName), font.ct_font.as_CFType()),
             ])
@@ -157,24 +191,32 @@ impl RasterizeExt for Rasterizer {
 
 impl crate::Rasterize for CoreTextRasterizer {
     fn new(device_pixel_ratio: f32) -> Result<CoreTextRasterizer, Error> {
-        Ok(CoreTextRasterizer { fonts: HashMap::new(), keys: HashMap::new(), device_pixel_ratio })
+        Ok(CoreTextRasterizer {
+            fonts: HashMap::new(),
+            keys: HashMap::new(),
+            sized_fonts: RefCell::new(HashMap::new()),
+            device_pixel_ratio,
+            origin: GlyphOrigin::default(),
+            blend_mode: BlendMode::default(),
+            color_order: ColorOrder::default(),
+            builtin: BuiltinGlyphs::default(),
+        })
     }
 
     /// Get metrics for font specified by FontKey.
-    fn metrics(&self, key: FontKey, _size: Size) -> Result<Metrics, Error> {
-        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+    fn metrics(&self, key: FontKey, size: Size) -> Result<Metrics, Error> {
+        let font = self.sized_font(key, size)?;
 
         Ok(font.metrics())
     }
 
     fn load_font(&mut self, desc: &FontDesc, size: Size) -> Result<FontKey, Error> {
-        let scaled_size = Size::new(size.as_f32_pts() * self.device_pixel_ratio);
-        self.keys.get(&(desc.to_owned(), scaled_size)).map(|k| Ok(*k)).unwrap_or_else(|| {
+        self.keys.get(desc).map(|k| Ok(*k)).unwrap_or_else(|| {
             let font = self.get_font(desc, size)?;
             let key = FontKey::next();
 
             self.fonts.insert(key, font);
-            self.keys.insert((desc.clone(), scaled_size), key);
+            self.keys.insert(desc.clone(), key);
 
             Ok(key)
         })
@@ -182,8 +224,22 @@ impl crate::Rasterize for CoreTextRasterizer {
 
     /// Get rasterized glyph for given glyph key.
     fn get_glyph(&mut self, glyph: GlyphKey) -> Result<RasterizedGlyph, Error> {
+        if self.builtin.contains(glyph.character) {
+            // Use the font's glyph if the cell size is unknown.
+            if let Ok(metrics) = self.metrics(glyph.font_key, glyph.size) {
+                let mut builtin = builtin_glyph(
+                    glyph.character,
+                    &metrics,
+                    glyph.font_key,
+                    self.device_pixel_ratio,
+                );
+                self.origin.apply(&mut builtin);
+                return Ok(builtin);
+            }
+        }
+
         // Get loaded font.
-        let font = self.fonts.get(&glyph.font_key).ok_or(Error::UnknownFontKey)?;
+        let font = &self.sized_font(glyph.font_key, glyph.size)?;
 
         // Find a font where the given character is present.
         let (font, glyph_index) = iter::once(font)
@@ -194,7 +250,11 @@ impl crate::Rasterize for CoreTextRasterizer {
             })
             .unwrap_or((font, MISSING_GLYPH_INDEX));
 
-        let glyph = font.get_glyph(glyph.character, glyph_index);
+        let mut glyph = font
+            .get_glyph(glyph.character, glyph_index, glyph.font_key)
+            .with_color_order(self.color_order);
+        glyph.dpr = self.device_pixel_ratio;
+        self.origin.apply(&mut glyph);
 
         if glyph_index == MISSING_GLYPH_INDEX {
             Err(Error::MissingGlyph(glyph))
@@ -228,20 +288,207 @@ impl crate::Rasterize for CoreTextRasterizer {
             height: 0,
             top: 0,
             left: 0,
+            top_f: 0.,
+            left_f: 0.,
             buffer: BitmapBuffer::RGB(Vec::new()),
+            font_key: glyph.font_key,
+            dpr: self.device_pixel_ratio,
         })
     }
 
-    fn kerning(&mut self, _left: GlyphKey, _right: GlyphKey) -> (f32, f32) {
-        (0., 0.)
+    fn get_glyph_on_background(
+        &mut self,
+        glyph_key: GlyphKey,
+        fg: [u8; 4],
+        bg: [u8; 4],
+        cell: (u32, u32),
+    ) -> Result<RasterizedGlyph, Error> {
+        let glyph = self.get_glyph(glyph_key)?;
+        let metrics = self.metrics(glyph_key.font_key, glyph_key.size)?;
+        let glyph = glyph.on_background(self.origin, &metrics, self.blend_mode, fg, bg, cell);
+        Ok(glyph.with_color_order(self.color_order))
+    }
+
+    fn tofu_glyph(&mut self, character: char, size: Size, cell: (u32, u32)) -> RasterizedGlyph {
+        let pixel_size = size.as_px(self.device_pixel_ratio);
+        let mut glyph = tofu_glyph(character, pixel_size, cell, self.device_pixel_ratio);
+        self.origin.apply(&mut glyph);
+        glyph
     }
 
     fn update_dpr(&mut self, device_pixel_ratio: f32) {
         self.device_pixel_ratio = device_pixel_ratio;
     }
+
+    fn device_pixel_ratio(&self) -> f32 {
+        self.device_pixel_ratio
+    }
+
+    fn set_origin_convention(&mut self, origin: GlyphOrigin) {
+        self.origin = origin;
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        self.blend_mode = mode;
+        Ok(())
+    }
+
+    fn set_color_order(&mut self, order: ColorOrder) -> Result<(), Error> {
+        self.color_order = order;
+        Ok(())
+    }
+
+    fn set_builtin_box_drawing(&mut self, enabled: bool) -> Result<(), Error> {
+        self.builtin.box_drawing = enabled;
+        Ok(())
+    }
+
+    fn set_builtin_powerline(&mut self, enabled: bool) -> Result<(), Error> {
+        self.builtin.powerline = enabled;
+        Ok(())
+    }
+
+    fn has_glyph(&self, key: FontKey, character: char) -> bool {
+        self.fonts
+            .get(&key)
+            .map_or(false, |font| font.glyph_index(character) != MISSING_GLYPH_INDEX)
+    }
+
+    fn supported_scripts(&self, key: FontKey) -> Result<Vec<Tag>, Error> {
+        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+        let gsub = font.ct_font.get_font_table(sfnt::GSUB);
+        let gpos = font.ct_font.get_font_table(sfnt::GPOS);
+        Ok(sfnt::script_tags(
+            gsub.as_ref().map(|table| table.bytes()),
+            gpos.as_ref().map(|table| table.bytes()),
+        ))
+    }
+
+    fn supported_languages(&self, key: FontKey, script: Tag) -> Result<Vec<Tag>, Error> {
+        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+        let gsub = font.ct_font.get_font_table(sfnt::GSUB);
+        let gpos = font.ct_font.get_font_table(sfnt::GPOS);
+        Ok(sfnt::language_tags(
+            gsub.as_ref().map(|table| table.bytes()),
+            gpos.as_ref().map(|table| table.bytes()),
+            script,
+        ))
+    }
+
+    fn character_variants(&self, key: FontKey) -> Result<Vec<CharacterVariant>, Error> {
+        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+        let gsub = font.ct_font.get_font_table(sfnt::GSUB);
+        let name = font.ct_font.get_font_table(sfnt::NAME);
+        Ok(sfnt::character_variants(
+            gsub.as_ref().map(|table| table.bytes()),
+            name.as_ref().map(|table| table.bytes()),
+        ))
+    }
+
+    fn font_metadata(&self, key: FontKey) -> Result<FontMetadata, Error> {
+        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+        let name = font.ct_font.get_font_table(sfnt::NAME);
+        let os2 = font.ct_font.get_font_table(sfnt::OS2);
+        Ok(sfnt::font_metadata(
+            name.as_ref().map(|table| table.bytes()),
+            os2.as_ref().map(|table| table.bytes()),
+        ))
+    }
+
+    fn refresh_font_sources(&mut self) -> Result<(), Error> {
+        // CoreText tracks font installations itself, so only the cached matches are outdated.
+        self.keys.clear();
+        Ok(())
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            color_glyphs: true,
+            shaping: true,
+            native_shaping: true,
+            ..Default::default()
+        }
+    }
+
+    fn describe_key(&self, key: FontKey) -> Option<String> {
+        self.fonts.get(&key).map(|font| font.ct_font.display_name())
+    }
+
+    fn face_id(&self, key: FontKey) -> Result<FaceId, Error> {
+        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+
+        // Font collections contain multiple faces in the same file, so the PostScript name is
+        // required to tell them apart.
+        let font_path = font.ct_font.copy_descriptor().font_path();
+        Ok(FaceId::from_hashable((font_path, font.ct_font.postscript_name())))
+    }
+
+    fn glyph_count(&self, key: FontKey) -> Result<u32, Error> {
+        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+        Ok(font.ct_font.glyph_count() as u32)
+    }
+
+    fn advances(&self, key: FontKey, chars: &[char], size: Size) -> Result<Vec<f32>, Error> {
+        let font = self.sized_font(key, size)?;
+        let glyphs: Vec<CGGlyph> =
+            chars.iter().map(|&character| font.glyph_index(character) as CGGlyph).collect();
+
+        let mut advances = vec![CGSize::new(0., 0.); glyphs.len()];
+        unsafe {
+            font.ct_font.get_advances_for_glyphs(
+                kCTFontDefaultOrientation,
+                glyphs.as_ptr(),
+                advances.as_mut_ptr(),
+                glyphs.len() as CFIndex,
+            );
+        }
+
+        Ok(glyphs
+            .iter()
+            .zip(advances)
+            .map(
+                |(&glyph, advance)| {
+                    if u32::from(glyph) == MISSING_GLYPH_INDEX {
+                        0.
+                    } else {
+                        advance.width as f32
+                    }
+                },
+            )
+            .collect())
+    }
+
+    fn tab_width(&self, key: FontKey, size: Size, spaces: u32) -> Result<f32, Error> {
+        let font = self.sized_font(key, size)?;
+        if font.glyph_index(' ') == MISSING_GLYPH_INDEX {
+            return Ok(font.metrics().tab_width(spaces));
+        }
+
+        Ok(font.glyph_advance(' ') as f32 * spaces as f32)
+    }
 }
 
 impl CoreTextRasterizer {
+    /// Get the font for `key` at the requested size.
+    ///
+    /// CoreText fonts are bound to a size, so fonts are copied with the new size whenever it
+    /// doesn't match the size the font was loaded with. Since copying a font also copies all of
+    /// its fallbacks, the copies are kept for later calls.
+    fn sized_font(&self, key: FontKey, size: Size) -> Result<Font, Error> {
+        let font = self.fonts.get(&key).ok_or(Error::UnknownFontKey)?;
+        let scaled_size = f64::from(size.as_f32_pts()) * f64::from(self.device_pixel_ratio);
+
+        if (font.ct_font.pt_size() - scaled_size).abs() < f64::EPSILON {
+            return Ok(font.clone());
+        }
+
+        let mut sized_fonts = self.sized_fonts.borrow_mut();
+        let sized_font = sized_fonts
+            .entry((key, scaled_size.to_bits()))
+            .or_insert_with(|| font.with_size(scaled_size));
+        Ok(sized_font.clone())
+    }
+
     fn get_specific_face(
         &mut self,
         desc: &FontDesc,
@@ -285,6 +532,8 @@ impl CoreTextRasterizer {
     }
 
     fn get_font(&mut self, desc: &FontDesc, size: Size) -> Result<Font, Error> {
+        profile_span!("match");
+
         match desc.style {
             Style::Specific(ref style) => self.get_specific_face(desc, style, size),
             Style::Description { slant, weight } => {
@@ -349,6 +598,66 @@ fn descriptors_for_family(family: &str) -> Vec<Descriptor> {
     out
 }
 
+/// Family name of the user's default font, or their default fixed-pitch font.
+pub(crate) fn user_font_family(fixed_pitch: bool) -> Option<String> {
+    autoreleasepool(|| unsafe {
+        let font: id = if fixed_pitch {
+            msg_send![class!(NSFont), userFixedPitchFontOfSize: 0.]
+        } else {
+            msg_send![class!(NSFont), userFontOfSize: 0.]
+        };
+        if font == nil {
+            return None;
+        }
+
+        let family: id = msg_send![font, familyName];
+        if family == nil {
+            return None;
+        }
+
+        Some(CStr::from_ptr(family.UTF8String()).to_string_lossy().into_owned())
+    })
+}
+
+/// Backing scale factor of the main screen.
+///
+/// Returns `1.0` if there is no screen.
+pub fn system_dpr() -> f32 {
+    autoreleasepool(|| unsafe {
+        let screen: id = msg_send![class!(NSScreen), mainScreen];
+        screen_dpr(screen)
+    })
+}
+
+/// Backing scale factors of all connected screens.
+///
+/// The first entry is the screen containing the menu bar.
+pub fn screen_dprs() -> Vec<f32> {
+    autoreleasepool(|| unsafe {
+        let screens: id = msg_send![class!(NSScreen), screens];
+        if screens == nil {
+            return Vec::new();
+        }
+
+        let count: usize = msg_send![screens, count];
+        (0..count)
+            .map(|index| {
+                let screen: id = msg_send![screens, objectAtIndex: index];
+                screen_dpr(screen)
+            })
+            .collect()
+    })
+}
+
+unsafe fn screen_dpr(screen: id) -> f32 {
+    if screen == nil {
+        return 1.;
+    }
+
+    let scale_factor: f64 = msg_send![screen, backingScaleFactor];
+    scale_factor as f32
+}
+
 // The AppleFontSmoothing user default controls font smoothing on macOS, which increases the stroke
 // width. By default it is unset, and the system behaves as though it is set to 2, which means a
 // medium level of font smoothing. The valid values are integers from 0 to 3. Any other type,
@@ -394,12 +703,40 @@ struct Font {
 unsafe impl Send for Font {}
 
 impl Font {
+    /// Copy the font and all its fallbacks with a different size.
+    fn with_size(&self, size: f64) -> Font {
+        profile_span!("set_size");
+
+        Font {
+            ct_font: self.ct_font.clone_with_font_size(size),
+            fallbacks: self.fallbacks.iter().map(|font| font.with_size(size)).collect(),
+        }
+    }
+
     fn metrics(&self) -> Metrics {
         let average_advance = self.glyph_advance('0');
 
-        let ascent = self.ct_font.ascent().round() as f64;
-        let descent = self.ct_font.descent().round() as f64;
-        let leading = self.ct_font.leading().round() as f64;
+        // Resolve line metrics from the font's tables, so they match the other backends.
+        let os2_table = self.ct_font.get_font_table(sfnt::OS2);
+        let hhea_table = self.ct_font.get_font_table(sfnt::HHEA);
+        let line_metrics = LineMetrics::from_tables(
+            os2_table.as_ref().map(|table| table.bytes()),
+            hhea_table.as_ref().map(|table| table.bytes()),
+        );
+
+        let (ascent, descent, leading) = match line_metrics {
+            Some(line_metrics) => {
+                let scale = self.ct_font.pt_size() / f64::from(self.ct_font.units_per_em());
+                let line_metrics = line_metrics.scale(scale as f32);
+                let descent = -f64::from(line_metrics.descent);
+                (f64::from(line_metrics.ascent), descent, f64::from(line_metrics.line_gap))
+            },
+            None => (
+                self.ct_font.ascent().round() as f64,
+                self.ct_font.descent().round() as f64,
+                self.ct_font.leading().round() as f64,
+            ),
+        };
         let line_height = ascent + descent + leading;
 
         // Strikeout and underline metrics.
@@ -408,15 +745,20 @@ impl Font {
         let underline_thickness = self.ct_font.underline_thickness() as f32;
         let strikeout_position = (line_height / 2. - descent) as f32;
         let strikeout_thickness = underline_thickness;
+        let (overline_position, overline_thickness) =
+            overline_metrics(ascent as f32, underline_thickness);
 
         Metrics {
             average_advance,
             line_height,
             descent: -(descent as f32),
+            line_gap: leading as f32,
             underline_position,
             underline_thickness,
             strikeout_position,
             strikeout_thickness,
+            overline_position,
+            overline_thickness,
         }
     }
 
@@ -447,7 +789,9 @@ impl Font {
         }
     }
 
-    fn get_glyph(&self, character: char, glyph_index: u32) -> RasterizedGlyph {
+    fn get_glyph(&self, character: char, glyph_index: u32, font_key: FontKey) -> RasterizedGlyph {
+        profile_span!("render");
+
         let bounds = self
             .ct_font
             .get_bounding_rects_for_glyphs(kCTFontDefaultOrientation, &[glyph_index as CGGlyph]);
@@ -466,8 +810,14 @@ impl Font {
                 height: 0,
                 top: 0,
                 left: 0,
+                top_f: 0.,
+                left_f: 0.,
                 advance: (0, 0),
+                advance_f: (0., 0.),
+                native_advance: None,
                 buffer: BitmapBuffer::Rgb(Vec::new()),
+                font_key,
+                dpr: 1.,
             };
         }
 
@@ -526,10 +876,16 @@ impl Font {
             character: KeyType::GlyphIndex(glyph_index),
             left: rasterized_left,
             top: (bounds.size.height + bounds.origin.y).ceil() as i32,
+            left_f: bounds.origin.x as f32,
+            top_f: (bounds.size.height + bounds.origin.y) as f32,
             width: rasterized_width as i32,
             height: rasterized_height as i32,
             advance: (0, 0),
+            advance_f: (0., 0.),
+            native_advance: None,
             buffer,
+            font_key,
+            dpr: 1.,
         }
     }
 
@@ -583,7 +939,10 @@ mod tests {
                 let glyph = font.get_glyph(*character, glyph_index);
 
                 let buffer = match &glyph.buffer {
-                    BitmapBuffer::Rgb(buffer) | BitmapBuffer::Rgba(buffer) => buffer,
+                    BitmapBuffer::Rgb(buffer)
+                    | BitmapBuffer::Rgba(buffer)
+                    | BitmapBuffer::Bgra(buffer) => buffer,
+                    BitmapBuffer::GrayF32(_) => unreachable!("coverage is 8-bit by default"),
                 };
 
                 // Debug the glyph.. sigh.
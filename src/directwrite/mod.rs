@@ -3,6 +3,7 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use std::ffi::OsString;
+use std::mem;
 use std::os::windows::ffi::OsStringExt;
 // use std::os::windows::ffi::{OsStrExt, OsStringExt};
 
@@ -14,22 +15,97 @@ use dwrote::{
 };
 
 use winapi::shared::ntdef::{HRESULT, LOCALE_NAME_MAX_LENGTH};
+use winapi::shared::windef::{HMONITOR, HWND};
 use winapi::shared::winerror::*;
 use winapi::um::dwrite;
+use winapi::um::shellscalingapi::{GetDpiForMonitor, MDT_EFFECTIVE_DPI};
 use winapi::um::unknwnbase::IUnknown;
+use winapi::um::wingdi::{GetDeviceCaps, LOGPIXELSX};
 use winapi::um::winnls::GetUserDefaultLocaleName;
+use winapi::um::winuser::{
+    GetDC, GetDpiForWindow, ReleaseDC, SystemParametersInfoW, NONCLIENTMETRICSW,
+    SPI_GETNONCLIENTMETRICS,
+};
 use winapi::Interface;
 use wio::com::ComPtr;
 
 use super::{
-    BitmapBuffer, Error, FontDesc, FontKey, GlyphKey, KeyType, Metrics, RasterizedGlyph, Size,
-    Slant, Style, Weight,
+    overline_metrics, shaping_features, BitmapBuffer, BlendMode, Capabilities, CharacterVariant,
+    ColorOrder, Error, FaceId, FontDesc, FontKey, FontMetadata, GlyphKey, GlyphOrigin, KeyType,
+    Metrics, RasterizedGlyph, Size, Slant, Style, Tag, Weight,
 };
 
+use crate::builtin::{builtin_glyph, tofu_glyph, BuiltinGlyphs};
+use crate::sfnt::{self, LineMetrics};
+
 /// DirectWrite uses 0 for missing glyph symbols.
 /// https://docs.microsoft.com/en-us/typography/opentype/spec/recom#glyph-0-the-notdef-glyph
 const MISSING_GLYPH_INDEX: u16 = 0;
 
+/// DPI corresponding to a device pixel ratio of 1.
+const BASE_DPI: f32 = 96.;
+
+/// Device pixel ratio of the primary monitor.
+///
+/// Returns `1.0` if the DPI can't be determined.
+pub fn system_dpr() -> f32 {
+    unsafe {
+        let dc = GetDC(std::ptr::null_mut());
+        if dc.is_null() {
+            return 1.;
+        }
+
+        let dpi = GetDeviceCaps(dc, LOGPIXELSX);
+        ReleaseDC(std::ptr::null_mut(), dc);
+
+        dpi_to_dpr(dpi as u32)
+    }
+}
+
+/// Device pixel ratio of a monitor.
+///
+/// Returns `1.0` if the DPI can't be determined.
+pub fn monitor_dpr(monitor: HMONITOR) -> f32 {
+    let (mut dpi_x, mut dpi_y) = (0, 0);
+    let hr = unsafe { GetDpiForMonitor(monitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) };
+    if !SUCCEEDED(hr) {
+        return 1.;
+    }
+
+    dpi_to_dpr(dpi_x)
+}
+
+/// Device pixel ratio of a window.
+///
+/// This requires Windows 10 version 1607 or later. Returns `1.0` if the DPI can't be determined.
+pub fn window_dpr(window: HWND) -> f32 {
+    dpi_to_dpr(unsafe { GetDpiForWindow(window) })
+}
+
+/// Family name of the system UI font used for messages.
+pub(crate) fn message_font_family() -> Option<String> {
+    let mut metrics = NONCLIENTMETRICSW::default();
+    metrics.cbSize = mem::size_of::<NONCLIENTMETRICSW>() as u32;
+    let metrics_ptr = &mut metrics as *mut NONCLIENTMETRICSW as *mut _;
+    if unsafe { SystemParametersInfoW(SPI_GETNONCLIENTMETRICS, metrics.cbSize, metrics_ptr, 0) }
+        == 0
+    {
+        return None;
+    }
+
+    let name = &metrics.lfMessageFont.lfFaceName;
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    OsString::from_wide(&name[..len]).into_string().ok()
+}
+
+fn dpi_to_dpr(dpi: u32) -> f32 {
+    if dpi == 0 {
+        1.
+    } else {
+        dpi as f32 / BASE_DPI
+    }
+}
+
 /// Cached DirectWrite font.
 struct Font {
     face: FontFace,
@@ -43,6 +119,10 @@ pub struct DirectWriteRasterizer {
     fonts: HashMap<FontKey, Font>,
     keys: HashMap<FontDesc, FontKey>,
     device_pixel_ratio: f32,
+    origin: GlyphOrigin,
+    blend_mode: BlendMode,
+    color_order: ColorOrder,
+    builtin: BuiltinGlyphs,
     available_fonts: FontCollection,
     fallback_sequence: Option<FontFallback>,
     analyzer: ComPtr<dwrite::IDWriteTextAnalyzer>,
@@ -55,7 +135,10 @@ impl DirectWriteRasterizer {
         face: &FontFace,
         size: Size,
         glyph_index: u16,
+        font_key: FontKey,
     ) -> Result<RasterizedGlyph, Error> {
+        profile_span!("render");
+
         let em_size = em_size(size);
 
         let glyph_run = DWRITE_GLYPH_RUN {
@@ -98,8 +181,14 @@ impl DirectWriteRasterizer {
             height: (bounds.bottom - bounds.top) as i32,
             top: -bounds.top,
             left: bounds.left,
+            top_f: -bounds.top as f32,
+            left_f: bounds.left as f32,
             advance: (0, 0),
+            advance_f: (0., 0.),
+            native_advance: None,
             buffer,
+            font_key,
+            dpr: self.device_pixel_ratio,
         })
     }
 
@@ -172,6 +261,10 @@ impl crate::Rasterize for DirectWriteRasterizer {
             fonts: HashMap::new(),
             keys: HashMap::new(),
             device_pixel_ratio,
+            origin: GlyphOrigin::default(),
+            blend_mode: BlendMode::default(),
+            color_order: ColorOrder::default(),
+            builtin: BuiltinGlyphs::default(),
             available_fonts: FontCollection::system(),
             fallback_sequence: FontFallback::get_system_fallback(),
             analyzer,
@@ -191,11 +284,19 @@ impl crate::Rasterize for DirectWriteRasterizer {
         let strikeout_position = f32::from(vmetrics.strikethroughPosition) * scale;
         let strikeout_thickness = f32::from(vmetrics.strikethroughThickness) * scale;
 
-        let ascent = f32::from(vmetrics.ascent) * scale;
-        let descent = -f32::from(vmetrics.descent) * scale;
-        let line_gap = f32::from(vmetrics.lineGap) * scale;
+        // Resolve line metrics from the font's tables, so they match the other backends.
+        let os2_table = face.get_font_table(dwrite_table_tag(sfnt::OS2));
+        let hhea_table = face.get_font_table(dwrite_table_tag(sfnt::HHEA));
+        let line_metrics = LineMetrics::from_tables(os2_table.as_deref(), hhea_table.as_deref())
+            .unwrap_or(LineMetrics {
+                ascent: f32::from(vmetrics.ascent),
+                descent: -f32::from(vmetrics.descent),
+                line_gap: f32::from(vmetrics.lineGap),
+            });
+        let LineMetrics { ascent, descent, line_gap } = line_metrics.scale(scale);
 
         let line_height = f64::from(ascent - descent + line_gap);
+        let (overline_position, overline_thickness) = overline_metrics(ascent, underline_thickness);
 
         // Since all monospace characters have the same width, we use `!` for horizontal metrics.
         let character = '!';
@@ -210,10 +311,13 @@ impl crate::Rasterize for DirectWriteRasterizer {
             descent,
             average_advance,
             line_height,
+            line_gap,
             underline_position,
             underline_thickness,
             strikeout_position,
             strikeout_thickness,
+            overline_position,
+            overline_thickness,
         })
     }
 
@@ -223,6 +327,8 @@ impl crate::Rasterize for DirectWriteRasterizer {
             return Ok(*key);
         }
 
+        profile_span!("match");
+
         let family = self
             .available_fonts
             .get_font_family_by_name(&desc.name)
@@ -262,6 +368,20 @@ impl crate::Rasterize for DirectWriteRasterizer {
     }
 
     fn get_glyph(&mut self, glyph: GlyphKey) -> Result<RasterizedGlyph, Error> {
+        if self.builtin.contains(glyph.character) {
+            // Use the font's glyph if the cell size is unknown.
+            if let Ok(metrics) = self.metrics(glyph.font_key, glyph.size) {
+                let mut builtin = builtin_glyph(
+                    glyph.character,
+                    &metrics,
+                    glyph.font_key,
+                    self.device_pixel_ratio,
+                );
+                self.origin.apply(&mut builtin);
+                return Ok(builtin);
+            }
+        }
+
         let loaded_font = self.get_loaded_font(glyph.font_key)?;
 
         let loaded_fallback_font;
@@ -276,7 +396,9 @@ impl crate::Rasterize for DirectWriteRasterizer {
             }
         }
 
-        let rasterized_glyph = self.rasterize_glyph(&font.face, glyph.size, glyph_index)?;
+        let mut rasterized_glyph =
+            self.rasterize_glyph(&font.face, glyph.size, glyph_index, glyph.font_key)?;
+        self.origin.apply(&mut rasterized_glyph);
 
         if glyph_index == MISSING_GLYPH_INDEX {
             Err(Error::MissingGlyph(rasterized_glyph))
@@ -285,17 +407,180 @@ impl crate::Rasterize for DirectWriteRasterizer {
         }
     }
 
-    fn kerning(&mut self, left: GlyphKey, right: GlyphKey) -> (f32, f32) {
-        (0., 0.)
+    fn get_glyph_on_background(
+        &mut self,
+        glyph_key: GlyphKey,
+        fg: [u8; 4],
+        bg: [u8; 4],
+        cell: (u32, u32),
+    ) -> Result<RasterizedGlyph, Error> {
+        let glyph = self.get_glyph(glyph_key)?;
+        let metrics = self.metrics(glyph_key.font_key, glyph_key.size)?;
+        let glyph = glyph.on_background(self.origin, &metrics, self.blend_mode, fg, bg, cell);
+        Ok(glyph.with_color_order(self.color_order))
+    }
+
+    fn tofu_glyph(&mut self, character: char, size: Size, cell: (u32, u32)) -> RasterizedGlyph {
+        let pixel_size = size.as_px(self.device_pixel_ratio);
+        let mut glyph = tofu_glyph(character, pixel_size, cell, self.device_pixel_ratio);
+        self.origin.apply(&mut glyph);
+        glyph
     }
 
     fn update_dpr(&mut self, device_pixel_ratio: f32) {
         self.device_pixel_ratio = device_pixel_ratio;
     }
+
+    fn device_pixel_ratio(&self) -> f32 {
+        self.device_pixel_ratio
+    }
+
+    fn set_origin_convention(&mut self, origin: GlyphOrigin) {
+        self.origin = origin;
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        self.blend_mode = mode;
+        Ok(())
+    }
+
+    fn set_color_order(&mut self, order: ColorOrder) -> Result<(), Error> {
+        self.color_order = order;
+        Ok(())
+    }
+
+    fn set_builtin_box_drawing(&mut self, enabled: bool) -> Result<(), Error> {
+        self.builtin.box_drawing = enabled;
+        Ok(())
+    }
+
+    fn set_builtin_powerline(&mut self, enabled: bool) -> Result<(), Error> {
+        self.builtin.powerline = enabled;
+        Ok(())
+    }
+
+    fn has_glyph(&self, key: FontKey, character: char) -> bool {
+        self.get_loaded_font(key)
+            .map_or(false, |font| self.get_char_index(&font.face, character) != MISSING_GLYPH_INDEX)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            lcd_subpixel: true,
+            shaping: true,
+            native_shaping: true,
+            ..Default::default()
+        }
+    }
+
+    fn describe_key(&self, key: FontKey) -> Option<String> {
+        let font = self.fonts.get(&key)?;
+        Some(format!(
+            "{} ({:?}, {:?}, {:?})",
+            font.family_name, font.weight, font.style, font.stretch
+        ))
+    }
+
+    fn face_id(&self, key: FontKey) -> Result<FaceId, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+
+        // Faces without a file path are loaded from memory, so their data identifies them.
+        let files: Vec<_> = face
+            .get_files()
+            .iter()
+            .map(|file| file.get_font_file_path().ok_or_else(|| file.get_font_file_bytes()))
+            .collect();
+        let simulations = face.get_simulations() as u32;
+        Ok(FaceId::from_hashable((files, face.get_index(), simulations)))
+    }
+
+    fn glyph_count(&self, key: FontKey) -> Result<u32, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+        Ok(u32::from(face.get_glyph_count()))
+    }
+
+    fn advances(&self, key: FontKey, chars: &[char], size: Size) -> Result<Vec<f32>, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+        let indices: Vec<u16> =
+            chars.iter().map(|&character| self.get_char_index(face, character)).collect();
+
+        let glyph_metrics = face.get_design_glyph_metrics(&indices, false);
+        let units_per_em = f32::from(face.metrics().metrics0().designUnitsPerEm);
+        let scale = em_size(size) * self.device_pixel_ratio / units_per_em;
+
+        Ok(indices
+            .iter()
+            .zip(glyph_metrics)
+            .map(|(&index, hmetrics)| {
+                if index == MISSING_GLYPH_INDEX {
+                    0.
+                } else {
+                    hmetrics.advanceWidth as f32 * scale
+                }
+            })
+            .collect())
+    }
+
+    fn tab_width(&self, key: FontKey, size: Size, spaces: u32) -> Result<f32, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+        let glyph_index = self.get_char_index(face, ' ');
+        if glyph_index == MISSING_GLYPH_INDEX {
+            return Ok(self.metrics(key, size)?.tab_width(spaces));
+        }
+
+        let glyph_metrics = face.get_design_glyph_metrics(&[glyph_index], false);
+        let hmetrics = glyph_metrics.first().ok_or(Error::MetricsNotFound)?;
+        let units_per_em = f32::from(face.metrics().metrics0().designUnitsPerEm);
+        let scale = em_size(size) * self.device_pixel_ratio / units_per_em;
+
+        Ok(hmetrics.advanceWidth as f32 * scale * spaces as f32)
+    }
+
+    fn supported_scripts(&self, key: FontKey) -> Result<Vec<Tag>, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+        let gsub = face.get_font_table(dwrite_table_tag(sfnt::GSUB));
+        let gpos = face.get_font_table(dwrite_table_tag(sfnt::GPOS));
+        Ok(sfnt::script_tags(gsub.as_deref(), gpos.as_deref()))
+    }
+
+    fn supported_languages(&self, key: FontKey, script: Tag) -> Result<Vec<Tag>, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+        let gsub = face.get_font_table(dwrite_table_tag(sfnt::GSUB));
+        let gpos = face.get_font_table(dwrite_table_tag(sfnt::GPOS));
+        Ok(sfnt::language_tags(gsub.as_deref(), gpos.as_deref(), script))
+    }
+
+    fn character_variants(&self, key: FontKey) -> Result<Vec<CharacterVariant>, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+        let gsub = face.get_font_table(dwrite_table_tag(sfnt::GSUB));
+        let name = face.get_font_table(dwrite_table_tag(sfnt::NAME));
+        Ok(sfnt::character_variants(gsub.as_deref(), name.as_deref()))
+    }
+
+    fn font_metadata(&self, key: FontKey) -> Result<FontMetadata, Error> {
+        let face = &self.get_loaded_font(key)?.face;
+        let name = face.get_font_table(dwrite_table_tag(sfnt::NAME));
+        let os2 = face.get_font_table(dwrite_table_tag(sfnt::OS2));
+        Ok(sfnt::font_metadata(name.as_deref(), os2.as_deref()))
+    }
+
+    fn refresh_font_sources(&mut self) -> Result<(), Error> {
+        self.available_fonts = FontCollection::get_system(true);
+        self.fallback_sequence = FontFallback::get_system_fallback();
+        self.keys.clear();
+        Ok(())
+    }
 }
 
 impl RasterizeExt for DirectWriteRasterizer {
-    fn shape(&mut self, text: &str, font_key: FontKey) -> Vec<Info> {
+    fn shape_with_features(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        features: &[(Tag, u32)],
+    ) -> Vec<Info> {
+        profile_span!("shape");
+
         let face = &self.get_loaded_font(font_key).unwrap().face;
         unsafe {
             let string: Vec<u16> = text.encode_utf16().collect();
@@ -309,17 +594,16 @@ impl RasterizeExt for DirectWriteRasterizer {
                 script: 0,
                 shapes: dwrite::DWRITE_SCRIPT_SHAPES_DEFAULT,
             };
-            let liga = dwrite::DWRITE_FONT_FEATURE {
-                nameTag: dwrite::DWRITE_FONT_FEATURE_TAG_STANDARD_LIGATURES,
-                parameter: 1,
-            };
-            let calt = dwrite::DWRITE_FONT_FEATURE {
-                nameTag: dwrite::DWRITE_FONT_FEATURE_TAG_CONTEXTUAL_ALTERNATES,
-                parameter: 1,
-            };
+            let mut dwrite_features: Vec<_> = shaping_features(features)
+                .into_iter()
+                .map(|(tag, parameter)| dwrite::DWRITE_FONT_FEATURE {
+                    nameTag: u32::from_le_bytes(tag.0),
+                    parameter,
+                })
+                .collect();
             let mut features_unit = dwrite::DWRITE_TYPOGRAPHIC_FEATURES {
-                features: [liga, calt].as_mut_ptr(),
-                featureCount: 2,
+                features: dwrite_features.as_mut_ptr(),
+                featureCount: dwrite_features.len() as u32,
             };
             let features =
                 [&mut features_unit as *const dwrite::DWRITE_TYPOGRAPHIC_FEATURES].as_mut_ptr();
@@ -376,12 +660,22 @@ impl RasterizeExt for DirectWriteRasterizer {
     }
 }
 
+/// Convert an SFNT table tag to the byte order used by `IDWriteFontFace::TryGetFontTable`.
+///
+/// DirectWrite tags store the first character in the lowest byte, while `sfnt` tags store it in
+/// the highest one.
+fn dwrite_table_tag(tag: u32) -> u32 {
+    tag.swap_bytes()
+}
+
 fn em_size(size: Size) -> f32 {
     size.as_f32_pts() * (96.0 / 72.0)
 }
 
 impl From<dwrote::Font> for Font {
     fn from(font: dwrote::Font) -> Font {
+        profile_span!("open");
+
         Font {
             face: font.create_font_face(),
             family_name: font.family_name(),
@@ -508,4 +802,14 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn table_tag_byte_order() {
+        use super::*;
+        assert_eq!(dwrite_table_tag(sfnt::OS2), u32::from_le_bytes(*b"OS/2"));
+        assert_eq!(dwrite_table_tag(sfnt::HHEA), u32::from_le_bytes(*b"hhea"));
+        assert_eq!(dwrite_table_tag(sfnt::GSUB), u32::from_le_bytes(*b"GSUB"));
+        assert_eq!(dwrite_table_tag(sfnt::GPOS), u32::from_le_bytes(*b"GPOS"));
+        assert_eq!(dwrite_table_tag(sfnt::NAME), u32::from_le_bytes(*b"name"));
+    }
 }
@@ -5,9 +5,9 @@ use foreign_types::{ForeignType, ForeignTypeRef};
 
 use fontconfig::fontconfig as ffi;
 
-use ffi::FcInitBringUptoDate;
 use ffi::FcResultNoMatch;
 use ffi::{FcFontList, FcFontMatch, FcFontSort};
+use ffi::{FcInitBringUptoDate, FcInitReinitialize};
 use ffi::{FcMatchFont, FcMatchPattern, FcMatchScan};
 use ffi::{FcSetApplication, FcSetSystem};
 use ffi::{FC_SLANT_ITALIC, FC_SLANT_OBLIQUE, FC_SLANT_ROMAN};
@@ -55,6 +55,14 @@ pub fn update_config() {
     }
 }
 
+/// Reinitialize Fontconfig, rescanning all fonts and configuration files.
+///
+/// Returns `false` if the new configuration couldn't be loaded, in which case the previous one
+/// is kept.
+pub fn reinitialize() -> bool {
+    unsafe { FcInitReinitialize() != 0 }
+}
+
 /// List fonts by closeness to the pattern.
 pub fn font_sort(config: &ConfigRef, pattern: &PatternRef) -> Option<FontSet> {
     unsafe {
@@ -182,7 +190,7 @@ impl From<isize> for Width {
 }
 
 /// Subpixel geometry.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Rgba {
     Unknown,
     Rgb,
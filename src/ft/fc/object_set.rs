@@ -45,4 +45,19 @@ impl ObjectSetRef {
     pub fn add_style(&mut self) {
         self.add(b"style\0");
     }
+
+    #[inline]
+    pub fn add_weight(&mut self) {
+        self.add(b"weight\0");
+    }
+
+    #[inline]
+    pub fn add_slant(&mut self) {
+        self.add(b"slant\0");
+    }
+
+    #[inline]
+    pub fn add_width(&mut self) {
+        self.add(b"width\0");
+    }
 }
@@ -12,12 +12,12 @@ use super::ffi::FcMatrix;
 use super::ffi::FcResultMatch;
 use super::ffi::{FcBool, FcFontRenderPrepare, FcPatternGetBool, FcPatternGetDouble};
 use super::ffi::{FcChar8, FcConfigSubstitute, FcDefaultSubstitute, FcPattern, FcPatternHash};
+use super::ffi::{FcNameParse, FcPatternAddInteger, FcPatternGetInteger, FcPatternPrint};
 use super::ffi::{
     FcPatternAddCharSet, FcPatternDestroy, FcPatternDuplicate, FcPatternGetCharSet,
     FcPatternGetMatrix,
 };
 use super::ffi::{FcPatternAddDouble, FcPatternAddString, FcPatternCreate, FcPatternGetString};
-use super::ffi::{FcPatternAddInteger, FcPatternGetInteger, FcPatternPrint};
 
 use super::{CharSetRef, ConfigRef, HintStyle, LcdFilter, MatchKind, Rgba, Slant, Weight, Width};
 
@@ -345,7 +345,7 @@ macro_rules! string_accessor {
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct PatternHash(pub u32);
 
-#[derive(Hash, Eq, PartialEq, Debug)]
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
 pub struct FtFaceLocation {
     pub path: PathBuf,
     pub index: isize,
@@ -361,6 +361,13 @@ impl Pattern {
     pub fn new() -> Self {
         Self::default()
     }
+
+    /// Parse a pattern in Fontconfig's font name syntax.
+    pub fn parse(name: &str) -> Option<Self> {
+        let name = CString::new(name).ok()?;
+        let pattern = unsafe { FcNameParse(name.as_ptr() as *const FcChar8) };
+        NonNull::new(pattern).map(Pattern)
+    }
 }
 
 impl Default for Pattern {
@@ -446,7 +453,10 @@ impl PatternRef {
     }
 
     pattern_get_integer! {
-        index() => b"index\0"
+        index() => b"index\0",
+        weight() => b"weight\0",
+        slant() => b"slant\0",
+        width() => b"width\0"
     }
 
     /// Prints the pattern to stdout.
@@ -1,32 +1,81 @@
 //! Rasterization powered by FreeType and Fontconfig.
 
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
 use std::cmp::{min, Ordering};
 use std::collections::HashMap;
+use std::env;
 use std::fmt::{self, Formatter};
-use std::rc::Rc;
+use std::fs;
+#[cfg(feature = "woff")]
+use std::fs::File;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "woff")]
+use std::io::Read;
+use std::mem;
+use std::path::Path;
+use std::ptr;
+use std::rc::{Rc, Weak};
+use std::slice;
+#[cfg(feature = "async")]
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use freetype::face::LoadFlag;
+use freetype::stroker::{StrokerLineCap, StrokerLineJoin};
 use freetype::tt_os2::TrueTypeOS2Table;
-use freetype::{self, Library, Matrix};
+use freetype::{self, Glyph, Matrix};
 use freetype::{freetype_sys, Face as FtFace};
-use harfbuzz_rs::Feature;
+use harfbuzz_rs::{
+    Blob, Direction, Face as HbFace, Feature, Font as HbFont, HarfbuzzObject, Owned, UnicodeBuffer,
+};
 use libc::{c_long, c_uint};
+#[cfg(feature = "async")]
+use log::error;
 use log::{debug, trace};
 
 pub mod fc;
 
-use fc::{CharSet, FtFaceLocation, Pattern, PatternHash, PatternRef, Rgba};
+pub use freetype::Library;
+#[cfg(feature = "msdf")]
+mod msdf;
+
+use fc::{CharSet, FontSet, FtFaceLocation, ObjectSet, Pattern, PatternHash, PatternRef, Rgba};
 
 use super::{
-    BitmapBuffer, Error, FontDesc, FontKey, GlyphKey, Metrics, Rasterize, RasterizedGlyph, Size,
-    Slant, Style, Weight,
+    char_cell_width, default_presentation, overline_metrics, shaping_features, AlphaPrecision,
+    AsciiAtlas, BitmapBuffer, BlendMode, Capabilities, CharacterVariant, CharmapPref, ColorOrder,
+    Error, FaceId, FallbackScaling, FontDesc, FontFormat, FontKey, FontMetadata, FontProbe,
+    GlyphFormat, GlyphKey, GlyphOrigin, Info, InkRect, Metrics, OutlineKind, Presentation,
+    Rasterize, RasterizeExt, RasterizedGlyph, RoundingMode, RunExtents, ShapePlanHandle, Size,
+    Slant, Stretch, StrokeStyle, Style, Tag, TtInterpreter, UnicodeBlock, Weight,
 };
 
+use crate::builtin::{builtin_glyph, tofu_glyph, BuiltinGlyphs};
+#[cfg(feature = "graphemes")]
+use crate::cluster::{self, ShapedGrapheme};
+use crate::sfnt::{self, LineMetrics};
+#[cfg(feature = "async")]
+use crate::task::BlockingTask;
+
 /// FreeType uses 0 for the missing glyph:
 /// https://freetype.org/freetype2/docs/reference/ft2-base_interface.html#ft_get_char_index
 const MISSING_GLYPH_INDEX: u32 = 0;
 
+/// Unicode Private Use Areas, the BMP area and the supplementary planes 15 and 16.
+const PRIVATE_USE_AREAS: [(freetype_sys::FT_ULong, freetype_sys::FT_ULong); 3] =
+    [(0xe000, 0xf8ff), (0xf0000, 0xffffd), (0x100000, 0x10fffd)];
+
+/// Variation selector requesting text presentation.
+const TEXT_PRESENTATION_SELECTOR: char = '\u{fe0e}';
+
+/// Variation selector requesting emoji presentation.
+const EMOJI_PRESENTATION_SELECTOR: char = '\u{fe0f}';
+
+/// Largest slant in degrees of synthetic obliques, steeper shears distort glyphs too much.
+const MAX_OBLIQUE_ANGLE: f64 = 20.;
+
 /// Delay before font config reload after creating the `Rasterizer`.
 const RELOAD_DELAY: Duration = Duration::from_secs(2);
 
@@ -54,6 +103,83 @@ struct FallbackList {
     coverage: CharSet,
 }
 
+/// Fonts matched by Fontconfig for a `FontDesc`, ordered by how well they match.
+struct FontMatch {
+    desc: FontDesc,
+    hash: PatternHash,
+    pattern: Pattern,
+    fonts: FontSet,
+    key: FontKey,
+}
+
+// Fontconfig objects are reference counted atomically, so it is safe to move the owned handles to
+// another thread.
+unsafe impl Send for FontMatch {}
+
+impl FontMatch {
+    fn new(desc: &FontDesc, size: Size, device_pixel_ratio: f32) -> Result<Self, Error> {
+        let mut pattern = Pattern::new();
+        pattern.add_family(&desc.name);
+
+        // Add style to a pattern.
+        match desc.style {
+            Style::Description { slant, weight } => {
+                // Match nearest font.
+                pattern.set_weight(weight.into());
+                pattern.set_slant(slant.into());
+            },
+            Style::Specific(ref style) => {
+                // If a name was specified, try and load specifically that font.
+                pattern.add_style(style);
+            },
+        }
+
+        Self::from_pattern(desc, pattern, size, device_pixel_ratio)
+    }
+
+    /// Match the fonts of a Fontconfig pattern.
+    fn from_pattern(
+        desc: &FontDesc,
+        mut pattern: Pattern,
+        size: Size,
+        device_pixel_ratio: f32,
+    ) -> Result<Self, Error> {
+        profile_span!("match");
+
+        // Adjust for DPR.
+        let size = f64::from(size.as_px(device_pixel_ratio));
+
+        let config = fc::Config::get_current();
+
+        // Hash requested pattern before adding the size, so the same `FontKey` is used for every
+        // size of a font.
+        let hash = pattern.hash();
+
+        // The size is still used for matching, since it is required to pick the right bitmap
+        // strike of non-scalable fonts. Sizes from the pattern itself take precedence.
+        if pattern.size().next().is_none() && pattern.pixelsize().next().is_none() {
+            pattern.add_pixelsize(size);
+        }
+
+        pattern.config_substitute(config, fc::MatchKind::Pattern);
+        pattern.default_substitute();
+
+        // Get font list using pattern. First font is the primary one while the rest are fallbacks.
+        let fonts =
+            fc::font_sort(config, &pattern).ok_or_else(|| Error::FontNotFound(desc.to_owned()))?;
+
+        let primary_font =
+            fonts.into_iter().next().ok_or_else(|| Error::FontNotFound(desc.to_owned()))?;
+
+        // Hash the matched font together with the request pattern. The matched font is hashed
+        // before rendering, since rendering would add the requested size to the pattern.
+        let key = FontKey::from_pattern_hashes(hash, primary_font.hash());
+
+        Ok(Self { desc: desc.to_owned(), hash, pattern, fonts, key })
+    }
+}
+
+#[derive(Clone)]
 struct FaceLoadingProperties {
     load_flags: LoadFlag,
     render_mode: freetype::RenderMode,
@@ -62,9 +188,66 @@ struct FaceLoadingProperties {
     colored_bitmap: bool,
     embolden: bool,
     matrix: Option<Matrix>,
-    pixelsize_fixup_factor: Option<f64>,
-    ft_face: Rc<FtFace>,
+    /// FreeType face, `None` while the font file is closed.
+    ft_face: Option<Rc<FtFace>>,
+    source: FaceSource,
     rgba: Rgba,
+    /// Optical size axis of variable fonts.
+    optical_size: Option<OpticalSizeAxis>,
+}
+
+/// Optical size axis of a variable font.
+#[derive(Debug, Copy, Clone)]
+struct OpticalSizeAxis {
+    /// Index of the axis in the face's design coordinates.
+    index: usize,
+    /// Total number of axes.
+    axis_count: usize,
+    min: f32,
+    default: f32,
+    max: f32,
+}
+
+impl OpticalSizeAxis {
+    fn from_face(ft_face: &FtFace) -> Option<Self> {
+        let axes = sfnt::variation_axes(&sfnt_table(ft_face, sfnt::FVAR)?);
+        let index = axes.iter().position(|axis| axis.tag == Tag::new(b"opsz"))?;
+        let axis = axes[index];
+        Some(Self {
+            index,
+            axis_count: axes.len(),
+            min: axis.min,
+            default: axis.default,
+            max: axis.max,
+        })
+    }
+
+    /// Move the face along the axis, leaving all other axes untouched.
+    fn set(&self, ft_face: &FtFace, value: f32) {
+        let face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+        let count = self.axis_count as freetype_sys::FT_UInt;
+        let mut coords: Vec<freetype_sys::FT_Fixed> = vec![0; self.axis_count];
+
+        unsafe {
+            if FT_Get_Var_Design_Coordinates(face, count, coords.as_mut_ptr()) != 0 {
+                return;
+            }
+
+            // Changing the coordinates flushes FreeType's caches, so avoid redundant updates.
+            let value = to_fixedpoint_16_6(f64::from(value.max(self.min).min(self.max)));
+            if coords[self.index] != value {
+                coords[self.index] = value;
+                FT_Set_Var_Design_Coordinates(face, count, coords.as_mut_ptr());
+            }
+        }
+    }
+}
+
+/// Origin of a face's font data.
+#[derive(Hash, Eq, PartialEq, Debug, Clone)]
+enum FaceSource {
+    File(FtFaceLocation),
+    Memory { data: Rc<Vec<u8>>, index: isize },
 }
 
 impl fmt::Debug for FaceLoadingProperties {
@@ -85,12 +268,271 @@ impl fmt::Debug for FaceLoadingProperties {
     }
 }
 
+impl FaceLoadingProperties {
+    /// FreeType face, if the face's font file is open.
+    fn ft_face(&self) -> Result<&FtFace, freetype::Error> {
+        self.ft_face.as_deref().ok_or(freetype::Error::InvalidFaceHandle)
+    }
+}
+
+/// Options for rendering a single glyph.
+#[derive(Default, Debug, Copy, Clone)]
+struct RenderOptions {
+    /// Horizontal offset of the pen position in pixels.
+    x_offset: f32,
+
+    /// Vertical offset of the pen position in pixels, positive values move it down.
+    y_offset: f32,
+
+    /// Unicode variation selector following the character.
+    variation_selector: Option<char>,
+
+    /// Size in pixels overriding the size of the `GlyphKey`.
+    pixel_size: Option<f32>,
+
+    /// Spread in pixels of a signed distance field to render instead of the glyph's coverage.
+    sdf_spread: Option<f32>,
+
+    /// Range in pixels of a multi-channel signed distance field generated from the outline.
+    msdf_range: Option<f32>,
+
+    /// Glyph index rendered instead of the `GlyphKey`'s character, without fallback fonts.
+    glyph_index: Option<u32>,
+
+    /// Width in pixels and style of a stroke to render instead of the glyph's fill.
+    stroke: Option<(f32, StrokeStyle)>,
+
+    /// Matrix `[xx, xy, yx, yy]` applied to the outline and advance.
+    transform: Option<[f32; 4]>,
+
+    /// Device pixel ratio overriding the rasterizer's.
+    dpr: Option<f32>,
+
+    /// Vertical alternate replacing the character's glyph in vertical text.
+    vertical_index: Option<u32>,
+
+    /// Force the autohinter on or off, instead of the font's configuration.
+    autohint: Option<bool>,
+
+    /// Face which is already set to the glyph's size, with the pixel size of its strike and the
+    /// loader's `open_count` at the time.
+    ///
+    /// Glyphs from this face skip configuring the face and library before loading, unless any
+    /// face was opened since, which might have replaced the prepared one.
+    prepared_face: Option<(FontKey, f32, u64)>,
+}
+
+/// Coverage of an outline rendered to an RGB bitmap.
+struct Coverage {
+    top: i32,
+    left: i32,
+    height: i32,
+    width: i32,
+    buffer: Vec<u8>,
+
+    /// Unrounded top and left bearings of the outline.
+    bearings: (f32, f32),
+}
+
+impl Coverage {
+    /// Remove the coverage of `other` from this one.
+    fn subtract(&mut self, other: &Coverage) {
+        self.combine(other, |value, other_value| value.saturating_sub(other_value));
+    }
+
+    /// Limit this coverage to the coverage of `other`.
+    fn intersect(&mut self, other: &Coverage) {
+        self.combine(other, min);
+    }
+
+    /// Combine every sample with the sample of `other` at the same position.
+    fn combine(&mut self, other: &Coverage, f: impl Fn(u8, u8) -> u8) {
+        for row in 0..self.height {
+            let other_row = other.top - self.top + row;
+            for column in 0..self.width {
+                let other_column = self.left - other.left + column;
+                let inside = (0..other.height).contains(&other_row)
+                    && (0..other.width).contains(&other_column);
+
+                let index = ((row * self.width + column) * 3) as usize;
+                let other_index = ((other_row * other.width + other_column) * 3) as usize;
+                for channel in 0..3 {
+                    let other_value = if inside { other.buffer[other_index + channel] } else { 0 };
+                    self.buffer[index + channel] = f(self.buffer[index + channel], other_value);
+                }
+            }
+        }
+    }
+}
+
+/// Presentation requested by a variation selector.
+///
+/// Without one, the character's `default_presentation` is used.
+fn selector_presentation(variation_selector: Option<char>) -> Option<Presentation> {
+    match variation_selector {
+        Some(TEXT_PRESENTATION_SELECTOR) => Some(Presentation::Text),
+        Some(EMOJI_PRESENTATION_SELECTOR) => Some(Presentation::Emoji),
+        _ => None,
+    }
+}
+
+/// Inspect font data without loading it into a rasterizer.
+///
+/// The data is opened with a temporary FreeType library, so invalid fonts are rejected before
+/// they're passed to `Rasterize::load_font_from_bytes`. The information is about the first face,
+/// other faces of collections can be loaded with their index.
+pub fn probe_font(data: &[u8]) -> Result<FontProbe, Error> {
+    let format = sfnt::font_format(data);
+    let library = Library::init()?;
+    let ft_face = library.new_memory_face(Rc::new(data.to_vec()), 0).map_err(|err| {
+        let reason = match format {
+            // FreeType decodes WOFF and WOFF2 itself, if it was built with zlib and brotli.
+            FontFormat::Woff | FontFormat::Woff2 => {
+                "FreeType might lack zlib or brotli support for WOFF fonts"
+            },
+            _ if data.is_empty() => "the data is empty",
+            _ => "the data is not a supported font format",
+        };
+        Error::PlatformError(format!("invalid font data, {}: {}", reason, err))
+    })?;
+
+    Ok(FontProbe {
+        family: ft_face.family_name().unwrap_or_default(),
+        style: ft_face.style_name().unwrap_or_default(),
+        is_color: ft_face.has_color(),
+        is_monospace: ft_face.is_fixed_width(),
+        glyph_count: ft_face.raw().num_glyphs as u32,
+        num_faces: ft_face.raw().num_faces as u32,
+        format,
+    })
+}
+
+/// Inspect a font file without loading it into a rasterizer.
+///
+/// See `probe_font` for details.
+pub fn probe_font_file(path: &Path) -> Result<FontProbe, Error> {
+    let data = fs::read(path).map_err(|err| {
+        Error::PlatformError(format!("could not read font file {}: {}", path.display(), err))
+    })?;
+    probe_font(&data)
+}
+
+thread_local! {
+    /// FreeType library shared by all rasterizers on the thread, freed with the last of them.
+    ///
+    /// The driver properties of the library right after its creation are kept alongside it.
+    static SHARED_LIBRARY: RefCell<(Weak<Library>, DriverProperties)> =
+        RefCell::new((Weak::new(), DriverProperties::default()));
+
+    /// Library and rasterizer ID of the last rasterizer which configured a library's drivers.
+    static CONFIGURED_LIBRARY: Cell<Option<(usize, u64)>> = Cell::new(None);
+
+    /// ID of the next rasterizer created on the thread.
+    static NEXT_RASTERIZER_ID: Cell<u64> = Cell::new(0);
+}
+
+/// Driver properties which can be changed for each rasterizer.
+#[derive(Copy, Clone, Default, Debug)]
+struct DriverProperties {
+    stem_darkening: Option<[(u32, u32); 4]>,
+    tt_interpreter: Option<TtInterpreter>,
+}
+
+impl DriverProperties {
+    /// Read the current driver properties of a library.
+    fn get(library: &Library) -> Self {
+        Self {
+            stem_darkening: library_stem_darkening(library),
+            tt_interpreter: library_tt_interpreter(library).ok(),
+        }
+    }
+}
+
+/// Get the FreeType library shared by all rasterizers on the current thread, with its defaults.
+fn shared_library() -> Result<(Rc<Library>, DriverProperties), Error> {
+    SHARED_LIBRARY.with(|shared| {
+        let library = shared.borrow().0.upgrade();
+        if let Some(library) = library {
+            return Ok((library, shared.borrow().1));
+        }
+
+        let library = Library::init()?;
+
+        #[cfg(ft_set_default_properties_available)]
+        unsafe {
+            // Initialize default properties, like user preferred interpreter.
+            freetype_sys::FT_Set_Default_Properties(library.raw());
+        };
+
+        let defaults = DriverProperties::get(&library);
+        let library = Rc::new(library);
+        *shared.borrow_mut() = (Rc::downgrade(&library), defaults);
+        Ok((library, defaults))
+    })
+}
+
+/// Device pixel ratio configured for the system.
+///
+/// This uses the `dpi` configured in Fontconfig, falling back to the integer `GDK_SCALE` used by
+/// GTK. Returns `1.0` if neither is set.
+pub fn system_dpr() -> f32 {
+    let config = fc::Config::get_current();
+    let mut pattern = Pattern::new();
+    pattern.config_substitute(config, fc::MatchKind::Pattern);
+
+    // The default substitution isn't applied, since it always sets 75 DPI.
+    if let Some(dpi) = pattern.dpi().next().filter(|dpi| *dpi > 0.) {
+        return dpi as f32 / 96.;
+    }
+
+    env::var("GDK_SCALE")
+        .ok()
+        .and_then(|scale| scale.trim().parse::<u32>().ok())
+        .filter(|scale| *scale > 0)
+        .map_or(1., |scale| scale as f32)
+}
+
 /// Rasterizes glyphs for a single font face.
+///
+/// Rasterizers created on the same thread share a FreeType library, which isn't thread-safe, so
+/// rasterizers are not `Send` and have to be created on the thread using them.
 pub struct FreeTypeRasterizer {
     loader: FreeTypeLoader,
     fallback_lists: HashMap<FontKey, FallbackList>,
     device_pixel_ratio: f32,
     features: Vec<Feature>,
+    ligatures: bool,
+    origin: GlyphOrigin,
+    blend_mode: BlendMode,
+    alpha_precision: AlphaPrecision,
+    color_order: ColorOrder,
+    emoji_font: Option<FontKey>,
+    block_routes: Vec<(UnicodeBlock, FontKey)>,
+    fallback_order: Vec<FontKey>,
+    charmap: CharmapPref,
+    codepoint_remap: HashMap<char, char>,
+    tracking: HashMap<FontKey, f32>,
+    rounding: RoundingMode,
+    reverse_cmaps: RefCell<HashMap<FontKey, HashMap<u32, Vec<char>>>>,
+    hb_fonts: HashMap<FontKey, Owned<HbFont<'static>>>,
+    simple_ascii_layout: HashMap<FontKey, bool>,
+    shape_plans: HashMap<ShapePlanHandle, ShapePlan>,
+    auto_optical_size: bool,
+    builtin: BuiltinGlyphs,
+    fallback_scaling: FallbackScaling,
+    fallback_cell_advance: bool,
+    bitmap_scaling: bool,
+    strict: bool,
+    vertical: bool,
+    stem_darkening: Option<[(u32, u32); 4]>,
+    tt_interpreter: Option<TtInterpreter>,
+
+    /// ID to tell if this rasterizer was the last to configure the library's drivers.
+    id: u64,
+
+    /// Fonts matched by `load_font_async` which haven't been loaded yet.
+    #[cfg(feature = "async")]
+    pending_fonts: Arc<Mutex<Vec<FontMatch>>>,
 
     /// Rasterizer creation time stamp to delay lazy font config updates
     /// in `Rasterizer::load_font`.
@@ -135,77 +577,21 @@ impl IntoF32 for i64 {
 }
 
 impl Rasterize for FreeTypeRasterizer {
+    /// Create a rasterizer using the FreeType library shared by all rasterizers on this thread.
     fn new(device_pixel_ratio: f32) -> Result<FreeTypeRasterizer, Error> {
-        let library = Library::init()?;
-
-        #[cfg(ft_set_default_properties_available)]
-        unsafe {
-            // Initialize default properties, like user preferred interpreter.
-            freetype_sys::FT_Set_Default_Properties(library.raw());
-        };
-
-        // let use_font_ligatures = if ligatures { 1 } else { 0 };
-        let use_font_ligatures = 1;
-        let features = vec![
-            Feature::new(b"liga", use_font_ligatures, ..),
-            Feature::new(b"calt", use_font_ligatures, ..),
-        ];
-        Ok(FreeTypeRasterizer {
-            loader: FreeTypeLoader::new()?,
-            fallback_lists: HashMap::new(),
-            device_pixel_ratio,
-            features,
-            creation_timestamp: Some(Instant::now()),
-        })
+        let (library, defaults) = shared_library()?;
+        let mut rasterizer = FreeTypeRasterizer::with_library(library, device_pixel_ratio);
+        rasterizer.stem_darkening = defaults.stem_darkening;
+        rasterizer.tt_interpreter = defaults.tt_interpreter;
+        Ok(rasterizer)
     }
 
-    fn metrics(&self, key: FontKey, _size: Size) -> Result<Metrics, Error> {
-        let face = &mut self.loader.faces.get(&key).ok_or(Error::UnknownFontKey)?;
-        let full = self.full_metrics(face)?;
-
-        let ascent = from_freetype_26_6(full.size_metrics.ascender);
-        let descent = from_freetype_26_6(full.size_metrics.descender);
-        let glyph_height = from_freetype_26_6(full.size_metrics.height) as f64;
-        let global_glyph_height = (ascent - descent) as f64;
-        let height = f64::max(glyph_height, global_glyph_height);
-
-        // Get underline position and thickness in device pixels.
-        let x_scale = full.size_metrics.x_scale as f32 / 65536.0;
-        let ft_underline_position = face.ft_face.underline_position();
-        let mut underline_position = from_freetype_26_6(ft_underline_position as f32 * x_scale);
-        let ft_underline_thickness = face.ft_face.underline_thickness();
-        let mut underline_thickness = from_freetype_26_6(ft_underline_thickness as f32 * x_scale);
-
-        // Fallback for bitmap fonts which do not provide underline metrics.
-        if underline_position == 0. {
-            underline_thickness = (descent.abs() / 5.).round();
-            underline_position = descent / 2.;
-        }
-
-        // Get strikeout position and thickness in device pixels.
-        let (strikeout_position, strikeout_thickness) =
-            match TrueTypeOS2Table::from_face(&mut (*face.ft_face).clone()) {
-                Some(os2) => (
-                    from_freetype_26_6(os2.y_strikeout_position() as f32 * x_scale),
-                    from_freetype_26_6(os2.y_strikeout_size() as f32 * x_scale),
-                ),
-                _ => {
-                    // Fallback if font doesn't provide info about strikeout.
-                    trace!("Using fallback strikeout metrics");
-                    let strikeout_position = height as f32 / 2. + descent;
-                    (strikeout_position, underline_thickness)
-                },
-            };
+    fn metrics(&self, key: FontKey, size: Size) -> Result<Metrics, Error> {
+        self.metrics_at(key, self.pixel_size(size), self.device_pixel_ratio)
+    }
 
-        Ok(Metrics {
-            average_advance: full.cell_width,
-            line_height: height,
-            descent,
-            underline_position,
-            underline_thickness,
-            strikeout_position,
-            strikeout_thickness,
-        })
+    fn metrics_px(&self, key: FontKey, pixel_size: f32) -> Result<Metrics, Error> {
+        self.metrics_at(key, pixel_size, 1.)
     }
 
     fn load_font(&mut self, desc: &FontDesc, size: Size) -> Result<FontKey, Error> {
@@ -214,430 +600,2532 @@ impl Rasterize for FreeTypeRasterizer {
             fc::update_config();
         }
 
+        self.add_pending_fonts();
+
         self.get_face(desc, size)
     }
 
-    fn get_glyph(&mut self, glyph_key: GlyphKey) -> Result<RasterizedGlyph, Error> {
-        let font_key = self.face_for_glyph(glyph_key);
-        let face = &self.loader.faces[&font_key];
-        let index = face.ft_face.get_char_index(glyph_key.character as usize);
-        let pixelsize = face
-            .non_scalable
-            .unwrap_or_else(|| glyph_key.size.as_f32_pts() * self.device_pixel_ratio * 96. / 72.);
+    fn family_styles(&self, family: &str) -> Result<Vec<(Weight, Slant, Stretch)>, Error> {
+        let config = fc::Config::get_current();
+        let mut pattern = Pattern::new();
+        pattern.add_family(family);
 
-        if !face.colored_bitmap {
-            face.ft_face.set_char_size(to_freetype_26_6(pixelsize), 0, 0, 0)?;
-        }
+        let mut objects = ObjectSet::new();
+        objects.add_weight();
+        objects.add_slant();
+        objects.add_width();
 
-        unsafe {
-            let ft_lib = self.loader.library.raw();
-            freetype::ffi::FT_Library_SetLcdFilter(ft_lib, face.lcd_filter);
-        }
+        let not_found = || {
+            let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+            Error::FontNotFound(FontDesc::new(family, style))
+        };
+        let fonts = fc::font_list(config, &pattern, &objects).ok_or_else(not_found)?;
 
-        face.ft_face.load_glyph(index, face.load_flags)?;
+        let mut styles: Vec<_> = fonts.into_iter().map(pattern_style).collect();
+        if styles.is_empty() {
+            return Err(not_found());
+        }
 
-        let glyph = face.ft_face.glyph();
+        styles.sort_unstable();
+        styles.dedup();
 
-        // Generate synthetic bold.
-        if face.embolden {
-            unsafe {
-                freetype_sys::FT_GlyphSlot_Embolden(glyph.raw()
-                    as *const freetype_sys::FT_GlyphSlotRec
-                    as *mut freetype_sys::FT_GlyphSlotRec);
-            }
-        }
+        Ok(styles)
+    }
 
-        let advance = unsafe {
-            // Transform glyphs with the matrix from Fontconfig. Primarily used to generate italics.
-            let raw_glyph = face.ft_face.raw().glyph;
-            if let Some(matrix) = face.matrix.as_ref() {
-                // Check that the glyph is a vectorial outline, not a bitmap.
-                if (*raw_glyph).format == freetype_sys::FT_GLYPH_FORMAT_OUTLINE {
-                    let outline = &(*raw_glyph).outline;
+    fn metrics_dpr(&self, key: FontKey, size: Size, dpr: f32) -> Result<Metrics, Error> {
+        self.metrics_at(key, size.as_px(dpr), dpr)
+    }
 
-                    freetype_sys::FT_Outline_Transform(outline, matrix);
-                }
-            }
+    fn get_glyph(&mut self, glyph_key: GlyphKey) -> Result<RasterizedGlyph, Error> {
+        self.get_glyph_dpr(glyph_key, self.device_pixel_ratio)
+    }
 
-            // Don't render bitmap glyphs, it results in error with freestype 2.11.0.
-            if (*raw_glyph).format != freetype_sys::FT_GLYPH_FORMAT_BITMAP {
-                glyph.render_glyph(face.render_mode)?;
+    fn get_glyph_dpr(&mut self, glyph_key: GlyphKey, dpr: f32) -> Result<RasterizedGlyph, Error> {
+        if self.builtin.contains(glyph_key.character) {
+            // Use the font's glyph if the cell size is unknown.
+            if let Ok(metrics) = self.metrics_dpr(glyph_key.font_key, glyph_key.size, dpr) {
+                let mut builtin =
+                    builtin_glyph(glyph_key.character, &metrics, glyph_key.font_key, dpr);
+                self.origin.apply(&mut builtin);
+                return Ok(builtin.with_alpha_precision(self.alpha_precision));
             }
+        }
 
-            let advance = (*raw_glyph).advance;
-            (from_freetype_26_6(advance.x) as i32, from_freetype_26_6(advance.y) as i32)
-        };
+        self.rasterize_glyph(glyph_key, RenderOptions { dpr: Some(dpr), ..Default::default() })
+    }
 
-        let (pixel_height, pixel_width, buffer) =
-            Self::normalize_buffer(&glyph.bitmap(), &face.rgba)?;
+    fn get_glyph_subpixel(
+        &mut self,
+        glyph_key: GlyphKey,
+        x_offset: f32,
+        y_offset: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        let options = RenderOptions { x_offset, y_offset, ..Default::default() };
+        self.rasterize_glyph(glyph_key, options)
+    }
 
-        let mut rasterized_glyph = RasterizedGlyph {
-            character: glyph_key.character,
-            top: glyph.bitmap_top(),
-            left: glyph.bitmap_left(),
-            width: pixel_width,
-            height: pixel_height,
-            advance,
-            buffer,
+    /// Rasterize the printable ASCII glyphs into a single buffer.
+    ///
+    /// The face is set to the size once for all glyphs, instead of for every glyph.
+    fn ascii_atlas(&mut self, key: FontKey, size: Size) -> Result<AsciiAtlas, Error> {
+        let dpr = self.device_pixel_ratio;
+        let pixel_size = self.pixel_size(size);
+        let metrics = self.metrics_at(key, pixel_size, dpr)?;
+
+        let mut options = RenderOptions {
+            dpr: Some(dpr),
+            prepared_face: Some(self.prepare_face(key, pixel_size, dpr)?),
+            ..Default::default()
         };
 
-        if index == MISSING_GLYPH_INDEX {
-            return Err(Error::MissingGlyph(rasterized_glyph));
-        }
+        // Reserve space for RGBA pixels of every glyph filling its cell.
+        let cell_size = metrics.average_advance.ceil() * metrics.line_height.ceil();
+        let glyph_count = AsciiAtlas::LAST as usize - AsciiAtlas::FIRST as usize + 1;
+        let mut atlas = AsciiAtlas::with_capacity(cell_size as usize * 4 * glyph_count);
 
-        if face.colored_bitmap {
-            let fixup_factor = match face.pixelsize_fixup_factor {
-                Some(fixup_factor) => fixup_factor,
-                None => {
-                    // Fallback if the user has bitmap scaling disabled.
-                    let metrics = face.ft_face.size_metrics().ok_or(Error::MetricsNotFound)?;
-                    f64::from(pixelsize) / f64::from(metrics.y_ppem)
-                },
+        for character in AsciiAtlas::FIRST..=AsciiAtlas::LAST {
+            let glyph_key = GlyphKey { character, font_key: key, size };
+            let glyph = match self.rasterize_glyph(glyph_key, options) {
+                Ok(glyph) | Err(Error::MissingGlyph(glyph)) => glyph,
+                Err(err) => return Err(err),
             };
 
-            // Scale glyph advance.
-            rasterized_glyph.advance.0 = (advance.0 as f64 * fixup_factor).round() as i32;
-            rasterized_glyph.advance.1 = (advance.1 as f64 * fixup_factor).round() as i32;
+            // Fallback fonts reconfigure the library and might close or resize the face.
+            if glyph.font_key != key {
+                options.prepared_face = Some(self.prepare_face(key, pixel_size, dpr)?);
+            }
 
-            rasterized_glyph = downsample_bitmap(rasterized_glyph, fixup_factor);
+            atlas.push(glyph);
         }
 
-        Ok(rasterized_glyph)
+        Ok(atlas)
     }
 
-    fn kerning(&mut self, left: GlyphKey, right: GlyphKey) -> (f32, f32) {
-        let font_key = self.face_for_glyph(left);
-        let mut ft_face = (*self.loader.faces[&font_key].ft_face).clone();
+    fn get_glyph_with_fallback(
+        &mut self,
+        base: FontKey,
+        character: char,
+        size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        // Fallback fonts are always resolved and tagged by `get_glyph`.
+        self.get_glyph(GlyphKey { character, font_key: base, size })
+    }
 
-        if !freetype_sys::FT_HAS_KERNING(ft_face.raw_mut()) {
-            return (0., 0.);
-        }
+    fn get_glyph_px(
+        &mut self,
+        font_key: FontKey,
+        character: char,
+        pixel_size: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        let glyph_key = GlyphKey { character, font_key, size: Size::new(0.) };
+        let options = RenderOptions { pixel_size: Some(pixel_size), ..Default::default() };
+        self.rasterize_glyph(glyph_key, options)
+    }
 
-        let left = ft_face.get_char_index(left.character as usize);
-        let right = ft_face.get_char_index(right.character as usize);
+    fn get_glyph_sdf(
+        &mut self,
+        glyph_key: GlyphKey,
+        spread: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterize_glyph(glyph_key, RenderOptions {
+            sdf_spread: Some(spread),
+            ..Default::default()
+        })
+    }
 
-        let mut kerning = freetype_sys::FT_Vector::default();
-        let mode = freetype_sys::FT_KERNING_DEFAULT;
+    fn get_glyph_stroked(
+        &mut self,
+        glyph_key: GlyphKey,
+        stroke_width: f32,
+        style: StrokeStyle,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterize_glyph(glyph_key, RenderOptions {
+            stroke: Some((stroke_width, style)),
+            ..Default::default()
+        })
+    }
 
-        unsafe {
-            freetype_sys::FT_Get_Kerning(ft_face.raw_mut(), left, right, mode, &mut kerning);
-        }
+    fn get_glyph_transformed(
+        &mut self,
+        glyph_key: GlyphKey,
+        matrix: [f32; 4],
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterize_glyph(glyph_key, RenderOptions {
+            transform: Some(matrix),
+            ..Default::default()
+        })
+    }
 
-        (from_freetype_26_6(kerning.x), from_freetype_26_6(kerning.y))
+    fn get_glyph_on_background(
+        &mut self,
+        glyph_key: GlyphKey,
+        fg: [u8; 4],
+        bg: [u8; 4],
+        cell: (u32, u32),
+    ) -> Result<RasterizedGlyph, Error> {
+        let glyph = self.get_glyph(glyph_key)?;
+        let metrics = self.metrics(glyph_key.font_key, glyph_key.size)?;
+        let glyph = glyph.on_background(self.origin, &metrics, self.blend_mode, fg, bg, cell);
+        Ok(glyph.with_color_order(self.color_order))
+    }
+
+    fn tofu_glyph(&mut self, character: char, size: Size, cell: (u32, u32)) -> RasterizedGlyph {
+        let pixel_size = size.as_px(self.device_pixel_ratio);
+        let mut glyph = tofu_glyph(character, pixel_size, cell, self.device_pixel_ratio);
+        self.origin.apply(&mut glyph);
+        glyph.with_alpha_precision(self.alpha_precision)
+    }
+
+    #[cfg(feature = "msdf")]
+    fn get_glyph_msdf(
+        &mut self,
+        glyph_key: GlyphKey,
+        range: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        self.rasterize_glyph(glyph_key, RenderOptions {
+            msdf_range: Some(range),
+            ..Default::default()
+        })
+    }
+
+    #[cfg(feature = "debug-images")]
+    fn compare_hinting(
+        &mut self,
+        glyph_key: GlyphKey,
+    ) -> Result<(RasterizedGlyph, RasterizedGlyph), Error> {
+        let native = RenderOptions { autohint: Some(false), ..Default::default() };
+        let autohinted = RenderOptions { autohint: Some(true), ..Default::default() };
+        Ok((self.rasterize_glyph(glyph_key, native)?, self.rasterize_glyph(glyph_key, autohinted)?))
+    }
+
+    fn get_glyph_variant(
+        &mut self,
+        font_key: FontKey,
+        base: char,
+        selector: char,
+        size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        let glyph_key = GlyphKey { character: base, font_key, size };
+        let options = RenderOptions { variation_selector: Some(selector), ..Default::default() };
+        self.rasterize_glyph(glyph_key, options)
+    }
+
+    fn get_glyph_by_index(
+        &mut self,
+        font_key: FontKey,
+        glyph_index: u32,
+        size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        if !self.loader.faces.contains_key(&font_key) {
+            return Err(Error::UnknownFontKey);
+        }
+
+        let glyph_key = GlyphKey { character: '\0', font_key, size };
+        let options = RenderOptions { glyph_index: Some(glyph_index), ..Default::default() };
+        self.rasterize_glyph(glyph_key, options)
+    }
+
+    fn glyph_format(&mut self, glyph_key: GlyphKey) -> Result<GlyphFormat, Error> {
+        let font_key = self.face_for_glyph(glyph_key, None);
+        let pixel_size = self.pixel_size(glyph_key.size);
+        self.loader.open_face(font_key)?;
+        let (index, _) =
+            self.load_glyph(font_key, glyph_key, RenderOptions::default(), pixel_size)?;
+
+        // Loading the glyph resolves embedded bitmaps without rendering anything.
+        let face = &self.loader.faces[&font_key];
+        let glyph = face.ft_face()?.glyph();
+        if glyph.raw().format == freetype_sys::FT_GLYPH_FORMAT_BITMAP {
+            use freetype::bitmap::PixelMode;
+
+            return Ok(match glyph.bitmap().pixel_mode()? {
+                PixelMode::Mono => GlyphFormat::Mono,
+                PixelMode::Lcd | PixelMode::LcdV => GlyphFormat::SubpixelRgb,
+                PixelMode::Bgra => GlyphFormat::ColorRgba,
+                _ => GlyphFormat::Gray8,
+            });
+        }
+
+        // FreeType only renders color layers in the normal render mode.
+        let color = face.load_flags.contains(LoadFlag::COLOR)
+            && matches!(face.render_mode, freetype::RenderMode::Normal)
+            && has_color_layers(face.ft_face()?, index);
+
+        Ok(match face.render_mode {
+            _ if color => GlyphFormat::ColorRgba,
+            freetype::RenderMode::Mono => GlyphFormat::Mono,
+            freetype::RenderMode::Lcd | freetype::RenderMode::LcdV => GlyphFormat::SubpixelRgb,
+            _ => GlyphFormat::Gray8,
+        })
+    }
+
+    fn kerning(&mut self, left: GlyphKey, right: GlyphKey) -> Result<(f32, f32), Error> {
+        let font_key = self.face_for_glyph(left, None);
+        let mut ft_face = self.loader.open_face(font_key)?.ft_face()?.clone();
+
+        if !freetype_sys::FT_HAS_KERNING(ft_face.raw_mut()) {
+            return Ok((0., 0.));
+        }
+
+        let left = char_index(&ft_face, self.remapped(left.character), self.charmap);
+        let right = char_index(&ft_face, self.remapped(right.character), self.charmap);
+
+        let mut kerning = freetype_sys::FT_Vector::default();
+        let mode = freetype_sys::FT_KERNING_DEFAULT;
+
+        unsafe {
+            freetype_sys::FT_Get_Kerning(ft_face.raw_mut(), left, right, mode, &mut kerning);
+        }
+
+        Ok((from_freetype_26_6(kerning.x), from_freetype_26_6(kerning.y)))
     }
 
     fn update_dpr(&mut self, device_pixel_ratio: f32) {
         self.device_pixel_ratio = device_pixel_ratio;
     }
-}
-struct FreeTypeLoader {
-    library: Library,
-    faces: HashMap<FontKey, FaceLoadingProperties>,
-    ft_faces: HashMap<FtFaceLocation, Rc<FtFace>>,
-}
 
-impl FreeTypeLoader {
-    fn new() -> Result<FreeTypeLoader, Error> {
-        let library = Library::init()?;
+    fn device_pixel_ratio(&self) -> f32 {
+        self.device_pixel_ratio
+    }
+
+    fn set_origin_convention(&mut self, origin: GlyphOrigin) {
+        self.origin = origin;
+    }
+
+    fn set_blend_mode(&mut self, mode: BlendMode) -> Result<(), Error> {
+        self.blend_mode = mode;
+        Ok(())
+    }
+
+    fn set_alpha_precision(&mut self, precision: AlphaPrecision) -> Result<(), Error> {
+        self.alpha_precision = precision;
+        Ok(())
+    }
+
+    fn set_color_order(&mut self, order: ColorOrder) -> Result<(), Error> {
+        self.color_order = order;
+        Ok(())
+    }
+
+    fn has_glyph(&self, key: FontKey, character: char) -> bool {
+        self.loader.face(key).map_or(false, |face| {
+            face.ft_face().map_or(false, |ft_face| {
+                char_index(ft_face, self.remapped(character), self.charmap) != MISSING_GLYPH_INDEX
+            })
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            color_glyphs: true,
+            outlines: true,
+            subpixel_positioning: true,
+            lcd_subpixel: true,
+            shaping: true,
+            native_shaping: false,
+            variable_fonts: true,
+            vertical_metrics: true,
+            sdf: true,
+            msdf: cfg!(feature = "msdf"),
+        }
+    }
+
+    fn describe_key(&self, key: FontKey) -> Option<String> {
+        let face = self.loader.face(key).ok()?;
+        let ft_face = face.ft_face().ok()?;
+        let family = ft_face.family_name()?;
+        Some(match ft_face.style_name() {
+            Some(style) => format!("{} {}", family, style),
+            None => family,
+        })
+    }
+
+    fn face_id(&self, key: FontKey) -> Result<FaceId, Error> {
+        let face = self.loader.face(key)?;
+
+        // The face index also contains the named instance of variable fonts in its upper bits,
+        // but instances are also created by moving the design coordinates directly.
+        let coords = design_coordinates(face.ft_face()?);
+        Ok(FaceId::from_hashable((&face.source, coords)))
+    }
+
+    fn glyph_count(&self, key: FontKey) -> Result<u32, Error> {
+        let face = self.loader.face(key)?;
+        Ok(face.ft_face()?.raw().num_glyphs as u32)
+    }
+
+    fn codepoints_for_glyph(&self, key: FontKey, glyph_index: u32) -> Result<Vec<char>, Error> {
+        let face = self.loader.face(key)?;
+        let ft_face = face.ft_face()?;
+
+        // Building the map walks the entire character map, so it is only done once per font.
+        let mut reverse_cmaps = self.reverse_cmaps.borrow_mut();
+        let reverse_cmap = reverse_cmaps.entry(key).or_insert_with(|| reverse_char_map(ft_face));
+
+        Ok(reverse_cmap.get(&glyph_index).cloned().unwrap_or_default())
+    }
+
+    fn private_use_characters(&self, key: FontKey) -> Result<Vec<char>, Error> {
+        let face = self.loader.face(key)?;
+        Ok(private_use_characters(face.ft_face()?))
+    }
+
+    fn set_codepoint_remap(&mut self, map: HashMap<char, char>) -> Result<(), Error> {
+        self.codepoint_remap = map;
+        Ok(())
+    }
+
+    fn has_aat_layout(&self, key: FontKey) -> Result<bool, Error> {
+        let face = self.loader.face(key)?;
+        let ft_face = face.ft_face()?;
+
+        // HarfBuzz only falls back to the AAT tables if the OpenType ones are missing.
+        let morx = has_sfnt_table(ft_face, sfnt::MORX) || has_sfnt_table(ft_face, sfnt::MORT);
+        let substitution = morx && !has_sfnt_table(ft_face, sfnt::GSUB);
+        let positioning =
+            has_sfnt_table(ft_face, sfnt::KERX) && !has_sfnt_table(ft_face, sfnt::GPOS);
+
+        Ok(substitution || positioning)
+    }
+
+    fn synthetic_oblique_angle(&self, key: FontKey) -> Result<Option<f32>, Error> {
+        let face = self.loader.face(key)?;
+        let angle = face
+            .matrix
+            .as_ref()
+            .filter(|matrix| matrix.xy != 0)
+            .map(|matrix| (matrix.xy as f64).atan2(matrix.yy as f64).to_degrees() as f32);
+        Ok(angle)
+    }
+
+    fn outline_kind(&self, key: FontKey) -> Result<OutlineKind, Error> {
+        let face = self.loader.face(key)?;
+        let ft_face = face.ft_face()?;
+
+        if ft_face.is_scalable() {
+            if has_sfnt_table(ft_face, sfnt::GLYF) {
+                let hinted =
+                    has_sfnt_table(ft_face, sfnt::FPGM) || has_sfnt_table(ft_face, sfnt::PREP);
+                return Ok(OutlineKind::TrueType { hinted });
+            }
+
+            for (tag, cff2) in [(sfnt::CFF, false), (sfnt::CFF2, true)] {
+                if let Some(cff) = sfnt_table(ft_face, tag) {
+                    return Ok(OutlineKind::Cff { hinted: sfnt::cff_hinted(&cff, cff2) });
+                }
+            }
+        }
+
+        if ft_face.has_fixed_sizes() {
+            Ok(OutlineKind::Bitmap)
+        } else {
+            Ok(OutlineKind::None)
+        }
+    }
+
+    fn set_fallback_order(&mut self, fonts: Vec<FontDesc>) -> Result<(), Error> {
+        let mut fallback_order = Vec::with_capacity(fonts.len());
+        for desc in &fonts {
+            let font_key = match self.find_loaded_face(desc) {
+                Some(font_key) => font_key,
+                None => {
+                    let font_key = self.load_font(desc, Size::new(12.))?;
+                    let face = self.loader.face(font_key)?;
+                    if !face_matches(face.ft_face()?, desc, false) {
+                        return Err(Error::FontNotFound(desc.to_owned()));
+                    }
+                    font_key
+                },
+            };
+            fallback_order.push(font_key);
+        }
+
+        self.fallback_order = fallback_order;
+
+        Ok(())
+    }
+
+    fn route_block(&mut self, block: UnicodeBlock, font: FontKey) -> Result<(), Error> {
+        if !self.loader.faces.contains_key(&font) {
+            return Err(Error::UnknownFontKey);
+        }
+
+        self.block_routes.retain(|&(routed, _)| routed != block);
+        self.block_routes.push((block, font));
+
+        Ok(())
+    }
+
+    fn set_emoji_font(&mut self, key: FontKey) -> Result<(), Error> {
+        if !self.loader.faces.contains_key(&key) {
+            return Err(Error::UnknownFontKey);
+        }
+
+        self.emoji_font = Some(key);
+
+        Ok(())
+    }
+
+    fn load_font_from_bytes(
+        &mut self,
+        data: Vec<u8>,
+        face_index: usize,
+        format: Option<FontFormat>,
+    ) -> Result<FontKey, Error> {
+        let format = sfnt::check_format(&data, format)?;
+        #[cfg(feature = "woff")]
+        let data = sfnt::decode_woff(data)?;
+        let woff =
+            !cfg!(feature = "woff") && matches!(format, FontFormat::Woff | FontFormat::Woff2);
+        let source = FaceSource::Memory { data: Rc::new(data), index: face_index as isize };
+        self.add_face(source).map_err(|err| match err {
+            // FreeType decodes WOFF and WOFF2 itself, if it was built with zlib and brotli.
+            Error::PlatformError(_) if woff => Error::PlatformError(format!(
+                "could not load WOFF font, FreeType might lack zlib or brotli support: {}",
+                err
+            )),
+            err => err,
+        })
+    }
+
+    fn load_font_file(&mut self, path: &Path, face_index: usize) -> Result<FontKey, Error> {
+        #[cfg(feature = "woff")]
+        if is_woff_file(path) {
+            let data = fs::read(path).map_err(|err| {
+                Error::PlatformError(format!("could not read font file {:?}: {}", path, err))
+            })?;
+            return self.load_font_from_bytes(data, face_index, None);
+        }
+
+        let location = FtFaceLocation::new(path.to_owned(), face_index as isize);
+        self.add_face(FaceSource::File(location))
+    }
+
+    fn string_advance(&mut self, text: &str, font_key: FontKey, size: Size) -> Result<f32, Error> {
+        let clusters = self.cluster_advances(text, font_key, size)?;
+        Ok(clusters.into_iter().map(|(_, advance)| advance).sum())
+    }
+
+    fn digit_width(&mut self, key: FontKey, size: Size) -> Result<f32, Error> {
+        let pixel_size = self.pixel_size(size);
+        let tracking = self.tracking.get(&key).copied().unwrap_or(0.);
+
+        // Later features take precedence, so `tnum` can't be disabled by the defaults.
+        let mut features = self.features.clone();
+        features.extend(hb_features(&[(Tag::new(b"tnum"), 1)]));
+
+        let hb_font = self.hb_font(key)?;
+        hb_font.set_scale(to_freetype_26_6(pixel_size) as i32, to_freetype_26_6(pixel_size) as i32);
+
+        let glyphs = harfbuzz_rs::shape(hb_font, UnicodeBuffer::new().add_str("0"), &features);
+        let advance =
+            glyphs.get_glyph_positions().iter().map(|position| position.x_advance).sum::<i32>();
+
+        Ok((from_freetype_26_6(advance) + tracking).max(0.))
+    }
+
+    fn shape_extents(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        size: Size,
+    ) -> Result<RunExtents, Error> {
+        let pixel_size = self.pixel_size(size);
+        let tracking = self.tracking.get(&font_key).copied().unwrap_or(0.);
+        let runs = self.fallback_runs(text, font_key, size);
+
+        let features = self.features.clone();
+        let mut ink = Vec::new();
+        let mut pen = 0.;
+        let mut last_cluster = None;
+        for (start, end, run_key) in runs {
+            profile_span!("shape");
+
+            let hb_font = self.hb_font(run_key)?;
+            hb_font.set_scale(
+                to_freetype_26_6(pixel_size) as i32,
+                to_freetype_26_6(pixel_size) as i32,
+            );
+
+            let buffer = UnicodeBuffer::new().add_str_item(text, &text[start..end]);
+            let glyphs = harfbuzz_rs::shape(hb_font, buffer, &features);
+
+            let infos = glyphs.get_glyph_infos().iter();
+            for (info, position) in infos.zip(glyphs.get_glyph_positions()) {
+                // Add tracking once per cluster, like `string_advance`.
+                if last_cluster.map_or(false, |cluster| cluster != info.cluster) {
+                    pen += tracking;
+                }
+                last_cluster = Some(info.cluster);
+
+                let x = pen + from_freetype_26_6(position.x_offset);
+                let y = from_freetype_26_6(position.y_offset);
+                let glyph_ink = match hb_font.get_glyph_extents(info.codepoint) {
+                    Some(extents) => {
+                        let left = x + from_freetype_26_6(extents.x_bearing);
+                        let top = y + from_freetype_26_6(extents.y_bearing);
+                        let right = left + from_freetype_26_6(extents.width);
+                        InkRect {
+                            left,
+                            right,
+                            top,
+                            bottom: top + from_freetype_26_6(extents.height),
+                        }
+                    },
+                    None => InkRect { left: x, right: x, top: y, bottom: y },
+                };
+                ink.push(glyph_ink);
+
+                pen += from_freetype_26_6(position.x_advance);
+            }
+        }
+
+        Ok(RunExtents::new(ink))
+    }
+
+    #[cfg(feature = "graphemes")]
+    fn shape_graphemes(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        size: Size,
+    ) -> Result<Vec<ShapedGrapheme>, Error> {
+        let clusters = self.cluster_advances(text, font_key, size)?;
+        Ok(cluster::grapheme_advances(text, &clusters))
+    }
+
+    fn advances(&self, key: FontKey, chars: &[char], size: Size) -> Result<Vec<f32>, Error> {
+        let face = self.loader.face(key)?;
+        let pixel_size = self.pixel_size(size);
+        let optical_size = self.optical_size(pixel_size, self.device_pixel_ratio);
+        Self::set_face_pixel_size(&face, pixel_size, optical_size)?;
+
+        // Without hinting, FreeType reads the advances from the metrics tables directly.
+        let load_flags = (face.load_flags | LoadFlag::NO_HINTING).bits();
+        let tracking = self.tracking.get(&key).copied().unwrap_or(0.);
+        let ft_face = face.ft_face()?;
+
+        let mut advances = Vec::with_capacity(chars.len());
+        for &character in chars {
+            let index = char_index(ft_face, self.remapped(character), self.charmap);
+            if index == MISSING_GLYPH_INDEX {
+                advances.push(0.);
+                continue;
+            }
+
+            let mut advance = 0;
+            let error = unsafe {
+                let face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+                FT_Get_Advance(face, index, load_flags, &mut advance)
+            };
+            if error != 0 {
+                return Err(freetype::Error::from(error).into());
+            }
+
+            advances.push(advance as f32 / 65536. + tracking);
+        }
+
+        Ok(advances)
+    }
+
+    fn tab_width(&self, key: FontKey, size: Size, spaces: u32) -> Result<f32, Error> {
+        let face = self.loader.face(key)?;
+        let pixel_size = self.pixel_size(size);
+        let optical_size = self.optical_size(pixel_size, self.device_pixel_ratio);
+        Self::set_face_pixel_size(&face, pixel_size, optical_size)?;
+
+        let ft_face = face.ft_face()?;
+        if ft_face.get_char_index(' ' as usize) == MISSING_GLYPH_INDEX {
+            return Ok(self.metrics(key, size)?.tab_width(spaces));
+        }
+
+        ft_face.load_char(' ' as usize, face.load_flags)?;
+        let advance = from_freetype_26_6(ft_face.glyph().advance().x as i32);
+        let tracking = self.tracking.get(&key).copied().unwrap_or(0.);
+        Ok((advance + tracking) * spaces as f32)
+    }
+
+    fn supported_scripts(&self, key: FontKey) -> Result<Vec<Tag>, Error> {
+        let face = self.loader.face(key)?;
+        let gsub = sfnt_table(face.ft_face()?, sfnt::GSUB);
+        let gpos = sfnt_table(face.ft_face()?, sfnt::GPOS);
+        Ok(sfnt::script_tags(gsub.as_deref(), gpos.as_deref()))
+    }
+
+    fn supported_languages(&self, key: FontKey, script: Tag) -> Result<Vec<Tag>, Error> {
+        let face = self.loader.face(key)?;
+        let gsub = sfnt_table(face.ft_face()?, sfnt::GSUB);
+        let gpos = sfnt_table(face.ft_face()?, sfnt::GPOS);
+        Ok(sfnt::language_tags(gsub.as_deref(), gpos.as_deref(), script))
+    }
+
+    fn character_variants(&self, key: FontKey) -> Result<Vec<CharacterVariant>, Error> {
+        let face = self.loader.face(key)?;
+        let gsub = sfnt_table(face.ft_face()?, sfnt::GSUB);
+        let name = sfnt_table(face.ft_face()?, sfnt::NAME);
+        Ok(sfnt::character_variants(gsub.as_deref(), name.as_deref()))
+    }
+
+    fn font_metadata(&self, key: FontKey) -> Result<FontMetadata, Error> {
+        let face = self.loader.face(key)?;
+        let name = sfnt_table(face.ft_face()?, sfnt::NAME);
+        let os2 = sfnt_table(face.ft_face()?, sfnt::OS2);
+        Ok(sfnt::font_metadata(name.as_deref(), os2.as_deref()))
+    }
+
+    fn refresh_font_sources(&mut self) -> Result<(), Error> {
+        // Fonts requested before the refresh keep the configuration they were matched with.
+        self.add_pending_fonts();
+
+        if !fc::reinitialize() {
+            return Err(Error::PlatformError("unable to reinitialize Fontconfig".into()));
+        }
+
+        Ok(())
+    }
+
+    fn set_tracking(&mut self, key: FontKey, px: f32) -> Result<(), Error> {
+        if !self.loader.faces.contains_key(&key) {
+            return Err(Error::UnknownFontKey);
+        }
+
+        if px == 0. {
+            self.tracking.remove(&key);
+        } else {
+            self.tracking.insert(key, px);
+        }
+
+        Ok(())
+    }
+
+    fn set_charmap_preference(&mut self, pref: CharmapPref) -> Result<(), Error> {
+        self.charmap = pref;
+        Ok(())
+    }
+
+    fn set_rounding(&mut self, mode: RoundingMode) -> Result<(), Error> {
+        self.rounding = mode;
+        Ok(())
+    }
+
+    fn set_ligatures(&mut self, enabled: bool) -> Result<(), Error> {
+        self.features = ligature_features(enabled);
+        self.ligatures = enabled;
+        self.shape_plans.clear();
+        Ok(())
+    }
+
+    fn set_fallback_scaling(&mut self, mode: FallbackScaling) -> Result<(), Error> {
+        self.fallback_scaling = mode;
+        Ok(())
+    }
+
+    fn set_fallback_cell_advance(&mut self, enabled: bool) -> Result<(), Error> {
+        self.fallback_cell_advance = enabled;
+        Ok(())
+    }
+
+    fn set_bitmap_scaling(&mut self, enabled: bool) -> Result<(), Error> {
+        self.bitmap_scaling = enabled;
+        Ok(())
+    }
+
+    fn set_vertical(&mut self, enabled: bool) -> Result<(), Error> {
+        self.vertical = enabled;
+        Ok(())
+    }
+
+    fn set_tt_interpreter_version(&mut self, version: TtInterpreter) -> Result<(), Error> {
+        // Other drivers might still be configured by another rasterizer.
+        CONFIGURED_LIBRARY.with(|configured| configured.set(None));
+
+        let error = self.apply_tt_interpreter(version);
+        if error != 0 {
+            return Err(freetype::Error::from(error).into());
+        }
+
+        self.tt_interpreter = Some(version);
+        Ok(())
+    }
+
+    fn tt_interpreter_version(&self) -> Result<TtInterpreter, Error> {
+        match self.tt_interpreter {
+            Some(version) => Ok(version),
+            None => library_tt_interpreter(&self.loader.library),
+        }
+    }
+
+    fn set_stem_darkening(
+        &mut self,
+        enabled: bool,
+        params: Option<[(u32, u32); 4]>,
+    ) -> Result<(), Error> {
+        let stem_darkening = enabled.then(|| params.unwrap_or(DEFAULT_DARKENING_PARAMETERS));
+
+        // Validate the parameters, they're applied again before loading the next glyph.
+        CONFIGURED_LIBRARY.with(|configured| configured.set(None));
+        let error = self.apply_stem_darkening(stem_darkening);
+        if error != 0 {
+            return Err(freetype::Error::from(error).into());
+        }
+
+        self.stem_darkening = stem_darkening;
+        Ok(())
+    }
+
+    fn set_strict(&mut self, enabled: bool) -> Result<(), Error> {
+        self.strict = enabled;
+        Ok(())
+    }
+
+    fn set_builtin_box_drawing(&mut self, enabled: bool) -> Result<(), Error> {
+        self.builtin.box_drawing = enabled;
+        Ok(())
+    }
+
+    fn set_builtin_powerline(&mut self, enabled: bool) -> Result<(), Error> {
+        self.builtin.powerline = enabled;
+        Ok(())
+    }
+
+    fn set_auto_optical_size(&mut self, enabled: bool) -> Result<(), Error> {
+        self.auto_optical_size = enabled;
+
+        // Faces are only moved along the axis when their size is set, so reset them right away.
+        if !enabled {
+            for face in self.loader.faces.values() {
+                if let (Some(ft_face), Some(axis)) = (&face.ft_face, face.optical_size) {
+                    axis.set(ft_face, axis.default);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn set_max_open_faces(&mut self, max: usize) -> Result<(), Error> {
+        self.loader.max_open_faces = max.max(1);
+        self.loader.close_faces(self.loader.max_open_faces);
+        Ok(())
+    }
+}
+
+impl RasterizeExt for FreeTypeRasterizer {
+    fn shape_with_features(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        features: &[(Tag, u32)],
+    ) -> Vec<Info> {
+        profile_span!("shape");
+
+        if features.is_empty() {
+            if let Some(glyphs) = self.ascii_glyphs(text, font_key) {
+                let clusters = 0..text.len() as u32;
+                let infos = glyphs.into_iter().zip(clusters);
+                return infos.map(|(codepoint, cluster)| Info { codepoint, cluster }).collect();
+            }
+        }
+
+        // Later features take precedence, so the requested ones override the defaults.
+        let mut hb_features = self.features.clone();
+        hb_features.extend(self::hb_features(features));
+
+        let hb_font = match self.hb_font(font_key) {
+            Ok(hb_font) => hb_font,
+            Err(_) => return Vec::new(),
+        };
+
+        let buffer = UnicodeBuffer::new().add_str(text);
+        let glyphs = harfbuzz_rs::shape(hb_font, buffer, &hb_features);
+        glyphs
+            .get_glyph_infos()
+            .iter()
+            .map(|info| Info { codepoint: info.codepoint, cluster: info.cluster })
+            .collect()
+    }
+
+    fn shape_graphite(&mut self, text: &str, font_key: FontKey) -> Result<Vec<Info>, Error> {
+        profile_span!("shape");
+
+        let face = self.loader.face(font_key)?;
+        if !has_sfnt_table(face.ft_face()?, sfnt::SILF)
+            || !has_sfnt_table(face.ft_face()?, sfnt::GLAT)
+        {
+            return Err(Error::PlatformError(String::from("font has no Graphite tables")));
+        }
+
+        let features = self.features.clone();
+        let hb_font = self.hb_font(font_key)?;
+        let buffer = UnicodeBuffer::new().add_str(text).guess_segment_properties().into_raw();
+
+        // HarfBuzz only uses the shapers in the list, instead of falling back to OpenType.
+        let shapers = [b"graphite2\0".as_ptr() as *const _, ptr::null()];
+
+        unsafe {
+            let shaped = harfbuzz_rs::hb::hb_shape_full(
+                hb_font.as_raw(),
+                buffer,
+                features.as_ptr() as *const _,
+                features.len() as _,
+                shapers.as_ptr(),
+            );
+
+            let mut length = 0;
+            let infos = harfbuzz_rs::hb::hb_buffer_get_glyph_infos(buffer, &mut length);
+            let result = if shaped == 0 {
+                Err(Error::PlatformError(String::from(
+                    "HarfBuzz was built without Graphite support",
+                )))
+            } else {
+                Ok(slice::from_raw_parts(infos, length as usize)
+                    .iter()
+                    .map(|info| Info { codepoint: info.codepoint, cluster: info.cluster })
+                    .collect())
+            };
+
+            harfbuzz_rs::hb::hb_buffer_destroy(buffer);
+
+            result
+        }
+    }
+
+    fn shape_plan(&mut self, font_key: FontKey, features: &[(Tag, u32)]) -> ShapePlanHandle {
+        let handle = ShapePlanHandle { font_key, features: features.to_vec() };
+        if !self.shape_plans.contains_key(&handle) {
+            let mut hb_features = self.features.clone();
+            hb_features.extend(self::hb_features(features));
+            self.shape_plans.insert(handle.clone(), ShapePlan::new(hb_features));
+        }
+        handle
+    }
+
+    fn shape_with_plan(&mut self, plan: &ShapePlanHandle, text: &str) -> Vec<Info> {
+        profile_span!("shape");
+
+        // Plans are dropped when the default features change.
+        if !self.shape_plans.contains_key(plan) {
+            self.shape_plan(plan.font_key(), plan.features());
+        }
+
+        let hb_font = match self.hb_font(plan.font_key()) {
+            Ok(hb_font) => hb_font.as_raw(),
+            Err(_) => return Vec::new(),
+        };
+        let shape_plan = self.shape_plans.get_mut(plan).unwrap();
+        let buffer = UnicodeBuffer::new().add_str(text).guess_segment_properties().into_raw();
+
+        unsafe {
+            let mut props = mem::zeroed();
+            harfbuzz_rs::hb::hb_buffer_get_segment_properties(buffer, &mut props);
+            let hb_face = harfbuzz_rs::hb::hb_font_get_face(hb_font);
+            let raw_plan = shape_plan.for_segment(hb_face, &props);
+
+            harfbuzz_rs::hb::hb_shape_plan_execute(
+                raw_plan,
+                hb_font,
+                buffer,
+                shape_plan.features.as_ptr() as *const _,
+                shape_plan.features.len() as _,
+            );
+
+            let mut length = 0;
+            let infos = harfbuzz_rs::hb::hb_buffer_get_glyph_infos(buffer, &mut length);
+            let result = slice::from_raw_parts(infos, length as usize)
+                .iter()
+                .map(|info| Info { codepoint: info.codepoint, cluster: info.cluster })
+                .collect();
+
+            harfbuzz_rs::hb::hb_buffer_destroy(buffer);
+
+            result
+        }
+    }
+}
+
+/// HarfBuzz shape plans of a `ShapePlanHandle`.
+///
+/// Plans depend on the direction, script and language of the text, so one is kept for every
+/// combination which was shaped.
+struct ShapePlan {
+    features: Vec<Feature>,
+    plans: Vec<(harfbuzz_rs::hb::hb_segment_properties_t, *mut harfbuzz_rs::hb::hb_shape_plan_t)>,
+}
+
+impl ShapePlan {
+    fn new(features: Vec<Feature>) -> Self {
+        Self { features, plans: Vec::new() }
+    }
+
+    /// Get the plan for text with the segment properties `props`, creating it if necessary.
+    unsafe fn for_segment(
+        &mut self,
+        hb_face: *mut harfbuzz_rs::hb::hb_face_t,
+        props: &harfbuzz_rs::hb::hb_segment_properties_t,
+    ) -> *mut harfbuzz_rs::hb::hb_shape_plan_t {
+        let existing = self.plans.iter().find(|(plan_props, _)| {
+            plan_props.direction == props.direction
+                && plan_props.script == props.script
+                && plan_props.language == props.language
+        });
+        if let Some(&(_, plan)) = existing {
+            return plan;
+        }
+
+        let plan = harfbuzz_rs::hb::hb_shape_plan_create_cached(
+            hb_face,
+            props,
+            self.features.as_ptr() as *const _,
+            self.features.len() as _,
+            ptr::null(),
+        );
+        self.plans.push((*props, plan));
+        plan
+    }
+}
+
+impl Drop for ShapePlan {
+    fn drop(&mut self) {
+        for &(_, plan) in &self.plans {
+            unsafe { harfbuzz_rs::hb::hb_shape_plan_destroy(plan) };
+        }
+    }
+}
+
+struct FreeTypeLoader {
+    library: Rc<Library>,
+    faces: HashMap<FontKey, FaceLoadingProperties>,
+
+    /// Open font files, shared by all faces using them.
+    ///
+    /// Files opened to access closed faces without mutable access are added too, so they are
+    /// reused and count toward `max_open_faces` once the limit is enforced again.
+    ft_faces: RefCell<HashMap<FtFaceLocation, Rc<FtFace>>>,
+
+    /// Last use of every file in `ft_faces`, to close the least recently used ones.
+    last_used: RefCell<HashMap<FtFaceLocation, u64>>,
+    use_counter: Cell<u64>,
+    max_open_faces: usize,
+
+    /// Number of faces opened so far, since newly opened faces have no size set.
+    open_count: Cell<u64>,
+}
+
+impl FreeTypeLoader {
+    fn new(library: Rc<Library>) -> FreeTypeLoader {
+        FreeTypeLoader {
+            library,
+            faces: HashMap::new(),
+            ft_faces: RefCell::new(HashMap::new()),
+            last_used: RefCell::new(HashMap::new()),
+            use_counter: Cell::new(0),
+            max_open_faces: usize::MAX,
+            open_count: Cell::new(0),
+        }
+    }
+
+    fn load_ft_face(&mut self, ft_face_location: FtFaceLocation) -> Result<Rc<FtFace>, Error> {
+        // Replacing the face of an open file doesn't open any additional files.
+        if !self.ft_faces.get_mut().contains_key(&ft_face_location) {
+            self.close_faces(self.max_open_faces - 1);
+        }
+
+        let ft_face = Rc::new(self.open_ft_face(&FaceSource::File(ft_face_location.clone()))?);
+        self.ft_faces.get_mut().insert(ft_face_location.clone(), Rc::clone(&ft_face));
+        self.mark_used(ft_face_location);
+
+        Ok(ft_face)
+    }
+
+    /// Get the face of a font file, opening the file if it isn't open already.
+    fn file_ft_face(&mut self, location: &FtFaceLocation) -> Result<Rc<FtFace>, Error> {
+        match self.ft_faces.get_mut().get(location).cloned() {
+            Some(ft_face) => {
+                self.mark_used(location.clone());
+
+                // Files opened without mutable access might have exceeded the limit.
+                self.close_faces(self.max_open_faces);
+
+                Ok(ft_face)
+            },
+            None => self.load_ft_face(location.clone()),
+        }
+    }
+
+    /// Get the face of a font file without mutable access, opening the file if necessary.
+    ///
+    /// Newly opened files are added to the open files, but no files are closed until the limit
+    /// of open files is enforced with mutable access.
+    fn shared_ft_face(&self, location: &FtFaceLocation) -> Result<Rc<FtFace>, Error> {
+        let open_face = self.ft_faces.borrow().get(location).cloned();
+        let ft_face = match open_face {
+            Some(ft_face) => ft_face,
+            None => {
+                let ft_face = Rc::new(self.open_ft_face(&FaceSource::File(location.clone()))?);
+                self.ft_faces.borrow_mut().insert(location.clone(), Rc::clone(&ft_face));
+                ft_face
+            },
+        };
+        self.mark_used(location.clone());
+
+        Ok(ft_face)
+    }
+
+    fn mark_used(&self, location: FtFaceLocation) {
+        let counter = self.use_counter.get() + 1;
+        self.use_counter.set(counter);
+        self.last_used.borrow_mut().insert(location, counter);
+    }
+
+    /// Close the least recently used font files until at most `max` files are open.
+    ///
+    /// The faces of closed files keep their properties, so `open_face` can open them again.
+    fn close_faces(&mut self, max: usize) {
+        while self.ft_faces.get_mut().len() > max {
+            let last_used = self.last_used.get_mut();
+            let location = match last_used.iter().min_by_key(|(_, used)| **used) {
+                Some((location, _)) => location.clone(),
+                None => return,
+            };
+
+            trace!("Closing font path={:?}, index={:?}", location.path, location.index);
+
+            last_used.remove(&location);
+            self.ft_faces.get_mut().remove(&location);
+
+            let source = FaceSource::File(location);
+            for face in self.faces.values_mut().filter(|face| face.source == source) {
+                face.ft_face = None;
+            }
+        }
+    }
+
+    /// Get a face, opening its font file again if it was closed.
+    fn open_face(&mut self, font_key: FontKey) -> Result<&FaceLoadingProperties, Error> {
+        let face = self.faces.get(&font_key).ok_or(Error::UnknownFontKey)?;
+
+        // Faces loaded from memory are never closed.
+        if let FaceSource::File(location) = &face.source {
+            let location = location.clone();
+            let ft_face = self
+                .file_ft_face(&location)
+                .map_err(|err| reopen_error(&FaceSource::File(location), err))?;
+            self.faces.get_mut(&font_key).unwrap().ft_face = Some(ft_face);
+        }
+
+        Ok(&self.faces[&font_key])
+    }
+
+    /// Get a face without mutable access, opening its font file again if it was closed.
+    ///
+    /// The reopened file is shared with `open_face`, so it is reused by later calls and counts
+    /// toward the limit of open files.
+    fn face(&self, font_key: FontKey) -> Result<Cow<'_, FaceLoadingProperties>, Error> {
+        let face = self.faces.get(&font_key).ok_or(Error::UnknownFontKey)?;
+        let location = match (&face.ft_face, &face.source) {
+            (None, FaceSource::File(location)) => location,
+            _ => return Ok(Cow::Borrowed(face)),
+        };
+
+        let ft_face =
+            self.shared_ft_face(location).map_err(|err| reopen_error(&face.source, err))?;
+        Ok(Cow::Owned(FaceLoadingProperties { ft_face: Some(ft_face), ..face.clone() }))
+    }
+
+    fn open_ft_face(&self, source: &FaceSource) -> Result<FtFace, Error> {
+        profile_span!("open");
+
+        self.open_count.set(self.open_count.get() + 1);
+        let mut ft_face = match source {
+            FaceSource::File(location) => self.library.new_face(&location.path, location.index)?,
+            FaceSource::Memory { data, index } => {
+                self.library.new_memory_face(Rc::clone(data), *index)?
+            },
+        };
+        if ft_face.has_color() && !ft_face.is_scalable() {
+            unsafe {
+                // Select the colored bitmap size to use from the array of available sizes.
+                freetype_sys::FT_Select_Size(ft_face.raw_mut(), 0);
+            }
+        }
+
+        Ok(ft_face)
+    }
+
+    /// Open a face's file again, replacing it for all fonts using it.
+    fn reload_face(&mut self, font_key: FontKey) -> Result<(), Error> {
+        let source = self.faces[&font_key].source.clone();
+        let ft_face = match &source {
+            FaceSource::File(location) => self.load_ft_face(location.clone()),
+            FaceSource::Memory { .. } => self.open_ft_face(&source).map(Rc::new),
+        }
+        .map_err(|err| reopen_error(&source, err))?;
+
+        for face in self.faces.values_mut().filter(|face| face.source == source) {
+            face.ft_face = Some(Rc::clone(&ft_face));
+        }
+
+        Ok(())
+    }
+
+    /// Open a face's file again, without replacing the existing face.
+    fn reopen_face(&self, face: &FaceLoadingProperties) -> Result<FaceLoadingProperties, Error> {
+        let ft_face =
+            self.open_ft_face(&face.source).map_err(|err| reopen_error(&face.source, err))?;
+        Ok(FaceLoadingProperties { ft_face: Some(Rc::new(ft_face)), ..face.clone() })
+    }
+
+    /// Load a face which wasn't matched by Fontconfig.
+    ///
+    /// Since there is no pattern for the face, the rendering settings are taken from
+    /// Fontconfig's defaults.
+    fn face_from_source(&mut self, source: FaceSource, font_key: FontKey) -> Result<(), Error> {
+        let ft_face = match &source {
+            FaceSource::File(location) => self.file_ft_face(location)?,
+            FaceSource::Memory { .. } => Rc::new(self.open_ft_face(&source)?),
+        };
+
+        let config = fc::Config::get_current();
+        let mut pattern = Pattern::new();
+        pattern.config_substitute(config, fc::MatchKind::Pattern);
+        pattern.default_substitute();
+
+        let mut load_flags = Self::ft_load_flags(&pattern);
+        if ft_face.has_color() {
+            load_flags |= LoadFlag::COLOR;
+        }
+
+        // Use the first bitmap strike of non-scalable fonts.
+        let non_scalable = if ft_face.is_scalable() {
+            None
+        } else {
+            let ft_face_raw = ft_face.raw();
+            if ft_face_raw.num_fixed_sizes <= 0 {
+                return Err(Error::PlatformError("font has no scalable or bitmap glyphs".into()));
+            }
+
+            Some(unsafe { from_freetype_26_6((*ft_face_raw.available_sizes).y_ppem) })
+        };
+
+        let face = FaceLoadingProperties {
+            load_flags,
+            render_mode: Self::ft_render_mode(&pattern),
+            lcd_filter: Self::ft_lcd_filter(&pattern),
+            non_scalable,
+            colored_bitmap: ft_face.has_color() && !ft_face.is_scalable(),
+            embolden: false,
+            matrix: None,
+            optical_size: OpticalSizeAxis::from_face(&ft_face),
+            ft_face: Some(ft_face),
+            source,
+            rgba: pattern.rgba().next().unwrap_or(Rgba::Unknown),
+        };
+
+        debug!("Loaded Face {:?}", face);
+
+        self.faces.insert(font_key, face);
+
+        Ok(())
+    }
+
+    fn face_from_pattern(
+        &mut self,
+        pattern: &PatternRef,
+        font_key: FontKey,
+    ) -> Result<Option<FontKey>, Error> {
+        if let Some(ft_face_location) = pattern.ft_face_location(0) {
+            if self.faces.get(&font_key).is_some() {
+                return Ok(Some(font_key));
+            }
+
+            trace!("Got font path={:?}, index={:?}", ft_face_location.path, ft_face_location.index);
+
+            let ft_face = self.file_ft_face(&ft_face_location)?;
+
+            let non_scalable = if pattern.scalable().next().unwrap_or(true) {
+                None
+            } else {
+                Some(pattern.pixelsize().next().expect("has 1+ pixelsize") as f32)
+            };
+
+            let embolden = pattern.embolden().next().unwrap_or(false);
+
+            let matrix = pattern.get_matrix().map(|matrix| {
+                // Convert Fontconfig matrix to FreeType matrix, limiting the shear of synthetic
+                // obliques.
+                let max_shear = MAX_OBLIQUE_ANGLE.to_radians().tan() * matrix.yy.abs();
+                let xx = to_fixedpoint_16_6(matrix.xx);
+                let xy = to_fixedpoint_16_6(matrix.xy.clamp(-max_shear, max_shear));
+                let yx = to_fixedpoint_16_6(matrix.yx);
+                let yy = to_fixedpoint_16_6(matrix.yy);
+
+                Matrix { xx, xy, yx, yy }
+            });
+
+            let rgba = pattern.rgba().next().unwrap_or(Rgba::Unknown);
+
+            let face = FaceLoadingProperties {
+                load_flags: Self::ft_load_flags(pattern),
+                render_mode: Self::ft_render_mode(pattern),
+                lcd_filter: Self::ft_lcd_filter(pattern),
+                non_scalable,
+                colored_bitmap: ft_face.has_color() && !ft_face.is_scalable(),
+                embolden,
+                matrix,
+                optical_size: OpticalSizeAxis::from_face(&ft_face),
+                ft_face: Some(ft_face),
+                source: FaceSource::File(ft_face_location),
+                rgba,
+            };
+
+            debug!("Loaded Face {:?}", face);
+
+            self.faces.insert(font_key, face);
+
+            Ok(Some(font_key))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ft_load_flags(pattern: &PatternRef) -> LoadFlag {
+        let antialias = pattern.antialias().next().unwrap_or(true);
+        let autohint = pattern.autohint().next().unwrap_or(false);
+        let hinting = pattern.hinting().next().unwrap_or(true);
+        let rgba = pattern.rgba().next().unwrap_or(Rgba::Unknown);
+        let embedded_bitmaps = pattern.embeddedbitmap().next().unwrap_or(true);
+        let scalable = pattern.scalable().next().unwrap_or(true);
+        let color = pattern.color().next().unwrap_or(false);
+
+        // Disable hinting if so was requested.
+        let hintstyle = if hinting {
+            pattern.hintstyle().next().unwrap_or(fc::HintStyle::Full)
+        } else {
+            fc::HintStyle::None
+        };
+
+        let mut flags = match (antialias, hintstyle, rgba) {
+            (false, fc::HintStyle::None, _) => LoadFlag::NO_HINTING | LoadFlag::MONOCHROME,
+            (false, ..) => LoadFlag::TARGET_MONO | LoadFlag::MONOCHROME,
+            (true, fc::HintStyle::None, _) => LoadFlag::NO_HINTING,
+            // `hintslight` does *not* use LCD hinting even when a subpixel mode
+            // is selected.
+            //
+            // According to the FreeType docs,
+            //
+            // > You can use a hinting algorithm that doesn't correspond to the
+            // > same rendering mode.  As an example, it is possible to use the
+            // > ‘light’ hinting algorithm and have the results rendered in
+            // > horizontal LCD pixel mode.
+            //
+            // In practice, this means we can have `FT_LOAD_TARGET_LIGHT` with
+            // subpixel render modes like `FT_RENDER_MODE_LCD`. Libraries like
+            // cairo take the same approach and consider `hintslight` to always
+            // prefer `FT_LOAD_TARGET_LIGHT`.
+            (true, fc::HintStyle::Slight, _) => LoadFlag::TARGET_LIGHT,
+            (true, fc::HintStyle::Medium, _) => LoadFlag::TARGET_NORMAL,
+            // If LCD hinting is to be used, must select hintmedium or hintfull,
+            // have AA enabled, and select a subpixel mode.
+            (true, fc::HintStyle::Full, Rgba::Rgb) | (true, fc::HintStyle::Full, Rgba::Bgr) => {
+                LoadFlag::TARGET_LCD
+            },
+            (true, fc::HintStyle::Full, Rgba::Vrgb) | (true, fc::HintStyle::Full, Rgba::Vbgr) => {
+                LoadFlag::TARGET_LCD_V
+            },
+            // For non-rgba modes with Full hinting, just use the default hinting algorithm.
+            (true, fc::HintStyle::Full, Rgba::Unknown)
+            | (true, fc::HintStyle::Full, Rgba::None) => LoadFlag::TARGET_NORMAL,
+        };
+
+        // Non scalable fonts only have bitmaps, so disabling them entirely is likely not a
+        // desirable thing. Colored fonts aren't scalable, but also only have bitmaps.
+        if !embedded_bitmaps && scalable && !color {
+            flags |= LoadFlag::NO_BITMAP;
+        }
+
+        // Use color for colored fonts.
+        if color {
+            flags |= LoadFlag::COLOR;
+        }
+
+        // Force autohint if it was requested.
+        if autohint {
+            flags |= LoadFlag::FORCE_AUTOHINT;
+        }
+
+        flags
+    }
+
+    fn ft_render_mode(pat: &PatternRef) -> freetype::RenderMode {
+        let antialias = pat.antialias().next().unwrap_or(true);
+        let rgba = pat.rgba().next().unwrap_or(Rgba::Unknown);
+
+        match (antialias, rgba) {
+            (false, _) => freetype::RenderMode::Mono,
+            (_, Rgba::Rgb) | (_, Rgba::Bgr) => freetype::RenderMode::Lcd,
+            (_, Rgba::Vrgb) | (_, Rgba::Vbgr) => freetype::RenderMode::LcdV,
+            (true, _) => freetype::RenderMode::Normal,
+        }
+    }
+
+    fn ft_lcd_filter(pat: &PatternRef) -> c_uint {
+        match pat.lcdfilter().next().unwrap_or(fc::LcdFilter::Default) {
+            fc::LcdFilter::None => freetype::ffi::FT_LCD_FILTER_NONE,
+            fc::LcdFilter::Default => freetype::ffi::FT_LCD_FILTER_DEFAULT,
+            fc::LcdFilter::Light => freetype::ffi::FT_LCD_FILTER_LIGHT,
+            fc::LcdFilter::Legacy => freetype::ffi::FT_LCD_FILTER_LEGACY,
+        }
+    }
+}
+
+pub trait IntoFontconfigType {
+    type FcType;
+    fn into_fontconfig_type(&self) -> Self::FcType;
+}
+
+impl IntoFontconfigType for Slant {
+    type FcType = fc::Slant;
+
+    fn into_fontconfig_type(&self) -> Self::FcType {
+        match *self {
+            Slant::Normal => fc::Slant::Roman,
+            Slant::Italic => fc::Slant::Italic,
+            Slant::Oblique => fc::Slant::Oblique,
+        }
+    }
+}
+
+impl From<Slant> for fc::Slant {
+    fn from(slant: Slant) -> Self {
+        match slant {
+            Slant::Normal => fc::Slant::Roman,
+            Slant::Italic => fc::Slant::Italic,
+            Slant::Oblique => fc::Slant::Oblique,
+        }
+    }
+}
+
+impl From<Weight> for fc::Weight {
+    fn from(weight: Weight) -> Self {
+        match weight {
+            Weight::Normal => fc::Weight::Regular,
+            Weight::Bold => fc::Weight::Bold,
+        }
+    }
+}
+
+/// Nearest style of a Fontconfig pattern.
+fn pattern_style(pattern: &PatternRef) -> (Weight, Slant, Stretch) {
+    // Weights are split halfway between regular and bold, like the matching does.
+    let regular = fc::Weight::Regular as isize;
+    let bold = fc::Weight::Bold as isize;
+    let weight = match pattern.weight().next() {
+        Some(weight) if weight >= (regular + bold) / 2 => Weight::Bold,
+        _ => Weight::Normal,
+    };
+
+    let slant = match pattern.slant().next() {
+        Some(slant) if slant == fc::Slant::Italic as isize => Slant::Italic,
+        Some(slant) if slant == fc::Slant::Oblique as isize => Slant::Oblique,
+        _ => Slant::Normal,
+    };
+
+    // Widths are percentages of the normal width, rounded to the nearest named width.
+    let stretch = match pattern.width().next().unwrap_or(100) {
+        width if width < 57 => Stretch::UltraCondensed,
+        width if width < 69 => Stretch::ExtraCondensed,
+        width if width < 81 => Stretch::Condensed,
+        width if width < 94 => Stretch::SemiCondensed,
+        width if width < 107 => Stretch::Normal,
+        width if width < 119 => Stretch::SemiExpanded,
+        width if width < 138 => Stretch::Expanded,
+        width if width < 175 => Stretch::ExtraExpanded,
+        _ => Stretch::UltraExpanded,
+    };
+
+    (weight, slant, stretch)
+}
+
+struct FullMetrics {
+    size_metrics: freetype::ffi::FT_Size_Metrics,
+    cell_width: f64,
+}
+
+impl FreeTypeRasterizer {
+    /// Create a rasterizer using an existing FreeType library.
+    ///
+    /// `Rasterize::new` shares one library between all rasterizers created on the same thread,
+    /// this allows picking the library explicitly instead, like a separate one per rasterizer.
+    /// Libraries can't be used from multiple threads, so faces are never shared between threads.
+    /// FreeType's default properties aren't applied to the library, unlike in `Rasterize::new`.
+    /// The library's current driver properties are used as this rasterizer's defaults.
+    pub fn with_library(library: Rc<Library>, device_pixel_ratio: f32) -> FreeTypeRasterizer {
+        let defaults = DriverProperties::get(&library);
+        FreeTypeRasterizer {
+            loader: FreeTypeLoader::new(library),
+            fallback_lists: HashMap::new(),
+            device_pixel_ratio,
+            features: ligature_features(true),
+            ligatures: true,
+            origin: GlyphOrigin::default(),
+            blend_mode: BlendMode::default(),
+            alpha_precision: AlphaPrecision::default(),
+            color_order: ColorOrder::default(),
+            emoji_font: None,
+            block_routes: Vec::new(),
+            fallback_order: Vec::new(),
+            charmap: CharmapPref::default(),
+            codepoint_remap: HashMap::new(),
+            tracking: HashMap::new(),
+            rounding: RoundingMode::default(),
+            reverse_cmaps: RefCell::new(HashMap::new()),
+            hb_fonts: HashMap::new(),
+            simple_ascii_layout: HashMap::new(),
+            shape_plans: HashMap::new(),
+            auto_optical_size: false,
+            builtin: BuiltinGlyphs::default(),
+            fallback_scaling: FallbackScaling::default(),
+            fallback_cell_advance: false,
+            bitmap_scaling: false,
+            strict: false,
+            vertical: false,
+            stem_darkening: defaults.stem_darkening,
+            tt_interpreter: defaults.tt_interpreter,
+            id: NEXT_RASTERIZER_ID.with(|id| id.replace(id.get() + 1)),
+            #[cfg(feature = "async")]
+            pending_fonts: Default::default(),
+            creation_timestamp: Some(Instant::now()),
+        }
+    }
+
+    /// Load a font face according to `FontDesc`.
+    ///
+    /// The `size` is only used for matching, the returned `FontKey` is valid for all sizes.
+    fn get_face(&mut self, desc: &FontDesc, size: Size) -> Result<FontKey, Error> {
+        let font_match = FontMatch::new(desc, size, self.device_pixel_ratio)?;
+        self.add_font(font_match)
+    }
+
+    /// Load a face without Fontconfig, using no fallback fonts.
+    fn add_face(&mut self, source: FaceSource) -> Result<FontKey, Error> {
+        let font_key = FontKey::next();
+        self.loader.face_from_source(source, font_key)?;
+        self.fallback_lists.insert(font_key, FallbackList::default());
+
+        Ok(font_key)
+    }
+
+    /// Load a font from a Fontconfig pattern string like `"Fira Code:style=Bold:size=12"`.
+    ///
+    /// Unlike `FontDesc`, patterns can request any Fontconfig property, including synthetic
+    /// styles like `:embolden=true` or transformations like `:matrix=1 0.2 0 1`. The `size` is
+    /// only used for matching if the pattern has no `size` or `pixelsize`, the returned `FontKey`
+    /// is valid for all sizes.
+    ///
+    /// This is only available with the FreeType backend on Linux and BSD.
+    pub fn load_pattern(&mut self, pattern: &str, size: Size) -> Result<FontKey, Error> {
+        let parsed = Pattern::parse(pattern).ok_or_else(|| {
+            Error::PlatformError(format!("invalid Fontconfig pattern {:?}", pattern))
+        })?;
+
+        let desc = FontDesc::new(
+            parsed.family().next().unwrap_or(pattern),
+            Style::Specific(parsed.style().next().unwrap_or_default().into()),
+        );
+
+        self.add_pending_fonts();
+
+        let font_match = FontMatch::from_pattern(&desc, parsed, size, self.device_pixel_ratio)?;
+        self.add_font(font_match)
+    }
+
+    /// Fonts Fontconfig matches for `desc`, from the best match to the `limit`-th best.
+    ///
+    /// The first candidate is the font `load_font` would use, the others are the alternatives
+    /// ranked behind it. Candidates are described by the family and style they resolved to, so
+    /// substitutions like `monospace` to a concrete family are visible.
+    ///
+    /// This is only available with the FreeType backend on Linux and BSD.
+    pub fn match_candidates(
+        &self,
+        desc: &FontDesc,
+        size: Size,
+        limit: usize,
+    ) -> Result<Vec<FontDesc>, Error> {
+        let font_match = FontMatch::new(desc, size, self.device_pixel_ratio)?;
+
+        let candidates = font_match
+            .fonts
+            .into_iter()
+            .filter_map(|font| {
+                let family = font.family().next()?;
+                let style = match font.style().next() {
+                    Some(style) => Style::Specific(style.into()),
+                    None => {
+                        let (weight, slant, _) = pattern_style(font);
+                        Style::Description { slant, weight }
+                    },
+                };
+                Some(FontDesc::new(family, style))
+            })
+            .take(limit)
+            .collect();
+
+        Ok(candidates)
+    }
+
+    /// Load the faces of a font matched by Fontconfig.
+    fn add_font(&mut self, font_match: FontMatch) -> Result<FontKey, Error> {
+        let FontMatch { desc, hash, pattern, fonts, key: primary_font_key } = font_match;
+
+        let config = fc::Config::get_current();
+        let mut matched_fonts = fonts.into_iter();
+
+        let primary_font =
+            matched_fonts.next().ok_or_else(|| Error::FontNotFound(desc.to_owned()))?;
+
+        // We should render patterns to get values like `embolden` and `matrix`.
+        let primary_font = pattern.render_prepare(config, primary_font);
+
+        // Return if we already have the same primary font.
+        if self.fallback_lists.contains_key(&primary_font_key) {
+            return Ok(primary_font_key);
+        }
+
+        // Load font if we haven't loaded it yet.
+        if !self.loader.faces.contains_key(&primary_font_key) {
+            self.loader
+                .face_from_pattern(&primary_font, primary_font_key)
+                .and_then(|pattern| pattern.ok_or_else(|| Error::FontNotFound(desc.to_owned())))?;
+        }
+
+        // Coverage for fallback fonts.
+        let coverage = CharSet::new();
+        let empty_charset = CharSet::new();
+
+        let list: Vec<FallbackFont> = matched_fonts
+            .map(|fallback_font| {
+                let charset = fallback_font.get_charset().unwrap_or(&empty_charset);
+
+                let fallback_font_key = FontKey::from_pattern_hashes(hash, fallback_font.hash());
+
+                // Use original pattern to preserve loading flags.
+                let fallback_font = pattern.render_prepare(config, fallback_font);
+
+                coverage.merge(charset);
+
+                FallbackFont::new(fallback_font, fallback_font_key)
+            })
+            .collect();
+
+        self.fallback_lists.insert(primary_font_key, FallbackList { list, coverage });
+
+        Ok(primary_font_key)
+    }
+
+    /// Load a font face according to `FontDesc` without blocking the current thread.
+    ///
+    /// Fontconfig's font matching is performed on a separate thread. The faces are loaded on the
+    /// next call to `load_font`, `get_glyph` or `kerning`, methods which only borrow the
+    /// rasterizer immutably like `metrics` will not find the returned `FontKey` before that.
+    #[cfg(feature = "async")]
+    pub fn load_font_async(
+        &self,
+        desc: FontDesc,
+        size: Size,
+    ) -> impl Future<Output = Result<FontKey, Error>> {
+        let pending_fonts = Arc::clone(&self.pending_fonts);
+        let device_pixel_ratio = self.device_pixel_ratio;
+
+        BlockingTask::spawn(move || {
+            let font_match = FontMatch::new(&desc, size, device_pixel_ratio)?;
+            let key = font_match.key;
+            pending_fonts.lock().unwrap().push(font_match);
+            Ok(key)
+        })
+    }
+
+    /// Load the faces of all fonts matched by `load_font_async`.
+    fn add_pending_fonts(&mut self) {
+        #[cfg(feature = "async")]
+        {
+            let pending_fonts = mem::take(&mut *self.pending_fonts.lock().unwrap());
+            for font_match in pending_fonts {
+                if let Err(err) = self.add_font(font_match) {
+                    error!("Unable to load font: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Rasterize a glyph with custom rendering options.
+    ///
+    /// The coverage is converted to the `AlphaPrecision`, except for multi-channel signed
+    /// distance fields. Color pixels are converted to the `ColorOrder`.
+    fn rasterize_glyph(
+        &mut self,
+        glyph_key: GlyphKey,
+        options: RenderOptions,
+    ) -> Result<RasterizedGlyph, Error> {
+        let glyph = self.fit_fallback_glyph(glyph_key, options)?;
+
+        // Empty bitmaps of visible characters point to broken glyphs.
+        let empty = glyph.width == 0 || glyph.height == 0;
+        if self.strict
+            && empty
+            && options.glyph_index.is_none()
+            && glyph.advance_f.0 != 0.
+            && !glyph.character.is_whitespace()
+            && char_cell_width(glyph.character) != 0
+        {
+            return Err(Error::MissingGlyph(glyph));
+        }
+
+        if options.msdf_range.is_some() {
+            return Ok(glyph);
+        }
+
+        Ok(glyph.with_alpha_precision(self.alpha_precision).with_color_order(self.color_order))
+    }
+
+    /// Rasterize a glyph, fitting glyphs from fallback fonts into the primary font's cells.
+    ///
+    /// Glyphs from fallback fonts are shrunk to the primary font's cell size according to the
+    /// `FallbackScaling`, and their advance is snapped to the cells if enabled.
+    fn fit_fallback_glyph(
+        &mut self,
+        glyph_key: GlyphKey,
+        options: RenderOptions,
+    ) -> Result<RasterizedGlyph, Error> {
+        let glyph = self.render_glyph(glyph_key, options)?;
+        let fits = self.fallback_scaling == FallbackScaling::None && !self.fallback_cell_advance;
+        if fits || glyph.font_key == glyph_key.font_key {
+            return Ok(glyph);
+        }
 
-        #[cfg(ft_set_default_properties_available)]
-        unsafe {
-            // Initialize default properties, like user preferred interpreter.
-            freetype_sys::FT_Set_Default_Properties(library.raw());
-        };
+        let (pixel_size, _) = self.render_scale(glyph_key.size, options);
+        let metrics = self.metrics_at(glyph_key.font_key, pixel_size, glyph.dpr)?;
+
+        let cells = usize::from(char_cell_width(glyph_key.character).max(1));
+        let mut glyph = self.scale_fallback_glyph(glyph_key, options, glyph, &metrics, cells)?;
 
-        Ok(FreeTypeLoader { library, faces: HashMap::new(), ft_faces: HashMap::new() })
+        if self.fallback_cell_advance {
+            let cell_width = self.rounding.round(metrics.average_advance as f32);
+            glyph.native_advance = Some(glyph.advance_f);
+            glyph.advance = (cell_width * cells as i32, 0);
+            glyph.advance_f = (glyph.advance.0 as f32, 0.);
+        }
+
+        Ok(glyph)
     }
 
-    fn load_ft_face(&mut self, ft_face_location: FtFaceLocation) -> Result<Rc<FtFace>, Error> {
-        let mut ft_face = self.library.new_face(&ft_face_location.path, ft_face_location.index)?;
-        if ft_face.has_color() && !ft_face.is_scalable() {
-            unsafe {
-                // Select the colored bitmap size to use from the array of available sizes.
-                freetype_sys::FT_Select_Size(ft_face.raw_mut(), 0);
-            }
+    /// Shrink a glyph from a fallback font to `cells` of the primary font's `metrics`.
+    fn scale_fallback_glyph(
+        &mut self,
+        glyph_key: GlyphKey,
+        options: RenderOptions,
+        glyph: RasterizedGlyph,
+        metrics: &Metrics,
+        cells: usize,
+    ) -> Result<RasterizedGlyph, Error> {
+        if self.fallback_scaling == FallbackScaling::None {
+            return Ok(glyph);
         }
 
-        let ft_face = Rc::new(ft_face);
-        self.ft_faces.insert(ft_face_location, Rc::clone(&ft_face));
+        let (pixel_size, _) = self.render_scale(glyph_key.size, options);
+        let advance = metrics.average_advance * cells as f64;
+        let mut scale = (advance / f64::from(glyph.advance_f.0)) as f32;
+        if self.fallback_scaling == FallbackScaling::FitCell {
+            scale = scale.min(metrics.line_height as f32 / glyph.height as f32);
+        }
 
-        Ok(ft_face)
+        if !scale.is_finite() || scale >= 1. {
+            return Ok(glyph);
+        }
+
+        let options = RenderOptions { pixel_size: Some(pixel_size * scale), ..options };
+        let mut scaled = self.render_glyph(glyph_key, options)?;
+        scaled.dpr = glyph.dpr;
+        Ok(scaled)
     }
 
-    fn face_from_pattern(
+    /// Rasterize a glyph without scaling fallback glyphs.
+    fn render_glyph(
         &mut self,
-        pattern: &PatternRef,
-        font_key: FontKey,
-    ) -> Result<Option<FontKey>, Error> {
-        if let Some(ft_face_location) = pattern.ft_face_location(0) {
-            if self.faces.get(&font_key).is_some() {
-                return Ok(Some(font_key));
+        glyph_key: GlyphKey,
+        options: RenderOptions,
+    ) -> Result<RasterizedGlyph, Error> {
+        profile_span!("render");
+
+        let font_key = match options.glyph_index {
+            Some(_) => glyph_key.font_key,
+            None => {
+                let presentation = selector_presentation(options.variation_selector);
+                self.face_for_glyph(glyph_key, presentation)
+            },
+        };
+        let (pixel_size, dpr) = self.render_scale(glyph_key.size, options);
+        self.loader.open_face(font_key)?;
+
+        let mut options = options;
+        if self.vertical && options.glyph_index.is_none() {
+            options.vertical_index = self.vertical_index(font_key, glyph_key.character)?;
+        }
+
+        // Reopen the face once if its file can't be read anymore, since it might have been
+        // replaced by an update.
+        let (index, pixelsize) = match self.load_glyph(font_key, glyph_key, options, pixel_size) {
+            Err(err) if is_stream_error(err) => {
+                self.loader.reload_face(font_key)?;
+                self.load_glyph(font_key, glyph_key, options, pixel_size)?
+            },
+            result => result?,
+        };
+
+        let face = &self.loader.faces[&font_key];
+        let glyph = face.ft_face()?.glyph();
+
+        // Generate synthetic bold.
+        if face.embolden {
+            unsafe {
+                freetype_sys::FT_GlyphSlot_Embolden(glyph.raw()
+                    as *const freetype_sys::FT_GlyphSlotRec
+                    as *mut freetype_sys::FT_GlyphSlotRec);
             }
+        }
 
-            trace!("Got font path={:?}, index={:?}", ft_face_location.path, ft_face_location.index);
+        // Vertical glyphs are moved from the horizontal to the vertical origin.
+        let vertical_shift = self.vertical.then(|| {
+            let metrics = glyph.metrics();
+            let x = from_freetype_26_6(metrics.vertBearingX - metrics.horiBearingX);
+            let y = -from_freetype_26_6(metrics.horiBearingY + metrics.vertBearingY);
+            (x, y)
+        });
+
+        let mut stroke = None;
+        let mut bitmap_shift = (0, 0);
+        let (advance, bearings) = unsafe {
+            // Transform glyphs with the matrix from Fontconfig. Primarily used to generate italics.
+            let raw_glyph = face.ft_face()?.raw().glyph;
+            if let Some(matrix) = face.matrix.as_ref() {
+                // Check that the glyph is a vectorial outline, not a bitmap.
+                if (*raw_glyph).format == freetype_sys::FT_GLYPH_FORMAT_OUTLINE {
+                    let outline = &(*raw_glyph).outline;
 
-            let ft_face = match self.ft_faces.get(&ft_face_location) {
-                Some(ft_face) => Rc::clone(ft_face),
-                None => self.load_ft_face(ft_face_location)?,
-            };
+                    freetype_sys::FT_Outline_Transform(outline, matrix);
+                }
+            }
 
-            let non_scalable = if pattern.scalable().next().unwrap_or(true) {
-                None
+            // Advance down, since positive vertical advances point up.
+            if let Some((x, y)) = vertical_shift {
+                if (*raw_glyph).format == freetype_sys::FT_GLYPH_FORMAT_OUTLINE {
+                    let outline = &(*raw_glyph).outline;
+                    freetype_sys::FT_Outline_Translate(
+                        outline,
+                        to_freetype_26_6(x) as _,
+                        to_freetype_26_6(y) as _,
+                    );
+                } else {
+                    bitmap_shift = (x.round() as i32, y.round() as i32);
+                }
+                (*raw_glyph).advance.x = 0;
+                (*raw_glyph).advance.y = -(*raw_glyph).advance.y;
+            }
+
+            // Apply the caller's transform to both the outline and the advance vector.
+            if let Some([xx, xy, yx, yy]) = options.transform {
+                if (*raw_glyph).format != freetype_sys::FT_GLYPH_FORMAT_OUTLINE {
+                    return Err(Error::PlatformError(String::from(
+                        "transforms require an outline glyph",
+                    )));
+                }
+
+                let matrix = Matrix {
+                    xx: to_fixedpoint_16_6(f64::from(xx)),
+                    xy: to_fixedpoint_16_6(f64::from(xy)),
+                    yx: to_fixedpoint_16_6(f64::from(yx)),
+                    yy: to_fixedpoint_16_6(f64::from(yy)),
+                };
+                freetype_sys::FT_Outline_Transform(&(*raw_glyph).outline, &matrix);
+
+                let advance = (*raw_glyph).advance;
+                let (x, y) = (advance.x as f32, advance.y as f32);
+                (*raw_glyph).advance.x = (xx * x + xy * y).round() as _;
+                (*raw_glyph).advance.y = (yx * x + yy * y).round() as _;
+            }
+
+            // Move the outline to render it at a subpixel position.
+            if (options.x_offset != 0. || options.y_offset != 0.)
+                && (*raw_glyph).format == freetype_sys::FT_GLYPH_FORMAT_OUTLINE
+            {
+                let outline = &(*raw_glyph).outline;
+                freetype_sys::FT_Outline_Translate(
+                    outline,
+                    to_freetype_26_6(options.x_offset) as _,
+                    to_freetype_26_6(-options.y_offset) as _,
+                );
+            }
+
+            // Get the unrounded bearings before the outline is snapped to the pixel grid.
+            let bearings = if (*raw_glyph).format == freetype_sys::FT_GLYPH_FORMAT_OUTLINE {
+                let mut cbox = mem::zeroed::<freetype_sys::FT_BBox>();
+                freetype_sys::FT_Outline_Get_CBox(&(*raw_glyph).outline, &mut cbox);
+                let bearings = (from_freetype_26_6(cbox.yMax), from_freetype_26_6(cbox.xMin));
+
+                // Move the bitmap origin to whole pixels with the rounding mode, unless the glyph
+                // was explicitly requested at a subpixel position.
+                if options.x_offset == 0. && options.y_offset == 0. {
+                    let dx = self.rounding.round(bearings.1) as f32 - bearings.1;
+                    let dy = self.rounding.round(bearings.0) as f32 - bearings.0;
+                    freetype_sys::FT_Outline_Translate(
+                        &(*raw_glyph).outline,
+                        to_freetype_26_6(dx) as _,
+                        to_freetype_26_6(dy) as _,
+                    );
+                }
+
+                bearings
             } else {
-                Some(pattern.pixelsize().next().expect("has 1+ pixelsize") as f32)
+                let top = glyph.bitmap_top() + bitmap_shift.1;
+                let left = glyph.bitmap_left() + bitmap_shift.0;
+                (top as f32, left as f32)
             };
 
-            let embolden = pattern.embolden().next().unwrap_or(false);
+            if let Some(spread) = options.sdf_spread {
+                // Bitmap glyphs are converted by the `bsdf` renderer, outlines by the `sdf` one.
+                let spread =
+                    spread.round().clamp(SDF_MIN_SPREAD, SDF_MAX_SPREAD) as freetype_sys::FT_Int;
+                for module in [&b"sdf\0"[..], &b"bsdf\0"[..]] {
+                    FT_Property_Set(
+                        self.loader.library.raw(),
+                        module.as_ptr() as *const _,
+                        b"spread\0".as_ptr() as *const _,
+                        &spread as *const freetype_sys::FT_Int as *const _,
+                    );
+                }
 
-            let matrix = pattern.get_matrix().map(|matrix| {
-                // Convert Fontconfig matrix to FreeType matrix.
-                let xx = to_fixedpoint_16_6(matrix.xx);
-                let xy = to_fixedpoint_16_6(matrix.xy);
-                let yx = to_fixedpoint_16_6(matrix.yx);
-                let yy = to_fixedpoint_16_6(matrix.yy);
+                let error = FT_Render_Glyph(raw_glyph, FT_RENDER_MODE_SDF);
+                if error != 0 {
+                    return Err(freetype::Error::from(error).into());
+                }
+            } else if options.msdf_range.is_some() {
+                // Multi-channel distance fields are generated from the outline directly.
+                if (*raw_glyph).format != freetype_sys::FT_GLYPH_FORMAT_OUTLINE {
+                    return Err(Error::PlatformError(String::from(
+                        "multi-channel distance fields require an outline glyph",
+                    )));
+                }
+            } else if let Some((width, style)) = options.stroke {
+                if (*raw_glyph).format != freetype_sys::FT_GLYPH_FORMAT_OUTLINE {
+                    return Err(Error::PlatformError(String::from(
+                        "strokes require an outline glyph",
+                    )));
+                }
 
-                Matrix { xx, xy, yx, yy }
-            });
+                stroke = Some(self.stroke_glyph(face, width, style)?);
+            } else if (*raw_glyph).format != freetype_sys::FT_GLYPH_FORMAT_BITMAP {
+                // Don't render bitmap glyphs, it results in error with freestype 2.11.0.
+                glyph.render_glyph(face.render_mode)?;
+            }
 
-            let pixelsize_fixup_factor = pattern.pixelsizefixupfactor().next();
+            let advance = (*raw_glyph).advance;
+            ((from_freetype_26_6(advance.x), from_freetype_26_6(advance.y)), bearings)
+        };
 
-            let rgba = pattern.rgba().next().unwrap_or(Rgba::Unknown);
+        let (top, left, pixel_height, pixel_width, buffer) = match options.msdf_range {
+            #[cfg(feature = "msdf")]
+            Some(range) => {
+                let msdf =
+                    msdf::generate(unsafe { &(*face.ft_face()?.raw().glyph).outline }, range);
+                (msdf.top, msdf.left, msdf.height, msdf.width, msdf.buffer)
+            },
+            _ => match stroke {
+                Some(Coverage { top, left, height, width, ref mut buffer, .. }) => {
+                    (top, left, height, width, BitmapBuffer::Rgb(mem::take(buffer)))
+                },
+                None => {
+                    let (pixel_height, pixel_width, buffer) =
+                        Self::normalize_buffer(&glyph.bitmap(), &face.rgba)?;
+                    let top = glyph.bitmap_top() + bitmap_shift.1;
+                    let left = glyph.bitmap_left() + bitmap_shift.0;
+                    (top, left, pixel_height, pixel_width, buffer)
+                },
+            },
+        };
+        let bearings = stroke.map_or(bearings, |stroke| stroke.bearings);
 
-            let face = FaceLoadingProperties {
-                load_flags: Self::ft_load_flags(pattern),
-                render_mode: Self::ft_render_mode(pattern),
-                lcd_filter: Self::ft_lcd_filter(pattern),
-                non_scalable,
-                colored_bitmap: ft_face.has_color() && !ft_face.is_scalable(),
-                embolden,
-                matrix,
-                pixelsize_fixup_factor,
-                ft_face,
-                rgba,
-            };
+        // Synthetic bold widens the glyph by a fraction of a pixel, so round the advance up to
+        // keep the emboldened bitmap within it.
+        let advance_px = if face.embolden {
+            (advance.0.ceil() as i32, advance.1.ceil() as i32)
+        } else {
+            (self.rounding.round(advance.0), self.rounding.round(advance.1))
+        };
 
-            debug!("Loaded Face {:?}", face);
+        let mut rasterized_glyph = RasterizedGlyph {
+            character: glyph_key.character,
+            top,
+            left,
+            top_f: bearings.0,
+            left_f: bearings.1,
+            width: pixel_width,
+            height: pixel_height,
+            advance: advance_px,
+            advance_f: advance,
+            native_advance: None,
+            buffer,
+            font_key,
+            dpr,
+        };
 
-            self.faces.insert(font_key, face);
+        // Glyphs requested by index are never missing, even the missing glyph itself.
+        if index == MISSING_GLYPH_INDEX && options.glyph_index.is_none() {
+            self.apply_tracking(glyph_key.font_key, &mut rasterized_glyph);
+            self.origin.apply(&mut rasterized_glyph);
+            return Err(Error::MissingGlyph(rasterized_glyph));
+        }
 
-            Ok(Some(font_key))
-        } else {
-            Ok(None)
+        if face.colored_bitmap {
+            // Fontconfig's `pixelsizefixupfactor` is only valid for the size the font was matched
+            // with, so the factor is derived from the requested size instead.
+            let metrics = face.ft_face()?.size_metrics().ok_or(Error::MetricsNotFound)?;
+            let fixup_factor = f64::from(pixelsize) / f64::from(metrics.y_ppem);
+
+            // Scale glyph advance.
+            let advance_x = f64::from(rasterized_glyph.advance.0) * fixup_factor;
+            let advance_y = f64::from(rasterized_glyph.advance.1) * fixup_factor;
+            rasterized_glyph.advance =
+                (self.rounding.round(advance_x as f32), self.rounding.round(advance_y as f32));
+            rasterized_glyph.advance_f.0 = (f64::from(advance.0) * fixup_factor) as f32;
+            rasterized_glyph.advance_f.1 = (f64::from(advance.1) * fixup_factor) as f32;
+
+            rasterized_glyph = downsample_bitmap(rasterized_glyph, fixup_factor);
+        } else if let Some(factor) = self.bitmap_scale(face, pixel_size, pixelsize) {
+            rasterized_glyph = scale_bitmap(rasterized_glyph, factor);
+            rasterized_glyph.advance = (
+                self.rounding.round(rasterized_glyph.advance_f.0),
+                self.rounding.round(rasterized_glyph.advance_f.1),
+            );
         }
-    }
 
-    fn ft_load_flags(pattern: &PatternRef) -> LoadFlag {
-        let antialias = pattern.antialias().next().unwrap_or(true);
-        let autohint = pattern.autohint().next().unwrap_or(false);
-        let hinting = pattern.hinting().next().unwrap_or(true);
-        let rgba = pattern.rgba().next().unwrap_or(Rgba::Unknown);
-        let embedded_bitmaps = pattern.embeddedbitmap().next().unwrap_or(true);
-        let scalable = pattern.scalable().next().unwrap_or(true);
-        let color = pattern.color().next().unwrap_or(false);
+        self.apply_tracking(glyph_key.font_key, &mut rasterized_glyph);
+        self.origin.apply(&mut rasterized_glyph);
 
-        // Disable hinting if so was requested.
-        let hintstyle = if hinting {
-            pattern.hintstyle().next().unwrap_or(fc::HintStyle::Full)
-        } else {
-            fc::HintStyle::None
-        };
+        Ok(rasterized_glyph)
+    }
 
-        let mut flags = match (antialias, hintstyle, rgba) {
-            (false, fc::HintStyle::None, _) => LoadFlag::NO_HINTING | LoadFlag::MONOCHROME,
-            (false, ..) => LoadFlag::TARGET_MONO | LoadFlag::MONOCHROME,
-            (true, fc::HintStyle::None, _) => LoadFlag::NO_HINTING,
-            // `hintslight` does *not* use LCD hinting even when a subpixel mode
-            // is selected.
-            //
-            // According to the FreeType docs,
-            //
-            // > You can use a hinting algorithm that doesn't correspond to the
-            // > same rendering mode.  As an example, it is possible to use the
-            // > ‘light’ hinting algorithm and have the results rendered in
-            // > horizontal LCD pixel mode.
-            //
-            // In practice, this means we can have `FT_LOAD_TARGET_LIGHT` with
-            // subpixel render modes like `FT_RENDER_MODE_LCD`. Libraries like
-            // cairo take the same approach and consider `hintslight` to always
-            // prefer `FT_LOAD_TARGET_LIGHT`.
-            (true, fc::HintStyle::Slight, _) => LoadFlag::TARGET_LIGHT,
-            (true, fc::HintStyle::Medium, _) => LoadFlag::TARGET_NORMAL,
-            // If LCD hinting is to be used, must select hintmedium or hintfull,
-            // have AA enabled, and select a subpixel mode.
-            (true, fc::HintStyle::Full, Rgba::Rgb) | (true, fc::HintStyle::Full, Rgba::Bgr) => {
-                LoadFlag::TARGET_LCD
+    /// Render the stroke of the outline in a face's glyph slot.
+    fn stroke_glyph(
+        &self,
+        face: &FaceLoadingProperties,
+        width: f32,
+        style: StrokeStyle,
+    ) -> Result<Coverage, Error> {
+        let glyph = face.ft_face()?.glyph().get_glyph()?;
+
+        // Strokes inside or outside of the outline are cut from a stroke twice as wide, since
+        // the borders of thin strokes intersect with each other.
+        let radius = match style {
+            StrokeStyle::Center => width / 2.,
+            StrokeStyle::Inside | StrokeStyle::Outside => width,
+        };
+        let stroker = self.loader.library.new_stroker()?;
+        stroker.set(
+            to_freetype_26_6(radius) as _,
+            StrokerLineCap::Round,
+            StrokerLineJoin::Round,
+            0,
+        );
+
+        let mut coverage = Self::render_coverage(face, &glyph.stroke(&stroker)?)?;
+        match style {
+            StrokeStyle::Center => (),
+            StrokeStyle::Inside => {
+                let mut fill = Self::render_coverage(face, &glyph)?;
+                fill.intersect(&coverage);
+                coverage = fill;
             },
-            (true, fc::HintStyle::Full, Rgba::Vrgb) | (true, fc::HintStyle::Full, Rgba::Vbgr) => {
-                LoadFlag::TARGET_LCD_V
+            StrokeStyle::Outside => coverage.subtract(&Self::render_coverage(face, &glyph)?),
+        }
+
+        Ok(coverage)
+    }
+
+    /// Render an outline glyph with the face's render mode.
+    fn render_coverage(face: &FaceLoadingProperties, glyph: &Glyph) -> Result<Coverage, Error> {
+        let cbox = glyph.get_cbox(freetype_sys::FT_GLYPH_BBOX_SUBPIXELS);
+        let bitmap_glyph = glyph.to_bitmap(face.render_mode, None)?;
+        let (height, width, buffer) = Self::normalize_buffer(&bitmap_glyph.bitmap(), &face.rgba)?;
+        let buffer = match buffer {
+            BitmapBuffer::Rgb(buffer) => buffer,
+            BitmapBuffer::Rgba(_) | BitmapBuffer::Bgra(_) => {
+                return Err(Error::PlatformError(String::from("strokes can't be colored")))
             },
-            // For non-rgba modes with Full hinting, just use the default hinting algorithm.
-            (true, fc::HintStyle::Full, Rgba::Unknown)
-            | (true, fc::HintStyle::Full, Rgba::None) => LoadFlag::TARGET_NORMAL,
+            BitmapBuffer::GrayF32(_) => unreachable!("FreeType renders 8-bit coverage"),
         };
 
-        // Non scalable fonts only have bitmaps, so disabling them entirely is likely not a
-        // desirable thing. Colored fonts aren't scalable, but also only have bitmaps.
-        if !embedded_bitmaps && scalable && !color {
-            flags |= LoadFlag::NO_BITMAP;
+        Ok(Coverage {
+            top: bitmap_glyph.top(),
+            left: bitmap_glyph.left(),
+            height,
+            width,
+            buffer,
+            bearings: (from_freetype_26_6(cbox.yMax), from_freetype_26_6(cbox.xMin)),
+        })
+    }
+
+    /// Shape text and get the byte offset and advance of every cluster, including tracking.
+    fn cluster_advances(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        size: Size,
+    ) -> Result<Vec<(usize, f32)>, Error> {
+        let pixel_size = self.pixel_size(size);
+        let tracking = self.tracking.get(&font_key).copied().unwrap_or(0.);
+        let runs = self.fallback_runs(text, font_key, size);
+
+        let features = self.features.clone();
+        let mut clusters = Vec::new();
+        for (start, end, run_key) in runs {
+            profile_span!("shape");
+
+            let ascii_glyphs = self.ascii_glyphs(&text[start..end], run_key);
+
+            let hb_font = self.hb_font(run_key)?;
+            hb_font.set_scale(
+                to_freetype_26_6(pixel_size) as i32,
+                to_freetype_26_6(pixel_size) as i32,
+            );
+
+            if let Some(ascii_glyphs) = ascii_glyphs {
+                clusters.extend(ascii_glyphs.into_iter().enumerate().map(|(index, glyph)| {
+                    let advance = from_freetype_26_6(hb_font.get_glyph_h_advance(glyph));
+                    (start + index, (advance + tracking).max(0.))
+                }));
+                continue;
+            }
+
+            let buffer = UnicodeBuffer::new().add_str_item(text, &text[start..end]);
+            let glyphs = harfbuzz_rs::shape(hb_font, buffer, &features);
+
+            // Add tracking once per cluster, so combining characters aren't spaced out.
+            let mut cluster_advances: Vec<(u32, f32)> = Vec::new();
+            let infos = glyphs.get_glyph_infos().iter();
+            for (info, position) in infos.zip(glyphs.get_glyph_positions()) {
+                let glyph_advance = from_freetype_26_6(position.x_advance);
+                match cluster_advances.last_mut() {
+                    Some((cluster, advance)) if *cluster == info.cluster => {
+                        *advance += glyph_advance
+                    },
+                    _ => cluster_advances.push((info.cluster, glyph_advance)),
+                }
+            }
+
+            clusters.extend(
+                cluster_advances
+                    .into_iter()
+                    .map(|(cluster, advance)| (cluster as usize, (advance + tracking).max(0.))),
+            );
         }
 
-        // Use color for colored fonts.
-        if color {
-            flags |= LoadFlag::COLOR;
+        Ok(clusters)
+    }
+
+    /// Glyphs of printable ASCII text in a monospace font, if it can be shaped without HarfBuzz.
+    ///
+    /// Without ligatures and without any layout lookups for Latin text, every character maps to
+    /// its nominal glyph with one cluster per byte.
+    fn ascii_glyphs(&mut self, text: &str, font_key: FontKey) -> Option<Vec<u32>> {
+        if self.ligatures || !text.bytes().all(|byte| (b' '..=b'~').contains(&byte)) {
+            return None;
         }
 
-        // Force autohint if it was requested.
-        if autohint {
-            flags |= LoadFlag::FORCE_AUTOHINT;
+        let face = self.loader.face(font_key).ok()?;
+        let ft_face = face.ft_face().ok()?;
+        let simple_layout = *self
+            .simple_ascii_layout
+            .entry(font_key)
+            .or_insert_with(|| ft_face.is_fixed_width() && !has_latin_layout(ft_face));
+        if !simple_layout {
+            return None;
         }
 
-        flags
+        let hb_font = self.hb_font(font_key).ok()?;
+        let glyph =
+            |byte: u8| hb_font.get_nominal_glyph(byte as char).unwrap_or(MISSING_GLYPH_INDEX);
+        Some(text.bytes().map(glyph).collect())
     }
 
-    fn ft_render_mode(pat: &PatternRef) -> freetype::RenderMode {
-        let antialias = pat.antialias().next().unwrap_or(true);
-        let rgba = pat.rgba().next().unwrap_or(Rgba::Unknown);
+    /// Split text into runs of byte ranges which use the same fallback font.
+    fn fallback_runs(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        size: Size,
+    ) -> Vec<(usize, usize, FontKey)> {
+        let mut runs: Vec<(usize, usize, FontKey)> = Vec::new();
+        for (index, character) in text.char_indices() {
+            let glyph_key = GlyphKey { character, font_key, size };
+            let run_key = self.face_for_glyph(glyph_key, None);
+            let end = index + character.len_utf8();
+            match runs.last_mut() {
+                Some((_, run_end, key)) if *key == run_key => *run_end = end,
+                _ => runs.push((index, end, run_key)),
+            }
+        }
+        runs
+    }
 
-        match (antialias, rgba) {
-            (false, _) => freetype::RenderMode::Mono,
-            (_, Rgba::Rgb) | (_, Rgba::Bgr) => freetype::RenderMode::Lcd,
-            (_, Rgba::Vrgb) | (_, Rgba::Vbgr) => freetype::RenderMode::LcdV,
-            (true, _) => freetype::RenderMode::Normal,
+    /// Get the HarfBuzz font for a loaded face.
+    fn hb_font(&mut self, font_key: FontKey) -> Result<&mut Owned<HbFont<'static>>, Error> {
+        if !self.hb_fonts.contains_key(&font_key) {
+            let face = self.loader.faces.get(&font_key).ok_or(Error::UnknownFontKey)?;
+
+            // The upper bits of the face index select named instances, which HarfBuzz ignores.
+            let hb_face = match &face.source {
+                FaceSource::File(location) => {
+                    HbFace::from_file(&location.path, (location.index & 0xffff) as u32)?
+                },
+                FaceSource::Memory { data, index } => {
+                    let blob = Blob::with_bytes_owned(data.to_vec(), |data| data);
+                    HbFace::new(blob, (index & 0xffff) as u32)
+                },
+            };
+
+            self.hb_fonts.insert(font_key, HbFont::new(hb_face));
         }
+
+        Ok(self.hb_fonts.get_mut(&font_key).unwrap())
     }
 
-    fn ft_lcd_filter(pat: &PatternRef) -> c_uint {
-        match pat.lcdfilter().next().unwrap_or(fc::LcdFilter::Default) {
-            fc::LcdFilter::None => freetype::ffi::FT_LCD_FILTER_NONE,
-            fc::LcdFilter::Default => freetype::ffi::FT_LCD_FILTER_DEFAULT,
-            fc::LcdFilter::Light => freetype::ffi::FT_LCD_FILTER_LIGHT,
-            fc::LcdFilter::Legacy => freetype::ffi::FT_LCD_FILTER_LEGACY,
+    /// Add the tracking of a font to a glyph's horizontal advance.
+    fn apply_tracking(&self, font_key: FontKey, glyph: &mut RasterizedGlyph) {
+        if let Some(&tracking) = self.tracking.get(&font_key) {
+            glyph.advance.0 = (glyph.advance.0 + tracking.round() as i32).max(0);
+            glyph.advance_f.0 = (glyph.advance_f.0 + tracking).max(0.);
         }
     }
-}
 
-pub trait IntoFontconfigType {
-    type FcType;
-    fn into_fontconfig_type(&self) -> Self::FcType;
-}
+    /// Load a glyph into the face's glyph slot.
+    ///
+    /// Returns the glyph index and the pixel size of the face.
+    fn load_glyph(
+        &self,
+        font_key: FontKey,
+        glyph_key: GlyphKey,
+        options: RenderOptions,
+        pixel_size: f32,
+    ) -> Result<(u32, f32), freetype::Error> {
+        let face = &self.loader.faces[&font_key];
+        let ft_face = face.ft_face()?;
+        let character = self.remapped(glyph_key.character);
+        let index = options.glyph_index.or(options.vertical_index).unwrap_or_else(|| {
+            options
+                .variation_selector
+                .map(|selector| char_variant_index(ft_face, character, selector))
+                .filter(|&index| index != MISSING_GLYPH_INDEX)
+                .unwrap_or_else(|| char_index(ft_face, character, self.charmap))
+        });
+        let pixelsize = match options.prepared_face {
+            Some((prepared, pixelsize, open_count))
+                if prepared == font_key && open_count == self.loader.open_count.get() =>
+            {
+                pixelsize
+            },
+            _ => {
+                let (_, dpr) = self.render_scale(glyph_key.size, options);
+                let optical_size = self.optical_size(pixel_size, dpr);
+                let pixelsize = Self::set_face_pixel_size(face, pixel_size, optical_size)?;
+                self.prepare_library(face);
+                pixelsize
+            },
+        };
 
-impl IntoFontconfigType for Slant {
-    type FcType = fc::Slant;
+        let mut load_flags = face.load_flags;
+        if self.vertical {
+            load_flags |= LoadFlag::VERTICAL_LAYOUT;
+        }
+        if let Some(autohint) = options.autohint {
+            load_flags.remove(LoadFlag::FORCE_AUTOHINT | LoadFlag::NO_AUTOHINT);
+            load_flags |= if autohint { LoadFlag::FORCE_AUTOHINT } else { LoadFlag::NO_AUTOHINT };
+        }
+        face.ft_face()?.load_glyph(index, load_flags)?;
+
+        Ok((index, pixelsize))
+    }
+
+    /// Open a face and set it to a pixel size, for loading multiple glyphs without any setup.
+    fn prepare_face(
+        &mut self,
+        key: FontKey,
+        pixel_size: f32,
+        dpr: f32,
+    ) -> Result<(FontKey, f32, u64), Error> {
+        self.loader.open_face(key)?;
+        let face = &self.loader.faces[&key];
+        let pixelsize =
+            Self::set_face_pixel_size(face, pixel_size, self.optical_size(pixel_size, dpr))?;
+        self.prepare_library(face);
+
+        Ok((key, pixelsize, self.loader.open_count.get()))
+    }
+
+    /// Configure the shared FreeType library for rendering glyphs of a face.
+    fn prepare_library(&self, face: &FaceLoadingProperties) {
+        unsafe {
+            let ft_lib = self.loader.library.raw();
+            freetype::ffi::FT_Library_SetLcdFilter(ft_lib, face.lcd_filter);
+        }
 
-    fn into_fontconfig_type(&self) -> Self::FcType {
-        match *self {
-            Slant::Normal => fc::Slant::Roman,
-            Slant::Italic => fc::Slant::Italic,
-            Slant::Oblique => fc::Slant::Oblique,
+        // The library is shared between rasterizers, so its drivers have to be reconfigured
+        // whenever another rasterizer configured them last.
+        let configured = (self.loader.library.raw() as usize, self.id);
+        if CONFIGURED_LIBRARY.with(|last| last.replace(Some(configured))) != Some(configured) {
+            self.apply_stem_darkening(self.stem_darkening);
+            if let Some(version) = self.tt_interpreter {
+                self.apply_tt_interpreter(version);
+            }
         }
     }
-}
 
-impl From<Slant> for fc::Slant {
-    fn from(slant: Slant) -> Self {
-        match slant {
-            Slant::Normal => fc::Slant::Roman,
-            Slant::Italic => fc::Slant::Italic,
-            Slant::Oblique => fc::Slant::Oblique,
+    /// Configure stem darkening of the CFF driver, returning FreeType's error code.
+    fn apply_stem_darkening(
+        &self,
+        stem_darkening: Option<[(u32, u32); 4]>,
+    ) -> freetype_sys::FT_Error {
+        let no_stem_darkening = freetype_sys::FT_Bool::from(stem_darkening.is_none());
+        let params = stem_darkening.unwrap_or(DEFAULT_DARKENING_PARAMETERS);
+        let params = params
+            .iter()
+            .flat_map(|&(x, y)| [x as freetype_sys::FT_Int, y as freetype_sys::FT_Int])
+            .collect::<Vec<_>>();
+
+        unsafe {
+            let ft_lib = self.loader.library.raw();
+            let error = FT_Property_Set(
+                ft_lib,
+                b"cff\0".as_ptr() as *const _,
+                b"darkening-parameters\0".as_ptr() as *const _,
+                params.as_ptr() as *const _,
+            );
+            if error != 0 {
+                return error;
+            }
+
+            FT_Property_Set(
+                ft_lib,
+                b"cff\0".as_ptr() as *const _,
+                b"no-stem-darkening\0".as_ptr() as *const _,
+                &no_stem_darkening as *const freetype_sys::FT_Bool as *const _,
+            )
         }
     }
-}
 
-impl From<Weight> for fc::Weight {
-    fn from(weight: Weight) -> Self {
-        match weight {
-            Weight::Normal => fc::Weight::Regular,
-            Weight::Bold => fc::Weight::Bold,
+    /// Configure the TrueType driver's interpreter, returning FreeType's error code.
+    fn apply_tt_interpreter(&self, version: TtInterpreter) -> freetype_sys::FT_Error {
+        let version: freetype_sys::FT_UInt = match version {
+            TtInterpreter::Classic35 => TT_INTERPRETER_VERSION_35,
+            TtInterpreter::Minimal38 => TT_INTERPRETER_VERSION_38,
+            TtInterpreter::Subpixel40 => TT_INTERPRETER_VERSION_40,
+        };
+
+        unsafe {
+            FT_Property_Set(
+                self.loader.library.raw(),
+                b"truetype\0".as_ptr() as *const _,
+                b"interpreter-version\0".as_ptr() as *const _,
+                &version as *const freetype_sys::FT_UInt as *const _,
+            )
         }
     }
-}
-
-struct FullMetrics {
-    size_metrics: freetype::ffi::FT_Size_Metrics,
-    cell_width: f64,
-}
 
-impl FreeTypeRasterizer {
-    /// Load a font face according to `FontDesc`.
-    fn get_face(&mut self, desc: &FontDesc, size: Size) -> Result<FontKey, Error> {
-        // Adjust for DPR.
-        let size = f64::from(size.as_f32_pts() * self.device_pixel_ratio * 96. / 72.);
+    /// Glyph substituted for a character by the `vert` and `vrt2` features of a font.
+    fn vertical_index(&mut self, font_key: FontKey, character: char) -> Result<Option<u32>, Error> {
+        let character = self.remapped(character);
+        let hb_font = self.hb_font(font_key)?;
+        let nominal = hb_font.get_nominal_glyph(character);
+
+        let mut text = [0; 4];
+        let buffer = UnicodeBuffer::new()
+            .add_str(character.encode_utf8(&mut text))
+            .set_direction(Direction::Ttb);
+        let features = hb_features(&[(Tag::new(b"vert"), 1), (Tag::new(b"vrt2"), 1)]);
+        let glyphs = harfbuzz_rs::shape(hb_font, buffer, &features);
+
+        let index = glyphs.get_glyph_infos().first().map(|info| info.codepoint);
+        Ok(index.filter(|&index| index != MISSING_GLYPH_INDEX && Some(index) != nominal))
+    }
 
-        let config = fc::Config::get_current();
-        let mut pattern = Pattern::new();
-        pattern.add_family(&desc.name);
-        pattern.add_pixelsize(size);
+    /// Pixel size of a font at `size` with the current device pixel ratio.
+    fn pixel_size(&self, size: Size) -> f32 {
+        size.as_px(self.device_pixel_ratio)
+    }
 
-        // Add style to a pattern.
-        match desc.style {
-            Style::Description { slant, weight } => {
-                // Match nearest font.
-                pattern.set_weight(weight.into());
-                pattern.set_slant(slant.into());
-            },
-            Style::Specific(ref style) => {
-                // If a name was specified, try and load specifically that font.
-                pattern.add_style(style);
-            },
+    /// Pixel size and device pixel ratio used to render a glyph at `size`.
+    fn render_scale(&self, size: Size, options: RenderOptions) -> (f32, f32) {
+        match (options.pixel_size, options.dpr) {
+            (Some(pixel_size), _) => (pixel_size, 1.),
+            (None, Some(dpr)) => (size.as_px(dpr), dpr),
+            (None, None) => (self.pixel_size(size), self.device_pixel_ratio),
         }
+    }
 
-        // Hash requested pattern.
-        let hash = pattern.hash();
+    /// Optical size in points for a pixel size, if it should be applied automatically.
+    fn optical_size(&self, pixel_size: f32, dpr: f32) -> Option<f32> {
+        if self.auto_optical_size {
+            Some(pixel_size / (dpr * Size::pixels_per_point()))
+        } else {
+            None
+        }
+    }
 
-        pattern.config_substitute(config, fc::MatchKind::Pattern);
-        pattern.default_substitute();
+    /// Get the metrics of a font at a pixel size rendered for a device pixel ratio.
+    fn metrics_at(&self, key: FontKey, pixel_size: f32, dpr: f32) -> Result<Metrics, Error> {
+        let face = self.loader.face(key)?;
+        let optical_size = self.optical_size(pixel_size, dpr);
+
+        let (metrics, pixelsize) = match Self::set_face_pixel_size(&face, pixel_size, optical_size)
+        {
+            Err(err) if is_stream_error(err) => {
+                // The face can't be replaced without mutable access, so use a temporary one.
+                let face = self.loader.reopen_face(&face)?;
+                let pixelsize = Self::set_face_pixel_size(&face, pixel_size, optical_size)?;
+                (self.face_metrics(&face)?, pixelsize)
+            },
+            result => {
+                let pixelsize = result?;
+                (self.face_metrics(&face)?, pixelsize)
+            },
+        };
 
-        // Get font list using pattern. First font is the primary one while the rest are fallbacks.
-        let matched_fonts =
-            fc::font_sort(config, &pattern).ok_or_else(|| Error::FontNotFound(desc.to_owned()))?;
-        let mut matched_fonts = matched_fonts.into_iter();
+        match self.bitmap_scale(&face, pixel_size, pixelsize) {
+            Some(factor) => Ok(scale_metrics(&metrics, factor)),
+            None => Ok(metrics),
+        }
+    }
 
-        let primary_font =
-            matched_fonts.next().ok_or_else(|| Error::FontNotFound(desc.to_owned()))?;
+    /// Factor from a bitmap strike's size to the requested size, if strikes should be scaled.
+    fn bitmap_scale(
+        &self,
+        face: &FaceLoadingProperties,
+        pixel_size: f32,
+        strike_size: f32,
+    ) -> Option<f32> {
+        let is_bitmap = face.non_scalable.is_some() && !face.colored_bitmap;
+        if self.bitmap_scaling && is_bitmap && (pixel_size - strike_size).abs() > f32::EPSILON {
+            Some(pixel_size / strike_size)
+        } else {
+            None
+        }
+    }
 
-        // We should render patterns to get values like `pixelsizefixupfactor`.
-        let primary_font = pattern.render_prepare(config, primary_font);
+    /// Set the face's character size in pixels.
+    ///
+    /// Faces are shared between all sizes of a `FontKey`, so this needs to be called before
+    /// anything that depends on the face's size. Variable fonts with an optical size axis are
+    /// moved to the `optical_size` in points. Returns the pixel size of the face.
+    fn set_face_pixel_size(
+        face: &FaceLoadingProperties,
+        pixel_size: f32,
+        optical_size: Option<f32>,
+    ) -> Result<f32, freetype::Error> {
+        profile_span!("set_size");
+
+        if let (Some(axis), Some(optical_size)) = (face.optical_size, optical_size) {
+            axis.set(face.ft_face()?, optical_size);
+        }
 
-        // Hash pattern together with request pattern to include requested font size in the hash.
-        let primary_font_key = FontKey::from_pattern_hashes(hash, primary_font.hash());
+        // Bitmap fonts use their strike closest to the requested size.
+        let pixelsize = match face.non_scalable {
+            Some(strike_size) if !face.colored_bitmap => {
+                nearest_strike(face.ft_face()?, pixel_size).unwrap_or(strike_size)
+            },
+            non_scalable => non_scalable.unwrap_or(pixel_size),
+        };
 
-        // Return if we already have the same primary font.
-        if self.fallback_lists.contains_key(&primary_font_key) {
-            return Ok(primary_font_key);
+        if !face.colored_bitmap {
+            face.ft_face()?.set_char_size(to_freetype_26_6(pixelsize), 0, 0, 0)?;
         }
 
-        // Load font if we haven't loaded it yet.
-        if !self.loader.faces.contains_key(&primary_font_key) {
-            self.loader
-                .face_from_pattern(&primary_font, primary_font_key)
-                .and_then(|pattern| pattern.ok_or_else(|| Error::FontNotFound(desc.to_owned())))?;
-        }
+        Ok(pixelsize)
+    }
 
-        // Coverage for fallback fonts.
-        let coverage = CharSet::new();
-        let empty_charset = CharSet::new();
+    /// Get the metrics of a face at its current size.
+    fn face_metrics(&self, face: &FaceLoadingProperties) -> Result<Metrics, Error> {
+        let full = self.full_metrics(face)?;
 
-        let list: Vec<FallbackFont> = matched_fonts
-            .map(|fallback_font| {
-                let charset = fallback_font.get_charset().unwrap_or(&empty_charset);
+        let x_scale = full.size_metrics.x_scale as f32 / 65536.0;
+        let y_scale = full.size_metrics.y_scale as f32 / 65536.0;
+        let os2 = TrueTypeOS2Table::from_face(&mut face.ft_face()?.clone());
+
+        // Resolve line metrics from the font's tables, falling back to FreeType's metrics for
+        // fonts without them.
+        let line_metrics = if face.ft_face()?.is_scalable() {
+            let os2_table = sfnt_table(face.ft_face()?, sfnt::OS2);
+            let hhea_table = sfnt_table(face.ft_face()?, sfnt::HHEA);
+            LineMetrics::from_tables(os2_table.as_deref(), hhea_table.as_deref())
+        } else {
+            None
+        };
+        let (ascent, descent, line_gap) = match line_metrics {
+            Some(line_metrics) => {
+                let LineMetrics { ascent, descent, line_gap } = line_metrics.scale(y_scale / 64.);
+                (ascent, descent, line_gap)
+            },
+            None => {
+                let ascent = from_freetype_26_6(full.size_metrics.ascender);
+                let descent = from_freetype_26_6(full.size_metrics.descender);
+                let glyph_height = from_freetype_26_6(full.size_metrics.height);
+                (ascent, descent, (glyph_height - (ascent - descent)).max(0.))
+            },
+        };
+        let height = f64::from(ascent - descent + line_gap);
 
-                // Use original pattern to preserve loading flags.
-                let fallback_font = pattern.render_prepare(config, fallback_font);
-                let fallback_font_key = FontKey::from_pattern_hashes(hash, fallback_font.hash());
+        // Get underline position and thickness in device pixels.
+        let ft_underline_position = face.ft_face()?.underline_position();
+        let mut underline_position = from_freetype_26_6(ft_underline_position as f32 * x_scale);
+        let ft_underline_thickness = face.ft_face()?.underline_thickness();
+        let mut underline_thickness = from_freetype_26_6(ft_underline_thickness as f32 * x_scale);
 
-                coverage.merge(charset);
+        // Fallback for bitmap fonts which do not provide underline metrics.
+        if underline_position == 0. {
+            underline_thickness = (descent.abs() / 5.).round();
+            underline_position = descent / 2.;
+        }
 
-                FallbackFont::new(fallback_font, fallback_font_key)
-            })
-            .collect();
+        // Get strikeout position and thickness in device pixels.
+        let (strikeout_position, strikeout_thickness) = match os2 {
+            Some(os2) => (
+                from_freetype_26_6(os2.y_strikeout_position() as f32 * x_scale),
+                from_freetype_26_6(os2.y_strikeout_size() as f32 * x_scale),
+            ),
+            _ => {
+                // Fallback if font doesn't provide info about strikeout.
+                trace!("Using fallback strikeout metrics");
+                let strikeout_position = height as f32 / 2. + descent;
+                (strikeout_position, underline_thickness)
+            },
+        };
 
-        self.fallback_lists.insert(primary_font_key, FallbackList { list, coverage });
+        let (overline_position, overline_thickness) = overline_metrics(ascent, underline_thickness);
 
-        Ok(primary_font_key)
+        Ok(Metrics {
+            average_advance: full.cell_width,
+            line_height: height,
+            descent,
+            line_gap,
+            underline_position,
+            underline_thickness,
+            strikeout_position,
+            strikeout_thickness,
+            overline_position,
+            overline_thickness,
+        })
     }
 
     fn full_metrics(&self, face_load_props: &FaceLoadingProperties) -> Result<FullMetrics, Error> {
-        let ft_face = &face_load_props.ft_face;
+        let ft_face = face_load_props.ft_face()?;
         let size_metrics = ft_face.size_metrics().ok_or(Error::MetricsNotFound)?;
 
         let width = match ft_face.load_char('0' as usize, face_load_props.load_flags) {
@@ -648,19 +3136,87 @@ impl FreeTypeRasterizer {
         Ok(FullMetrics { size_metrics, cell_width: width as f64 })
     }
 
-    fn face_for_glyph(&mut self, glyph_key: GlyphKey) -> FontKey {
-        if let Some(face) = self.loader.faces.get(&glyph_key.font_key) {
-            let index = face.ft_face.get_char_index(glyph_key.character as usize);
+    fn face_for_glyph(
+        &mut self,
+        glyph_key: GlyphKey,
+        presentation: Option<Presentation>,
+    ) -> FontKey {
+        self.add_pending_fonts();
+
+        let presentation =
+            presentation.unwrap_or_else(|| default_presentation(glyph_key.character));
+        let emoji = presentation == Presentation::Emoji;
+
+        // Prefer the emoji font for characters with emoji presentation.
+        if let Some(emoji_font) = self.emoji_font.filter(|_| emoji) {
+            if let Some(ft_face) =
+                self.loader.open_face(emoji_font).ok().and_then(|face| face.ft_face().ok())
+            {
+                if ft_face.get_char_index(glyph_key.character as usize) != MISSING_GLYPH_INDEX {
+                    return emoji_font;
+                }
+            }
+        }
+
+        // All other fonts are searched for the remapped character.
+        let glyph_key = GlyphKey { character: self.remapped(glyph_key.character), ..glyph_key };
+
+        let charmap = self.charmap;
+        let route =
+            self.block_routes.iter().rev().find(|(block, _)| block.contains(glyph_key.character));
+        if let Some(&(_, font_key)) = route {
+            if let Some(ft_face) =
+                self.loader.open_face(font_key).ok().and_then(|face| face.ft_face().ok())
+            {
+                if char_index(ft_face, glyph_key.character, charmap) != MISSING_GLYPH_INDEX {
+                    return font_key;
+                }
+            }
+        }
+
+        if let Some(ft_face) =
+            self.loader.open_face(glyph_key.font_key).ok().and_then(|face| face.ft_face().ok())
+        {
+            let index = char_index(ft_face, glyph_key.character, charmap);
 
             if index != 0 {
                 return glyph_key.font_key;
             }
         }
 
+        for index in 0..self.fallback_order.len() {
+            let font_key = self.fallback_order[index];
+            if let Some(ft_face) =
+                self.loader.open_face(font_key).ok().and_then(|face| face.ft_face().ok())
+            {
+                if char_index(ft_face, glyph_key.character, charmap) != MISSING_GLYPH_INDEX {
+                    return font_key;
+                }
+            }
+        }
+
         self.load_face_with_glyph(glyph_key).unwrap_or(glyph_key.font_key)
     }
 
+    /// Character whose glyph is used for `character`, according to the codepoint remap.
+    fn remapped(&self, character: char) -> char {
+        self.codepoint_remap.get(&character).copied().unwrap_or(character)
+    }
+
+    /// Find the first loaded face matching a font description.
+    fn find_loaded_face(&self, desc: &FontDesc) -> Option<FontKey> {
+        let mut font_keys: Vec<FontKey> = self.loader.faces.keys().copied().collect();
+        font_keys.sort_by_key(|font_key| font_key.token);
+
+        font_keys.into_iter().find(|&font_key| {
+            self.loader.face(font_key).map_or(false, |face| {
+                face.ft_face().map_or(false, |ft_face| face_matches(ft_face, desc, true))
+            })
+        })
+    }
+
     fn load_face_with_glyph(&mut self, glyph: GlyphKey) -> Result<FontKey, Error> {
+        let charmap = self.charmap;
         let fallback_list = self.fallback_lists.get(&glyph.font_key).unwrap();
 
         // Check whether glyph is presented in any fallback font.
@@ -671,26 +3227,23 @@ impl FreeTypeRasterizer {
         for fallback_font in &fallback_list.list {
             let font_key = fallback_font.key;
             let font_pattern = &fallback_font.pattern;
-            match self.loader.faces.get(&font_key) {
-                Some(face) => {
-                    let index = face.ft_face.get_char_index(glyph.character as usize);
+            if self.loader.faces.contains_key(&font_key) {
+                let face = self.loader.open_face(font_key)?;
+                let index = char_index(face.ft_face()?, glyph.character, charmap);
 
-                    // We found something in a current face, so let's use it.
-                    if index != 0 {
-                        return Ok(font_key);
-                    }
-                },
-                None => {
-                    if !font_pattern.get_charset().map_or(false, |cs| cs.has_char(glyph.character))
-                    {
-                        continue;
-                    }
+                // We found something in a current face, so let's use it.
+                if index != 0 {
+                    return Ok(font_key);
+                }
+            } else {
+                if !font_pattern.get_charset().map_or(false, |cs| cs.has_char(glyph.character)) {
+                    continue;
+                }
 
-                    let pattern = font_pattern.clone();
-                    if let Some(key) = self.loader.face_from_pattern(&pattern, font_key)? {
-                        return Ok(key);
-                    }
-                },
+                let pattern = font_pattern.clone();
+                if let Some(key) = self.loader.face_from_pattern(&pattern, font_key)? {
+                    return Ok(key);
+                }
             }
         }
 
@@ -707,6 +3260,11 @@ impl FreeTypeRasterizer {
     ) -> freetype::FtResult<(i32, i32, BitmapBuffer)> {
         use freetype::bitmap::PixelMode;
 
+        // Empty glyphs like spaces have no buffer at all.
+        if bitmap.raw().buffer.is_null() {
+            return Ok((0, 0, BitmapBuffer::Rgb(Vec::new())));
+        }
+
         let buf = bitmap.buffer();
         let mut packed = Vec::with_capacity((bitmap.rows() * bitmap.width()) as usize);
         let pitch = bitmap.pitch().unsigned_abs() as usize;
@@ -760,48 +3318,515 @@ impl FreeTypeRasterizer {
                     }
                 }
 
-                for i in 0..(bitmap.rows() as usize) {
-                    let mut columns = bitmap.width();
-                    let mut byte = 0;
-                    let offset = i * bitmap.pitch().unsigned_abs() as usize;
-                    while columns != 0 {
-                        let bits = min(8, columns);
-                        unpack_byte(&mut packed, buf[offset + byte], bits as u8);
+                for i in 0..(bitmap.rows() as usize) {
+                    let mut columns = bitmap.width();
+                    let mut byte = 0;
+                    let offset = i * bitmap.pitch().unsigned_abs() as usize;
+                    while columns != 0 {
+                        let bits = min(8, columns);
+                        unpack_byte(&mut packed, buf[offset + byte], bits as u8);
+
+                        columns -= bits;
+                        byte += 1;
+                    }
+                }
+                Ok((bitmap.rows(), bitmap.width(), BitmapBuffer::Rgb(packed)))
+            },
+            // Gray data is stored as a value between 0 and 255 using 1 byte per pixel.
+            PixelMode::Gray => {
+                for i in 0..bitmap.rows() {
+                    let start = (i as usize) * pitch;
+                    let stop = start + bitmap.width() as usize;
+                    for byte in &buf[start..stop] {
+                        packed.push(*byte);
+                        packed.push(*byte);
+                        packed.push(*byte);
+                    }
+                }
+                Ok((bitmap.rows(), bitmap.width(), BitmapBuffer::Rgb(packed)))
+            },
+            PixelMode::Bgra => {
+                let buf_size = (bitmap.rows() * bitmap.width() * 4) as usize;
+                let mut i = 0;
+                while i < buf_size {
+                    packed.push(buf[i + 2]);
+                    packed.push(buf[i + 1]);
+                    packed.push(buf[i]);
+                    packed.push(buf[i + 3]);
+                    i += 4;
+                }
+                Ok((bitmap.rows(), bitmap.width(), BitmapBuffer::Rgba(packed)))
+            },
+            mode => panic!("unhandled pixel mode: {:?}", mode),
+        }
+    }
+}
+
+/// Render mode producing signed distance fields, available since FreeType 2.11.0.
+const FT_RENDER_MODE_SDF: freetype_sys::FT_Render_Mode = 5;
+
+/// Versions of FreeType's TrueType bytecode interpreter.
+const TT_INTERPRETER_VERSION_35: freetype_sys::FT_UInt = 35;
+const TT_INTERPRETER_VERSION_38: freetype_sys::FT_UInt = 38;
+const TT_INTERPRETER_VERSION_40: freetype_sys::FT_UInt = 40;
+
+/// Current stem darkening of a library's CFF driver, `None` if it's disabled.
+fn library_stem_darkening(library: &Library) -> Option<[(u32, u32); 4]> {
+    let mut no_stem_darkening: freetype_sys::FT_Bool = 1;
+    let mut params: [freetype_sys::FT_Int; 8] = [0; 8];
+    unsafe {
+        let ft_lib = library.raw();
+        let error = FT_Property_Get(
+            ft_lib,
+            b"cff\0".as_ptr() as *const _,
+            b"no-stem-darkening\0".as_ptr() as *const _,
+            &mut no_stem_darkening as *mut freetype_sys::FT_Bool as *mut _,
+        );
+        if error != 0 || no_stem_darkening != 0 {
+            return None;
+        }
+
+        let error = FT_Property_Get(
+            ft_lib,
+            b"cff\0".as_ptr() as *const _,
+            b"darkening-parameters\0".as_ptr() as *const _,
+            params.as_mut_ptr() as *mut _,
+        );
+        if error != 0 {
+            return Some(DEFAULT_DARKENING_PARAMETERS);
+        }
+    }
+
+    let mut stem_darkening = [(0, 0); 4];
+    for (point, xy) in stem_darkening.iter_mut().zip(params.chunks(2)) {
+        *point = (xy[0] as u32, xy[1] as u32);
+    }
+    Some(stem_darkening)
+}
+
+/// Current version of a library's TrueType bytecode interpreter.
+fn library_tt_interpreter(library: &Library) -> Result<TtInterpreter, Error> {
+    let mut version: freetype_sys::FT_UInt = 0;
+    let error = unsafe {
+        FT_Property_Get(
+            library.raw(),
+            b"truetype\0".as_ptr() as *const _,
+            b"interpreter-version\0".as_ptr() as *const _,
+            &mut version as *mut freetype_sys::FT_UInt as *mut _,
+        )
+    };
+    if error != 0 {
+        return Err(freetype::Error::from(error).into());
+    }
+
+    match version {
+        TT_INTERPRETER_VERSION_35 => Ok(TtInterpreter::Classic35),
+        TT_INTERPRETER_VERSION_38 => Ok(TtInterpreter::Minimal38),
+        TT_INTERPRETER_VERSION_40 => Ok(TtInterpreter::Subpixel40),
+        _ => Err(Error::PlatformError(format!("unknown interpreter version {}", version))),
+    }
+}
+
+/// Default `darkening-parameters` of FreeType's CFF driver.
+const DEFAULT_DARKENING_PARAMETERS: [(u32, u32); 4] =
+    [(500, 400), (1000, 275), (1667, 275), (2333, 0)];
+
+/// Spread range supported by FreeType's SDF renderers.
+const SDF_MIN_SPREAD: f32 = 2.;
+const SDF_MAX_SPREAD: f32 = 32.;
+
+extern "C" {
+    fn FT_Face_GetCharVariantIndex(
+        face: freetype_sys::FT_Face,
+        charcode: freetype_sys::FT_ULong,
+        variant_selector: freetype_sys::FT_ULong,
+    ) -> freetype_sys::FT_UInt;
+
+    fn FT_Get_Advance(
+        face: freetype_sys::FT_Face,
+        glyph_index: freetype_sys::FT_UInt,
+        load_flags: freetype_sys::FT_Int32,
+        advance: *mut freetype_sys::FT_Fixed,
+    ) -> freetype_sys::FT_Error;
+
+    fn FT_Load_Sfnt_Table(
+        face: freetype_sys::FT_Face,
+        tag: freetype_sys::FT_ULong,
+        offset: freetype_sys::FT_Long,
+        buffer: *mut freetype_sys::FT_Byte,
+        length: *mut freetype_sys::FT_ULong,
+    ) -> freetype_sys::FT_Error;
+
+    fn FT_Property_Set(
+        library: freetype_sys::FT_Library,
+        module_name: *const freetype_sys::FT_String,
+        property_name: *const freetype_sys::FT_String,
+        value: *const libc::c_void,
+    ) -> freetype_sys::FT_Error;
+
+    fn FT_Property_Get(
+        library: freetype_sys::FT_Library,
+        module_name: *const freetype_sys::FT_String,
+        property_name: *const freetype_sys::FT_String,
+        value: *mut libc::c_void,
+    ) -> freetype_sys::FT_Error;
+
+    fn FT_Get_Var_Design_Coordinates(
+        face: freetype_sys::FT_Face,
+        num_coords: freetype_sys::FT_UInt,
+        coords: *mut freetype_sys::FT_Fixed,
+    ) -> freetype_sys::FT_Error;
+
+    fn FT_Set_Var_Design_Coordinates(
+        face: freetype_sys::FT_Face,
+        num_coords: freetype_sys::FT_UInt,
+        coords: *mut freetype_sys::FT_Fixed,
+    ) -> freetype_sys::FT_Error;
+
+    fn FT_Render_Glyph(
+        slot: freetype_sys::FT_GlyphSlot,
+        render_mode: freetype_sys::FT_Render_Mode,
+    ) -> freetype_sys::FT_Error;
+
+    fn FT_Get_Color_Glyph_Layer(
+        face: freetype_sys::FT_Face,
+        base_glyph: freetype_sys::FT_UInt,
+        aglyph_index: *mut freetype_sys::FT_UInt,
+        acolor_index: *mut freetype_sys::FT_UInt,
+        iterator: *mut LayerIterator,
+    ) -> freetype_sys::FT_Bool;
+}
+
+/// FreeType's `FT_LayerIterator`, which isn't exposed by `freetype-sys`.
+#[repr(C)]
+struct LayerIterator {
+    num_layers: freetype_sys::FT_UInt,
+    layer: freetype_sys::FT_UInt,
+    p: *mut freetype_sys::FT_Byte,
+}
+
+/// Check if a FreeType error was caused by failing to read the font file.
+fn is_stream_error(err: freetype::Error) -> bool {
+    use freetype::Error as FtError;
+
+    matches!(
+        err,
+        FtError::CannotOpenStream
+            | FtError::InvalidStreamSeek
+            | FtError::InvalidStreamSkip
+            | FtError::InvalidStreamRead
+            | FtError::InvalidStreamOperation
+            | FtError::InvalidFileFormat
+            | FtError::InvalidTable
+    )
+}
+
+/// Error for a face which couldn't be recovered after its file became unreadable.
+fn reopen_error(source: &FaceSource, err: Error) -> Error {
+    match source {
+        FaceSource::File(location) => Error::PlatformError(format!(
+            "font file {:?} became unreadable and could not be reopened: {}",
+            location.path, err
+        )),
+        FaceSource::Memory { .. } => {
+            Error::PlatformError(format!("font data could not be reopened: {}", err))
+        },
+    }
+}
+
+/// Check if a font file uses a WOFF or WOFF2 container.
+#[cfg(feature = "woff")]
+fn is_woff_file(path: &Path) -> bool {
+    let mut magic = [0; 4];
+    File::open(path).and_then(|mut file| file.read_exact(&mut magic)).is_ok()
+        && matches!(sfnt::font_format(&magic), FontFormat::Woff | FontFormat::Woff2)
+}
+
+/// Get the design coordinates currently applied to a variable font.
+///
+/// Returns no coordinates for fonts without variation axes.
+fn design_coordinates(ft_face: &FtFace) -> Vec<freetype_sys::FT_Fixed> {
+    let axis_count = match sfnt_table(ft_face, sfnt::FVAR) {
+        Some(fvar) => sfnt::variation_axes(&fvar).len(),
+        None => return Vec::new(),
+    };
+
+    let face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+    let mut coords: Vec<freetype_sys::FT_Fixed> = vec![0; axis_count];
+    let count = axis_count as freetype_sys::FT_UInt;
+    if unsafe { FT_Get_Var_Design_Coordinates(face, count, coords.as_mut_ptr()) } != 0 {
+        coords.clear();
+    }
+
+    coords
+}
+
+/// Load the raw data of an SFNT table.
+fn sfnt_table(ft_face: &FtFace, tag: u32) -> Option<Vec<u8>> {
+    let face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+    let tag = tag as freetype_sys::FT_ULong;
+
+    unsafe {
+        let mut length = 0;
+        if FT_Load_Sfnt_Table(face, tag, 0, ptr::null_mut(), &mut length) != 0 {
+            return None;
+        }
+
+        let mut table = vec![0; length as usize];
+        if FT_Load_Sfnt_Table(face, tag, 0, table.as_mut_ptr(), &mut length) != 0 {
+            return None;
+        }
+
+        Some(table)
+    }
+}
+
+/// Check if a face belongs to the family of a font description.
+///
+/// With `exact_style`, the face also needs to have the described style instead of just being the
+/// closest match Fontconfig found within the family.
+fn face_matches(ft_face: &FtFace, desc: &FontDesc, exact_style: bool) -> bool {
+    use freetype::face::StyleFlag;
+
+    let family = ft_face.family_name().unwrap_or_default();
+    if !family.eq_ignore_ascii_case(desc.name()) {
+        return false;
+    }
+
+    match desc.style() {
+        _ if !exact_style => true,
+        Style::Specific(style) => {
+            ft_face.style_name().map_or(false, |name| name.eq_ignore_ascii_case(style))
+        },
+        Style::Description { slant, weight } => {
+            let flags = ft_face.style_flags();
+            flags.contains(StyleFlag::BOLD) == (*weight == Weight::Bold)
+                && flags.contains(StyleFlag::ITALIC) == (*slant != Slant::Normal)
+        },
+    }
+}
+
+/// Get the glyph index of a character with the preferred character map.
+fn char_index(ft_face: &FtFace, character: char, pref: CharmapPref) -> u32 {
+    match pref {
+        CharmapPref::Unicode => ft_face.get_char_index(character as usize),
+        CharmapPref::Symbol => symbol_char_index(ft_face, character),
+        CharmapPref::Auto => match ft_face.get_char_index(character as usize) {
+            MISSING_GLYPH_INDEX => symbol_char_index(ft_face, character),
+            index => index,
+        },
+    }
+}
+
+/// Get the glyph index of a character in the face's symbol character map.
+///
+/// Characters are looked up both as 8-bit codes and in the `U+F000` block.
+fn symbol_char_index(ft_face: &FtFace, character: char) -> u32 {
+    let face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+
+    unsafe {
+        let charmaps = slice::from_raw_parts((*face).charmaps, (*face).num_charmaps as usize);
+        let symbol = match charmaps
+            .iter()
+            .find(|charmap| (***charmap).encoding == freetype_sys::FT_ENCODING_MS_SYMBOL)
+        {
+            Some(&symbol) => symbol,
+            None => return MISSING_GLYPH_INDEX,
+        };
 
-                        columns -= bits;
-                        byte += 1;
-                    }
-                }
-                Ok((bitmap.rows(), bitmap.width(), BitmapBuffer::Rgb(packed)))
-            },
-            // Gray data is stored as a value between 0 and 255 using 1 byte per pixel.
-            PixelMode::Gray => {
-                for i in 0..bitmap.rows() {
-                    let start = (i as usize) * pitch;
-                    let stop = start + bitmap.width() as usize;
-                    for byte in &buf[start..stop] {
-                        packed.push(*byte);
-                        packed.push(*byte);
-                        packed.push(*byte);
-                    }
-                }
-                Ok((bitmap.rows(), bitmap.width(), BitmapBuffer::Rgb(packed)))
-            },
-            PixelMode::Bgra => {
-                let buf_size = (bitmap.rows() * bitmap.width() * 4) as usize;
-                let mut i = 0;
-                while i < buf_size {
-                    packed.push(buf[i + 2]);
-                    packed.push(buf[i + 1]);
-                    packed.push(buf[i]);
-                    packed.push(buf[i + 3]);
-                    i += 4;
-                }
-                Ok((bitmap.rows(), bitmap.width(), BitmapBuffer::Rgba(packed)))
-            },
-            mode => panic!("unhandled pixel mode: {:?}", mode),
+        // The active character map is shared by all users of the face, so restore it afterwards.
+        let active = (*face).charmap;
+        if freetype_sys::FT_Set_Charmap(face, symbol) != 0 {
+            return MISSING_GLYPH_INDEX;
+        }
+
+        let code = character as u32;
+        let alternate = match code {
+            0..=0xff => Some(code + 0xf000),
+            0xf000..=0xf0ff => Some(code - 0xf000),
+            _ => None,
+        };
+
+        let mut index = freetype_sys::FT_Get_Char_Index(face, code as freetype_sys::FT_ULong);
+        if let (MISSING_GLYPH_INDEX, Some(alternate)) = (index, alternate) {
+            index = freetype_sys::FT_Get_Char_Index(face, alternate as freetype_sys::FT_ULong);
+        }
+
+        (*face).charmap = active;
+
+        index
+    }
+}
+
+/// Whether shaping Latin text could substitute or position any glyphs.
+fn has_latin_layout(ft_face: &FtFace) -> bool {
+    [sfnt::KERN, sfnt::KERX, sfnt::MORX].iter().any(|&tag| has_sfnt_table(ft_face, tag))
+        || [sfnt::GSUB, sfnt::GPOS]
+            .iter()
+            .filter_map(|&tag| sfnt_table(ft_face, tag))
+            .any(|table| sfnt::has_latin_lookups(&table))
+}
+
+/// Check if a face has a non-empty SFNT table, without loading it.
+fn has_sfnt_table(ft_face: &FtFace, tag: u32) -> bool {
+    let face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+    let mut length = 0;
+    unsafe {
+        FT_Load_Sfnt_Table(face, tag as freetype_sys::FT_ULong, 0, ptr::null_mut(), &mut length)
+            == 0
+            && length > 0
+    }
+}
+
+/// Get the glyph index of a Unicode variation sequence.
+///
+/// Returns the missing glyph index if the face has no glyph for the sequence.
+fn char_variant_index(ft_face: &FtFace, character: char, selector: char) -> u32 {
+    unsafe {
+        FT_Face_GetCharVariantIndex(
+            ft_face.raw() as *const _ as freetype_sys::FT_Face,
+            character as freetype_sys::FT_ULong,
+            selector as freetype_sys::FT_ULong,
+        )
+    }
+}
+
+/// HarfBuzz features for shaping with or without standard ligatures.
+///
+/// All other features match the `DEFAULT_FEATURES`.
+fn ligature_features(enabled: bool) -> Vec<Feature> {
+    let value = u32::from(enabled);
+    let ligatures =
+        [(Tag::new(b"liga"), value), (Tag::new(b"clig"), value), (Tag::new(b"calt"), value)];
+    hb_features(&shaping_features(&ligatures))
+}
+
+/// Convert OpenType features to HarfBuzz features applied to the entire text.
+fn hb_features(features: &[(Tag, u32)]) -> Vec<Feature> {
+    features.iter().map(|(tag, value)| Feature::new(&tag.0, *value, ..)).collect()
+}
+
+/// Map the glyph indices of a face to the characters mapped to them.
+fn reverse_char_map(ft_face: &FtFace) -> HashMap<u32, Vec<char>> {
+    let raw_face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+    let mut reverse_cmap: HashMap<u32, Vec<char>> = HashMap::new();
+
+    unsafe {
+        let mut index = 0;
+        let mut charcode = freetype_sys::FT_Get_First_Char(raw_face, &mut index);
+        while index != 0 {
+            if let Some(character) = char::from_u32(charcode as u32) {
+                reverse_cmap.entry(index).or_default().push(character);
+            }
+            charcode = freetype_sys::FT_Get_Next_Char(raw_face, charcode, &mut index);
+        }
+    }
+
+    reverse_cmap
+}
+
+/// Characters in the Private Use Areas mapped by a face, in ascending order.
+fn private_use_characters(ft_face: &FtFace) -> Vec<char> {
+    let raw_face = ft_face.raw() as *const _ as freetype_sys::FT_Face;
+    let mut characters = Vec::new();
+
+    for &(start, end) in &PRIVATE_USE_AREAS {
+        unsafe {
+            let mut index = 0;
+            let mut charcode = freetype_sys::FT_Get_Next_Char(raw_face, start - 1, &mut index);
+            while index != 0 && charcode <= end {
+                characters.extend(char::from_u32(charcode as u32));
+                charcode = freetype_sys::FT_Get_Next_Char(raw_face, charcode, &mut index);
+            }
+        }
+    }
+
+    characters
+}
+
+/// Check if a glyph is drawn from layers of the `COLR` table.
+fn has_color_layers(ft_face: &FtFace, index: u32) -> bool {
+    let mut iterator = LayerIterator { num_layers: 0, layer: 0, p: ptr::null_mut() };
+    let (mut layer_index, mut color_index) = (0, 0);
+    unsafe {
+        FT_Get_Color_Glyph_Layer(
+            ft_face.raw() as *const _ as freetype_sys::FT_Face,
+            index,
+            &mut layer_index,
+            &mut color_index,
+            &mut iterator,
+        ) != 0
+    }
+}
+
+/// Pixel size of the bitmap strike closest to `pixel_size`.
+fn nearest_strike(ft_face: &FtFace, pixel_size: f32) -> Option<f32> {
+    let ft_face_raw = ft_face.raw();
+    let num_sizes = ft_face_raw.num_fixed_sizes.max(0) as usize;
+    if num_sizes == 0 || ft_face_raw.available_sizes.is_null() {
+        return None;
+    }
+
+    let sizes = unsafe { slice::from_raw_parts(ft_face_raw.available_sizes, num_sizes) };
+    sizes.iter().map(|size| from_freetype_26_6(size.y_ppem)).min_by(|a, b| {
+        let (a, b) = ((a - pixel_size).abs(), (b - pixel_size).abs());
+        a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+    })
+}
+
+/// Scale all metrics by `factor`.
+fn scale_metrics(metrics: &Metrics, factor: f32) -> Metrics {
+    Metrics {
+        average_advance: metrics.average_advance * f64::from(factor),
+        line_height: metrics.line_height * f64::from(factor),
+        descent: metrics.descent * factor,
+        line_gap: metrics.line_gap * factor,
+        underline_position: metrics.underline_position * factor,
+        underline_thickness: metrics.underline_thickness * factor,
+        strikeout_position: metrics.strikeout_position * factor,
+        strikeout_thickness: metrics.strikeout_thickness * factor,
+        overline_position: metrics.overline_position * factor,
+        overline_thickness: metrics.overline_thickness * factor,
+    }
+}
+
+/// Scale an alphamask by `factor` with nearest-neighbor sampling.
+///
+/// This keeps the pixels of bitmap fonts sharp, instead of blurring them like filtering would.
+/// The bearings and advance are scaled along with the bitmap, the rounded advance is left to the
+/// caller.
+fn scale_bitmap(mut glyph: RasterizedGlyph, factor: f32) -> RasterizedGlyph {
+    let pixels = match &glyph.buffer {
+        BitmapBuffer::Rgb(pixels) => pixels,
+        _ => return glyph,
+    };
+
+    let factor = f64::from(factor);
+    let (width, height) = (glyph.width as usize, glyph.height as usize);
+    let target_width = (width as f64 * factor).round() as usize;
+    let target_height = (height as f64 * factor).round() as usize;
+
+    let mut scaled = Vec::with_capacity(target_width * target_height * 3);
+    for y in 0..target_height {
+        let source_y = (((y as f64 + 0.5) / factor) as usize).min(height - 1);
+        for x in 0..target_width {
+            let source_x = (((x as f64 + 0.5) / factor) as usize).min(width - 1);
+            let offset = (source_y * width + source_x) * 3;
+            scaled.extend_from_slice(&pixels[offset..offset + 3]);
         }
     }
+
+    glyph.buffer = BitmapBuffer::Rgb(scaled);
+    glyph.width = target_width as i32;
+    glyph.height = target_height as i32;
+    glyph.top = (f64::from(glyph.top) * factor).round() as i32;
+    glyph.left = (f64::from(glyph.left) * factor).round() as i32;
+    glyph.top_f = (f64::from(glyph.top_f) * factor) as f32;
+    glyph.left_f = (f64::from(glyph.left_f) * factor) as f32;
+    glyph.advance_f.0 = (f64::from(glyph.advance_f.0) * factor) as f32;
+    glyph.advance_f.1 = (f64::from(glyph.advance_f.1) * factor) as f32;
+
+    glyph
 }
 
 /// Downscale a bitmap by a fixed factor.
@@ -866,9 +3891,12 @@ fn downsample_bitmap(mut bitmap_glyph: RasterizedGlyph, fixup_factor: f64) -> Ra
 
     bitmap_glyph.buffer = BitmapBuffer::Rgba(downsampled_buffer);
 
-    // Downscale the metrics.
-    bitmap_glyph.top = (f64::from(bitmap_glyph.top) * fixup_factor) as i32;
-    bitmap_glyph.left = (f64::from(bitmap_glyph.left) * fixup_factor) as i32;
+    // Downscale the metrics, rounding the origin outward so negative bearings aren't truncated
+    // toward zero.
+    bitmap_glyph.top = (f64::from(bitmap_glyph.top) * fixup_factor).ceil() as i32;
+    bitmap_glyph.left = (f64::from(bitmap_glyph.left) * fixup_factor).floor() as i32;
+    bitmap_glyph.top_f = (f64::from(bitmap_glyph.top_f) * fixup_factor) as f32;
+    bitmap_glyph.left_f = (f64::from(bitmap_glyph.left_f) * fixup_factor) as f32;
     bitmap_glyph.width = target_width as i32;
     bitmap_glyph.height = target_height as i32;
 
@@ -881,10 +3909,841 @@ impl From<freetype::Error> for Error {
     }
 }
 
-unsafe impl Send for FreeTypeRasterizer {}
-
 impl From<std::io::Error> for Error {
     fn from(val: std::io::Error) -> Error {
         Error::PlatformError(val.to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::REPLACEMENT_CHARACTER;
+
+    /// Rasterizer with the regular style of a font family loaded at `size`.
+    fn load_test_font(family: &str, size: Size) -> (FreeTypeRasterizer, FontKey) {
+        let mut rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let font_key = rasterizer.load_font(&FontDesc::new(family, style), size).unwrap();
+        (rasterizer, font_key)
+    }
+
+    #[test]
+    fn synthetic_bold_is_not_clipped() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'M', font_key, size };
+
+        let regular = rasterizer.get_glyph(glyph_key).unwrap();
+        rasterizer.loader.faces.get_mut(&font_key).unwrap().embolden = true;
+        let bold = rasterizer.get_glyph(glyph_key).unwrap();
+
+        // Both glyphs are moved to whole pixels, so the wider outline might fit the same width.
+        let ink = |glyph: &RasterizedGlyph| match &glyph.buffer {
+            BitmapBuffer::Rgb(buffer) | BitmapBuffer::Rgba(buffer) => {
+                buffer.iter().map(|&value| u32::from(value)).sum::<u32>()
+            },
+            _ => 0,
+        };
+        assert!(ink(&bold) > ink(&regular));
+        assert!(bold.width >= regular.width);
+        assert!(bold.height >= regular.height);
+        assert!(bold.left <= regular.left);
+        assert!(bold.left + bold.width >= regular.left + regular.width);
+        assert!(bold.advance.0 as f32 >= bold.advance_f.0);
+    }
+
+    #[test]
+    fn negative_bearing_is_floored() {
+        let mut rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let style = Style::Description { slant: Slant::Italic, weight: Weight::Normal };
+        let size = Size::new(16.);
+        let font_key = rasterizer.load_font(&FontDesc::new("sans", style), size).unwrap();
+
+        rasterizer.set_rounding(RoundingMode::Floor).unwrap();
+        let glyph = rasterizer.get_glyph(GlyphKey { character: 'j', font_key, size }).unwrap();
+
+        assert!(glyph.left_f < 0.);
+        assert_eq!(glyph.left, glyph.left_f.floor() as i32);
+    }
+
+    #[test]
+    fn ascii_atlas_matches_glyphs() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+
+        let atlas = rasterizer.ascii_atlas(font_key, size).unwrap();
+        assert_eq!(atlas.glyphs.len(), 95);
+
+        let glyph = rasterizer.get_glyph(GlyphKey { character: 'g', font_key, size }).unwrap();
+        let (atlas_glyph, pixels) = atlas.get('g').unwrap();
+        assert_eq!((atlas_glyph.width, atlas_glyph.top), (glyph.width, glyph.top));
+        assert!(matches!(&glyph.buffer, BitmapBuffer::Rgb(buffer) if buffer == pixels));
+
+        // Fallback glyphs can close the face in the middle of the atlas.
+        rasterizer.set_codepoint_remap([('a', '∰')].iter().copied().collect()).unwrap();
+        rasterizer.set_max_open_faces(1).unwrap();
+        let atlas = rasterizer.ascii_atlas(font_key, size).unwrap();
+        assert_ne!(atlas.glyphs[usize::from(b'a' - b' ')].glyph.font_key, font_key);
+        let (atlas_glyph, pixels) = atlas.get('g').unwrap();
+        assert_eq!((atlas_glyph.width, atlas_glyph.top), (glyph.width, glyph.top));
+        assert!(matches!(glyph.buffer, BitmapBuffer::Rgb(buffer) if buffer == pixels));
+    }
+
+    #[test]
+    fn closed_faces_are_reopened() {
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let size = Size::new(12.);
+        let (mut rasterizer, mono) = load_test_font("monospace", size);
+        let mono_key = GlyphKey { character: 'a', font_key: mono, size };
+        let mono_glyph = rasterizer.get_glyph(mono_key).unwrap();
+
+        rasterizer.set_max_open_faces(1).unwrap();
+        let serif = rasterizer.load_font(&FontDesc::new("serif", style), size).unwrap();
+        assert_eq!(rasterizer.loader.ft_faces.borrow().len(), 1);
+        assert!(rasterizer.loader.faces[&mono].ft_face.is_none());
+
+        // Closed faces can still be used without mutable access, reusing the reopened file.
+        assert!(rasterizer.metrics(mono, size).is_ok());
+        assert!(rasterizer.has_glyph(mono, 'a'));
+        assert!(rasterizer.loader.faces[&mono].ft_face.is_none());
+        assert_eq!(rasterizer.loader.ft_faces.borrow().len(), 2);
+
+        let reopened_glyph = rasterizer.get_glyph(mono_key).unwrap();
+        assert_eq!(reopened_glyph.width, mono_glyph.width);
+        assert_eq!(reopened_glyph.advance, mono_glyph.advance);
+        assert!(rasterizer.loader.faces[&serif].ft_face.is_none());
+        assert_eq!(rasterizer.loader.ft_faces.borrow().len(), 1);
+    }
+
+    #[test]
+    fn discretionary_ligatures_are_opt_in() {
+        let (mut rasterizer, font_key) = load_test_font("sans", Size::new(12.));
+
+        // DejaVu Sans only has a discretionary ligature for `st`.
+        assert_eq!(rasterizer.shape("st", font_key).len(), 2);
+        let dlig = [(Tag::new(b"dlig"), 1)];
+        assert_eq!(rasterizer.shape_with_features("st", font_key, &dlig).len(), 1);
+    }
+
+    #[test]
+    fn outside_stroke_surrounds_glyph() {
+        let size = Size::new(24.);
+        let (mut rasterizer, font_key) = load_test_font("sans", size);
+        let glyph_key = GlyphKey { character: 'o', font_key, size };
+
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        let outside = rasterizer.get_glyph_stroked(glyph_key, 2., StrokeStyle::Outside).unwrap();
+        let inside = rasterizer.get_glyph_stroked(glyph_key, 2., StrokeStyle::Inside).unwrap();
+
+        assert_eq!(outside.advance, glyph.advance);
+        assert!(outside.width >= glyph.width + 2 && outside.left < glyph.left);
+        assert!(inside.width <= glyph.width && inside.left >= glyph.left);
+    }
+
+    #[test]
+    fn fallback_glyphs_fit_cell() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let cell_width = rasterizer.metrics(font_key, size).unwrap().average_advance as f32;
+
+        // DejaVu Sans Mono has no volume integral, so it is rendered with a wider fallback font.
+        let glyph_key = GlyphKey { character: '∰', font_key, size };
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        assert!(glyph.font_key != font_key && glyph.advance_f.0 > cell_width);
+
+        rasterizer.set_fallback_scaling(FallbackScaling::FitWidth).unwrap();
+        let scaled = rasterizer.get_glyph(glyph_key).unwrap();
+        assert!(scaled.advance_f.0 <= cell_width + 0.5);
+        assert!(scaled.height < glyph.height);
+    }
+
+    #[test]
+    fn fallback_order_before_system_fallback() {
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: '∰', font_key, size };
+
+        let aliased =
+            rasterizer.set_fallback_order(vec![FontDesc::new("monospace", style.clone())]);
+        assert!(matches!(aliased, Err(Error::FontNotFound(_))));
+
+        let math = FontDesc::new("DejaVu Math TeX Gyre", style);
+        rasterizer.set_fallback_order(vec![math]).unwrap();
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        let family = rasterizer.describe_key(glyph.font_key).unwrap();
+        assert!(family.starts_with("DejaVu Math TeX Gyre"));
+    }
+
+    #[test]
+    fn rotated_glyph() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'l', font_key, size };
+
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        let rotated = rasterizer.get_glyph_transformed(glyph_key, [0., -1., 1., 0.]).unwrap();
+
+        assert!(glyph.height > glyph.width && rotated.width > rotated.height);
+        assert_eq!(rotated.advance, (0, glyph.advance.0));
+        assert!(rotated.left < 0);
+    }
+
+    #[test]
+    fn tab_width_of_spaces() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+
+        let metrics = rasterizer.metrics(font_key, size).unwrap();
+        let tab_width = rasterizer.tab_width(font_key, size, 4).unwrap();
+        assert!((tab_width - metrics.tab_width(4)).abs() < 0.01);
+
+        rasterizer.set_tracking(font_key, 1.).unwrap();
+        let tracked = rasterizer.tab_width(font_key, size, 4).unwrap();
+        assert!((tracked - tab_width - 4.).abs() < 0.01);
+    }
+
+    #[test]
+    fn probe_font_files() {
+        let size = Size::new(12.);
+        let (rasterizer, font_key) = load_test_font("monospace", size);
+        let path = match &rasterizer.loader.faces[&font_key].source {
+            FaceSource::File(location) => location.path.clone(),
+            FaceSource::Memory { .. } => unreachable!(),
+        };
+
+        let probe = probe_font_file(&path).unwrap();
+        assert_eq!(probe.family, "DejaVu Sans Mono");
+        assert!(probe.is_monospace && !probe.is_color);
+        assert_eq!((probe.num_faces, probe.format), (1, FontFormat::TrueType));
+        assert_eq!(probe.glyph_count, rasterizer.glyph_count(font_key).unwrap());
+
+        assert!(matches!(probe_font(b"not a font"), Err(Error::PlatformError(_))));
+    }
+
+    #[test]
+    fn library_shared_per_thread() {
+        let first = FreeTypeRasterizer::new(1.).unwrap();
+        let second = FreeTypeRasterizer::new(1.).unwrap();
+        assert!(Rc::ptr_eq(&first.loader.library, &second.loader.library));
+
+        let library = Rc::new(Library::init().unwrap());
+        let explicit = FreeTypeRasterizer::with_library(library.clone(), 1.);
+        assert!(Rc::ptr_eq(&explicit.loader.library, &library));
+
+        let other_thread = std::thread::spawn(|| {
+            let rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+            rasterizer.loader.library.raw() as usize
+        });
+        assert_ne!(other_thread.join().unwrap(), first.loader.library.raw() as usize);
+    }
+
+    #[test]
+    fn rasterizer_is_not_send() {
+        // Calling `is_send` is ambiguous and fails to compile if the rasterizer is `Send`.
+        trait AmbiguousIfSend<A> {
+            fn is_send() {}
+        }
+        impl<T: ?Sized> AmbiguousIfSend<()> for T {}
+        impl<T: ?Sized + Send> AmbiguousIfSend<u8> for T {}
+
+        <FreeTypeRasterizer as AmbiguousIfSend<_>>::is_send();
+    }
+
+    #[test]
+    fn charmap_preference() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+
+        // DejaVu Sans Mono has no symbol character map.
+        rasterizer.set_charmap_preference(CharmapPref::Symbol).unwrap();
+        assert!(!rasterizer.has_glyph(font_key, 'a'));
+
+        rasterizer.set_charmap_preference(CharmapPref::Auto).unwrap();
+        assert!(rasterizer.has_glyph(font_key, 'a'));
+        assert!(!rasterizer.has_glyph(font_key, '\u{f041}'));
+    }
+
+    #[test]
+    fn codepoint_remap() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_b = rasterizer.get_glyph(GlyphKey { character: 'b', font_key, size }).unwrap();
+
+        let icon = '\u{f0001}';
+        assert!(!rasterizer.has_glyph(font_key, icon));
+        assert!(!rasterizer.private_use_characters(font_key).unwrap().contains(&icon));
+
+        rasterizer.set_codepoint_remap([(icon, 'b')].iter().copied().collect()).unwrap();
+        assert!(rasterizer.has_glyph(font_key, icon));
+
+        let glyph = rasterizer.get_glyph(GlyphKey { character: icon, font_key, size }).unwrap();
+        assert_eq!(glyph.character, icon);
+        assert_eq!(glyph.font_key, font_key);
+        assert_eq!((glyph.width, glyph.height), (glyph_b.width, glyph_b.height));
+    }
+
+    #[test]
+    fn glyphs_for_multiple_dprs() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'a', font_key, size };
+
+        let scaled = rasterizer.get_glyph_dpr(glyph_key, 2.).unwrap();
+        let scaled_metrics = rasterizer.metrics_dpr(font_key, size, 2.).unwrap();
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        assert_eq!(glyph.dpr, 1.);
+        assert_eq!(scaled.dpr, 2.);
+
+        rasterizer.update_dpr(2.);
+        let expected = rasterizer.get_glyph(glyph_key).unwrap();
+        assert_eq!((scaled.width, scaled.height), (expected.width, expected.height));
+        assert_eq!(scaled.top, expected.top);
+        assert!(scaled.width > glyph.width);
+
+        let metrics = rasterizer.metrics(font_key, size).unwrap();
+        assert_eq!(scaled_metrics.line_height, metrics.line_height);
+    }
+
+    #[test]
+    fn styles_of_family() {
+        let rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+
+        let styles = rasterizer.family_styles("DejaVu Sans Mono").unwrap();
+        assert!(styles.contains(&(Weight::Normal, Slant::Normal, Stretch::Normal)));
+        assert!(styles.contains(&(Weight::Bold, Slant::Normal, Stretch::Normal)));
+        assert!(styles.windows(2).all(|pair| pair[0] < pair[1]));
+
+        assert!(rasterizer.family_styles("Missing Family For Tests").is_err());
+    }
+
+    #[test]
+    fn f32_coverage() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'a', font_key, size };
+
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        rasterizer.set_alpha_precision(AlphaPrecision::F32).unwrap();
+        let precise = rasterizer.get_glyph(glyph_key).unwrap();
+
+        let (pixels, coverage) = match (glyph.buffer, precise.buffer) {
+            (BitmapBuffer::Rgb(pixels), BitmapBuffer::GrayF32(coverage)) => (pixels, coverage),
+            _ => panic!("expected RGB and f32 coverage"),
+        };
+        assert_eq!(coverage.len() * 3, pixels.len());
+        assert!(coverage.iter().all(|alpha| (0. ..=1.).contains(alpha)));
+        assert!(coverage.iter().any(|&alpha| alpha == 1.));
+    }
+
+    #[test]
+    fn routed_block() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let bold = Style::Description { slant: Slant::Normal, weight: Weight::Bold };
+        let bold_key = rasterizer.load_font(&FontDesc::new("monospace", bold), size).unwrap();
+
+        let block = UnicodeBlock::new('a', 'z');
+        rasterizer.route_block(block, bold_key).unwrap();
+
+        let routed = rasterizer.get_glyph(GlyphKey { character: 'a', font_key, size }).unwrap();
+        assert_eq!(routed.font_key, bold_key);
+        let glyph = rasterizer.get_glyph(GlyphKey { character: 'A', font_key, size }).unwrap();
+        assert_eq!(glyph.font_key, font_key);
+
+        rasterizer.route_block(block, font_key).unwrap();
+        let glyph = rasterizer.get_glyph(GlyphKey { character: 'a', font_key, size }).unwrap();
+        assert_eq!(glyph.font_key, font_key);
+    }
+
+    #[test]
+    fn advances_of_characters() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+
+        let advances = rasterizer.advances(font_key, &['a', 'W', '\u{10ffff}'], size).unwrap();
+        let shaped = rasterizer.string_advance("a", font_key, size).unwrap();
+        assert!((advances[0] - shaped).abs() < 0.01);
+        assert_eq!(advances[0], advances[1]);
+        assert_eq!(advances[2], 0.);
+    }
+
+    #[test]
+    fn nearest_neighbor_bitmap_scaling() {
+        let glyph = RasterizedGlyph {
+            width: 2,
+            height: 1,
+            top: 1,
+            left: 1,
+            top_f: 1.,
+            left_f: 1.,
+            advance_f: (2., 0.),
+            buffer: BitmapBuffer::Rgb(vec![255, 255, 255, 0, 0, 0]),
+            ..RasterizedGlyph::default()
+        };
+
+        let scaled = scale_bitmap(glyph, 2.);
+        assert_eq!((scaled.width, scaled.height, scaled.top, scaled.left), (4, 2, 2, 2));
+        assert_eq!(scaled.advance_f, (4., 0.));
+        let pixels = match scaled.buffer {
+            BitmapBuffer::Rgb(pixels) => pixels,
+            _ => panic!("expected RGB pixels"),
+        };
+        let row = [255, 255, 255, 255, 255, 255, 0, 0, 0, 0, 0, 0];
+        assert_eq!(pixels, [row, row].concat());
+    }
+
+    #[test]
+    fn replacement_character() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+
+        let glyph = rasterizer.replacement_glyph(font_key, size).unwrap();
+        assert_eq!(glyph.character, REPLACEMENT_CHARACTER);
+        assert_eq!(glyph.font_key, font_key);
+        assert!(glyph.width > 0 && glyph.height > 0);
+    }
+
+    #[test]
+    fn glyph_mip_levels() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'M', font_key, size };
+
+        let mips = rasterizer.get_glyph_mips(glyph_key, &[2., 1.]).unwrap();
+        let double = rasterizer.get_glyph(GlyphKey { size: Size::new(24.), ..glyph_key }).unwrap();
+        assert_eq!(mips.len(), 2);
+        assert_eq!((mips[0].width, mips[0].height), (double.width, double.height));
+        assert!(mips[1].height < mips[0].height);
+    }
+
+    #[test]
+    fn strict_empty_glyphs() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        rasterizer.set_strict(true).unwrap();
+
+        let glyph_key = |character| GlyphKey { character, font_key, size };
+        assert!(rasterizer.get_glyph(glyph_key('a')).is_ok());
+        assert!(rasterizer.get_glyph(glyph_key(' ')).is_ok());
+        assert!(rasterizer.get_glyph(glyph_key('\u{200b}')).is_ok());
+    }
+
+    #[test]
+    fn vertical_glyphs() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'M', font_key, size };
+
+        let horizontal = rasterizer.get_glyph(glyph_key).unwrap();
+        rasterizer.set_vertical(true).unwrap();
+        let vertical = rasterizer.get_glyph(glyph_key).unwrap();
+
+        // Glyphs hang below the vertical origin, centered in their column.
+        assert_eq!(vertical.advance.0, 0);
+        assert!(vertical.advance.1 < 0);
+        assert!(vertical.top <= 0);
+        assert!(vertical.left < 0 && vertical.left + vertical.width as i32 > 0);
+        assert_eq!((vertical.width, vertical.height), (horizontal.width, horizontal.height));
+    }
+
+    #[test]
+    fn reused_shape_plan() {
+        let (mut rasterizer, font_key) = load_test_font("sans", Size::new(12.));
+
+        let dlig = [(Tag::new(b"dlig"), 1)];
+        let plan = rasterizer.shape_plan(font_key, &dlig);
+        assert_eq!(rasterizer.shape_with_plan(&plan, "st").len(), 1);
+        assert_eq!(rasterizer.shape_with_plan(&plan, "first").len(), 3);
+
+        // Plans are kept for every direction, so right-to-left text can use the same handle.
+        let hebrew = "\u{5e9}\u{5dc}\u{5d5}\u{5dd}";
+        let planned = rasterizer.shape_with_plan(&plan, hebrew);
+        let shaped = rasterizer.shape_with_features(hebrew, font_key, &dlig);
+        let clusters = |infos: &[Info]| infos.iter().map(|info| info.cluster).collect::<Vec<_>>();
+        assert_eq!(clusters(&planned), clusters(&shaped));
+        assert_eq!(rasterizer.shape_plans[&plan].plans.len(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "debug-images")]
+    fn native_and_autohinted_glyphs() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'g', font_key, size };
+
+        let before = rasterizer.get_glyph(glyph_key).unwrap();
+        let (native, autohinted) = rasterizer.compare_hinting(glyph_key).unwrap();
+        assert!(native.width > 0 && autohinted.width > 0);
+
+        // The font's hinting configuration is kept.
+        let after = rasterizer.get_glyph(glyph_key).unwrap();
+        assert_eq!((after.top, after.left), (before.top, before.left));
+        assert_eq!((after.width, after.height), (before.width, before.height));
+    }
+
+    #[test]
+    fn stem_darkening_parameters() {
+        let mut rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+
+        let params = [(500, 400), (1000, 300), (1667, 300), (2333, 0)];
+        assert!(rasterizer.set_stem_darkening(true, Some(params)).is_ok());
+        assert_eq!(rasterizer.stem_darkening, Some(params));
+
+        // Stem widths have to increase, the previous parameters are kept otherwise.
+        let decreasing = [(1000, 400), (500, 275), (1667, 275), (2333, 0)];
+        assert!(rasterizer.set_stem_darkening(true, Some(decreasing)).is_err());
+        assert_eq!(rasterizer.stem_darkening, Some(params));
+
+        assert!(rasterizer.set_stem_darkening(false, None).is_ok());
+        assert_eq!(rasterizer.stem_darkening, None);
+    }
+
+    #[test]
+    fn stem_darkening_library_default() {
+        // Enable stem darkening by default, like `FREETYPE_PROPERTIES` would.
+        let library = Library::init().unwrap();
+        let no_stem_darkening: freetype_sys::FT_Bool = 0;
+        unsafe {
+            FT_Property_Set(
+                library.raw(),
+                b"cff\0".as_ptr() as *const _,
+                b"no-stem-darkening\0".as_ptr() as *const _,
+                &no_stem_darkening as *const freetype_sys::FT_Bool as *const _,
+            );
+        }
+        let library = Rc::new(library);
+
+        let size = Size::new(12.);
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let desc = FontDesc::new("sans", style);
+        let mut rasterizer = FreeTypeRasterizer::with_library(library.clone(), 1.);
+        let font_key = rasterizer.load_font(&desc, size).unwrap();
+        assert_eq!(rasterizer.stem_darkening, Some(DEFAULT_DARKENING_PARAMETERS));
+
+        let mut other = FreeTypeRasterizer::with_library(library.clone(), 1.);
+        let other_key = other.load_font(&desc, size).unwrap();
+        other.set_stem_darkening(false, None).unwrap();
+        other.get_glyph(GlyphKey { character: 'a', font_key: other_key, size }).unwrap();
+        assert_eq!(library_stem_darkening(&library), None);
+
+        // The default isn't overridden by the other rasterizer.
+        rasterizer.get_glyph(GlyphKey { character: 'a', font_key, size }).unwrap();
+        assert_eq!(library_stem_darkening(&library), Some(DEFAULT_DARKENING_PARAMETERS));
+    }
+
+    #[test]
+    fn classic_tt_interpreter() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        assert!(rasterizer.tt_interpreter_version().is_ok());
+
+        rasterizer.set_tt_interpreter_version(TtInterpreter::Classic35).unwrap();
+        assert_eq!(rasterizer.tt_interpreter_version().unwrap(), TtInterpreter::Classic35);
+
+        let glyph = rasterizer.get_glyph(GlyphKey { character: 'a', font_key, size }).unwrap();
+        assert!(glyph.width > 0);
+    }
+
+    #[test]
+    fn tt_interpreter_per_rasterizer() {
+        let size = Size::new(12.);
+        let (mut first, first_key) = load_test_font("monospace", size);
+        let (mut second, second_key) = load_test_font("monospace", size);
+        let default = second.tt_interpreter_version().unwrap();
+        let version = match default {
+            TtInterpreter::Classic35 => TtInterpreter::Subpixel40,
+            _ => TtInterpreter::Classic35,
+        };
+
+        first.set_tt_interpreter_version(version).unwrap();
+        first.get_glyph(GlyphKey { character: 'a', font_key: first_key, size }).unwrap();
+        assert_eq!(second.tt_interpreter_version().unwrap(), default);
+
+        // The library's default is restored for the second rasterizer.
+        second.get_glyph(GlyphKey { character: 'a', font_key: second_key, size }).unwrap();
+        assert_eq!(library_tt_interpreter(&second.loader.library).unwrap(), default);
+
+        first.get_glyph(GlyphKey { character: 'a', font_key: first_key, size }).unwrap();
+        assert_eq!(library_tt_interpreter(&first.loader.library).unwrap(), version);
+    }
+
+    #[test]
+    fn opentype_layout_is_not_aat() {
+        let (rasterizer, font_key) = load_test_font("sans", Size::new(12.));
+
+        assert!(!rasterizer.has_aat_layout(font_key).unwrap());
+    }
+
+    #[test]
+    fn ink_extents_of_text() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+
+        let extents = rasterizer.shape_extents("a g", font_key, size).unwrap();
+        let advance = rasterizer.string_advance("a g", font_key, size).unwrap();
+        assert_eq!(extents.glyphs.len(), 3);
+        assert!(extents.glyphs[1].is_empty());
+
+        // The descender of `g` extends below the baseline.
+        let bounds = extents.bounds;
+        assert!(bounds.left >= 0. && bounds.right <= advance);
+        assert!(bounds.top > 0. && bounds.bottom < 0.);
+        assert_eq!(bounds.bottom, extents.glyphs[2].bottom);
+    }
+
+    #[test]
+    fn ascii_fast_path_matches_harfbuzz() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let text: String = (' '..='~').collect();
+        let shaped_advance = rasterizer.string_advance(&text, font_key, size).unwrap();
+
+        rasterizer.set_ligatures(false).unwrap();
+
+        // DejaVu Sans Mono composes and positions marks for Latin text, so it's always shaped.
+        assert!(rasterizer.ascii_glyphs(&text, font_key).is_none());
+
+        // Explicit features always use HarfBuzz.
+        let fast = rasterizer.shape(&text, font_key);
+        let shaped = rasterizer.shape_with_features(&text, font_key, &[(Tag::new(b"liga"), 0)]);
+        let glyphs = |infos: &[Info]| {
+            infos.iter().map(|info| (info.codepoint, info.cluster)).collect::<Vec<_>>()
+        };
+        assert_eq!(glyphs(&fast), glyphs(&shaped));
+        assert_eq!(rasterizer.string_advance(&text, font_key, size).unwrap(), shaped_advance);
+    }
+
+    #[test]
+    fn tabular_digit_width() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("sans", size);
+
+        // DejaVu Sans has tabular digits by default.
+        let digit_width = rasterizer.digit_width(font_key, size).unwrap();
+        let digits = rasterizer.string_advance("0123456789", font_key, size).unwrap();
+        assert!(digit_width > 0.);
+        assert!((digit_width * 10. - digits).abs() < 0.01);
+    }
+
+    #[test]
+    fn synthetic_oblique_extent() {
+        let mut rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let size = Size::new(12.);
+        let upright = rasterizer.load_pattern("monospace", size).unwrap();
+        let oblique = rasterizer.load_pattern("monospace:matrix=1 0.2 0 1", size).unwrap();
+        let steep = rasterizer.load_pattern("monospace:matrix=1 1 0 1", size).unwrap();
+
+        assert_eq!(rasterizer.synthetic_oblique_angle(upright).unwrap(), None);
+        let angle = rasterizer.synthetic_oblique_angle(oblique).unwrap().unwrap();
+        assert!((angle - 11.3).abs() < 0.1);
+        let angle = rasterizer.synthetic_oblique_angle(steep).unwrap().unwrap();
+        assert!((angle - 20.).abs() < 0.1);
+
+        let glyph = |rasterizer: &mut FreeTypeRasterizer, font_key| {
+            rasterizer.get_glyph(GlyphKey { character: 'l', font_key, size }).unwrap()
+        };
+        let upright = glyph(&mut rasterizer, upright);
+        let oblique = glyph(&mut rasterizer, oblique);
+        let steep = glyph(&mut rasterizer, steep);
+
+        // The top of the stem leans right, while the foot stays on the baseline.
+        assert!(oblique.left > upright.left);
+        assert!(steep.left > oblique.left);
+        assert!(oblique.left + oblique.width >= upright.left + upright.width);
+        assert!(steep.left + steep.width >= upright.left + upright.width);
+        assert_eq!(oblique.advance, upright.advance);
+    }
+
+    #[test]
+    fn ranked_match_candidates() {
+        let rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let desc = FontDesc::new("monospace", style);
+
+        let candidates = rasterizer.match_candidates(&desc, Size::new(12.), 3).unwrap();
+        assert_eq!(candidates.len(), 3);
+        assert_ne!(candidates[0].name(), "monospace");
+        assert!(matches!(candidates[0].style(), Style::Specific(_)));
+        assert!(rasterizer.match_candidates(&desc, Size::new(12.), 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn vertical_subpixel_offset() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let glyph_key = GlyphKey { character: 'o', font_key, size };
+
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        let shifted = rasterizer.get_glyph_subpixel(glyph_key, 0., 0.5).unwrap();
+        assert_eq!(shifted.top_f, glyph.top_f - 0.5);
+        assert_eq!(shifted.advance, glyph.advance);
+
+        // Whole pixels move the bearings of the cached bitmap.
+        let mut cache = crate::CachingRasterizer::with_rasterizer(rasterizer);
+        let offset = cache.get_glyph_subpixel(glyph_key, 0., 1. / 3.).unwrap();
+        let moved = cache.get_glyph_subpixel(glyph_key, 0., 2. + 1. / 3.).unwrap();
+        assert_eq!(moved.top, offset.top - 2);
+        assert_eq!((moved.width, moved.height), (offset.width, offset.height));
+
+        // Top-left bearings grow when moving down.
+        cache.set_origin_convention(GlyphOrigin::TopLeft { ascent: 10 });
+        let glyph = cache.get_glyph(glyph_key).unwrap();
+        let offset = cache.get_glyph_subpixel(glyph_key, 0., 1. / 3.).unwrap();
+        let moved = cache.get_glyph_subpixel(glyph_key, 0., 2. + 1. / 3.).unwrap();
+        assert!(offset.top_f > glyph.top_f);
+        assert_eq!(moved.top, offset.top + 2);
+        assert_eq!(moved.top_f, offset.top_f + 2.);
+    }
+
+    #[test]
+    fn freetype_capabilities() {
+        let rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let capabilities = rasterizer.capabilities();
+        assert!(capabilities.outlines && capabilities.subpixel_positioning);
+        assert!(capabilities.shaping && !capabilities.native_shaping);
+        assert_eq!(capabilities.msdf, cfg!(feature = "msdf"));
+
+        let cache = crate::CachingRasterizer::with_rasterizer(rasterizer);
+        assert_eq!(cache.capabilities(), capabilities);
+    }
+
+    #[test]
+    fn fallback_advance_snapped_to_cell() {
+        let size = Size::new(12.);
+        let (mut rasterizer, font_key) = load_test_font("monospace", size);
+        let cell_width = rasterizer.metrics(font_key, size).unwrap().average_advance.round();
+
+        let glyph_key = GlyphKey { character: '∰', font_key, size };
+        let glyph = rasterizer.get_glyph(glyph_key).unwrap();
+        assert_eq!(glyph.native_advance, None);
+
+        rasterizer.set_fallback_cell_advance(true).unwrap();
+        let snapped = rasterizer.get_glyph(glyph_key).unwrap();
+        assert_eq!(snapped.advance, (cell_width as i32, 0));
+        assert_eq!(snapped.native_advance, Some(glyph.advance_f));
+
+        // Glyphs of the primary font keep their advance.
+        let glyph = rasterizer.get_glyph(GlyphKey { character: 'a', font_key, size }).unwrap();
+        assert_eq!(glyph.native_advance, None);
+    }
+
+    #[test]
+    fn shared_glyph_cache() {
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let desc = FontDesc::new("monospace", style);
+        let size = Size::new(12.);
+
+        let mut first = crate::CachingRasterizer::<FreeTypeRasterizer>::new(1.).unwrap();
+        let font_key = first.load_font(&desc, size).unwrap();
+        let cached = first.warm_cache(font_key, size, &[' '..='~']).unwrap();
+
+        let rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let mut second =
+            crate::CachingRasterizer::with_shared_cache(rasterizer, first.shared_cache());
+        assert_eq!(second.load_font(&desc, size).unwrap(), font_key);
+        second.get_glyph(GlyphKey { character: 'a', font_key, size }).unwrap();
+        assert_eq!(second.cache().len(), cached);
+
+        // Settings of one rasterizer don't affect the glyphs of the other.
+        second.set_subpixel_levels(4);
+        second.update_dpr(2.);
+        second.set_origin_convention(GlyphOrigin::TopLeft { ascent: 20 });
+        assert_eq!(first.cache().len(), cached);
+
+        let glyph_key = GlyphKey { character: 'b', font_key, size };
+        let large = second.get_glyph(glyph_key).unwrap();
+        assert_eq!(first.cache().len(), cached + 1);
+        let small = first.get_glyph(glyph_key).unwrap();
+        assert_eq!(first.cache().len(), cached + 1);
+        assert!(large.height > small.height);
+        assert!(small.top > 0);
+
+        // Rasterizers with the same settings share glyphs again.
+        first.update_dpr(2.);
+        first.set_subpixel_levels(4);
+        first.set_origin_convention(GlyphOrigin::TopLeft { ascent: 20 });
+        assert_eq!(first.get_glyph(glyph_key).unwrap().top, large.top);
+        assert_eq!(first.cache().len(), cached + 1);
+    }
+
+    #[test]
+    fn face_id_dedups_keys() {
+        let mut rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let regular = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let bold = Style::Description { slant: Slant::Normal, weight: Weight::Bold };
+
+        let small =
+            rasterizer.load_font(&FontDesc::new("monospace", regular.clone()), Size::new(8.));
+        let large = rasterizer.load_font(&FontDesc::new("monospace", regular), Size::new(24.));
+        let bold = rasterizer.load_font(&FontDesc::new("monospace", bold), Size::new(8.));
+
+        let small = rasterizer.face_id(small.unwrap()).unwrap();
+        assert_eq!(small, rasterizer.face_id(large.unwrap()).unwrap());
+        assert_ne!(small, rasterizer.face_id(bold.unwrap()).unwrap());
+    }
+
+    #[test]
+    fn cache_capacity_evicts_least_recently_used() {
+        use std::sync::{Arc, Mutex};
+
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let desc = FontDesc::new("monospace", style);
+        let size = Size::new(12.);
+
+        let mut first = crate::CachingRasterizer::<FreeTypeRasterizer>::new(1.).unwrap();
+        let font_key = first.load_font(&desc, size).unwrap();
+        let rasterizer = FreeTypeRasterizer::new(1.).unwrap();
+        let mut second =
+            crate::CachingRasterizer::with_shared_cache(rasterizer, first.shared_cache());
+
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        for rasterizer in [&mut first, &mut second] {
+            let evicted = evicted.clone();
+            rasterizer.on_evict(move |key| evicted.lock().unwrap().push(key.character));
+        }
+
+        first.set_capacity(Some(2));
+        for character in ['a', 'b', 'a', 'c'] {
+            first.get_glyph(GlyphKey { character, font_key, size }).unwrap();
+        }
+
+        // Both rasterizers sharing the cache are notified about the least recently used glyph.
+        assert_eq!(*evicted.lock().unwrap(), ['b', 'b']);
+        assert_eq!(first.cache().len(), 2);
+
+        drop(second);
+        first.set_capacity(Some(1));
+        assert_eq!(*evicted.lock().unwrap(), ['b', 'b', 'a']);
+    }
+
+    #[test]
+    fn rounding_negative_bearings() {
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let size = Size::new(12.);
+        let (mut rasterizer, serif) = load_test_font("serif", size);
+        let mono = rasterizer.load_font(&FontDesc::new("monospace", style), size).unwrap();
+
+        // The hook of `j` extends left of the origin, `_` is entirely below the baseline.
+        let modes = [
+            (RoundingMode::Truncate, -1, -3),
+            (RoundingMode::Round, -2, -3),
+            (RoundingMode::Floor, -2, -4),
+            (RoundingMode::Ceil, -1, -3),
+        ];
+        for (mode, left, top) in modes {
+            rasterizer.set_rounding(mode).unwrap();
+
+            let glyph = rasterizer.get_glyph(GlyphKey { character: 'j', font_key: serif, size });
+            let glyph = glyph.unwrap();
+            assert!(glyph.left_f < -1. && glyph.left_f > -2.);
+            assert_eq!(glyph.left, left);
+
+            let glyph = rasterizer.get_glyph(GlyphKey { character: '_', font_key: mono, size });
+            let glyph = glyph.unwrap();
+            assert!(glyph.top_f < -3. && glyph.top_f > -4.);
+            assert_eq!(glyph.top, top);
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//! Multi-channel signed distance fields generated from glyph outlines.
+//!
+//! Edges are colored with the simple edge coloring of Viktor Chlumsky's msdfgen. Every contour is
+//! split at its corners, which are joints turning by more than [`CORNER_ANGLE_THRESHOLD`]. Smooth
+//! contours are white, contours with a single corner are split into three parts colored cyan,
+//! white and magenta, and otherwise the color switches between cyan, magenta and yellow at every
+//! corner, with the last color always sharing a single channel with the first one.
+//!
+//! Every channel stores the signed pseudo-distance to the nearest edge containing the channel.
+//! The distance is positive inside of the glyph and mapped so `range` pixels cover the whole
+//! value range, which means the outline is at the median of the three channels being `0.5`.
+//! There is no error correction pass, so shaders should use the plain median of the channels.
+
+use std::cmp::Ordering;
+use std::ops::{Add, Mul, Sub};
+
+use freetype::freetype_sys::{self, FT_Outline, FT_Vector};
+use libc::c_void;
+
+use crate::BitmapBuffer;
+
+/// Angle in radians at which the joint between two edges is considered a corner.
+const CORNER_ANGLE_THRESHOLD: f64 = 3.;
+
+/// Number of samples used to find the nearest point on a curve before refining it.
+const CURVE_SAMPLES: usize = 16;
+
+/// Newton iterations used to refine the nearest point on a curve.
+const CURVE_REFINEMENTS: usize = 4;
+
+const RED: u8 = 0b001;
+const GREEN: u8 = 0b010;
+const BLUE: u8 = 0b100;
+const CYAN: u8 = GREEN | BLUE;
+const WHITE: u8 = RED | GREEN | BLUE;
+
+/// Distance field of a glyph.
+pub struct Msdf {
+    pub top: i32,
+    pub left: i32,
+    pub width: i32,
+    pub height: i32,
+    pub buffer: BitmapBuffer,
+}
+
+/// Generate the distance field of an outline in 26.6 pixel coordinates.
+pub fn generate(outline: &FT_Outline, range: f32) -> Msdf {
+    let range = f64::from(range.max(f32::EPSILON));
+
+    let mut contours = decompose(outline);
+    for contour in &mut contours {
+        color_edges(contour);
+    }
+
+    // Outlines filled on the right of their edges have inverted distances.
+    let orientation =
+        unsafe { freetype_sys::FT_Outline_Get_Orientation(outline as *const _ as *mut FT_Outline) };
+    let sign = if orientation == freetype_sys::FT_ORIENTATION_TRUETYPE { -1. } else { 1. };
+
+    let mut cbox = freetype_sys::FT_BBox { xMin: 0, yMin: 0, xMax: 0, yMax: 0 };
+    unsafe { freetype_sys::FT_Outline_Get_CBox(outline, &mut cbox) };
+
+    let padding = (range / 2.).ceil() as i32;
+    let left = (cbox.xMin as f64 / 64.).floor() as i32 - padding;
+    let right = (cbox.xMax as f64 / 64.).ceil() as i32 + padding;
+    let top = (cbox.yMax as f64 / 64.).ceil() as i32 + padding;
+    let bottom = (cbox.yMin as f64 / 64.).floor() as i32 - padding;
+
+    if contours.is_empty() {
+        return Msdf {
+            top: 0,
+            left: 0,
+            width: 0,
+            height: 0,
+            buffer: BitmapBuffer::Rgb(Vec::new()),
+        };
+    }
+
+    let (width, height) = (right - left, top - bottom);
+    let mut buffer = Vec::with_capacity((width * height * 3) as usize);
+    for row in 0..height {
+        for column in 0..width {
+            let point = Point::new(f64::from(left + column) + 0.5, f64::from(top - row) - 0.5);
+
+            for channel in [RED, GREEN, BLUE] {
+                let distance = sign * pseudo_distance(&contours, point, channel);
+                let value = (distance / range + 0.5).clamp(0., 1.);
+                buffer.push((value * 255.).round() as u8);
+            }
+        }
+    }
+
+    Msdf { top, left, width, height, buffer: BitmapBuffer::Rgb(buffer) }
+}
+
+/// Signed pseudo-distance from a point to the nearest edge containing a channel.
+fn pseudo_distance(contours: &[Vec<Edge>], point: Point, channel: u8) -> f64 {
+    let mut nearest: Option<(&Edge, SignedDistance)> = None;
+    for edge in contours.iter().flatten().filter(|edge| edge.color & channel != 0) {
+        let distance = edge.segment.distance(point);
+        if nearest.as_ref().map_or(true, |(_, nearest)| distance.is_closer(nearest)) {
+            nearest = Some((edge, distance));
+        }
+    }
+
+    match nearest {
+        Some((edge, distance)) => edge.segment.pseudo_distance(point, distance),
+        None => f64::NEG_INFINITY,
+    }
+}
+
+/// Assign colors to the edges of a contour.
+fn color_edges(contour: &mut Vec<Edge>) {
+    let count = contour.len();
+    let corners: Vec<usize> = (0..count)
+        .filter(|&index| {
+            let previous = contour[(index + count - 1) % count].segment.direction(1.).normalize();
+            let next = contour[index].segment.direction(0.).normalize();
+            is_corner(previous, next)
+        })
+        .collect();
+
+    match corners.len() {
+        0 => {
+            for edge in contour.iter_mut() {
+                edge.color = WHITE;
+            }
+        },
+        // A single corner is split into three differently colored parts, so it stays sharp.
+        1 => {
+            let first = switch_color(WHITE, 0);
+            let colors = [first, WHITE, switch_color(first, 0)];
+
+            contour.rotate_left(corners[0]);
+            if count < 3 {
+                let parts: Vec<Segment> =
+                    contour.iter().flat_map(|edge| edge.segment.split_in_thirds()).collect();
+                let part_colors: &[u8] = if count == 1 {
+                    &colors
+                } else {
+                    &[colors[0], colors[0], colors[1], colors[1], colors[2], colors[2]]
+                };
+
+                *contour = parts
+                    .into_iter()
+                    .zip(part_colors)
+                    .map(|(segment, &color)| Edge { segment, color })
+                    .collect();
+            } else {
+                for (index, edge) in contour.iter_mut().enumerate() {
+                    edge.color = colors[symmetrical_trichotomy(index, count)];
+                }
+            }
+        },
+        corner_count => {
+            let start = corners[0];
+            let mut spline = 0;
+            let mut color = switch_color(WHITE, 0);
+            let initial_color = color;
+
+            for offset in 0..count {
+                let index = (start + offset) % count;
+                if spline + 1 < corner_count && corners[spline + 1] == index {
+                    spline += 1;
+                    let banned = if spline == corner_count - 1 { initial_color } else { 0 };
+                    color = switch_color(color, banned);
+                }
+                contour[index].color = color;
+            }
+        },
+    }
+}
+
+/// Check if the joint between two normalized directions is a corner.
+fn is_corner(previous: Point, next: Point) -> bool {
+    previous.dot(next) <= 0. || previous.cross(next).abs() > CORNER_ANGLE_THRESHOLD.sin()
+}
+
+/// Pick the next color, avoiding sharing two channels with the `banned` color.
+fn switch_color(color: u8, banned: u8) -> u8 {
+    let combined = color & banned;
+    if combined == RED || combined == GREEN || combined == BLUE {
+        return combined ^ WHITE;
+    }
+
+    if color == 0 || color == WHITE {
+        return CYAN;
+    }
+
+    let shifted = color << 1;
+    (shifted | shifted >> 3) & WHITE
+}
+
+/// Split `count` positions into three groups, with the middle one being the largest.
+fn symmetrical_trichotomy(position: usize, count: usize) -> usize {
+    let position = position as f64 / (count - 1) as f64;
+    (3. + 2.875 * position - 1.4375 + 0.5) as usize - 2
+}
+
+/// Collect the contours of an outline.
+fn decompose(outline: &FT_Outline) -> Vec<Vec<Edge>> {
+    struct Builder {
+        contours: Vec<Vec<Edge>>,
+        position: Point,
+    }
+
+    impl Builder {
+        fn push(&mut self, segment: Segment, to: *const FT_Vector) -> i32 {
+            if let Some(contour) = self.contours.last_mut() {
+                contour.push(Edge { segment, color: WHITE });
+            }
+            self.position = Point::from_ft(to);
+            0
+        }
+    }
+
+    extern "C" fn move_to(to: *const FT_Vector, user: *mut c_void) -> i32 {
+        let builder = unsafe { &mut *(user as *mut Builder) };
+        builder.contours.push(Vec::new());
+        builder.position = Point::from_ft(to);
+        0
+    }
+
+    extern "C" fn line_to(to: *const FT_Vector, user: *mut c_void) -> i32 {
+        let builder = unsafe { &mut *(user as *mut Builder) };
+        let segment = Segment::Line(builder.position, Point::from_ft(to));
+        builder.push(segment, to)
+    }
+
+    extern "C" fn conic_to(
+        control: *const FT_Vector,
+        to: *const FT_Vector,
+        user: *mut c_void,
+    ) -> i32 {
+        let builder = unsafe { &mut *(user as *mut Builder) };
+        let control = Point::from_ft(control);
+        let segment = Segment::Quadratic(builder.position, control, Point::from_ft(to));
+        builder.push(segment, to)
+    }
+
+    extern "C" fn cubic_to(
+        control1: *const FT_Vector,
+        control2: *const FT_Vector,
+        to: *const FT_Vector,
+        user: *mut c_void,
+    ) -> i32 {
+        let builder = unsafe { &mut *(user as *mut Builder) };
+        let (control1, control2) = (Point::from_ft(control1), Point::from_ft(control2));
+        let segment = Segment::Cubic(builder.position, control1, control2, Point::from_ft(to));
+        builder.push(segment, to)
+    }
+
+    let funcs =
+        freetype_sys::FT_Outline_Funcs { move_to, line_to, conic_to, cubic_to, shift: 0, delta: 0 };
+
+    let mut builder = Builder { contours: Vec::new(), position: Point::new(0., 0.) };
+    unsafe {
+        freetype_sys::FT_Outline_Decompose(
+            outline as *const _ as *mut FT_Outline,
+            &funcs,
+            &mut builder as *mut Builder as *mut c_void,
+        );
+    }
+
+    // Drop degenerate edges, since they have no direction.
+    for contour in &mut builder.contours {
+        contour.retain(|edge| !edge.segment.is_degenerate());
+    }
+    builder.contours.retain(|contour| !contour.is_empty());
+
+    builder.contours
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Point {
+    x: f64,
+    y: f64,
+}
+
+impl Point {
+    fn new(x: f64, y: f64) -> Self {
+        Self { x, y }
+    }
+
+    fn from_ft(vector: *const FT_Vector) -> Self {
+        let vector = unsafe { &*vector };
+        Self::new(vector.x as f64 / 64., vector.y as f64 / 64.)
+    }
+
+    fn dot(self, other: Self) -> f64 {
+        self.x * other.x + self.y * other.y
+    }
+
+    fn cross(self, other: Self) -> f64 {
+        self.x * other.y - self.y * other.x
+    }
+
+    fn length(self) -> f64 {
+        self.dot(self).sqrt()
+    }
+
+    fn normalize(self) -> Self {
+        let length = self.length();
+        if length == 0. {
+            self
+        } else {
+            self * (1. / length)
+        }
+    }
+}
+
+impl Add for Point {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self::new(self.x + other.x, self.y + other.y)
+    }
+}
+
+impl Sub for Point {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self::new(self.x - other.x, self.y - other.y)
+    }
+}
+
+impl Mul<f64> for Point {
+    type Output = Self;
+
+    fn mul(self, factor: f64) -> Self {
+        Self::new(self.x * factor, self.y * factor)
+    }
+}
+
+/// Distance to the nearest point of a segment.
+#[derive(Copy, Clone, Debug)]
+struct SignedDistance {
+    /// Distance, positive on the left side of the segment.
+    distance: f64,
+
+    /// Absolute cosine between the segment's direction and the direction to the point.
+    ///
+    /// This decides between segments at the same distance, like the two segments of a corner.
+    dot: f64,
+
+    /// Parameter of the nearest point on the segment.
+    t: f64,
+}
+
+impl SignedDistance {
+    fn is_closer(&self, other: &Self) -> bool {
+        let (distance, other_distance) = (self.distance.abs(), other.distance.abs());
+        distance < other_distance
+            || ((distance - other_distance).abs() <= f64::EPSILON && self.dot < other.dot)
+    }
+}
+
+struct Edge {
+    segment: Segment,
+    color: u8,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Segment {
+    Line(Point, Point),
+    Quadratic(Point, Point, Point),
+    Cubic(Point, Point, Point, Point),
+}
+
+impl Segment {
+    fn point(&self, t: f64) -> Point {
+        let s = 1. - t;
+        match *self {
+            Segment::Line(p0, p1) => p0 * s + p1 * t,
+            Segment::Quadratic(p0, p1, p2) => p0 * (s * s) + p1 * (2. * s * t) + p2 * (t * t),
+            Segment::Cubic(p0, p1, p2, p3) => {
+                p0 * (s * s * s) + p1 * (3. * s * s * t) + p2 * (3. * s * t * t) + p3 * (t * t * t)
+            },
+        }
+    }
+
+    fn derivative(&self, t: f64) -> Point {
+        let s = 1. - t;
+        match *self {
+            Segment::Line(p0, p1) => p1 - p0,
+            Segment::Quadratic(p0, p1, p2) => (p1 - p0) * (2. * s) + (p2 - p1) * (2. * t),
+            Segment::Cubic(p0, p1, p2, p3) => {
+                (p1 - p0) * (3. * s * s) + (p2 - p1) * (6. * s * t) + (p3 - p2) * (3. * t * t)
+            },
+        }
+    }
+
+    fn second_derivative(&self, t: f64) -> Point {
+        match *self {
+            Segment::Line(..) => Point::new(0., 0.),
+            Segment::Quadratic(p0, p1, p2) => (p2 - p1 * 2. + p0) * 2.,
+            Segment::Cubic(p0, p1, p2, p3) => {
+                (p2 - p1 * 2. + p0) * (6. * (1. - t)) + (p3 - p2 * 2. + p1) * (6. * t)
+            },
+        }
+    }
+
+    /// Direction of the segment, which is well defined even if a control point is on an end.
+    fn direction(&self, t: f64) -> Point {
+        let derivative = self.derivative(t);
+        if derivative.length() > f64::EPSILON {
+            return derivative;
+        }
+
+        match *self {
+            Segment::Line(p0, p1) => p1 - p0,
+            Segment::Quadratic(p0, _, p2) => p2 - p0,
+            Segment::Cubic(p0, _, p2, p3) if t < 0.5 => {
+                if (p2 - p0).length() > f64::EPSILON {
+                    p2 - p0
+                } else {
+                    p3 - p0
+                }
+            },
+            Segment::Cubic(p0, p1, _, p3) => {
+                if (p3 - p1).length() > f64::EPSILON {
+                    p3 - p1
+                } else {
+                    p3 - p0
+                }
+            },
+        }
+    }
+
+    fn is_degenerate(&self) -> bool {
+        match *self {
+            Segment::Line(p0, p1) => p0 == p1,
+            Segment::Quadratic(p0, p1, p2) => p0 == p1 && p1 == p2,
+            Segment::Cubic(p0, p1, p2, p3) => p0 == p1 && p1 == p2 && p2 == p3,
+        }
+    }
+
+    /// Split the segment into three parts of equal parameter range.
+    fn split_in_thirds(&self) -> [Segment; 3] {
+        [
+            self.sub_segment(0., 1. / 3.),
+            self.sub_segment(1. / 3., 2. / 3.),
+            self.sub_segment(2. / 3., 1.),
+        ]
+    }
+
+    /// Part of the segment between two parameters.
+    fn sub_segment(&self, start: f64, end: f64) -> Segment {
+        let (p0, p3) = (self.point(start), self.point(end));
+        let scale = end - start;
+        match *self {
+            Segment::Line(..) => Segment::Line(p0, p3),
+            Segment::Quadratic(..) => {
+                let control = p0 + self.derivative(start) * (scale / 2.);
+                Segment::Quadratic(p0, control, p3)
+            },
+            Segment::Cubic(..) => {
+                let control1 = p0 + self.derivative(start) * (scale / 3.);
+                let control2 = p3 - self.derivative(end) * (scale / 3.);
+                Segment::Cubic(p0, control1, control2, p3)
+            },
+        }
+    }
+
+    /// Signed distance to the nearest point of the segment.
+    fn distance(&self, point: Point) -> SignedDistance {
+        let t = match *self {
+            Segment::Line(p0, p1) => {
+                let direction = p1 - p0;
+                ((point - p0).dot(direction) / direction.dot(direction)).clamp(0., 1.)
+            },
+            _ => self.nearest_curve_parameter(point),
+        };
+
+        let offset = point - self.point(t);
+        let direction = self.direction(t);
+        let distance = offset.length();
+        let sign = if direction.cross(offset) >= 0. { 1. } else { -1. };
+        let dot = direction.normalize().dot(offset.normalize()).abs();
+
+        SignedDistance { distance: sign * distance, dot, t }
+    }
+
+    /// Parameter of the point on a curve closest to `point`.
+    fn nearest_curve_parameter(&self, point: Point) -> f64 {
+        let squared_distance = |t: f64| {
+            let offset = self.point(t) - point;
+            offset.dot(offset)
+        };
+
+        let mut t = (0..=CURVE_SAMPLES)
+            .map(|sample| sample as f64 / CURVE_SAMPLES as f64)
+            .min_by(|a, b| {
+                squared_distance(*a).partial_cmp(&squared_distance(*b)).unwrap_or(Ordering::Equal)
+            })
+            .unwrap_or(0.);
+
+        // Refine the sample by minimizing the squared distance with Newton's method.
+        for _ in 0..CURVE_REFINEMENTS {
+            let offset = self.point(t) - point;
+            let derivative = self.derivative(t);
+            let slope = offset.dot(derivative);
+            let curvature = derivative.dot(derivative) + offset.dot(self.second_derivative(t));
+            if curvature.abs() <= f64::EPSILON {
+                break;
+            }
+
+            let refined = (t - slope / curvature).clamp(0., 1.);
+            if squared_distance(refined) > squared_distance(t) {
+                break;
+            }
+            t = refined;
+        }
+
+        t
+    }
+
+    /// Extend the distance beyond the segment's ends along its tangents.
+    ///
+    /// This keeps the distance of both edges meeting at a corner equal, resulting in sharp
+    /// corners when the channels are combined.
+    fn pseudo_distance(&self, point: Point, distance: SignedDistance) -> f64 {
+        let (end, t) = if distance.t <= 0. {
+            (self.point(0.), 0.)
+        } else if distance.t >= 1. {
+            (self.point(1.), 1.)
+        } else {
+            return distance.distance;
+        };
+
+        let direction = self.direction(t).normalize();
+        let offset = point - end;
+        let along = offset.dot(direction);
+        if (t == 0. && along < 0.) || (t == 1. && along > 0.) {
+            let pseudo_distance = direction.cross(offset);
+            if pseudo_distance.abs() <= distance.distance.abs() {
+                return pseudo_distance;
+            }
+        }
+
+        distance.distance
+    }
+}
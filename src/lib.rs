@@ -6,51 +6,130 @@
 
 #![deny(clippy::all, clippy::if_not_else, clippy::enum_glob_use)]
 
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fmt::{self, Display, Formatter};
-use std::ops::{Add, Mul};
+use std::hash::{Hash, Hasher};
+use std::ops::{Add, Mul, Range};
+use std::path::Path;
+use std::str::FromStr;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
+use log::debug;
+
 #[cfg(not(any(target_os = "macos", windows)))]
 extern crate harfbuzz_rs;
 
+/// Enter a `tracing` span until the end of the current scope.
+///
+/// All spans use the `crossfont` target, so their timings can be aggregated across backends.
+/// Without the `profiling` feature this expands to nothing.
+macro_rules! profile_span {
+    ($name:literal) => {
+        #[cfg(feature = "profiling")]
+        let _span = tracing::span!(target: "crossfont", tracing::Level::TRACE, $name).entered();
+    };
+}
+
+pub mod cache;
+pub use cache::CachingRasterizer;
+
+#[cfg(feature = "atlas")]
+pub mod atlas;
+
+#[cfg(all(feature = "async", not(any(target_os = "macos", windows))))]
+mod task;
+
+mod emoji;
+pub use emoji::{default_presentation, Presentation};
+
+mod sfnt;
+
+mod builtin;
+pub use builtin::{is_builtin_glyph, is_powerline_glyph};
+
+mod width;
+pub use width::char_cell_width;
+
+mod caret;
+pub use caret::ligature_caret_offset;
+
+mod cluster;
+#[cfg(feature = "graphemes")]
+pub use cluster::{graphemes, ShapedGrapheme};
+pub use cluster::{shaped_clusters, Cluster};
+
 // If target isn't macos or windows, reexport everything from ft.
 #[cfg(not(any(target_os = "macos", windows)))]
 pub mod ft;
 #[cfg(not(any(target_os = "macos", windows)))]
 pub use ft::FreeTypeRasterizer as Rasterizer;
+#[cfg(not(any(target_os = "macos", windows)))]
+pub use ft::{probe_font, probe_font_file, system_dpr};
 
 #[cfg(windows)]
 pub mod directwrite;
 #[cfg(windows)]
 pub use directwrite::DirectWriteRasterizer as Rasterizer;
+#[cfg(windows)]
+pub use directwrite::{monitor_dpr, system_dpr, window_dpr};
 
 #[cfg(target_os = "macos")]
 pub mod darwin;
 #[cfg(target_os = "macos")]
 pub use darwin::CoreTextRasterizer as Rasterizer;
+#[cfg(target_os = "macos")]
+pub use darwin::{screen_dprs, system_dpr};
+
+/// Font backend used by `Rasterizer`.
+#[cfg(not(any(target_os = "macos", windows)))]
+pub const BACKEND: BackendKind = BackendKind::FreeType;
+#[cfg(windows)]
+pub const BACKEND: BackendKind = BackendKind::DirectWrite;
+#[cfg(target_os = "macos")]
+pub const BACKEND: BackendKind = BackendKind::CoreText;
 
 /// Placeholder glyph key that represents a blank glyph
 pub const PLACEHOLDER_GLYPH: KeyType = KeyType::Placeholder;
 
+/// Character rendered by `Rasterize::replacement_glyph` for characters which can't be displayed.
+pub const REPLACEMENT_CHARACTER: char = '\u{fffd}';
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct FontDesc {
     name: String,
     style: Style,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Slant {
     Normal,
     Italic,
     Oblique,
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub enum Weight {
     Normal,
     Bold,
 }
 
+/// Width of a font's glyphs relative to its normal style.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stretch {
+    UltraCondensed,
+    ExtraCondensed,
+    Condensed,
+    SemiCondensed,
+    Normal,
+    SemiExpanded,
+    Expanded,
+    ExtraExpanded,
+    UltraExpanded,
+}
+
 /// Style of font.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Style {
@@ -69,6 +148,75 @@ impl fmt::Display for Style {
     }
 }
 
+impl FromStr for Style {
+    type Err = Infallible;
+
+    /// Parse style names like `Bold Italic` or `Regular`.
+    ///
+    /// Unrecognized names are kept as `Style::Specific`, since fonts can have named styles like
+    /// `Condensed Medium`.
+    fn from_str(style: &str) -> Result<Self, Self::Err> {
+        let description = match style.strip_prefix("slant=") {
+            Some(display) => parse_style_display(display),
+            None => parse_style_words(style),
+        };
+
+        Ok(match description {
+            Some((slant, weight)) => Style::Description { slant, weight },
+            None => Style::Specific(style.into()),
+        })
+    }
+}
+
+impl From<&str> for Style {
+    fn from(style: &str) -> Self {
+        match style.parse() {
+            Ok(style) => style,
+            Err(infallible) => match infallible {},
+        }
+    }
+}
+
+/// Parse the slant and weight from a style's `Display` output, without the leading `slant=`.
+fn parse_style_display(display: &str) -> Option<(Slant, Weight)> {
+    let (slant, weight) = display.split_once(", weight=")?;
+
+    let slant = match slant {
+        "Normal" => Slant::Normal,
+        "Italic" => Slant::Italic,
+        "Oblique" => Slant::Oblique,
+        _ => return None,
+    };
+
+    let weight = match weight {
+        "Normal" => Weight::Normal,
+        "Bold" => Weight::Bold,
+        _ => return None,
+    };
+
+    Some((slant, weight))
+}
+
+/// Parse the slant and weight from whitespace separated style names.
+fn parse_style_words(style: &str) -> Option<(Slant, Weight)> {
+    let mut slant = Slant::Normal;
+    let mut weight = Weight::Normal;
+    let mut words = style.split_whitespace().peekable();
+    words.peek()?;
+
+    for word in words {
+        match word.to_ascii_lowercase().as_str() {
+            "regular" | "normal" => (),
+            "bold" => weight = Weight::Bold,
+            "italic" => slant = Slant::Italic,
+            "oblique" => slant = Slant::Oblique,
+            _ => return None,
+        }
+    }
+
+    Some((slant, weight))
+}
+
 impl FontDesc {
     pub fn new<S>(name: S, style: Style) -> FontDesc
     where
@@ -76,6 +224,50 @@ impl FontDesc {
     {
         FontDesc { name: name.into(), style }
     }
+
+    /// Family name of the font.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Style of the font.
+    pub fn style(&self) -> &Style {
+        &self.style
+    }
+
+    /// Copy of this description with a different style.
+    pub fn with_style(&self, style: Style) -> FontDesc {
+        FontDesc { name: self.name.clone(), style }
+    }
+
+    /// Copy of this description with a different weight.
+    ///
+    /// Specific styles are replaced by a description with a normal slant.
+    pub fn with_weight(&self, weight: Weight) -> FontDesc {
+        let slant = match self.style {
+            Style::Description { slant, .. } => slant,
+            Style::Specific(_) => Slant::Normal,
+        };
+        self.with_style(Style::Description { slant, weight })
+    }
+
+    /// Copy of this description with a different slant.
+    ///
+    /// Specific styles are replaced by a description with a normal weight.
+    pub fn with_slant(&self, slant: Slant) -> FontDesc {
+        let weight = match self.style {
+            Style::Description { weight, .. } => weight,
+            Style::Specific(_) => Weight::Normal,
+        };
+        self.with_style(Style::Description { slant, weight })
+    }
+
+    /// Check if both descriptions refer to the same family, regardless of their style.
+    ///
+    /// Family names are compared ignoring ASCII case, like font matching does.
+    pub fn same_family(&self, other: &FontDesc) -> bool {
+        self.name.eq_ignore_ascii_case(&other.name)
+    }
 }
 
 impl fmt::Display for FontDesc {
@@ -84,6 +276,120 @@ impl fmt::Display for FontDesc {
     }
 }
 
+/// Ordered list of loaded fonts.
+///
+/// The first font is the primary font, all other fonts are used as fallbacks in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontStack {
+    keys: Vec<FontKey>,
+}
+
+impl FontStack {
+    /// Key of the primary font.
+    pub fn primary(&self) -> FontKey {
+        self.keys[0]
+    }
+
+    /// Keys of all fonts in the stack, in order of preference.
+    pub fn keys(&self) -> &[FontKey] {
+        &self.keys
+    }
+}
+
+/// Split a CSS-like font stack into its family names.
+///
+/// Families are separated by commas and can optionally be quoted with `"` or `'`, which allows
+/// commas inside of family names.
+fn parse_font_stack(stack: &str) -> Vec<String> {
+    let mut families = Vec::new();
+    let mut family = String::new();
+    let mut quote = None;
+
+    for c in stack.chars() {
+        match (quote, c) {
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (Some(q), c) if q == c => quote = None,
+            (None, ',') => families.push(std::mem::take(&mut family)),
+            _ => family.push(c),
+        }
+    }
+    families.push(family);
+
+    families.into_iter().map(|family| family.trim().to_owned()).filter(|f| !f.is_empty()).collect()
+}
+
+/// Generic font families, as used in CSS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum GenericFamily {
+    Monospace,
+    SansSerif,
+    Serif,
+    Cursive,
+    Fantasy,
+}
+
+impl GenericFamily {
+    /// Family name of the platform's default font for the generic family.
+    ///
+    /// Fontconfig resolves generic families through its aliases, so the CSS name is used directly.
+    #[cfg(not(any(target_os = "macos", windows)))]
+    pub fn family_name(self) -> Cow<'static, str> {
+        Cow::Borrowed(match self {
+            GenericFamily::Monospace => "monospace",
+            GenericFamily::SansSerif => "sans-serif",
+            GenericFamily::Serif => "serif",
+            GenericFamily::Cursive => "cursive",
+            GenericFamily::Fantasy => "fantasy",
+        })
+    }
+
+    /// Family name of the platform's default font for the generic family.
+    ///
+    /// Monospace and sans-serif use the user's default fonts from the system settings.
+    #[cfg(target_os = "macos")]
+    pub fn family_name(self) -> Cow<'static, str> {
+        let (user_font, fallback) = match self {
+            GenericFamily::Monospace => (darwin::user_font_family(true), "Menlo"),
+            GenericFamily::SansSerif => (darwin::user_font_family(false), "Helvetica"),
+            GenericFamily::Serif => (None, "Times"),
+            GenericFamily::Cursive => (None, "Apple Chancery"),
+            GenericFamily::Fantasy => (None, "Papyrus"),
+        };
+        user_font.map_or(Cow::Borrowed(fallback), Cow::Owned)
+    }
+
+    /// Family name of the platform's default font for the generic family.
+    ///
+    /// Sans-serif uses the system UI font, Windows has no setting for the other families.
+    #[cfg(windows)]
+    pub fn family_name(self) -> Cow<'static, str> {
+        let (system_font, fallback) = match self {
+            GenericFamily::Monospace => (None, "Consolas"),
+            GenericFamily::SansSerif => (directwrite::message_font_family(), "Segoe UI"),
+            GenericFamily::Serif => (None, "Times New Roman"),
+            GenericFamily::Cursive => (None, "Comic Sans MS"),
+            GenericFamily::Fantasy => (None, "Impact"),
+        };
+        system_font.map_or(Cow::Borrowed(fallback), Cow::Owned)
+    }
+}
+
+impl FromStr for GenericFamily {
+    type Err = ();
+
+    /// Parse the CSS name of a generic family, ignoring ASCII case like CSS does.
+    fn from_str(family: &str) -> Result<Self, Self::Err> {
+        match family.to_ascii_lowercase().as_str() {
+            "monospace" => Ok(GenericFamily::Monospace),
+            "sans-serif" => Ok(GenericFamily::SansSerif),
+            "serif" => Ok(GenericFamily::Serif),
+            "cursive" => Ok(GenericFamily::Cursive),
+            "fantasy" => Ok(GenericFamily::Fantasy),
+            _ => Err(()),
+        }
+    }
+}
+
 /// Identifier for a Font for use in maps/etc.
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct FontKey {
@@ -101,6 +407,229 @@ impl FontKey {
     }
 }
 
+impl Display for FontKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "font {}", self.token)
+    }
+}
+
+/// Identifier for a physical font face.
+///
+/// Unlike `FontKey`, which identifies a font request, two fonts resolving to the same face in the
+/// same font file share a `FaceId`. This makes it possible to share glyph index based caches
+/// between different `FontKey`s.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct FaceId(u64);
+
+impl FaceId {
+    /// Create an identifier from anything uniquely identifying a face.
+    pub(crate) fn from_hashable<T: Hash>(face: T) -> FaceId {
+        let mut hasher = DefaultHasher::new();
+        face.hash(&mut hasher);
+        FaceId(hasher.finish())
+    }
+}
+
+/// OpenType tag, like the `latn` script or the `liga` feature.
+#[derive(Debug, Copy, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Tag(pub [u8; 4]);
+
+impl Tag {
+    pub const fn new(tag: &[u8; 4]) -> Self {
+        Tag(*tag)
+    }
+}
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.0))
+    }
+}
+
+/// OpenType features every backend uses for shaping text by default.
+///
+/// Standard ligatures, contextual alternates, kerning and mark positioning are enabled, while
+/// discretionary and historical ligatures have to be enabled explicitly.
+pub const DEFAULT_FEATURES: [(Tag, u32); 8] = [
+    (Tag::new(b"liga"), 1),
+    (Tag::new(b"clig"), 1),
+    (Tag::new(b"calt"), 1),
+    (Tag::new(b"kern"), 1),
+    (Tag::new(b"mark"), 1),
+    (Tag::new(b"mkmk"), 1),
+    (Tag::new(b"dlig"), 0),
+    (Tag::new(b"hlig"), 0),
+];
+
+/// Combine the `DEFAULT_FEATURES` with features overriding them.
+fn shaping_features(features: &[(Tag, u32)]) -> Vec<(Tag, u32)> {
+    let mut combined = DEFAULT_FEATURES.to_vec();
+    for &(tag, value) in features {
+        match combined.iter_mut().find(|(default, _)| *default == tag) {
+            Some(feature) => feature.1 = value,
+            None => combined.push((tag, value)),
+        }
+    }
+    combined
+}
+
+/// Character variant feature of a font, from `cv01` to `cv99`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CharacterVariant {
+    /// Feature tag, like `cv01`.
+    pub tag: Tag,
+
+    /// Name of the feature for user interfaces.
+    pub label: Option<String>,
+
+    /// Description of the feature for user interfaces.
+    pub tooltip: Option<String>,
+
+    /// Text showing the effect of the feature.
+    pub sample_text: Option<String>,
+
+    /// Names of the alternates, for the feature values starting at `1`.
+    pub alternate_labels: Vec<String>,
+
+    /// Characters whose glyphs are changed by the feature.
+    pub characters: Vec<char>,
+
+    /// Number of alternates, selected with feature values from `1` up to this count.
+    pub alternate_count: u16,
+}
+
+/// File format of font data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FontFormat {
+    /// SFNT font with TrueType outlines, usually a `.ttf` or `.ttc` file.
+    TrueType,
+
+    /// SFNT font with CFF outlines, usually an `.otf` file.
+    OpenType,
+
+    /// Compressed SFNT font in the WOFF container.
+    Woff,
+
+    /// Compressed SFNT font in the WOFF2 container.
+    Woff2,
+
+    /// Any other format the backend can read, like Type 1 or PCF.
+    Other,
+}
+
+/// Descriptive and licensing information of a font.
+///
+/// The strings are taken from the font's `name` table, entries missing from the font are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FontMetadata {
+    /// Copyright notice, name ID 0.
+    pub copyright: Option<String>,
+
+    /// Trademark notice, name ID 7.
+    pub trademark: Option<String>,
+
+    /// Name of the designer, name ID 9.
+    pub designer: Option<String>,
+
+    /// Description of the license, name ID 13.
+    pub license: Option<String>,
+
+    /// URL of the license, name ID 14.
+    pub license_url: Option<String>,
+
+    /// Embedding permissions from the OS/2 table's `fsType`.
+    pub embedding: EmbeddingPermissions,
+}
+
+/// Restrictions on embedding a font into documents.
+///
+/// Fonts without any restriction, or without an OS/2 table, are installable.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct EmbeddingPermissions {
+    /// The font must not be embedded without the legal owner's permission.
+    pub restricted: bool,
+
+    /// The font may only be embedded for viewing and printing documents.
+    pub preview_and_print: bool,
+
+    /// The font may be embedded into documents which can be edited.
+    pub editable: bool,
+
+    /// The font must be embedded completely, without subsetting it.
+    pub no_subsetting: bool,
+
+    /// Only the bitmaps of the font may be embedded.
+    pub bitmap_only: bool,
+}
+
+impl EmbeddingPermissions {
+    /// Check if the font may be embedded and installed permanently.
+    pub fn is_installable(&self) -> bool {
+        !self.restricted && !self.preview_and_print && !self.editable
+    }
+}
+
+/// Features supported by a rasterizer, with the backend and crate features it was built with.
+///
+/// Methods of unsupported features return `Error::Unsupported`.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Capabilities {
+    /// Color glyphs like emoji, rasterized as `BitmapBuffer::Rgba`.
+    pub color_glyphs: bool,
+
+    /// Access to glyph outlines, for strokes, transforms and distance fields.
+    pub outlines: bool,
+
+    /// Rendering at fractional pen positions with `Rasterize::get_glyph_subpixel`.
+    pub subpixel_positioning: bool,
+
+    /// Subpixel antialiasing with separate coverage for the red, green and blue channels.
+    pub lcd_subpixel: bool,
+
+    /// Text shaping with `RasterizeExt::shape`.
+    pub shaping: bool,
+
+    /// Text shaping with the platform's text stack instead of HarfBuzz.
+    pub native_shaping: bool,
+
+    /// Variation axes of variable fonts, like the optical size.
+    pub variable_fonts: bool,
+
+    /// Vertical glyph metrics and alternates for vertical text.
+    pub vertical_metrics: bool,
+
+    /// Signed distance fields with `Rasterize::get_glyph_sdf`.
+    pub sdf: bool,
+
+    /// Multi-channel signed distance fields, which require the `msdf` feature.
+    pub msdf: bool,
+}
+
+/// Information about font data, gathered without loading it into a rasterizer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontProbe {
+    /// Family name of the first face.
+    pub family: String,
+
+    /// Style name of the first face, like `Bold Italic`.
+    pub style: String,
+
+    /// Whether the first face has color glyphs.
+    pub is_color: bool,
+
+    /// Whether all glyphs of the first face have the same advance.
+    pub is_monospace: bool,
+
+    /// Number of glyphs in the first face.
+    pub glyph_count: u32,
+
+    /// Number of faces in the data, more than one for font collections.
+    pub num_faces: u32,
+
+    /// File format of the data.
+    pub format: FontFormat,
+}
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub struct GlyphKey {
     pub character: char,
@@ -108,6 +637,20 @@ pub struct GlyphKey {
     pub size: Size,
 }
 
+impl Display for GlyphKey {
+    /// Control characters are escaped, like `'\n' (U+000A) in font 3 at 12pt`.
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} (U+{:04X}) in {} at {}pt",
+            self.character,
+            u32::from(self.character),
+            self.font_key,
+            self.size.as_f32_pts()
+        )
+    }
+}
+
 /// Captures possible outcomes of shaping, if shaping succeeded it will return a `GlyphIndex`.
 /// If shaping failed or did not occur, `Fallback` will be returned.
 #[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
@@ -159,6 +702,33 @@ impl Size {
     pub fn as_f32_pts(self) -> f32 {
         f32::from(self.0) / Size::factor()
     }
+
+    /// Create a new `Size` from a size in pixels at a device pixel ratio.
+    ///
+    /// This is the inverse of `as_px`, the size is rounded to the nearest representable size.
+    pub fn from_px(px: f32, dpr: f32) -> Size {
+        let pts = px / (dpr * Size::pixels_per_point());
+        Size((pts * Size::factor()).round() as i16)
+    }
+
+    /// Get the size in pixels at a device pixel ratio.
+    ///
+    /// Backends rasterize a size of `pts` points at `pts * dpr * pixels_per_point()` pixels.
+    pub fn as_px(self, dpr: f32) -> f32 {
+        self.as_f32_pts() * dpr * Size::pixels_per_point()
+    }
+
+    /// Pixels per point at a device pixel ratio of `1.`.
+    ///
+    /// CoreText uses 72 DPI, while FreeType and DirectWrite use 96 DPI.
+    #[inline]
+    pub fn pixels_per_point() -> f32 {
+        if cfg!(target_os = "macos") {
+            1.
+        } else {
+            96. / 72.
+        }
+    }
 }
 
 impl<T: Into<Size>> Add<T> for Size {
@@ -188,113 +758,1678 @@ pub struct RasterizedGlyph {
     pub character: char,
     pub width: i32,
     pub height: i32,
+    /// Bearings snapped to the pixel grid of `buffer`.
     pub top: i32,
     pub left: i32,
+    /// Unrounded bearings of the glyph's outline.
+    ///
+    /// These are not aligned with `buffer`, but can be used to position glyphs at their exact
+    /// location instead of the pixel-snapped `top` and `left`.
+    pub top_f: f32,
+    pub left_f: f32,
     pub advance: (i32, i32),
+    /// Unrounded glyph advance.
+    pub advance_f: (f32, f32),
+    /// Unrounded advance of a fallback glyph, before it was snapped to the primary font's cells.
+    ///
+    /// This is only set with `Rasterize::set_fallback_cell_advance`, `advance` and `advance_f`
+    /// hold the snapped advance then.
+    pub native_advance: Option<(f32, f32)>,
     pub buffer: BitmapBuffer,
-}
 
-#[derive(Clone, Debug)]
-pub enum BitmapBuffer {
-    /// RGB alphamask.
-    Rgb(Vec<u8>),
+    /// Font the glyph was rasterized with.
+    ///
+    /// FreeType reports the fallback font's key when the requested font does not contain the
+    /// glyph, other backends always report the requested font.
+    pub font_key: FontKey,
 
-    /// RGBA pixels with premultiplied alpha.
-    Rgba(Vec<u8>),
+    /// Device pixel ratio the glyph was rasterized for.
+    ///
+    /// Glyphs rasterized at a size in pixels always report a ratio of `1.`.
+    pub dpr: f32,
 }
 
-impl Default for RasterizedGlyph {
-    fn default() -> RasterizedGlyph {
-        RasterizedGlyph {
-            character: ' ',
-            width: 0,
-            height: 0,
-            top: 0,
-            left: 0,
-            advance: (0, 0),
-            buffer: BitmapBuffer::Rgb(Vec::new()),
-        }
-    }
-}
+/// Coordinate system of a glyph's `top` and `left` bearings.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GlyphOrigin {
+    /// Bearings are relative to the pen position on the baseline.
+    ///
+    /// `left` is the distance from the pen position to the bitmap's left edge and `top` is the
+    /// distance from the baseline up to the bitmap's top edge.
+    Baseline,
 
-struct BufDebugger<'a>(&'a [u8]);
+    /// Bearings are relative to the top-left corner of a cell with the baseline `ascent` pixels
+    /// below its top edge.
+    ///
+    /// `left` is unchanged and `top` is the distance from the cell's top edge down to the bitmap's
+    /// top edge, so `top == ascent - baseline_top`.
+    TopLeft { ascent: i32 },
+}
 
-impl<'a> fmt::Debug for BufDebugger<'a> {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.debug_struct("GlyphBuffer").field("len", &self.0.len()).field("bytes", &self.0).finish()
+impl Default for GlyphOrigin {
+    fn default() -> Self {
+        GlyphOrigin::Baseline
     }
 }
 
-#[derive(Debug, Copy, Clone)]
-pub struct Metrics {
-    pub average_advance: f64,
-    pub line_height: f64,
-    pub descent: f32,
-    pub underline_position: f32,
-    pub underline_thickness: f32,
-    pub strikeout_position: f32,
-    pub strikeout_thickness: f32,
+impl GlyphOrigin {
+    /// Convert a glyph rasterized with baseline bearings to this origin.
+    fn apply(self, glyph: &mut RasterizedGlyph) {
+        if let GlyphOrigin::TopLeft { ascent } = self {
+            glyph.top = ascent - glyph.top;
+            glyph.top_f = ascent as f32 - glyph.top_f;
+        }
+    }
 }
 
-/// Errors occuring when using the rasterizer.
-#[derive(Debug)]
-pub enum Error {
-    /// Unable to find a font matching the description.
-    FontNotFound(FontDesc),
-
-    /// Unable to find metrics for a font face.
-    MetricsNotFound,
+/// Conversion of fractional pixel positions to whole pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum RoundingMode {
+    /// Round toward zero, like an `as` cast.
+    Truncate,
 
-    /// The glyph could not be found in any font.
-    MissingGlyph(RasterizedGlyph),
+    /// Round to the nearest pixel, with halfway cases rounded away from zero.
+    Round,
 
-    /// Requested an operation with a FontKey that isn't known to the rasterizer.
-    UnknownFontKey,
+    /// Round toward negative infinity.
+    Floor,
 
-    /// Error from platfrom's font system.
-    PlatformError(String),
+    /// Round toward positive infinity.
+    Ceil,
 }
 
-impl std::error::Error for Error {
-    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
-        None
+impl Default for RoundingMode {
+    fn default() -> Self {
+        RoundingMode::Round
     }
 }
 
-impl Display for Error {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+impl RoundingMode {
+    /// Convert a fractional position in pixels to whole pixels.
+    pub fn round(self, value: f32) -> i32 {
         match self {
-            Error::FontNotFound(font) => write!(f, "font {:?} not found", font),
-            Error::MissingGlyph(glyph) => {
-                write!(f, "glyph for character {:?} not found", glyph.character)
-            },
-            Error::UnknownFontKey => f.write_str("invalid font key"),
-            Error::MetricsNotFound => f.write_str("metrics not found"),
-            Error::PlatformError(err) => write!(f, "{}", err),
+            RoundingMode::Truncate => value as i32,
+            RoundingMode::Round => value.round() as i32,
+            RoundingMode::Floor => value.floor() as i32,
+            RoundingMode::Ceil => value.ceil() as i32,
         }
     }
 }
 
-pub trait Rasterize {
-    /// Create a new Rasterizer.
-    fn new(device_pixel_ratio: f32) -> Result<Self, Error>
-    where
-        Self: Sized;
+/// Scaling of glyphs from fallback fonts to the primary font's cell size.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FallbackScaling {
+    /// Render fallback glyphs at the requested size.
+    None,
 
-    /// Get `Metrics` for the given `FontKey`.
-    fn metrics(&self, _: FontKey, _: Size) -> Result<Metrics, Error>;
+    /// Shrink glyphs wider than their cells, which is two cells for wide characters.
+    FitWidth,
 
-    /// Load the font described by `FontDesc` and `Size`.
+    /// Shrink glyphs wider than their cells or taller than the line height.
+    FitCell,
+}
+
+impl Default for FallbackScaling {
+    fn default() -> Self {
+        FallbackScaling::None
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum BitmapBuffer {
+    /// RGB alphamask.
+    Rgb(Vec<u8>),
+
+    /// RGBA pixels with premultiplied alpha.
+    Rgba(Vec<u8>),
+
+    /// Grayscale alphamask from `0.0` to `1.0`.
+    ///
+    /// Produced instead of `Rgb` with `AlphaPrecision::F32`. Subpixel coverage is averaged.
+    GrayF32(Vec<f32>),
+
+    /// BGRA pixels with premultiplied alpha.
+    ///
+    /// Produced instead of `Rgba` with `ColorOrder::Bgra`.
+    Bgra(Vec<u8>),
+}
+
+/// Numeric type of the coverage in glyph alphamasks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum AlphaPrecision {
+    /// 8-bit coverage in `BitmapBuffer::Rgb`.
+    U8,
+
+    /// Normalized coverage in `BitmapBuffer::GrayF32`.
+    ///
+    /// Rasterizers produce 8-bit coverage, so this doesn't add precision. It only avoids
+    /// converting the coverage for compositing in floating point.
+    F32,
+}
+
+impl Default for AlphaPrecision {
+    fn default() -> Self {
+        AlphaPrecision::U8
+    }
+}
+
+/// Channel order of color glyph pixels.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ColorOrder {
+    /// Red, green, blue and alpha in `BitmapBuffer::Rgba`.
+    Rgba,
+
+    /// Blue, green, red and alpha in `BitmapBuffer::Bgra`, like Direct3D's and Metal's
+    /// `B8G8R8A8` texture formats.
+    Bgra,
+}
+
+impl Default for ColorOrder {
+    fn default() -> Self {
+        ColorOrder::Rgba
+    }
+}
+
+/// Placement of a glyph's stroke relative to its outline.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum StrokeStyle {
+    /// Stroke centered on the outline.
+    Center,
+
+    /// Stroke inside of the outline, which doesn't grow the glyph.
+    Inside,
+
+    /// Stroke outside of the outline.
+    Outside,
+}
+
+/// Version of FreeType's TrueType bytecode interpreter.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TtInterpreter {
+    /// Classic interpreter executing all instructions, for sharp but distorted glyphs.
+    Classic35,
+
+    /// Subpixel hinting with per-font compatibility tweaks, if FreeType was built with it.
+    Minimal38,
+
+    /// Subpixel hinting which only snaps vertically, like ClearType.
+    Subpixel40,
+}
+
+impl Default for TtInterpreter {
+    fn default() -> Self {
+        TtInterpreter::Subpixel40
+    }
+}
+
+/// Color space used to blend glyphs with their background.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlendMode {
+    /// Blend the sRGB encoded values directly.
+    ///
+    /// This makes dark text on light backgrounds look bolder and light text on dark backgrounds
+    /// look thinner than intended.
+    Srgb,
+
+    /// Blend in linear light, after decoding the sRGB transfer function.
+    Linear,
+
+    /// Blend after raising the values to the power of the gamma, like `2.2` or `1.8`.
+    GammaCorrected(f32),
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Srgb
+    }
+}
+
+impl BlendMode {
+    /// Convert a color channel from `0` to `1` into the color space used for blending.
+    fn to_blend_space(self, value: f32) -> f32 {
+        match self {
+            BlendMode::Srgb => value,
+            BlendMode::Linear if value <= 0.04045 => value / 12.92,
+            BlendMode::Linear => ((value + 0.055) / 1.055).powf(2.4),
+            BlendMode::GammaCorrected(gamma) => value.powf(gamma),
+        }
+    }
+
+    /// Convert a blended color channel from `0` to `1` back to sRGB.
+    fn to_srgb(self, value: f32) -> f32 {
+        match self {
+            BlendMode::Srgb => value,
+            BlendMode::Linear if value <= 0.0031308 => value * 12.92,
+            BlendMode::Linear => 1.055 * value.powf(1. / 2.4) - 0.055,
+            BlendMode::GammaCorrected(gamma) => value.powf(1. / gamma),
+        }
+    }
+}
+
+/// Inclusive range of characters which can be routed to a font.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct UnicodeBlock {
+    pub first: char,
+    pub last: char,
+}
+
+impl UnicodeBlock {
+    pub const CJK_SYMBOLS_AND_PUNCTUATION: Self = Self::new('\u{3000}', '\u{303f}');
+    pub const CJK_UNIFIED_IDEOGRAPHS: Self = Self::new('\u{4e00}', '\u{9fff}');
+    pub const EMOTICONS: Self = Self::new('\u{1f600}', '\u{1f64f}');
+    pub const HALFWIDTH_AND_FULLWIDTH_FORMS: Self = Self::new('\u{ff00}', '\u{ffef}');
+    pub const HANGUL_SYLLABLES: Self = Self::new('\u{ac00}', '\u{d7af}');
+    pub const HIRAGANA: Self = Self::new('\u{3040}', '\u{309f}');
+    pub const KATAKANA: Self = Self::new('\u{30a0}', '\u{30ff}');
+    pub const MISCELLANEOUS_SYMBOLS_AND_PICTOGRAPHS: Self = Self::new('\u{1f300}', '\u{1f5ff}');
+    pub const SUPPLEMENTAL_SYMBOLS_AND_PICTOGRAPHS: Self = Self::new('\u{1f900}', '\u{1f9ff}');
+
+    /// Block of all characters from `first` to `last`.
+    pub const fn new(first: char, last: char) -> Self {
+        Self { first, last }
+    }
+
+    /// Check if the block contains a character.
+    pub fn contains(self, character: char) -> bool {
+        (self.first..=self.last).contains(&character)
+    }
+}
+
+/// Character map used to look up the glyphs of characters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum CharmapPref {
+    /// Only use the font's Unicode character map.
+    Unicode,
+
+    /// Only use the font's symbol character map.
+    ///
+    /// Symbol fonts map their glyphs to `U+F020` to `U+F0FF`, but are often used with the 8-bit
+    /// codes of these characters, so both `U+0041` and `U+F041` find the same glyph.
+    Symbol,
+
+    /// Use the Unicode character map and fall back to the symbol character map.
+    Auto,
+}
+
+impl Default for CharmapPref {
+    fn default() -> Self {
+        CharmapPref::Unicode
+    }
+}
+
+/// Pixel format of a rasterized glyph.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GlyphFormat {
+    /// Monochrome coverage, stored as `BitmapBuffer::Rgb` with every sample either `0` or `255`.
+    Mono,
+
+    /// Grayscale coverage, stored as `BitmapBuffer::Rgb` with the same value in all channels.
+    Gray8,
+
+    /// Subpixel coverage, stored as `BitmapBuffer::Rgb` with one value per channel.
+    SubpixelRgb,
+
+    /// Color pixels, stored as `BitmapBuffer::Rgba` or `BitmapBuffer::Bgra`.
+    ColorRgba,
+}
+
+/// Outline format of a font.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum OutlineKind {
+    /// Quadratic TrueType outlines, `hinted` if the font ships a `fpgm` or `prep` program.
+    TrueType { hinted: bool },
+
+    /// Cubic outlines from a `CFF ` or `CFF2` table, `hinted` if the font defines alignment zones.
+    Cff { hinted: bool },
+
+    /// Only embedded bitmaps without any outlines.
+    Bitmap,
+
+    /// Neither outlines nor bitmaps in a known format.
+    None,
+}
+
+impl Default for RasterizedGlyph {
+    fn default() -> RasterizedGlyph {
+        RasterizedGlyph {
+            character: ' ',
+            width: 0,
+            height: 0,
+            top: 0,
+            left: 0,
+            top_f: 0.,
+            left_f: 0.,
+            advance: (0, 0),
+            advance_f: (0., 0.),
+            native_advance: None,
+            buffer: BitmapBuffer::Rgb(Vec::new()),
+            font_key: FontKey { token: 0 },
+            dpr: 1.,
+        }
+    }
+}
+
+impl RasterizedGlyph {
+    /// Convert an `Rgb` alphamask to the coverage type of the `precision`.
+    pub(crate) fn with_alpha_precision(mut self, precision: AlphaPrecision) -> Self {
+        if let (AlphaPrecision::F32, BitmapBuffer::Rgb(pixels)) = (precision, &self.buffer) {
+            let coverage = pixels
+                .chunks_exact(3)
+                .map(|rgb| rgb.iter().map(|&value| f32::from(value)).sum::<f32>() / (3. * 255.))
+                .collect();
+            self.buffer = BitmapBuffer::GrayF32(coverage);
+        }
+
+        self
+    }
+
+    /// Swap the red and blue channels of color pixels for the `ColorOrder`.
+    pub(crate) fn with_color_order(mut self, order: ColorOrder) -> Self {
+        if let (ColorOrder::Bgra, BitmapBuffer::Rgba(pixels)) = (order, &mut self.buffer) {
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            self.buffer = BitmapBuffer::Bgra(std::mem::take(pixels));
+        }
+
+        self
+    }
+
+    /// Composite the glyph over a background color filling a cell of `cell` pixels.
+    ///
+    /// The colors are RGBA without premultiplied alpha, the result uses premultiplied alpha like
+    /// all `BitmapBuffer::Rgba` buffers. With baseline bearings the baseline is placed where the
+    /// `metrics` put it in a line, with lines centered vertically in taller or shorter cells.
+    /// Colors are mixed in the color space of the `blend_mode`.
+    pub(crate) fn on_background(
+        &self,
+        origin: GlyphOrigin,
+        metrics: &Metrics,
+        blend_mode: BlendMode,
+        fg: [u8; 4],
+        bg: [u8; 4],
+        cell: (u32, u32),
+    ) -> RasterizedGlyph {
+        let (cell_width, cell_height) = (cell.0 as i32, cell.1 as i32);
+
+        // Distance from the cell's top edge down to the baseline, if bearings are relative to it.
+        let baseline = match origin {
+            GlyphOrigin::Baseline => {
+                let line_height = metrics.line_height.round() as i32;
+                let baseline = (metrics.line_height + f64::from(metrics.descent)).round() as i32;
+                Some((cell_height - line_height) / 2 + baseline)
+            },
+            GlyphOrigin::TopLeft { .. } => None,
+        };
+        let glyph_y = baseline.map_or(self.top, |baseline| baseline - self.top);
+
+        // Colors are blended as premultiplied values from `0` to `1` in the blending color space.
+        let premultiply = |color: [u8; 4]| -> [f32; 4] {
+            let alpha = f32::from(color[3]) / 255.;
+            let channel = |value: u8| blend_mode.to_blend_space(f32::from(value) / 255.) * alpha;
+            [channel(color[0]), channel(color[1]), channel(color[2]), alpha]
+        };
+        let (fg, bg) = (premultiply(fg), premultiply(bg));
+
+        let mut buffer = Vec::with_capacity((cell_width * cell_height * 4) as usize);
+        for y in 0..cell_height {
+            for x in 0..cell_width {
+                let (glyph_x, glyph_y) = (x - self.left, y - glyph_y);
+                let inside =
+                    (0..self.width).contains(&glyph_x) && (0..self.height).contains(&glyph_y);
+                let index = (glyph_y * self.width + glyph_x) as usize;
+
+                // Premultiplied source color and per-channel coverage of the source's alpha.
+                let (source, coverage) = match &self.buffer {
+                    _ if !inside => ([0.; 4], [0.; 3]),
+                    BitmapBuffer::Rgb(pixels) => {
+                        let coverage = [
+                            f32::from(pixels[index * 3]) / 255.,
+                            f32::from(pixels[index * 3 + 1]) / 255.,
+                            f32::from(pixels[index * 3 + 2]) / 255.,
+                        ];
+                        let max = coverage[0].max(coverage[1]).max(coverage[2]);
+                        let source = [
+                            fg[0] * coverage[0],
+                            fg[1] * coverage[1],
+                            fg[2] * coverage[2],
+                            fg[3] * max,
+                        ];
+                        let alpha = fg[3];
+                        (source, [coverage[0] * alpha, coverage[1] * alpha, coverage[2] * alpha])
+                    },
+                    BitmapBuffer::GrayF32(pixels) => {
+                        let coverage = pixels[index];
+                        let source = [
+                            fg[0] * coverage,
+                            fg[1] * coverage,
+                            fg[2] * coverage,
+                            fg[3] * coverage,
+                        ];
+                        (source, [coverage * fg[3]; 3])
+                    },
+                    BitmapBuffer::Rgba(pixels) | BitmapBuffer::Bgra(pixels) => {
+                        let mut pixel = [0; 4];
+                        pixel.copy_from_slice(&pixels[index * 4..index * 4 + 4]);
+                        if let BitmapBuffer::Bgra(_) = self.buffer {
+                            pixel.swap(0, 2);
+                        }
+                        let alpha = f32::from(pixel[3]) / 255.;
+                        let channel = |value: u8| {
+                            let value =
+                                if alpha > 0. { f32::from(value) / 255. / alpha } else { 0. };
+                            blend_mode.to_blend_space(value.min(1.)) * alpha
+                        };
+                        let source =
+                            [channel(pixel[0]), channel(pixel[1]), channel(pixel[2]), alpha];
+                        (source, [alpha; 3])
+                    },
+                };
+
+                let max_coverage = coverage[0].max(coverage[1]).max(coverage[2]);
+                let alpha = (source[3] + bg[3] * (1. - max_coverage)).min(1.);
+                for channel in 0..3 {
+                    let value = source[channel] + bg[channel] * (1. - coverage[channel]);
+                    let value = if alpha > 0. {
+                        blend_mode.to_srgb((value / alpha).min(1.)) * alpha
+                    } else {
+                        0.
+                    };
+                    buffer.push((value * 255.).round() as u8);
+                }
+                buffer.push((alpha * 255.).round() as u8);
+            }
+        }
+
+        let top = baseline.unwrap_or(0);
+        RasterizedGlyph {
+            character: self.character,
+            width: cell_width,
+            height: cell_height,
+            top,
+            left: 0,
+            top_f: top as f32,
+            left_f: 0.,
+            advance: self.advance,
+            advance_f: self.advance_f,
+            native_advance: self.native_advance,
+            buffer: BitmapBuffer::Rgba(buffer),
+            font_key: self.font_key,
+            dpr: self.dpr,
+        }
+    }
+}
+
+/// Printable ASCII glyphs rasterized into a single buffer.
+#[derive(Debug, Clone, Default)]
+pub struct AsciiAtlas {
+    /// Pixels of all glyphs, stored back to back.
+    pub buffer: Vec<u8>,
+
+    /// Glyphs from `AsciiAtlas::FIRST` to `AsciiAtlas::LAST`, in order.
+    pub glyphs: Vec<AtlasGlyph>,
+}
+
+impl AsciiAtlas {
+    /// First character in the atlas.
+    pub const FIRST: char = ' ';
+    /// Last character in the atlas.
+    pub const LAST: char = '~';
+
+    /// Create an empty atlas with space for `buffer_size` bytes of pixels.
+    pub(crate) fn with_capacity(buffer_size: usize) -> Self {
+        let glyph_count = Self::LAST as usize - Self::FIRST as usize + 1;
+        Self { buffer: Vec::with_capacity(buffer_size), glyphs: Vec::with_capacity(glyph_count) }
+    }
+
+    /// Move the pixels of the next glyph into the atlas.
+    ///
+    /// The glyph keeps an empty buffer for its pixel format.
+    pub(crate) fn push(&mut self, mut glyph: RasterizedGlyph) {
+        let pixels = match &mut glyph.buffer {
+            BitmapBuffer::Rgb(pixels) | BitmapBuffer::Rgba(pixels) | BitmapBuffer::Bgra(pixels) => {
+                std::mem::take(pixels)
+            },
+            BitmapBuffer::GrayF32(coverage) => {
+                let pixels =
+                    coverage.iter().flat_map(|&alpha| [(alpha * 255.).round() as u8; 3]).collect();
+                glyph.buffer = BitmapBuffer::Rgb(Vec::new());
+                pixels
+            },
+        };
+
+        let start = self.buffer.len();
+        self.buffer.extend_from_slice(&pixels);
+        self.glyphs.push(AtlasGlyph { glyph, range: start..self.buffer.len() });
+    }
+
+    /// Get a glyph and its pixels.
+    pub fn get(&self, character: char) -> Option<(&RasterizedGlyph, &[u8])> {
+        let index = (character as usize).checked_sub(Self::FIRST as usize)?;
+        let atlas_glyph = self.glyphs.get(index)?;
+        Some((&atlas_glyph.glyph, &self.buffer[atlas_glyph.range.clone()]))
+    }
+}
+
+/// Glyph stored in an `AsciiAtlas`.
+#[derive(Debug, Clone)]
+pub struct AtlasGlyph {
+    /// Glyph metrics.
+    ///
+    /// The glyph's `buffer` is empty, its variant tells whether the pixels are RGB or RGBA.
+    pub glyph: RasterizedGlyph,
+
+    /// Location of the glyph's pixels in the atlas buffer.
+    pub range: Range<usize>,
+}
+
+struct BufDebugger<'a>(&'a [u8]);
+
+impl<'a> fmt::Debug for BufDebugger<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GlyphBuffer").field("len", &self.0.len()).field("bytes", &self.0).finish()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Metrics {
+    pub average_advance: f64,
+
+    /// Distance between two baselines.
+    ///
+    /// This is the font's ascent minus its `descent` plus the `line_gap`.
+    pub line_height: f64,
+    pub descent: f32,
+
+    /// Recommended spacing between the descent of a line and the ascent of the next one.
+    ///
+    /// All backends resolve the vertical metrics from the font's tables in the same order:
+    /// the OS/2 typographic metrics if the `USE_TYPO_METRICS` flag is set, otherwise the hhea
+    /// metrics and finally the OS/2 Windows metrics without a line gap.
+    pub line_gap: f32,
+    pub underline_position: f32,
+    pub underline_thickness: f32,
+    pub strikeout_position: f32,
+    pub strikeout_thickness: f32,
+
+    /// Position of the overline's center relative to the baseline.
+    ///
+    /// OpenType fonts don't have overline metrics, so the overline is as thick as the underline
+    /// and its top edge is aligned with the font's ascent, right above the capital letters.
+    pub overline_position: f32,
+    pub overline_thickness: f32,
+}
+
+impl Metrics {
+    /// Width in pixels of a tab stop spanning `spaces` cells.
+    ///
+    /// This uses the `average_advance` as cell width, for monospace grids where every column has
+    /// the same width. Use `Rasterize::tab_width` to measure the font's space glyph instead.
+    pub fn tab_width(&self, spaces: u32) -> f32 {
+        (self.average_advance * f64::from(spaces)) as f32
+    }
+
+    /// Check if the cell size of `other` is within `tolerance` of these metrics.
+    ///
+    /// Both the `average_advance` and the `line_height` are compared, the `tolerance` is a
+    /// fraction of these metrics. A tolerance of `0.05` accepts differences of up to 5%.
+    pub fn is_compatible(&self, other: &Metrics, tolerance: f32) -> bool {
+        let within = |value: f64, other: f64| (value - other).abs() <= value * f64::from(tolerance);
+        within(self.average_advance, other.average_advance)
+            && within(self.line_height, other.line_height)
+    }
+
+    /// Pixel rectangle of a text decoration spanning `width_px` pixels.
+    ///
+    /// The rectangle is relative to the top-left corner of the line, which is `line_height`
+    /// pixels tall with the baseline `descent` pixels above its bottom. The decoration is centered
+    /// on its position, snapped to whole pixels and at least one pixel thick. It is moved up or
+    /// down if necessary to stay within the line.
+    pub fn decoration_rect(&self, kind: Decoration, width_px: f32) -> Rect {
+        let line_height = self.line_height as f32;
+        let baseline = line_height + self.descent;
+        let (position, thickness) = match kind {
+            Decoration::Underline => (self.underline_position, self.underline_thickness),
+            Decoration::Strikeout => (self.strikeout_position, self.strikeout_thickness),
+            Decoration::Overline => (self.overline_position, self.overline_thickness),
+        };
+
+        let height = thickness.round().max(1.);
+        let y = (baseline - position - height / 2.).round();
+        let y = y.min(line_height.round() - height).max(0.);
+
+        Rect { x: 0, y: y as i32, width: width_px.round() as i32, height: height as i32 }
+    }
+}
+
+/// Overline position and thickness derived from the ascent and the underline thickness.
+fn overline_metrics(ascent: f32, underline_thickness: f32) -> (f32, f32) {
+    (ascent - underline_thickness / 2., underline_thickness)
+}
+
+/// Line drawn along a run of text.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Decoration {
+    Underline,
+    Strikeout,
+    Overline,
+}
+
+/// Rectangle on the pixel grid.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+/// Ink extents in pixels relative to the pen position at the start of a shaped run.
+///
+/// Vertical positions are measured up from the baseline, so `top` is above `bottom`.
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct InkRect {
+    pub left: f32,
+    pub right: f32,
+    pub top: f32,
+    pub bottom: f32,
+}
+
+impl InkRect {
+    /// Check if the rectangle contains no ink.
+    pub fn is_empty(&self) -> bool {
+        self.left >= self.right || self.bottom >= self.top
+    }
+
+    /// Smallest rectangle containing both rectangles.
+    fn union(self, other: InkRect) -> InkRect {
+        InkRect {
+            left: self.left.min(other.left),
+            right: self.right.max(other.right),
+            top: self.top.max(other.top),
+            bottom: self.bottom.min(other.bottom),
+        }
+    }
+}
+
+/// Ink extents of shaped text.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RunExtents {
+    /// Union of the ink of all glyphs, empty if none of them has any ink.
+    pub bounds: InkRect,
+
+    /// Ink of every shaped glyph in visual order, empty for glyphs like spaces.
+    pub glyphs: Vec<InkRect>,
+}
+
+impl RunExtents {
+    /// Create the extents of a run from the ink of its glyphs.
+    pub(crate) fn new(glyphs: Vec<InkRect>) -> Self {
+        let bounds = glyphs
+            .iter()
+            .filter(|ink| !ink.is_empty())
+            .fold(None, |bounds: Option<InkRect>, &ink| {
+                Some(bounds.map_or(ink, |bounds| bounds.union(ink)))
+            })
+            .unwrap_or_default();
+        Self { bounds, glyphs }
+    }
+}
+
+/// Font backend used by a rasterizer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BackendKind {
+    /// FreeType and Fontconfig, used everywhere except macOS and Windows.
+    FreeType,
+
+    /// CoreText, used on macOS.
+    CoreText,
+
+    /// DirectWrite, used on Windows.
+    DirectWrite,
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            BackendKind::FreeType => "FreeType",
+            BackendKind::CoreText => "CoreText",
+            BackendKind::DirectWrite => "DirectWrite",
+        })
+    }
+}
+
+/// Errors occuring when using the rasterizer.
+#[derive(Debug)]
+pub enum Error {
+    /// Unable to find a font matching the description.
+    FontNotFound(FontDesc),
+
+    /// Unable to find metrics for a font face.
+    MetricsNotFound,
+
+    /// The glyph could not be found in any font.
+    ///
+    /// In strict mode this is also returned for glyphs which rasterized to an empty bitmap.
+    MissingGlyph(RasterizedGlyph),
+
+    /// Requested an operation with a FontKey that isn't known to the rasterizer.
+    UnknownFontKey,
+
+    /// Error from platfrom's font system.
+    PlatformError(String),
+
+    /// The operation can't be performed by the font backend.
+    ///
+    /// This is never returned for operations the backend supports, so it is safe to fall back to
+    /// an alternative implementation when it is encountered.
+    Unsupported { operation: &'static str, backend: BackendKind },
+}
+
+impl Error {
+    /// Error for an operation which isn't supported by the current backend.
+    pub(crate) fn unsupported(operation: &'static str) -> Error {
+        Error::Unsupported { operation, backend: BACKEND }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        None
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FontNotFound(font) => write!(f, "font {:?} not found", font),
+            Error::MissingGlyph(glyph) => {
+                write!(f, "glyph for character {:?} not found", glyph.character)
+            },
+            Error::UnknownFontKey => f.write_str("invalid font key"),
+            Error::MetricsNotFound => f.write_str("metrics not found"),
+            Error::PlatformError(err) => write!(f, "{}", err),
+            Error::Unsupported { operation, backend } => {
+                write!(f, "{} is not supported by the {} backend", operation, backend)
+            },
+        }
+    }
+}
+
+/// Font rasterization backend.
+///
+/// Methods which are not available on every backend document which backends support them, all
+/// other backends return [`Error::Unsupported`].
+pub trait Rasterize {
+    /// Create a new Rasterizer.
+    fn new(device_pixel_ratio: f32) -> Result<Self, Error>
+    where
+        Self: Sized;
+
+    /// Get `Metrics` for the given `FontKey` at `Size`.
+    fn metrics(&self, _: FontKey, _: Size) -> Result<Metrics, Error>;
+
+    /// Load the font described by `FontDesc`.
+    ///
+    /// The `Size` is only used as a hint for matching the font, the returned `FontKey` is not
+    /// bound to it and can be used with any size in `metrics` and `get_glyph`. Loading the same
+    /// `FontDesc` again with a different size will return the same `FontKey`.
     fn load_font(&mut self, _: &FontDesc, _: Size) -> Result<FontKey, Error>;
 
-    /// Rasterize the glyph described by `GlyphKey`..
-    fn get_glyph(&mut self, _: GlyphKey) -> Result<RasterizedGlyph, Error>;
+    /// Rasterize the glyph described by `GlyphKey`.
+    fn get_glyph(&mut self, _: GlyphKey) -> Result<RasterizedGlyph, Error>;
+
+    /// Rasterize the glyph described by `GlyphKey` with the pen moved `x_offset` pixels right
+    /// and `y_offset` pixels down.
+    ///
+    /// This allows rendering glyphs at fractional positions, like while scrolling smoothly. The
+    /// glyph's `top` and `left` bearings include the offsets. Supported by FreeType.
+    fn get_glyph_subpixel(
+        &mut self,
+        _: GlyphKey,
+        _x_offset: f32,
+        _y_offset: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_subpixel"))
+    }
+
+    /// Get font metrics for a size in pixels.
+    ///
+    /// Unlike `metrics`, the device pixel ratio is not applied. Supported by FreeType.
+    fn metrics_px(&self, _key: FontKey, _pixel_size: f32) -> Result<Metrics, Error> {
+        Err(Error::unsupported("metrics_px"))
+    }
+
+    /// List the styles available within a font family.
+    ///
+    /// Every face of the family is reduced to the nearest `Weight`, `Slant` and `Stretch`, so
+    /// the result tells which of the styles in a `FontDesc` exist without synthesizing them. The
+    /// styles are sorted and free of duplicates. Supported by FreeType.
+    fn family_styles(&self, _family: &str) -> Result<Vec<(Weight, Slant, Stretch)>, Error> {
+        Err(Error::unsupported("family_styles"))
+    }
+
+    /// Get font metrics for a device pixel ratio other than the rasterizer's.
+    ///
+    /// This allows using the same fonts on screens with different scales at the same time,
+    /// without changing the ratio used by `metrics`. Supported by FreeType.
+    fn metrics_dpr(&self, _key: FontKey, _size: Size, _dpr: f32) -> Result<Metrics, Error> {
+        Err(Error::unsupported("metrics_dpr"))
+    }
+
+    /// Pixel rectangle of a text decoration spanning `width_px` pixels.
+    ///
+    /// See `Metrics::decoration_rect` for how the rectangle is positioned. Supported by all
+    /// backends.
+    fn decoration_rect(
+        &self,
+        key: FontKey,
+        size: Size,
+        kind: Decoration,
+        width_px: f32,
+    ) -> Result<Rect, Error> {
+        Ok(self.metrics(key, size)?.decoration_rect(kind, width_px))
+    }
+
+    /// Distance in pixels from one baseline to the next.
+    ///
+    /// This is the ascent plus the absolute descent plus the line gap, from the first of these
+    /// metric sets present in the font: the OS/2 typographic metrics if the `USE_TYPO_METRICS`
+    /// flag is set, the hhea metrics, or the OS/2 Windows metrics without a line gap. The ascent
+    /// and descent are rounded away from the baseline to whole pixels and the line gap is
+    /// rounded to the nearest pixel, so this equals `Metrics::line_height`. Backends read these
+    /// metrics from the font's tables, so they should agree for the same font and pixel size, but
+    /// `Size` converts points to different pixel sizes per platform. Supported by all backends.
+    fn baseline_distance(&self, key: FontKey, size: Size) -> Result<f32, Error> {
+        Ok(self.metrics(key, size)?.line_height as f32)
+    }
+
+    /// Check if two fonts have the same cell size within `tolerance`.
+    ///
+    /// See `Metrics::is_compatible` for how the metrics are compared. Fonts without metrics
+    /// are never compatible. Supported by all backends.
+    fn metrics_compatible(&self, a: FontKey, b: FontKey, size: Size, tolerance: f32) -> bool {
+        match (self.metrics(a, size), self.metrics(b, size)) {
+            (Ok(a), Ok(b)) => a.is_compatible(&b, tolerance),
+            _ => false,
+        }
+    }
+
+    /// Rasterize a glyph as a signed distance field.
+    ///
+    /// Every pixel stores the distance to the glyph's outline, with `128` on the outline, higher
+    /// values inside and lower values outside of it. Distances are scaled so `0` and `255` are
+    /// `spread` pixels away from the outline, the bitmap is padded by `spread` pixels on every
+    /// side and `top`/`left` include the padding. Like coverage, the samples are stored in all
+    /// three channels of an RGB buffer.
+    ///
+    /// Supported by FreeType 2.11.0 and later, which limits the spread to 2 up to 32 pixels.
+    fn get_glyph_sdf(
+        &mut self,
+        _glyph_key: GlyphKey,
+        _spread: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_sdf"))
+    }
+
+    /// Rasterize a glyph as a multi-channel signed distance field.
+    ///
+    /// Edges of the outline are assigned to color channels so corners stay sharp, the glyph's
+    /// distance is the median of the red, green and blue samples of the RGB buffer. A median of
+    /// `128` is on the outline, with higher values inside of it and `0` and `255` being `range / 2`
+    /// pixels away from the outline. The bitmap is padded by `range / 2` pixels rounded up and
+    /// `top`/`left` include the padding.
+    ///
+    /// Supported by FreeType with the `msdf` feature, for glyphs with outlines.
+    fn get_glyph_msdf(
+        &mut self,
+        _glyph_key: GlyphKey,
+        _range: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_msdf"))
+    }
+
+    /// Rasterize a glyph with the font's native hinting and with the autohinter, for debugging.
+    ///
+    /// Returns the `(native, autohinted)` glyphs, which can be diffed to choose a hinting policy
+    /// for a font. The rasterizer's hinting configuration is not changed. Fonts without hinting
+    /// return the same unhinted glyph twice.
+    ///
+    /// Supported by FreeType with the `debug-images` feature.
+    fn compare_hinting(
+        &mut self,
+        _glyph_key: GlyphKey,
+    ) -> Result<(RasterizedGlyph, RasterizedGlyph), Error> {
+        Err(Error::unsupported("compare_hinting"))
+    }
+
+    /// Rasterize the stroke of a glyph's outline instead of its fill.
+    ///
+    /// The stroke is `stroke_width` pixels wide with round joins. Its `style` decides how much of
+    /// it lies outside of the outline, growing the bitmap's `width` and `height` and moving `left`
+    /// and `top` by that amount, while the advance is unchanged. Drawing the regular glyph on top
+    /// of an `Outside` stroke produces outlined text. Only glyphs with outlines can be stroked.
+    /// Supported by FreeType.
+    fn get_glyph_stroked(
+        &mut self,
+        _glyph_key: GlyphKey,
+        _stroke_width: f32,
+        _style: StrokeStyle,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_stroked"))
+    }
+
+    /// Rasterize a glyph with its outline transformed by a 2x2 matrix.
+    ///
+    /// The `matrix` is `[xx, xy, yx, yy]` and maps a point `(x, y)` of the outline to
+    /// `(xx * x + xy * y, yx * x + yy * y)`, with `y` pointing up from the baseline. A rotation by
+    /// 90° counterclockwise is `[0., -1., 1., 0.]`, a skew to the right is `[1., 0.2, 0., 1.]`.
+    /// The bitmap, `top` and `left` are computed for the transformed outline.
+    ///
+    /// The advance vector is transformed like the outline, so rotated glyphs advance along
+    /// `advance.1` instead of `advance.0`, with positive values pointing up. Vertical text should
+    /// still use its own line metrics, since the rotated horizontal advance isn't the glyph's
+    /// vertical advance. Only glyphs with outlines can be transformed. Supported by FreeType.
+    fn get_glyph_transformed(
+        &mut self,
+        _glyph_key: GlyphKey,
+        _matrix: [f32; 4],
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_transformed"))
+    }
+
+    /// Rasterize glyphs upright for top-to-bottom vertical text.
+    ///
+    /// Glyphs use the font's vertical metrics, with `top` and `left` relative to the vertical
+    /// origin at the top center of the glyph in its column. `advance.0` is zero and `advance.1`
+    /// the negative vertical advance, since positive values point up like for
+    /// `get_glyph_transformed`. Characters with vertical alternates from the `vert` and `vrt2`
+    /// features, like brackets and dashes, are replaced by their rotated forms. Disabled by
+    /// default. Supported by FreeType.
+    fn set_vertical(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_vertical"))
+    }
+
+    /// Rasterize a glyph composited over a background color filling a cell.
+    ///
+    /// The result is a color bitmap of exactly `cell` pixels in the `ColorOrder`, with the glyph
+    /// drawn in `fg` at the pen position on the cell's left edge. Both colors are RGBA without
+    /// premultiplied alpha, color glyphs keep their own colors. The colors are mixed according to
+    /// the `BlendMode`. The bitmap's `top` and `left` are the cell's top-left corner in the current
+    /// `GlyphOrigin`, so it is drawn like any other glyph. Parts of the glyph outside of the cell
+    /// are clipped. Supported by all backends.
+    fn get_glyph_on_background(
+        &mut self,
+        _glyph_key: GlyphKey,
+        _fg: [u8; 4],
+        _bg: [u8; 4],
+        _cell: (u32, u32),
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_on_background"))
+    }
+
+    /// Rasterize a glyph for a device pixel ratio other than the rasterizer's.
+    ///
+    /// The glyph is identical to the one `get_glyph` returns after `update_dpr` with the same
+    /// ratio, without affecting other glyphs. Supported by FreeType.
+    fn get_glyph_dpr(&mut self, _: GlyphKey, _dpr: f32) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_dpr"))
+    }
+
+    /// Rasterize a glyph at a size in pixels.
+    ///
+    /// Unlike `get_glyph`, the size is neither quantized nor scaled by the device pixel ratio.
+    /// Supported by FreeType.
+    fn get_glyph_px(
+        &mut self,
+        _font: FontKey,
+        _character: char,
+        _pixel_size: f32,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_px"))
+    }
+
+    /// Rasterize a glyph at multiples of its size, like for the levels of a mipmap.
+    ///
+    /// Every level is rendered at the `GlyphKey`'s size times the level, so `[1., 2., 4.]`
+    /// renders the glyph at its own size and twice and four times as large. The glyphs are in
+    /// the same order as the `levels`. The scaled sizes are quantized like any other `Size`.
+    /// Supported by all backends.
+    fn get_glyph_mips(
+        &mut self,
+        glyph_key: GlyphKey,
+        levels: &[f32],
+    ) -> Result<Vec<RasterizedGlyph>, Error> {
+        levels
+            .iter()
+            .map(|&level| {
+                let size = Size::new(glyph_key.size.as_f32_pts() * level);
+                self.get_glyph(GlyphKey { size, ..glyph_key })
+            })
+            .collect()
+    }
+
+    /// Rasterize a glyph, falling back to other fonts if `base` does not contain it.
+    ///
+    /// Fallback fonts are registered with the rasterizer and the glyph's `font_key` is set to
+    /// the font which contains the glyph. Supported by FreeType.
+    fn get_glyph_with_fallback(
+        &mut self,
+        _base: FontKey,
+        _character: char,
+        _size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_with_fallback"))
+    }
+
+    /// Rasterize the variant of `base` selected by the Unicode variation `selector`.
+    ///
+    /// The text (U+FE0E) and emoji (U+FE0F) presentation selectors choose between the regular and
+    /// the emoji font, other selectors pick glyphs from the font's variation sequences. The glyph
+    /// of `base` is used if the font has no glyph for the sequence. Supported by FreeType.
+    fn get_glyph_variant(
+        &mut self,
+        _font: FontKey,
+        _base: char,
+        _selector: char,
+        _size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_variant"))
+    }
+
+    /// Pixel format `get_glyph` produces for a glyph with the current settings.
+    ///
+    /// This resolves fallback fonts like `get_glyph`, but doesn't rasterize the glyph. Supported
+    /// by FreeType.
+    fn glyph_format(&mut self, _glyph_key: GlyphKey) -> Result<GlyphFormat, Error> {
+        Err(Error::unsupported("glyph_format"))
+    }
+
+    /// Rasterize a glyph by its index in the font instead of a character.
+    ///
+    /// This also reaches glyphs which aren't mapped to any character, like in many icon fonts.
+    /// Fallback fonts are never used and the glyph's `character` is `'\0'`. Supported by
+    /// FreeType.
+    fn get_glyph_by_index(
+        &mut self,
+        _font: FontKey,
+        _glyph_index: u32,
+        _size: Size,
+    ) -> Result<RasterizedGlyph, Error> {
+        Err(Error::unsupported("get_glyph_by_index"))
+    }
+
+    /// Set the coordinate system used for the `top` and `left` of rasterized glyphs.
+    ///
+    /// The default is `GlyphOrigin::Baseline`.
+    fn set_origin_convention(&mut self, origin: GlyphOrigin);
 
     /// Update the Rasterizer's DPI factor.
     fn update_dpr(&mut self, device_pixel_ratio: f32);
 
+    /// Device pixel ratio glyphs are rasterized for.
+    fn device_pixel_ratio(&self) -> f32;
+
     /// Kerning between two characters.
-    fn kerning(&mut self, left: GlyphKey, right: GlyphKey) -> (f32, f32);
+    ///
+    /// Characters without a kerning pair in the font have no kerning. Supported by FreeType.
+    fn kerning(&mut self, _left: GlyphKey, _right: GlyphKey) -> Result<(f32, f32), Error> {
+        Err(Error::unsupported("kerning"))
+    }
+
+    /// Check if the font contains a glyph for the character, without considering any fallbacks.
+    fn has_glyph(&self, key: FontKey, character: char) -> bool;
+
+    /// Load a CSS-like font stack like `"Fira Code", Menlo, monospace`.
+    ///
+    /// Every family which is available is loaded with `style`, unavailable families are skipped.
+    /// Generic families like `monospace` resolve to the platform's default font of that kind.
+    fn load_font_stack(&mut self, stack: &str, style: Style, size: Size) -> Result<FontStack, Error>
+    where
+        Self: Sized,
+    {
+        let mut keys = Vec::new();
+        for family in parse_font_stack(stack) {
+            let desc = match family.parse::<GenericFamily>() {
+                Ok(generic) => FontDesc::new(generic.family_name(), style.clone()),
+                Err(()) => FontDesc::new(family, style.clone()),
+            };
+            match self.load_font(&desc, size) {
+                Ok(key) if !keys.contains(&key) => keys.push(key),
+                Ok(_) => (),
+                Err(err) => debug!("Skipping font {} in font stack: {}", desc, err),
+            }
+        }
+
+        if keys.is_empty() {
+            return Err(Error::FontNotFound(FontDesc::new(stack, style)));
+        }
+
+        Ok(FontStack { keys })
+    }
+
+    /// Load the platform's default font for a generic family.
+    fn generic_family(&mut self, family: GenericFamily, size: Size) -> Result<FontKey, Error>
+    where
+        Self: Sized,
+    {
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        self.load_font(&FontDesc::new(family.family_name(), style), size)
+    }
+
+    /// Rasterize a character using the first font of the stack which contains it.
+    ///
+    /// If no font in the stack has the glyph, the platform's fallback of the primary font is used.
+    fn get_stack_glyph(
+        &mut self,
+        stack: &FontStack,
+        character: char,
+        size: Size,
+    ) -> Result<RasterizedGlyph, Error>
+    where
+        Self: Sized,
+    {
+        let font_key = stack
+            .keys()
+            .iter()
+            .copied()
+            .find(|key| self.has_glyph(*key, character))
+            .unwrap_or_else(|| stack.primary());
+
+        self.get_glyph(GlyphKey { character, font_key, size })
+    }
+
+    /// Features supported by this rasterizer.
+    ///
+    /// This allows enabling options depending on the backend, without calling methods which
+    /// might return `Error::Unsupported`.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
+    /// Human readable name of the font loaded for `FontKey`, like `DejaVu Sans Bold`.
+    ///
+    /// This is meant for logging, the format is not stable and differs between backends.
+    fn describe_key(&self, _key: FontKey) -> Option<String> {
+        None
+    }
+
+    /// Get the identifier of the physical face used by `FontKey`.
+    ///
+    /// Supported by FreeType, CoreText and DirectWrite.
+    fn face_id(&self, _key: FontKey) -> Result<FaceId, Error> {
+        Err(Error::unsupported("face_id"))
+    }
+
+    /// Number of glyphs in the font.
+    ///
+    /// Valid glyph indices for `get_glyph_by_index` are `0..glyph_count`. Supported by FreeType,
+    /// CoreText and DirectWrite.
+    fn glyph_count(&self, _key: FontKey) -> Result<u32, Error> {
+        Err(Error::unsupported("glyph_count"))
+    }
+
+    /// Characters mapped to a glyph index by the font's character map.
+    ///
+    /// Multiple characters can share a glyph, so all of them are returned in ascending order.
+    /// Glyphs which are only reachable through shaping, like ligatures, have no characters.
+    /// Supported by FreeType.
+    fn codepoints_for_glyph(&self, _key: FontKey, _glyph_index: u32) -> Result<Vec<char>, Error> {
+        Err(Error::unsupported("codepoints_for_glyph"))
+    }
+
+    /// Characters in the Unicode Private Use Areas mapped by the font, in ascending order.
+    ///
+    /// Icon fonts like Nerd Fonts place their glyphs in these areas, so this lists the icons
+    /// a font actually has. Codepoint remaps are not applied. Supported by FreeType.
+    fn private_use_characters(&self, _key: FontKey) -> Result<Vec<char>, Error> {
+        Err(Error::unsupported("private_use_characters"))
+    }
+
+    /// Look up the glyphs of characters with a different codepoint.
+    ///
+    /// Every character which is a key in `map` is rendered with the glyph of its value, in all
+    /// fonts including fallbacks. This redirects icons whose codepoints changed between versions
+    /// of an icon font. The rasterized glyph still reports the requested character. Replaces any
+    /// previous remap. Supported by FreeType.
+    fn set_codepoint_remap(&mut self, _map: HashMap<char, char>) -> Result<(), Error> {
+        Err(Error::unsupported("set_codepoint_remap"))
+    }
+
+    /// Check if a font is shaped with Apple Advanced Typography tables.
+    ///
+    /// Fonts from Apple often use `morx` or `mort` instead of `GSUB` for substitutions and `kerx`
+    /// instead of `GPOS` for positioning. When a font has no `GSUB` or `GPOS` table, HarfBuzz
+    /// shapes it with the AAT tables instead, which requires HarfBuzz to be built with AAT
+    /// support. That's the default, including for the bundled HarfBuzz. Supported by FreeType.
+    fn has_aat_layout(&self, _key: FontKey) -> Result<bool, Error> {
+        Err(Error::unsupported("has_aat_layout"))
+    }
+
+    /// Slant in degrees of a font's synthetic oblique, if its glyphs are sheared.
+    ///
+    /// Fontconfig shears upright fonts to synthesize italics when a family has no italic face.
+    /// Shears steeper than 20 degrees are clamped, so this reports the angle actually applied.
+    /// Positive angles lean to the right. Sheared glyphs keep the advance of the upright glyph,
+    /// their `left` and `width` include the slanted parts. Supported by FreeType.
+    fn synthetic_oblique_angle(&self, _key: FontKey) -> Result<Option<f32>, Error> {
+        Err(Error::unsupported("synthetic_oblique_angle"))
+    }
+
+    /// Outline format of a font and whether it carries hinting instructions.
+    ///
+    /// Unhinted fonts usually look better with light or no hinting, so this helps to pick a
+    /// default hinting mode per font. Supported by FreeType.
+    fn outline_kind(&self, _key: FontKey) -> Result<OutlineKind, Error> {
+        Err(Error::unsupported("outline_kind"))
+    }
+
+    /// Fonts to check for missing glyphs before the system's fallback fonts.
+    ///
+    /// The fonts are tried in order for every font, so glyphs missing from the requested font are
+    /// taken from the same fallback font on every platform. Fonts already loaded from bytes or
+    /// files are preferred over system fonts with the same family and style. Aliases like
+    /// `monospace` are rejected with `Error::FontNotFound`, since they resolve to a different
+    /// font on every system. An empty list restores the system fallback. Supported by FreeType.
+    fn set_fallback_order(&mut self, _fonts: Vec<FontDesc>) -> Result<(), Error> {
+        Err(Error::unsupported("set_fallback_order"))
+    }
+
+    /// Use a loaded font for all characters in a Unicode block.
+    ///
+    /// Routed fonts are tried after the emoji font and before the font requested in the
+    /// `GlyphKey`, as long as they contain a glyph for the character. Routing the same block
+    /// again replaces its font, with overlapping blocks the most recently routed one is used.
+    /// Supported by FreeType.
+    fn route_block(&mut self, _block: UnicodeBlock, _font: FontKey) -> Result<(), Error> {
+        Err(Error::unsupported("route_block"))
+    }
+
+    /// Use a loaded font for all characters which are displayed as emoji by default.
+    ///
+    /// The emoji font takes precedence over the font requested in the `GlyphKey`, as long as it
+    /// contains a glyph for the character. Supported by FreeType.
+    fn set_emoji_font(&mut self, _key: FontKey) -> Result<(), Error> {
+        Err(Error::unsupported("set_emoji_font"))
+    }
+
+    /// Add `px` pixels of spacing after every glyph requested with the font.
+    ///
+    /// This only changes the glyphs' advance, their bitmaps and bearings are not affected.
+    /// Negative tracking tightens the spacing, but never results in a negative advance. Supported
+    /// by FreeType.
+    fn set_tracking(&mut self, _key: FontKey, _px: f32) -> Result<(), Error> {
+        Err(Error::unsupported("set_tracking"))
+    }
+
+    /// Limit the number of font files kept open at the same time.
+    ///
+    /// The least recently used files are closed once the limit is exceeded, their fonts keep
+    /// working and open the file again on their next use. Fonts loaded from memory are never
+    /// closed and don't count toward the limit. The minimum is one file, by default the number
+    /// of open files is unlimited. Supported by FreeType.
+    fn set_max_open_faces(&mut self, _max: usize) -> Result<(), Error> {
+        Err(Error::unsupported("set_max_open_faces"))
+    }
+
+    /// Set the numeric type of the coverage in glyph alphamasks.
+    ///
+    /// With `AlphaPrecision::F32` glyphs use `BitmapBuffer::GrayF32` instead of
+    /// `BitmapBuffer::Rgb`, color glyphs and multi-channel signed distance fields are unchanged.
+    /// FreeType renders 8-bit coverage, so the values are just `coverage as f32 / 255.0`.
+    /// Supported by FreeType.
+    fn set_alpha_precision(&mut self, _precision: AlphaPrecision) -> Result<(), Error> {
+        Err(Error::unsupported("set_alpha_precision"))
+    }
+
+    /// Set the channel order of color glyphs.
+    ///
+    /// With `ColorOrder::Bgra` color glyphs and glyphs composited by `get_glyph_on_background`
+    /// use `BitmapBuffer::Bgra` instead of `BitmapBuffer::Rgba`, so they can be uploaded to BGRA
+    /// textures without swizzling. Alphamasks are unchanged. Supported by all backends.
+    fn set_color_order(&mut self, _order: ColorOrder) -> Result<(), Error> {
+        Err(Error::unsupported("set_color_order"))
+    }
+
+    /// Set the color space used to blend glyphs with their background.
+    ///
+    /// This only affects `get_glyph_on_background`, since other glyphs are blended by the caller.
+    /// The default is `BlendMode::Srgb`. `BlendMode::Linear` is recommended for light text on
+    /// dark themes, which looks too thin when blended in sRGB. Supported by all backends.
+    fn set_blend_mode(&mut self, _mode: BlendMode) -> Result<(), Error> {
+        Err(Error::unsupported("set_blend_mode"))
+    }
+
+    /// Select the character map used to find the glyphs of characters.
+    ///
+    /// Legacy icon fonts like the original FontAwesome only map their glyphs in a symbol
+    /// character map, which isn't used by default. `CharmapPref::Auto` finds these glyphs while
+    /// keeping the Unicode mapping for all other fonts. The default is `CharmapPref::Unicode`.
+    /// Supported by FreeType.
+    fn set_charmap_preference(&mut self, _pref: CharmapPref) -> Result<(), Error> {
+        Err(Error::unsupported("set_charmap_preference"))
+    }
+
+    /// Set how the fractional advance and bearings of glyphs are converted to whole pixels.
+    ///
+    /// The default is `RoundingMode::Round`, `RoundingMode::Truncate` matches older versions.
+    /// Glyphs with synthetic bold always round their advance up. Outlines are moved by less than
+    /// a pixel, so the `top` and `left` bearings of their bitmap are the rounded `top_f` and
+    /// `left_f`. Glyphs rendered at a subpixel position keep their exact bearings. Supported by
+    /// FreeType.
+    fn set_rounding(&mut self, _mode: RoundingMode) -> Result<(), Error> {
+        Err(Error::unsupported("set_rounding"))
+    }
+
+    /// Scale the strikes of bitmap fonts to the requested size.
+    ///
+    /// Bitmap fonts always use the strike closest to the requested size. Without scaling, which
+    /// is the default, glyphs and metrics have the strike's native size and the caller has to
+    /// handle the difference to the requested size. With scaling the strike is enlarged or
+    /// shrunk to the requested size without smoothing, with the advance and metrics scaled to
+    /// match. Color bitmap fonts are always scaled down. Supported by FreeType.
+    fn set_bitmap_scaling(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_bitmap_scaling"))
+    }
+
+    /// Set the TrueType bytecode interpreter used to hint glyphs.
+    ///
+    /// FreeType uses `TtInterpreter::Subpixel40` unless configured otherwise by the
+    /// `FREETYPE_PROPERTIES` environment variable, which is kept until this is called. Fonts
+    /// without TrueType outlines are not affected. Supported by FreeType.
+    fn set_tt_interpreter_version(&mut self, _version: TtInterpreter) -> Result<(), Error> {
+        Err(Error::unsupported("set_tt_interpreter_version"))
+    }
+
+    /// TrueType bytecode interpreter used to hint glyphs. Supported by FreeType.
+    fn tt_interpreter_version(&self) -> Result<TtInterpreter, Error> {
+        Err(Error::unsupported("tt_interpreter_version"))
+    }
+
+    /// Darken the stems of CFF glyphs, making small text heavier like on macOS.
+    ///
+    /// The `params` are the four `(stem width, darkening)` control points of FreeType's
+    /// `darkening-parameters`, in font units at 1000 units per em and with increasing stem widths.
+    /// `None` uses FreeType's defaults. Other outline formats are not affected. Disabled by
+    /// default. Supported by FreeType.
+    fn set_stem_darkening(
+        &mut self,
+        _enabled: bool,
+        _params: Option<[(u32, u32); 4]>,
+    ) -> Result<(), Error> {
+        Err(Error::unsupported("set_stem_darkening"))
+    }
+
+    /// Treat visible characters which rasterize to an empty bitmap as missing.
+    ///
+    /// Broken glyphs of some fonts have no pixels although their character should render. In
+    /// strict mode these are returned as `Error::MissingGlyph`, making such fonts easy to spot
+    /// during development. Whitespace and characters without an advance or cell width are exempt.
+    /// Disabled by default. Supported by FreeType.
+    fn set_strict(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_strict"))
+    }
+
+    /// Set how glyphs from fallback fonts are fit into the primary font's cells.
+    ///
+    /// Glyphs are shrunk uniformly by rendering them at a smaller size, so they keep their
+    /// aspect ratio and stay sharp. The number of cells is taken from `char_cell_width`. The
+    /// default is `FallbackScaling::None`. Supported by FreeType.
+    fn set_fallback_scaling(&mut self, _mode: FallbackScaling) -> Result<(), Error> {
+        Err(Error::unsupported("set_fallback_scaling"))
+    }
+
+    /// Snap the advance of glyphs from fallback fonts to the primary font's cells.
+    ///
+    /// Fallback glyphs then advance by one cell, or two for wide characters according to
+    /// `char_cell_width`, so they stay aligned to the primary font's grid. Their own advance is
+    /// kept in `RasterizedGlyph::native_advance`. Disabled by default. Supported by FreeType.
+    fn set_fallback_cell_advance(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_fallback_cell_advance"))
+    }
+
+    /// Enable or disable ligatures when shaping text.
+    ///
+    /// Disabling ligatures turns off the `liga`, `clig` and `calt` OpenType features, so sequences
+    /// like `->` are shaped as separate glyphs. Enabling them turns them back on, the `dlig` and
+    /// `hlig` ligatures always stay off like in the `DEFAULT_FEATURES`. Ligatures are enabled by
+    /// default. Supported by FreeType.
+    fn set_ligatures(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_ligatures"))
+    }
+
+    /// Draw box drawing and block element glyphs instead of using the font's glyphs.
+    ///
+    /// The glyphs for all characters matching `is_builtin_glyph` are drawn to fill a cell of the
+    /// font's line height and average advance exactly, so lines and blocks of adjacent cells
+    /// connect without gaps. Disabled by default.
+    fn set_builtin_box_drawing(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_builtin_box_drawing"))
+    }
+
+    /// Draw Powerline separators instead of using the font's glyphs.
+    ///
+    /// Like `set_builtin_box_drawing`, the glyphs for all characters matching
+    /// `is_powerline_glyph` are drawn to fill the cell exactly, so separators line up with the
+    /// background of adjacent cells. Disabled by default.
+    fn set_builtin_powerline(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_builtin_powerline"))
+    }
+
+    /// Set the optical size axis of variable fonts to the point size they're rendered at.
+    ///
+    /// Fonts without an `opsz` axis are not affected, sizes outside of the axis' range are
+    /// clamped to it. Disabled by default. Supported by FreeType.
+    fn set_auto_optical_size(&mut self, _enabled: bool) -> Result<(), Error> {
+        Err(Error::unsupported("set_auto_optical_size"))
+    }
+
+    /// Load a font from raw font data, without any fallback fonts.
+    ///
+    /// The `face_index` selects the face in font collections. WOFF and WOFF2 data is decoded with
+    /// the `woff` feature, otherwise only if the platform's font library supports it. With a
+    /// `format`, like from a content type, data of another format is rejected with a
+    /// descriptive error before it's handed to the font library. `None` detects the format from
+    /// the data. Supported by FreeType.
+    fn load_font_from_bytes(
+        &mut self,
+        _data: Vec<u8>,
+        _face_index: usize,
+        _format: Option<FontFormat>,
+    ) -> Result<FontKey, Error> {
+        Err(Error::unsupported("load_font_from_bytes"))
+    }
+
+    /// Load a font from a font file, without any fallback fonts.
+    ///
+    /// With the `woff` feature, WOFF and WOFF2 files are decoded into memory before loading them.
+    /// Supported by FreeType.
+    fn load_font_file(&mut self, _path: &Path, _face_index: usize) -> Result<FontKey, Error> {
+        Err(Error::unsupported("load_font_file"))
+    }
+
+    /// Advance in pixels of the tabular digits of a font, for aligning numbers in columns.
+    ///
+    /// The digits are shaped with the `tnum` feature, so fonts with proportional figures by
+    /// default use their tabular ones. Fonts without `tnum` use the advance of their `0`. Tracking
+    /// is included like in `string_advance`. Supported by FreeType.
+    fn digit_width(&mut self, _key: FontKey, _size: Size) -> Result<f32, Error> {
+        Err(Error::unsupported("digit_width"))
+    }
+
+    /// Ink extents of shaped text, without rasterizing any glyphs.
+    ///
+    /// Glyphs are positioned like in `string_advance`, including fallback fonts and tracking, and
+    /// measured from their unhinted outlines. This gives tight boxes around text, like for
+    /// highlights, but they can differ slightly from the rasterized glyphs. Supported by FreeType.
+    fn shape_extents(
+        &mut self,
+        _text: &str,
+        _font_key: FontKey,
+        _size: Size,
+    ) -> Result<RunExtents, Error> {
+        Err(Error::unsupported("shape_extents"))
+    }
+
+    /// Total horizontal advance of a string in pixels, without rasterizing any glyphs.
+    ///
+    /// The text is shaped, so kerning and ligatures are accounted for, and combining characters
+    /// which are part of a grapheme cluster don't add any advance. Characters missing from the
+    /// font use the same fallback fonts as `get_glyph`. The advances are not hinted, so they can
+    /// differ slightly from the sum of the rasterized glyphs' advances. Tracking is added once per
+    /// grapheme cluster. Supported by FreeType.
+    fn string_advance(
+        &mut self,
+        _text: &str,
+        _font_key: FontKey,
+        _size: Size,
+    ) -> Result<f32, Error> {
+        Err(Error::unsupported("string_advance"))
+    }
+
+    /// Horizontal advances in pixels of individual characters, without shaping or rasterizing.
+    ///
+    /// The advances are read from the font's metrics for the glyph each character is mapped to,
+    /// unhinted and including the font's tracking. Fallback fonts are not used, characters
+    /// missing from the font have an advance of `0.0`. Supported by FreeType, CoreText and
+    /// DirectWrite.
+    fn advances(&self, _key: FontKey, _chars: &[char], _size: Size) -> Result<Vec<f32>, Error> {
+        Err(Error::unsupported("advances"))
+    }
+
+    /// Rasterize the replacement character `U+FFFD` to display missing characters.
+    ///
+    /// The glyph is resolved like any other by `get_glyph`: the font itself is tried first,
+    /// followed by the emoji font, routed blocks, the fallback order and the system's fallback
+    /// fonts. Since there is no bundled last-resort font, the font's missing glyph is returned if
+    /// none of them contains the character. Supported by all backends.
+    fn replacement_glyph(&mut self, key: FontKey, size: Size) -> Result<RasterizedGlyph, Error> {
+        let glyph_key = GlyphKey { character: REPLACEMENT_CHARACTER, font_key: key, size };
+        match self.get_glyph(glyph_key) {
+            Ok(glyph) | Err(Error::MissingGlyph(glyph)) => Ok(glyph),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Draw a box with the hexadecimal codepoint of a character, like the Last Resort font.
+    ///
+    /// The box fills a cell of `cell` pixels, its border thickness scales with the font `size`.
+    /// Since it doesn't use any font, every missing character can be identified at a glance, even
+    /// without fallback fonts. The glyph's bottom edge is on the baseline and its `font_key` is a
+    /// placeholder. The box is drawn for the rasterizer's device pixel ratio and glyph origin.
+    fn tofu_glyph(&mut self, character: char, size: Size, cell: (u32, u32)) -> RasterizedGlyph;
+
+    /// Shape text and measure the advance of every grapheme, without rasterizing any glyphs.
+    ///
+    /// The advances are the same as the ones `string_advance` adds up, distributed over the
+    /// `graphemes` of the text. Ligatures spanning multiple graphemes share their advance evenly,
+    /// so cursors can be placed within them. Supported by FreeType.
+    #[cfg(feature = "graphemes")]
+    fn shape_graphemes(
+        &mut self,
+        _text: &str,
+        _font_key: FontKey,
+        _size: Size,
+    ) -> Result<Vec<ShapedGrapheme>, Error> {
+        Err(Error::unsupported("shape_graphemes"))
+    }
+
+    /// Width in pixels of a tab stop spanning `spaces` space characters.
+    ///
+    /// This is the unrounded advance of the font's space glyph times `spaces`, including the
+    /// font's tracking. Fonts without a space glyph use their cell width like
+    /// `Metrics::tab_width`. Supported by FreeType, CoreText and DirectWrite.
+    fn tab_width(&self, _key: FontKey, _size: Size, _spaces: u32) -> Result<f32, Error> {
+        Err(Error::unsupported("tab_width"))
+    }
+
+    /// Number of terminal cells a character occupies when rendered with a font.
+    ///
+    /// This starts from `char_cell_width`, but also uses two cells for characters which are
+    /// narrow by their East Asian Width, if the font's glyph is wider than one and a half cells.
+    /// Characters which are zero or two cells wide are never changed, so the result only differs
+    /// from `char_cell_width` for fonts with wide glyphs for ambiguous or narrow characters.
+    fn char_cell_width_in_font(
+        &mut self,
+        key: FontKey,
+        character: char,
+        size: Size,
+    ) -> Result<u8, Error> {
+        let width = char_cell_width(character);
+        if width != 1 {
+            return Ok(width);
+        }
+
+        let cell_width = self.metrics(key, size)?.average_advance;
+        let glyph = match self.get_glyph(GlyphKey { character, font_key: key, size }) {
+            Ok(glyph) | Err(Error::MissingGlyph(glyph)) => glyph,
+            Err(err) => return Err(err),
+        };
+
+        if f64::from(glyph.advance_f.0) > cell_width * 1.5 {
+            Ok(2)
+        } else {
+            Ok(1)
+        }
+    }
+
+    /// Rasterize all printable ASCII characters into one buffer.
+    ///
+    /// Characters without a glyph in the font or its fallbacks use the font's missing glyph.
+    /// Supported by all backends.
+    fn ascii_atlas(&mut self, key: FontKey, size: Size) -> Result<AsciiAtlas, Error> {
+        let mut atlas = AsciiAtlas::with_capacity(0);
+
+        for character in AsciiAtlas::FIRST..=AsciiAtlas::LAST {
+            match self.get_glyph(GlyphKey { character, font_key: key, size }) {
+                Ok(glyph) | Err(Error::MissingGlyph(glyph)) => atlas.push(glyph),
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(atlas)
+    }
+
+    /// OpenType scripts the font declares support for in its layout tables.
+    ///
+    /// Fonts without `GSUB` or `GPOS` tables don't declare any scripts, even if they cover their
+    /// characters. Supported by FreeType, CoreText and DirectWrite.
+    fn supported_scripts(&self, _key: FontKey) -> Result<Vec<Tag>, Error> {
+        Err(Error::unsupported("supported_scripts"))
+    }
+
+    /// OpenType languages the font declares support for within a script.
+    ///
+    /// The default language of the script is not included. Supported by FreeType, CoreText and
+    /// DirectWrite.
+    fn supported_languages(&self, _key: FontKey, _script: Tag) -> Result<Vec<Tag>, Error> {
+        Err(Error::unsupported("supported_languages"))
+    }
+
+    /// Copyright, designer and license information of the font.
+    ///
+    /// Entries missing from the font are `None` instead of causing an error. Supported by
+    /// FreeType, CoreText and DirectWrite.
+    fn font_metadata(&self, _key: FontKey) -> Result<FontMetadata, Error> {
+        Err(Error::unsupported("font_metadata"))
+    }
+
+    /// Character variant features of the font, with their labels and number of alternates.
+    ///
+    /// Supported by FreeType, CoreText and DirectWrite.
+    fn character_variants(&self, _key: FontKey) -> Result<Vec<CharacterVariant>, Error> {
+        Err(Error::unsupported("character_variants"))
+    }
+
+    /// Pick up fonts installed or removed since the rasterizer was created.
+    ///
+    /// Existing `FontKey`s stay valid and keep rendering with the fonts they were loaded with.
+    /// Only fonts loaded afterwards are matched against the updated font list, so a description
+    /// needs to be loaded again to pick up a better match. Supported by FreeType, CoreText and
+    /// DirectWrite.
+    fn refresh_font_sources(&mut self) -> Result<(), Error> {
+        Err(Error::unsupported("refresh_font_sources"))
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -303,8 +2438,366 @@ pub struct Info {
     pub cluster: u32,
 }
 
+/// Shaping setup for a font and set of OpenType features, created by `RasterizeExt::shape_plan`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ShapePlanHandle {
+    font_key: FontKey,
+    features: Vec<(Tag, u32)>,
+}
+
+impl ShapePlanHandle {
+    /// Font shaped with this plan.
+    pub fn font_key(&self) -> FontKey {
+        self.font_key
+    }
+
+    /// Features overriding the `DEFAULT_FEATURES`.
+    pub fn features(&self) -> &[(Tag, u32)] {
+        &self.features
+    }
+}
+
 /// Extends the Rasterizer with Harfbuzz specific functionality.
 pub trait RasterizeExt {
     /// Shape the provided text into a set of glyphs.
-    fn shape(&mut self, text: &str, font_key: FontKey) -> Vec<Info>;
+    ///
+    /// The text is shaped with the `DEFAULT_FEATURES`.
+    fn shape(&mut self, text: &str, font_key: FontKey) -> Vec<Info> {
+        self.shape_with_features(text, font_key, &[])
+    }
+
+    /// Shape text with `features` overriding the `DEFAULT_FEATURES`.
+    ///
+    /// CoreText only supports toggling the `liga` and `dlig` ligatures. With FreeType, fonts with
+    /// only AAT layout tables are shaped with those, see `Rasterize::has_aat_layout`.
+    fn shape_with_features(
+        &mut self,
+        text: &str,
+        font_key: FontKey,
+        features: &[(Tag, u32)],
+    ) -> Vec<Info>;
+
+    /// Shape text with the Graphite rules of a font instead of its OpenType layout.
+    ///
+    /// Only fonts with `Silf` and `Glat` tables can be shaped with Graphite, other fonts return
+    /// an error. This also requires HarfBuzz to be built with Graphite support, which isn't the
+    /// case for the bundled HarfBuzz. Supported by FreeType.
+    fn shape_graphite(&mut self, _text: &str, _font_key: FontKey) -> Result<Vec<Info>, Error> {
+        Err(Error::unsupported("shape_graphite"))
+    }
+
+    /// Prepare shaping text with a font and `features` overriding the `DEFAULT_FEATURES`.
+    ///
+    /// The handle should be kept and passed to `shape_with_plan` for every edit of the text.
+    /// FreeType keeps HarfBuzz shape plans for it, so the font's lookups aren't resolved again for
+    /// each call, which makes reshaping short paragraphs on every keystroke much cheaper. Other
+    /// backends shape like `shape_with_features`.
+    fn shape_plan(&mut self, font_key: FontKey, features: &[(Tag, u32)]) -> ShapePlanHandle {
+        ShapePlanHandle { font_key, features: features.to_vec() }
+    }
+
+    /// Shape text with a plan created by `shape_plan`.
+    ///
+    /// The result is the same as `shape_with_features` with the plan's font and features.
+    fn shape_with_plan(&mut self, plan: &ShapePlanHandle, text: &str) -> Vec<Info> {
+        self.shape_with_features(text, plan.font_key, &plan.features)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Metrics of a cell without any decorations.
+    fn cell_metrics(width: f64, height: f64) -> Metrics {
+        Metrics {
+            average_advance: width,
+            line_height: height,
+            descent: 0.,
+            line_gap: 0.,
+            underline_position: 0.,
+            underline_thickness: 0.,
+            strikeout_position: 0.,
+            strikeout_thickness: 0.,
+            overline_position: 0.,
+            overline_thickness: 0.,
+        }
+    }
+
+    #[test]
+    fn generic_family_case_insensitive() {
+        assert_eq!("monospace".parse(), Ok(GenericFamily::Monospace));
+        assert_eq!("Monospace".parse(), Ok(GenericFamily::Monospace));
+        assert_eq!("SANS-SERIF".parse(), Ok(GenericFamily::SansSerif));
+        assert_eq!("Menlo".parse::<GenericFamily>(), Err(()));
+    }
+
+    #[test]
+    #[cfg(not(windows))]
+    fn baseline_distance_of_known_font() {
+        // DejaVu Sans Mono and Menlo share the metrics of Bitstream Vera Sans Mono. Neither sets
+        // `USE_TYPO_METRICS`, so the hhea ascender of 1901 and descender of -483 units are used
+        // without a line gap, at 2048 units per em. At 16px they're rounded up from 14.85 to 15
+        // and from 3.77 to 4 pixels.
+        let family = if cfg!(target_os = "macos") { "Menlo" } else { "DejaVu Sans Mono" };
+        let style = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let size = Size::from_px(16., 1.);
+
+        let mut rasterizer = Rasterizer::new(1.).unwrap();
+        let font_key = rasterizer.load_font(&FontDesc::new(family, style), size).unwrap();
+        assert_eq!(rasterizer.baseline_distance(font_key, size).unwrap(), 19.);
+    }
+
+    #[test]
+    fn glyph_origin_top_left() {
+        let mut glyph =
+            RasterizedGlyph { top: 10, left: -1, top_f: 9.5, left_f: -1.25, ..Default::default() };
+        let baseline = glyph.clone();
+
+        GlyphOrigin::Baseline.apply(&mut glyph);
+        assert_eq!((glyph.top, glyph.left), (baseline.top, baseline.left));
+
+        GlyphOrigin::TopLeft { ascent: 12 }.apply(&mut glyph);
+        assert_eq!(glyph.top, 12 - baseline.top);
+        assert_eq!(glyph.left, baseline.left);
+        assert_eq!(glyph.top_f, 12. - baseline.top_f);
+        assert_eq!(glyph.left_f, baseline.left_f);
+    }
+
+    #[test]
+    fn rounding_negative_bearings() {
+        let round = |mode: RoundingMode| {
+            [-1.5, -0.7, -0.3, 0.3, 0.7].iter().map(|&value| mode.round(value)).collect::<Vec<_>>()
+        };
+
+        assert_eq!(round(RoundingMode::Truncate), [-1, 0, 0, 0, 0]);
+        assert_eq!(round(RoundingMode::Round), [-2, -1, 0, 0, 1]);
+        assert_eq!(round(RoundingMode::Floor), [-2, -1, -1, 0, 0]);
+        assert_eq!(round(RoundingMode::Ceil), [-1, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn same_family_ignores_style() {
+        let regular = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let bold = FontDesc::new("DejaVu Sans", Style::Specific("Bold".into()));
+
+        assert!(FontDesc::new("dejavu sans", regular.clone()).same_family(&bold));
+        assert!(!FontDesc::new("DejaVu Serif", regular).same_family(&bold));
+        assert_eq!(bold.name(), "DejaVu Sans");
+    }
+
+    #[test]
+    fn parse_style() {
+        assert_eq!(
+            "Bold Italic".parse(),
+            Ok(Style::Description { slant: Slant::Italic, weight: Weight::Bold })
+        );
+        assert_eq!(Style::from("regular"), Style::Description {
+            slant: Slant::Normal,
+            weight: Weight::Normal
+        });
+        assert_eq!(Style::from("Condensed Medium"), Style::Specific("Condensed Medium".into()));
+        assert_eq!(Style::from(""), Style::Specific(String::new()));
+
+        for slant in [Slant::Normal, Slant::Italic, Slant::Oblique] {
+            for weight in [Weight::Normal, Weight::Bold] {
+                let style = Style::Description { slant, weight };
+                assert_eq!(Style::from(style.to_string().as_str()), style);
+            }
+        }
+    }
+
+    #[test]
+    fn size_in_pixels() {
+        let size = Size::new(12.);
+        let px = 12. * Size::pixels_per_point();
+
+        assert_eq!(size.as_px(1.), px);
+        assert_eq!(size.as_px(2.), 2. * px);
+        assert_eq!(Size::from_px(px, 1.), size);
+        assert_eq!(Size::from_px(2. * px, 2.), size);
+    }
+
+    #[test]
+    fn glyph_key_display() {
+        let glyph_key =
+            GlyphKey { character: '\n', font_key: FontKey { token: 3 }, size: Size::new(12.) };
+        assert_eq!(glyph_key.to_string(), "'\\n' (U+000A) in font 3 at 12pt");
+    }
+
+    #[test]
+    fn font_desc_builders() {
+        let regular = Style::Description { slant: Slant::Normal, weight: Weight::Normal };
+        let desc = FontDesc::new("monospace", regular).with_weight(Weight::Bold);
+        let bold_italic = desc.with_slant(Slant::Italic);
+
+        assert_eq!(desc.style(), &Style::Description {
+            slant: Slant::Normal,
+            weight: Weight::Bold
+        });
+        assert_eq!(bold_italic.style(), &Style::Description {
+            slant: Slant::Italic,
+            weight: Weight::Bold
+        });
+        assert_eq!(bold_italic.name(), "monospace");
+    }
+
+    #[test]
+    fn compatible_metrics() {
+        let metrics = Metrics {
+            descent: -4.,
+            underline_position: -2.,
+            underline_thickness: 1.,
+            strikeout_position: 5.,
+            strikeout_thickness: 1.,
+            overline_position: 15.5,
+            overline_thickness: 1.,
+            ..cell_metrics(10., 20.)
+        };
+        let wide = Metrics { average_advance: 11., ..metrics };
+
+        assert!(metrics.is_compatible(&metrics, 0.));
+        assert!(metrics.is_compatible(&wide, 0.1));
+        assert!(!metrics.is_compatible(&wide, 0.05));
+    }
+
+    #[test]
+    fn glyph_on_background() {
+        let metrics = Metrics {
+            descent: -2.,
+            underline_position: -1.,
+            underline_thickness: 1.,
+            strikeout_position: 2.,
+            strikeout_thickness: 1.,
+            overline_position: 3.5,
+            overline_thickness: 1.,
+            ..cell_metrics(4., 6.)
+        };
+
+        // Fully covered 2x2 glyph right on the baseline, one pixel from the pen position.
+        let glyph = RasterizedGlyph {
+            width: 2,
+            height: 2,
+            top: 2,
+            left: 1,
+            buffer: BitmapBuffer::Rgb(vec![255; 12]),
+            ..RasterizedGlyph::default()
+        };
+
+        let fg = [255, 0, 0, 255];
+        let bg = [0, 0, 255, 128];
+        let cell =
+            glyph.on_background(GlyphOrigin::Baseline, &metrics, BlendMode::Srgb, fg, bg, (4, 6));
+        let pixels = match cell.buffer {
+            BitmapBuffer::Rgba(pixels) => pixels,
+            _ => panic!("expected RGBA pixels"),
+        };
+
+        assert_eq!((cell.width, cell.height, cell.top, cell.left), (4, 6, 4, 0));
+        assert_eq!(pixels.len(), 4 * 6 * 4);
+
+        // The baseline is 4 pixels below the top, so the glyph covers rows 2 and 3.
+        let pixel = |x: usize, y: usize| &pixels[(y * 4 + x) * 4..(y * 4 + x) * 4 + 4];
+        assert_eq!(pixel(0, 0), &[0, 0, 128, 128]);
+        assert_eq!(pixel(1, 2), &[255, 0, 0, 255]);
+        assert_eq!(pixel(2, 3), &[255, 0, 0, 255]);
+        assert_eq!(pixel(3, 3), &[0, 0, 128, 128]);
+        assert_eq!(pixel(1, 4), &[0, 0, 128, 128]);
+    }
+
+    #[test]
+    fn blend_modes() {
+        // Half covered white pixel on black.
+        let glyph = RasterizedGlyph {
+            width: 1,
+            height: 1,
+            top: 1,
+            buffer: BitmapBuffer::Rgb(vec![128; 3]),
+            ..RasterizedGlyph::default()
+        };
+
+        let metrics = cell_metrics(1., 1.);
+
+        let blend = |mode| {
+            let cell = glyph.on_background(
+                GlyphOrigin::Baseline,
+                &metrics,
+                mode,
+                [255; 4],
+                [0, 0, 0, 255],
+                (1, 1),
+            );
+            match cell.buffer {
+                BitmapBuffer::Rgba(pixels) => pixels[0],
+                _ => panic!("expected RGBA pixels"),
+            }
+        };
+
+        assert_eq!(blend(BlendMode::Srgb), 128);
+        assert_eq!(blend(BlendMode::Linear), 188);
+        assert_eq!(blend(BlendMode::GammaCorrected(2.)), 181);
+    }
+
+    #[test]
+    fn bgra_color_order() {
+        let glyph = RasterizedGlyph {
+            width: 1,
+            height: 1,
+            top: 1,
+            buffer: BitmapBuffer::Rgba(vec![255, 128, 0, 255]),
+            ..RasterizedGlyph::default()
+        };
+
+        let bgra = glyph.clone().with_color_order(ColorOrder::Bgra);
+        assert!(
+            matches!(&bgra.buffer, BitmapBuffer::Bgra(pixels) if pixels == &[0, 128, 255, 255])
+        );
+        assert!(matches!(
+            glyph.clone().with_color_order(ColorOrder::Rgba).buffer,
+            BitmapBuffer::Rgba(_)
+        ));
+
+        // BGRA glyphs keep their colors when composited.
+        let metrics = cell_metrics(1., 1.);
+        let composite = |glyph: &RasterizedGlyph| {
+            let cell = glyph.on_background(
+                GlyphOrigin::Baseline,
+                &metrics,
+                BlendMode::Srgb,
+                [255; 4],
+                [0, 0, 0, 255],
+                (1, 1),
+            );
+            cell.buffer
+        };
+        match (composite(&glyph), composite(&bgra)) {
+            (BitmapBuffer::Rgba(rgba), BitmapBuffer::Rgba(from_bgra)) => {
+                assert_eq!(rgba, from_bgra)
+            },
+            _ => panic!("expected RGBA pixels"),
+        }
+    }
+
+    #[test]
+    fn decoration_rects() {
+        let metrics = Metrics {
+            descent: -4.,
+            underline_position: -1.8,
+            underline_thickness: 0.4,
+            strikeout_position: 4.,
+            strikeout_thickness: 1.2,
+            overline_position: 12.8,
+            overline_thickness: 0.4,
+            ..cell_metrics(7., 17.)
+        };
+
+        let rect = |kind| metrics.decoration_rect(kind, 20.4);
+        assert_eq!(rect(Decoration::Underline), Rect { x: 0, y: 14, width: 20, height: 1 });
+        assert_eq!(rect(Decoration::Strikeout), Rect { x: 0, y: 9, width: 20, height: 1 });
+        assert_eq!(rect(Decoration::Overline), Rect { x: 0, y: 0, width: 20, height: 1 });
+
+        // Decorations below the line are moved back into it.
+        let metrics = Metrics { underline_position: -5., underline_thickness: 2., ..metrics };
+        assert_eq!(metrics.decoration_rect(Decoration::Underline, 7.).y, 15);
+    }
 }
@@ -0,0 +1,962 @@
+//! Parsing of OpenType tables shared between all backends.
+
+use crate::{CharacterVariant, EmbeddingPermissions, Error, FontFormat, FontMetadata, Tag};
+
+/// Tag of the OS/2 and Windows metrics table.
+pub const OS2: u32 = tag(b"OS/2");
+
+/// Tag of the horizontal header table.
+pub const HHEA: u32 = tag(b"hhea");
+
+/// Tag of the glyph substitution table.
+pub const GSUB: u32 = tag(b"GSUB");
+
+/// Tag of the glyph positioning table.
+pub const GPOS: u32 = tag(b"GPOS");
+
+/// Tag of the naming table.
+pub const NAME: u32 = tag(b"name");
+
+/// Tag of the font variations table.
+pub const FVAR: u32 = tag(b"fvar");
+
+/// Tag of the TrueType glyph data table.
+pub const GLYF: u32 = tag(b"glyf");
+
+/// Tag of the Compact Font Format table.
+pub const CFF: u32 = tag(b"CFF ");
+
+/// Tag of the Compact Font Format 2 table.
+pub const CFF2: u32 = tag(b"CFF2");
+
+/// Tag of the Graphite rules table.
+pub const SILF: u32 = tag(b"Silf");
+
+/// Tag of the Graphite glyph attributes table.
+pub const GLAT: u32 = tag(b"Glat");
+
+/// Tag of the AAT extended glyph metamorphosis table.
+pub const MORX: u32 = tag(b"morx");
+
+/// Tag of the AAT glyph metamorphosis table.
+pub const MORT: u32 = tag(b"mort");
+
+/// Tag of the AAT extended kerning table.
+pub const KERX: u32 = tag(b"kerx");
+
+/// Tag of the legacy kerning table.
+pub const KERN: u32 = tag(b"kern");
+
+/// Tag of the TrueType font program table.
+pub const FPGM: u32 = tag(b"fpgm");
+
+/// Tag of the TrueType control value program table.
+pub const PREP: u32 = tag(b"prep");
+
+/// Lookup type of single substitutions.
+const SINGLE_SUBSTITUTION: u16 = 1;
+
+/// Lookup type of alternate substitutions.
+const ALTERNATE_SUBSTITUTION: u16 = 3;
+
+/// Lookup type of extension substitutions, which wrap other lookups.
+const EXTENSION_SUBSTITUTION: u16 = 7;
+
+/// CFF DICT operator of a font's private DICT.
+const CFF_PRIVATE: u16 = 18;
+
+/// CFF DICT operator of the alignment zones in a private DICT.
+const CFF_BLUE_VALUES: u16 = 6;
+
+/// CFF DICT operator of the font DICTs of CID-keyed and CFF2 fonts.
+const CFF_FD_ARRAY: u16 = 12 << 8 | 36;
+
+/// Windows English (United States) language ID of the naming table.
+const ENGLISH_US: u16 = 0x409;
+
+/// OS/2 `fsSelection` flag indicating that the typographic metrics should be used.
+const USE_TYPO_METRICS: u16 = 1 << 7;
+
+/// Convert a table name to its tag.
+const fn tag(name: &[u8; 4]) -> u32 {
+    u32::from_be_bytes(*name)
+}
+
+fn read_u16(table: &[u8], offset: usize) -> Option<u16> {
+    let bytes = table.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_i16(table: &[u8], offset: usize) -> Option<i16> {
+    read_u16(table, offset).map(|value| value as i16)
+}
+
+fn read_u24(table: &[u8], offset: usize) -> Option<u32> {
+    let bytes = table.get(offset..offset + 3)?;
+    Some(u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]))
+}
+
+fn read_u32(table: &[u8], offset: usize) -> Option<u32> {
+    let bytes = table.get(offset..offset + 4)?;
+    Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read a 16.16 fixed point number.
+fn read_fixed(table: &[u8], offset: usize) -> Option<f32> {
+    read_u32(table, offset).map(|value| value as i32 as f32 / 65536.)
+}
+
+fn read_tag(table: &[u8], offset: usize) -> Option<Tag> {
+    let bytes = table.get(offset..offset + 4)?;
+    Some(Tag([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Read the tag and offset of every record in a `GSUB` or `GPOS` record list.
+///
+/// The `count_offset` points to the number of records, which are stored right after it.
+fn records(table: &[u8], count_offset: usize) -> Option<Vec<(Tag, usize)>> {
+    let count = usize::from(read_u16(table, count_offset)?);
+    (0..count)
+        .map(|index| {
+            let record = count_offset + 2 + index * 6;
+            Some((read_tag(table, record)?, usize::from(read_u16(table, record + 4)?)))
+        })
+        .collect()
+}
+
+/// Offset of the script list in a `GSUB` or `GPOS` table.
+fn script_list(table: &[u8]) -> Option<usize> {
+    read_u16(table, 4).map(usize::from)
+}
+
+fn table_script_tags(table: &[u8]) -> Option<Vec<Tag>> {
+    let scripts = records(table, script_list(table)?)?;
+    Some(scripts.into_iter().map(|(tag, _)| tag).collect())
+}
+
+fn table_language_tags(table: &[u8], script: Tag) -> Option<Vec<Tag>> {
+    let script_list = script_list(table)?;
+    let script = match records(table, script_list)?.into_iter().find(|(tag, _)| *tag == script) {
+        Some((_, offset)) => script_list + offset,
+        None => return Some(Vec::new()),
+    };
+
+    // Language system records follow the script's default language system offset.
+    let languages = records(table, script + 2)?;
+    Some(languages.into_iter().map(|(tag, _)| tag).collect())
+}
+
+/// Merge the tags found in the `GSUB` and `GPOS` tables.
+///
+/// Malformed tables are ignored.
+fn merge_tags<F>(gsub: Option<&[u8]>, gpos: Option<&[u8]>, tags: F) -> Vec<Tag>
+where
+    F: Fn(&[u8]) -> Option<Vec<Tag>>,
+{
+    let mut merged: Vec<Tag> = gsub.into_iter().chain(gpos).filter_map(tags).flatten().collect();
+    merged.sort_unstable();
+    merged.dedup();
+    merged
+}
+
+/// Script tags declared in the raw `GSUB` and `GPOS` tables.
+pub fn script_tags(gsub: Option<&[u8]>, gpos: Option<&[u8]>) -> Vec<Tag> {
+    merge_tags(gsub, gpos, table_script_tags)
+}
+
+/// Language system tags declared for a script in the raw `GSUB` and `GPOS` tables.
+///
+/// The default language system has no tag and is not included.
+pub fn language_tags(gsub: Option<&[u8]>, gpos: Option<&[u8]>, script: Tag) -> Vec<Tag> {
+    merge_tags(gsub, gpos, |table| table_language_tags(table, script))
+}
+
+/// Whether any feature of the default or Latin script in a raw `GSUB` or `GPOS` table has lookups.
+///
+/// Malformed tables are assumed to have lookups.
+pub fn has_latin_lookups(table: &[u8]) -> bool {
+    latin_lookups(table).unwrap_or(true)
+}
+
+fn latin_lookups(table: &[u8]) -> Option<bool> {
+    let script_list = script_list(table)?;
+    let features = feature_records(table)?;
+    for (tag, offset) in records(table, script_list)? {
+        if tag != Tag::new(b"DFLT") && tag != Tag::new(b"latn") {
+            continue;
+        }
+
+        let script = script_list + offset;
+        let mut language_systems: Vec<usize> =
+            records(table, script + 2)?.into_iter().map(|(_, offset)| script + offset).collect();
+        match read_u16(table, script)? {
+            0 => (),
+            default => language_systems.push(script + usize::from(default)),
+        }
+
+        for language_system in language_systems {
+            // Required features are always applied.
+            if read_u16(table, language_system + 2)? != 0xFFFF {
+                return Some(true);
+            }
+
+            let feature_count = usize::from(read_u16(table, language_system + 4)?);
+            for index in 0..feature_count {
+                let feature_index = read_u16(table, language_system + 6 + index * 2)?;
+                let (_, feature) = features.get(usize::from(feature_index))?;
+                if read_u16(table, feature + 2)? != 0 {
+                    return Some(true);
+                }
+            }
+        }
+    }
+
+    Some(false)
+}
+
+/// Character variant features declared in the raw `GSUB` table.
+///
+/// Labels are resolved from the raw `name` table. Malformed tables are ignored.
+pub fn character_variants(gsub: Option<&[u8]>, name: Option<&[u8]>) -> Vec<CharacterVariant> {
+    let gsub = match gsub {
+        Some(gsub) => gsub,
+        None => return Vec::new(),
+    };
+
+    let mut variants: Vec<CharacterVariant> = Vec::new();
+    for (tag, feature) in feature_records(gsub).unwrap_or_default() {
+        let is_variant = tag.0[..2] == *b"cv" && tag.0[2..].iter().all(u8::is_ascii_digit);
+        if !is_variant || variants.iter().any(|variant| variant.tag == tag) {
+            continue;
+        }
+
+        if let Some(variant) = character_variant(gsub, name, tag, feature) {
+            variants.push(variant);
+        }
+    }
+
+    variants.sort_unstable_by_key(|variant| variant.tag);
+    variants
+}
+
+/// Tag and table offset of every feature in a `GSUB` or `GPOS` table.
+fn feature_records(table: &[u8]) -> Option<Vec<(Tag, usize)>> {
+    let feature_list = usize::from(read_u16(table, 6)?);
+    let features = records(table, feature_list)?;
+    Some(features.into_iter().map(|(tag, offset)| (tag, feature_list + offset)).collect())
+}
+
+fn character_variant(
+    gsub: &[u8],
+    name: Option<&[u8]>,
+    tag: Tag,
+    feature: usize,
+) -> Option<CharacterVariant> {
+    let lookup_count = usize::from(read_u16(gsub, feature + 2)?);
+    let alternate_count = (0..lookup_count)
+        .filter_map(|index| read_u16(gsub, feature + 4 + index * 2))
+        .filter_map(|lookup| lookup_alternate_count(gsub, lookup))
+        .max()
+        .unwrap_or(0);
+
+    let mut variant = CharacterVariant {
+        tag,
+        label: None,
+        tooltip: None,
+        sample_text: None,
+        alternate_labels: Vec::new(),
+        characters: Vec::new(),
+        alternate_count,
+    };
+
+    // Feature parameters are optional, without them the variant is still usable.
+    let params = match read_u16(gsub, feature)? {
+        0 => return Some(variant),
+        offset => feature + usize::from(offset),
+    };
+
+    let label = |offset| name.and_then(|name| name_string(name, read_u16(gsub, offset)?));
+    variant.label = label(params + 2);
+    variant.tooltip = label(params + 4);
+    variant.sample_text = label(params + 6);
+
+    let named_parameters = read_u16(gsub, params + 8)?;
+    let first_parameter = read_u16(gsub, params + 10)?;
+    variant.alternate_labels = (0..named_parameters)
+        .filter_map(|index| name.and_then(|name| name_string(name, first_parameter + index)))
+        .collect();
+
+    let char_count = usize::from(read_u16(gsub, params + 12)?);
+    variant.characters = (0..char_count)
+        .filter_map(|index| read_u24(gsub, params + 14 + index * 3))
+        .filter_map(char::from_u32)
+        .collect();
+
+    Some(variant)
+}
+
+/// Number of alternates a `GSUB` lookup can choose from.
+fn lookup_alternate_count(gsub: &[u8], lookup_index: u16) -> Option<u16> {
+    let lookup_list = usize::from(read_u16(gsub, 8)?);
+    let lookup =
+        lookup_list + usize::from(read_u16(gsub, lookup_list + 2 + usize::from(lookup_index) * 2)?);
+    let lookup_type = read_u16(gsub, lookup)?;
+    let subtable_count = usize::from(read_u16(gsub, lookup + 4)?);
+
+    (0..subtable_count)
+        .filter_map(|index| {
+            let subtable = lookup + usize::from(read_u16(gsub, lookup + 6 + index * 2)?);
+            subtable_alternate_count(gsub, lookup_type, subtable)
+        })
+        .max()
+}
+
+fn subtable_alternate_count(gsub: &[u8], lookup_type: u16, subtable: usize) -> Option<u16> {
+    match lookup_type {
+        SINGLE_SUBSTITUTION => Some(1),
+        ALTERNATE_SUBSTITUTION => {
+            let set_count = usize::from(read_u16(gsub, subtable + 4)?);
+            (0..set_count)
+                .filter_map(|index| {
+                    let set = subtable + usize::from(read_u16(gsub, subtable + 6 + index * 2)?);
+                    read_u16(gsub, set)
+                })
+                .max()
+        },
+        EXTENSION_SUBSTITUTION => {
+            let extension_type = read_u16(gsub, subtable + 2)?;
+            let extension = subtable + read_u32(gsub, subtable + 4)? as usize;
+            subtable_alternate_count(gsub, extension_type, extension)
+        },
+        _ => None,
+    }
+}
+
+/// Read the name of a feature parameter from the raw `name` table.
+fn name_string(name: &[u8], name_id: u16) -> Option<String> {
+    // Name ID zero is used for missing names in feature parameters.
+    if name_id == 0 {
+        return None;
+    }
+
+    name_record(name, name_id)
+}
+
+/// Read a string from the raw `name` table, preferring English names.
+fn name_record(name: &[u8], name_id: u16) -> Option<String> {
+    let count = usize::from(read_u16(name, 2)?);
+    let storage = usize::from(read_u16(name, 4)?);
+
+    let mut best: Option<(bool, String)> = None;
+    for index in 0..count {
+        let record = 6 + index * 12;
+        if read_u16(name, record + 6)? != name_id {
+            continue;
+        }
+
+        // Only Unicode names are supported, which are stored as UTF-16.
+        let platform = read_u16(name, record)?;
+        let language = read_u16(name, record + 4)?;
+        if platform != 0 && platform != 3 {
+            continue;
+        }
+
+        let length = usize::from(read_u16(name, record + 8)?);
+        let offset = storage + usize::from(read_u16(name, record + 10)?);
+        let units: Vec<u16> =
+            (0..length / 2).filter_map(|unit| read_u16(name, offset + unit * 2)).collect();
+
+        let english = platform == 3 && language == ENGLISH_US;
+        if best.as_ref().map_or(true, |(best_english, _)| english && !best_english) {
+            best = Some((english, String::from_utf16_lossy(&units)));
+        }
+    }
+
+    best.map(|(_, string)| string)
+}
+
+/// Copyright, designer and license information from the raw `name` and `OS/2` tables.
+pub fn font_metadata(name: Option<&[u8]>, os2: Option<&[u8]>) -> FontMetadata {
+    let string = |name_id| name.and_then(|name| name_record(name, name_id));
+
+    // The `fsType` embedding flags follow the version, average width, weight and width class.
+    let fs_type = os2.and_then(|os2| read_u16(os2, 8)).unwrap_or(0);
+    let embedding = EmbeddingPermissions {
+        restricted: fs_type & 0x0002 != 0,
+        preview_and_print: fs_type & 0x0004 != 0,
+        editable: fs_type & 0x0008 != 0,
+        no_subsetting: fs_type & 0x0100 != 0,
+        bitmap_only: fs_type & 0x0200 != 0,
+    };
+
+    FontMetadata {
+        copyright: string(0),
+        trademark: string(7),
+        designer: string(9),
+        license: string(13),
+        license_url: string(14),
+        embedding,
+    }
+}
+
+/// Axis of a variable font, with its values in design units.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VariationAxis {
+    pub tag: Tag,
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+}
+
+/// Read the variation axes from a raw `fvar` table, in the order of the font's coordinates.
+pub fn variation_axes(fvar: &[u8]) -> Vec<VariationAxis> {
+    let axes = || -> Option<Vec<VariationAxis>> {
+        let axes_offset = usize::from(read_u16(fvar, 4)?);
+        let axis_count = usize::from(read_u16(fvar, 8)?);
+        let axis_size = usize::from(read_u16(fvar, 10)?);
+
+        (0..axis_count)
+            .map(|index| {
+                let record = axes_offset + index * axis_size;
+                Some(VariationAxis {
+                    tag: read_tag(fvar, record)?,
+                    min: read_fixed(fvar, record + 4)?,
+                    default: read_fixed(fvar, record + 8)?,
+                    max: read_fixed(fvar, record + 12)?,
+                })
+            })
+            .collect()
+    };
+
+    axes().unwrap_or_default()
+}
+
+/// Detect the format of font data from its header.
+///
+/// Collections are reported with the format of their first font. Returns `FontFormat::Other` for
+/// data which isn't an SFNT font.
+pub fn font_format(data: &[u8]) -> FontFormat {
+    let offset = match data.get(..4) {
+        Some(b"wOFF") => return FontFormat::Woff,
+        Some(b"wOF2") => return FontFormat::Woff2,
+        // The offsets of all fonts in a collection follow its header.
+        Some(b"ttcf") => match read_u32(data, 12) {
+            Some(offset) => offset as usize,
+            None => return FontFormat::Other,
+        },
+        _ => 0,
+    };
+
+    match data.get(offset..offset + 4) {
+        Some(b"OTTO") => FontFormat::OpenType,
+        Some(&[0, 1, 0, 0]) | Some(b"true") => FontFormat::TrueType,
+        _ => FontFormat::Other,
+    }
+}
+
+/// Detect the format of font data, checking it against the format declared by the caller.
+///
+/// Data declared as `FontFormat::Other` is not checked, since the backend might still read it.
+pub fn check_format(data: &[u8], declared: Option<FontFormat>) -> Result<FontFormat, Error> {
+    let format = font_format(data);
+    match declared {
+        Some(declared) if declared != format && declared != FontFormat::Other => Err(
+            Error::PlatformError(format!("expected {:?} font data, found {:?}", declared, format)),
+        ),
+        _ => Ok(format),
+    }
+}
+
+/// Decode WOFF and WOFF2 data to a raw SFNT font.
+///
+/// Data of other formats is returned unchanged.
+#[cfg(feature = "woff")]
+pub fn decode_woff(data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    match font_format(&data) {
+        FontFormat::Woff => {
+            woff_to_sfnt(&data).ok_or_else(|| Error::PlatformError("invalid WOFF font data".into()))
+        },
+        FontFormat::Woff2 => woff2::convert_woff2_to_ttf(&mut data.as_slice())
+            .map_err(|err| Error::PlatformError(format!("invalid WOFF2 font data: {}", err))),
+        _ => Ok(data),
+    }
+}
+
+/// Rebuild the SFNT font stored in a WOFF container, decompressing its tables.
+#[cfg(feature = "woff")]
+fn woff_to_sfnt(woff: &[u8]) -> Option<Vec<u8>> {
+    let flavor = read_u32(woff, 4)?;
+    let num_tables = read_u16(woff, 12)?;
+
+    // The SFNT table directory is followed by the tables, each padded to four bytes.
+    let mut sfnt = Vec::with_capacity(read_u32(woff, 16)? as usize);
+    let entry_selector = 15 - num_tables.max(1).leading_zeros() as u16;
+    let search_range: u16 = 16 << entry_selector;
+    sfnt.extend_from_slice(&flavor.to_be_bytes());
+    sfnt.extend_from_slice(&num_tables.to_be_bytes());
+    sfnt.extend_from_slice(&search_range.to_be_bytes());
+    sfnt.extend_from_slice(&entry_selector.to_be_bytes());
+    sfnt.extend_from_slice(
+        &num_tables.saturating_mul(16).saturating_sub(search_range).to_be_bytes(),
+    );
+
+    let mut tables = Vec::new();
+    for index in 0..usize::from(num_tables) {
+        let entry = 44 + index * 20;
+        let offset = read_u32(woff, entry + 4)? as usize;
+        let comp_length = read_u32(woff, entry + 8)? as usize;
+        let orig_length = read_u32(woff, entry + 12)? as usize;
+        let compressed = woff.get(offset..offset.checked_add(comp_length)?)?;
+
+        let table = if comp_length < orig_length {
+            miniz_oxide::inflate::decompress_to_vec_zlib(compressed).ok()?
+        } else {
+            compressed.to_vec()
+        };
+        if table.len() != orig_length {
+            return None;
+        }
+
+        let table_offset = 12 + usize::from(num_tables) * 16 + tables.len();
+        sfnt.extend_from_slice(woff.get(entry..entry + 4)?);
+        sfnt.extend_from_slice(woff.get(entry + 16..entry + 20)?);
+        sfnt.extend_from_slice(&(table_offset as u32).to_be_bytes());
+        sfnt.extend_from_slice(&(orig_length as u32).to_be_bytes());
+
+        tables.extend_from_slice(&table);
+        tables.resize((tables.len() + 3) & !3, 0);
+    }
+
+    sfnt.append(&mut tables);
+
+    Some(sfnt)
+}
+
+/// Check if a raw `CFF ` or `CFF2` table has PostScript hints.
+///
+/// Fonts count as hinted if one of their private DICTs defines alignment zones with
+/// `BlueValues`, since the stem hints of the glyphs depend on them.
+pub fn cff_hinted(cff: &[u8], cff2: bool) -> bool {
+    cff_private_dicts(cff, cff2).unwrap_or_default().into_iter().any(|private| {
+        dict_operands(private, CFF_BLUE_VALUES).map_or(false, |operands| operands.len() >= 2)
+    })
+}
+
+/// Get the private DICTs of all fonts in a CFF table.
+fn cff_private_dicts(cff: &[u8], cff2: bool) -> Option<Vec<&[u8]>> {
+    let header_size = usize::from(*cff.get(2)?);
+    let top_dict = if cff2 {
+        let length = usize::from(read_u16(cff, 3)?);
+        cff.get(header_size..header_size + length)?
+    } else {
+        let (_, names_end) = cff_index(cff, header_size, false)?;
+        let (top_dicts, _) = cff_index(cff, names_end, false)?;
+        *top_dicts.first()?
+    };
+
+    let font_dicts = match dict_operands(top_dict, CFF_FD_ARRAY) {
+        Some(operands) => cff_index(cff, *operands.first()? as usize, cff2)?.0,
+        None => vec![top_dict],
+    };
+
+    Some(
+        font_dicts
+            .into_iter()
+            .filter_map(|font_dict| {
+                let operands = dict_operands(font_dict, CFF_PRIVATE)?;
+                let (size, offset) = (*operands.first()? as usize, *operands.get(1)? as usize);
+                cff.get(offset..offset + size)
+            })
+            .collect(),
+    )
+}
+
+/// Read the elements of a CFF INDEX and the offset right after it.
+fn cff_index(cff: &[u8], offset: usize, cff2: bool) -> Option<(Vec<&[u8]>, usize)> {
+    let (count, header_size) = if cff2 {
+        (read_u32(cff, offset)? as usize, 4)
+    } else {
+        (usize::from(read_u16(cff, offset)?), 2)
+    };
+
+    if count == 0 {
+        return Some((Vec::new(), offset + header_size));
+    }
+
+    let offset_size = usize::from(*cff.get(offset + header_size)?);
+    let offsets_start = offset + header_size + 1;
+    let read_offset = |index: usize| -> Option<usize> {
+        let bytes = cff.get(offsets_start + index * offset_size..)?.get(..offset_size)?;
+        Some(bytes.iter().fold(0, |value, &byte| value << 8 | usize::from(byte)))
+    };
+
+    // Offsets are relative to the byte before the data.
+    let data_start = offsets_start + (count + 1) * offset_size - 1;
+    let mut elements = Vec::with_capacity(count);
+    for index in 0..count {
+        let (start, end) = (read_offset(index)?, read_offset(index + 1)?);
+        elements.push(cff.get(data_start + start..data_start + end)?);
+    }
+
+    let end = data_start + read_offset(count)?;
+    Some((elements, end))
+}
+
+/// Get the operands of an operator in a CFF DICT.
+///
+/// Real numbers are not decoded, they're returned as zero.
+fn dict_operands(dict: &[u8], operator: u16) -> Option<Vec<i32>> {
+    let mut operands = Vec::new();
+    let mut offset = 0;
+    while let Some(&byte) = dict.get(offset) {
+        offset += 1;
+        match byte {
+            0..=21 => {
+                let current = if byte == 12 {
+                    offset += 1;
+                    12 << 8 | u16::from(*dict.get(offset - 1)?)
+                } else {
+                    u16::from(byte)
+                };
+
+                if current == operator {
+                    return Some(operands);
+                }
+                operands.clear();
+            },
+            28 => {
+                operands.push(i32::from(read_i16(dict, offset)?));
+                offset += 2;
+            },
+            29 => {
+                operands.push(read_u32(dict, offset)? as i32);
+                offset += 4;
+            },
+            30 => {
+                // Skip nibbles until the end of number nibble.
+                while dict.get(offset).map_or(false, |&byte| byte & 0xf != 0xf && byte >> 4 != 0xf)
+                {
+                    offset += 1;
+                }
+                offset += 1;
+                operands.push(0);
+            },
+            32..=246 => operands.push(i32::from(byte) - 139),
+            247..=250 => {
+                let next = i32::from(*dict.get(offset)?);
+                operands.push((i32::from(byte) - 247) * 256 + next + 108);
+                offset += 1;
+            },
+            251..=254 => {
+                let next = i32::from(*dict.get(offset)?);
+                operands.push(-(i32::from(byte) - 251) * 256 - next - 108);
+                offset += 1;
+            },
+            _ => return None,
+        }
+    }
+
+    None
+}
+
+/// Vertical metrics of a font.
+///
+/// The `descent` is negative for fonts extending below the baseline.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct LineMetrics {
+    pub ascent: f32,
+    pub descent: f32,
+    pub line_gap: f32,
+}
+
+impl LineMetrics {
+    /// Resolve the line metrics in font units from the raw `OS/2` and `hhea` tables.
+    ///
+    /// This follows the resolution order of modern browsers:
+    ///
+    ///  1. The OS/2 typographic metrics if the `USE_TYPO_METRICS` flag is set.
+    ///  2. The hhea metrics unless its ascent and descent are both zero.
+    ///  3. The OS/2 Windows metrics, without any line gap.
+    pub fn from_tables(os2: Option<&[u8]>, hhea: Option<&[u8]>) -> Option<Self> {
+        if let Some(os2) = os2 {
+            if read_u16(os2, 62)? & USE_TYPO_METRICS != 0 {
+                return Some(Self {
+                    ascent: f32::from(read_i16(os2, 68)?),
+                    descent: f32::from(read_i16(os2, 70)?),
+                    line_gap: f32::from(read_i16(os2, 72)?),
+                });
+            }
+        }
+
+        if let Some(hhea) = hhea {
+            let ascent = read_i16(hhea, 4)?;
+            let descent = read_i16(hhea, 6)?;
+            if ascent != 0 || descent != 0 {
+                return Some(Self {
+                    ascent: f32::from(ascent),
+                    descent: f32::from(descent),
+                    line_gap: f32::from(read_i16(hhea, 8)?),
+                });
+            }
+        }
+
+        let os2 = os2?;
+        Some(Self {
+            ascent: f32::from(read_u16(os2, 74)?),
+            descent: -f32::from(read_u16(os2, 76)?),
+            line_gap: 0.,
+        })
+    }
+
+    /// Scale the metrics to whole pixels.
+    ///
+    /// Ascent and descent are rounded away from the baseline, so no glyph is clipped.
+    pub fn scale(self, pixels_per_unit: f32) -> Self {
+        Self {
+            ascent: (self.ascent * pixels_per_unit).ceil(),
+            descent: (self.descent * pixels_per_unit).floor(),
+            line_gap: (self.line_gap * pixels_per_unit).round().max(0.),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(string: &str) -> Vec<u8> {
+        string.encode_utf16().flat_map(u16::to_be_bytes).collect()
+    }
+
+    #[test]
+    fn character_variant_parameters() {
+        #[rustfmt::skip]
+        let gsub: Vec<u8> = [
+            // Header with script list, feature list and lookup list offsets.
+            &[0, 1, 0, 0, 0, 10, 0, 12, 0, 44][..],
+            // Empty script list.
+            &[0, 0],
+            // Feature list with `cv01` at offset 8.
+            &[0, 1, b'c', b'v', b'0', b'1', 0, 8],
+            // Feature with parameters at offset 6, using lookup 0.
+            &[0, 6, 0, 1, 0, 0],
+            // Parameters: label 256, two named alternates from 257 and the character `a`.
+            &[0, 0, 1, 0, 0, 0, 0, 0, 0, 2, 1, 1, 0, 1, 0, 0, 0x61, 0],
+            // Lookup list with a single alternate substitution lookup.
+            &[0, 1, 0, 4, 0, 3, 0, 0, 0, 1, 0, 8],
+            // Alternate substitution with one set of three alternates.
+            &[0, 1, 0, 0, 0, 1, 0, 8, 0, 3, 0, 1, 0, 2, 0, 3],
+        ]
+        .concat();
+
+        let strings = [utf16("Alternate a"), utf16("Round"), utf16("Single-story")];
+        let mut name = vec![0, 0, 0, 3, 0, 42];
+        let mut offset = 0;
+        for (index, string) in strings.iter().enumerate() {
+            let name_id = 256 + index as u16;
+            for value in [3, 1, ENGLISH_US, name_id, string.len() as u16, offset] {
+                name.extend_from_slice(&value.to_be_bytes());
+            }
+            offset += string.len() as u16;
+        }
+        name.extend(strings.concat());
+
+        let variants = character_variants(Some(&gsub), Some(&name));
+        assert_eq!(variants, [CharacterVariant {
+            tag: Tag::new(b"cv01"),
+            label: Some("Alternate a".into()),
+            tooltip: None,
+            sample_text: None,
+            alternate_labels: vec!["Round".into(), "Single-story".into()],
+            characters: vec!['a'],
+            alternate_count: 3,
+        }]);
+    }
+
+    #[test]
+    fn metadata_entries() {
+        let strings = [utf16("Copyright"), utf16("https://example.com/license")];
+        let mut name = vec![0, 0, 0, 2, 0, 30];
+        let mut offset = 0;
+        for (string, name_id) in strings.iter().zip([0, 14]) {
+            for value in [3, 1, ENGLISH_US, name_id, string.len() as u16, offset] {
+                name.extend_from_slice(&value.to_be_bytes());
+            }
+            offset += string.len() as u16;
+        }
+        name.extend(strings.concat());
+
+        // Preview and print embedding without subsetting.
+        let os2 = [0, 4, 0, 0, 1, 144, 0, 5, 1, 4];
+
+        let metadata = font_metadata(Some(&name), Some(&os2));
+        assert_eq!(metadata.copyright.as_deref(), Some("Copyright"));
+        assert_eq!(metadata.license_url.as_deref(), Some("https://example.com/license"));
+        assert_eq!(metadata.designer, None);
+        assert_eq!(metadata.embedding, EmbeddingPermissions {
+            preview_and_print: true,
+            no_subsetting: true,
+            ..EmbeddingPermissions::default()
+        });
+        assert!(!metadata.embedding.is_installable());
+
+        assert_eq!(font_metadata(None, None), FontMetadata::default());
+    }
+
+    #[test]
+    fn optical_size_axis() {
+        #[rustfmt::skip]
+        let fvar: Vec<u8> = [
+            // Header with axes at offset 16, two axes of 20 bytes and no instances.
+            &[0, 1, 0, 0, 0, 16, 0, 2, 0, 2, 0, 20, 0, 0, 0, 0][..],
+            // `wght` from 100 to 900, defaulting to 400.
+            &[b'w', b'g', b'h', b't', 0, 100, 0, 0, 1, 144, 0, 0, 3, 132, 0, 0, 0, 0, 1, 0],
+            // `opsz` from 8 to 144, defaulting to 12.5.
+            &[b'o', b'p', b's', b'z', 0, 8, 0, 0, 0, 12, 128, 0, 0, 144, 0, 0, 0, 0, 1, 1],
+        ]
+        .concat();
+
+        let axes = variation_axes(&fvar);
+        assert_eq!(axes.len(), 2);
+        assert_eq!(axes[1], VariationAxis {
+            tag: Tag::new(b"opsz"),
+            min: 8.,
+            default: 12.5,
+            max: 144.,
+        });
+        assert!(variation_axes(&fvar[..40]).is_empty());
+    }
+
+    #[test]
+    fn cff_blue_values() {
+        #[rustfmt::skip]
+        let cff = |private: &[u8]| -> Vec<u8> {
+            let size = 139 + private.len() as u8;
+            [
+                // Header with a size of 4 bytes.
+                &[1, 0, 4, 1][..],
+                // Name INDEX with the font name `A`.
+                &[0, 1, 1, 1, 2, b'A'],
+                // Top DICT INDEX with the private DICT at offset 18.
+                &[0, 1, 1, 1, 4, size, 157, 18],
+                private,
+            ]
+            .concat()
+        };
+
+        // BlueValues of `-10 0`.
+        assert!(cff_hinted(&cff(&[129, 139, 6]), false));
+
+        // Only a StdHW of `50`.
+        assert!(!cff_hinted(&cff(&[189, 10]), false));
+        assert!(!cff_hinted(&[1, 0, 4], false));
+    }
+
+    #[test]
+    fn declared_font_format() {
+        let woff2 = b"wOF2\0\x01\0\0";
+        assert!(matches!(check_format(woff2, None), Ok(FontFormat::Woff2)));
+        assert!(matches!(check_format(woff2, Some(FontFormat::Woff2)), Ok(FontFormat::Woff2)));
+        assert!(matches!(check_format(woff2, Some(FontFormat::Other)), Ok(FontFormat::Woff2)));
+        assert!(check_format(woff2, Some(FontFormat::TrueType)).is_err());
+    }
+
+    /// Wrap an SFNT font in a WOFF container, compressing tables where it saves space.
+    #[cfg(feature = "woff")]
+    fn sfnt_to_woff(sfnt: &[u8]) -> Vec<u8> {
+        let num_tables = usize::from(read_u16(sfnt, 4).unwrap());
+
+        let mut directory = Vec::new();
+        let mut tables = Vec::new();
+        for index in 0..num_tables {
+            let entry = 12 + index * 16;
+            let offset = read_u32(sfnt, entry + 8).unwrap() as usize;
+            let length = read_u32(sfnt, entry + 12).unwrap() as usize;
+            let table = &sfnt[offset..offset + length];
+
+            let compressed = miniz_oxide::deflate::compress_to_vec_zlib(table, 6);
+            let data = if compressed.len() < length { &compressed[..] } else { table };
+
+            let woff_offset = 44 + num_tables * 20 + tables.len();
+            directory.extend_from_slice(&sfnt[entry..entry + 4]);
+            directory.extend_from_slice(&(woff_offset as u32).to_be_bytes());
+            directory.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            directory.extend_from_slice(&(length as u32).to_be_bytes());
+            directory.extend_from_slice(&sfnt[entry + 4..entry + 8]);
+
+            tables.extend_from_slice(data);
+            tables.resize((tables.len() + 3) & !3, 0);
+        }
+
+        let length = 44 + directory.len() + tables.len();
+        let mut header = b"wOFF".to_vec();
+        header.extend_from_slice(&sfnt[..4]);
+        header.extend_from_slice(&(length as u32).to_be_bytes());
+        header.extend_from_slice(&(num_tables as u16).to_be_bytes());
+        header.extend_from_slice(&[0, 0]);
+        header.extend_from_slice(&(sfnt.len() as u32).to_be_bytes());
+        header.resize(44, 0);
+
+        [header, directory, tables].concat()
+    }
+
+    #[test]
+    #[cfg(feature = "woff")]
+    fn woff_round_trip() {
+        #[rustfmt::skip]
+        let sfnt: Vec<u8> = [
+            // Header with two tables, search range 32, entry selector 1 and range shift 0.
+            &[0, 1, 0, 0, 0, 2, 0, 32, 0, 1, 0, 0][..],
+            // `hhea` table with checksum 1 at offset 44 with a length of 64.
+            b"hhea", &[0, 0, 0, 1, 0, 0, 0, 44, 0, 0, 0, 64],
+            // `name` table with checksum 2 at offset 108 with a length of 3.
+            b"name", &[0, 0, 0, 2, 0, 0, 0, 108, 0, 0, 0, 3],
+            // Compressible `hhea` data.
+            &[7; 64],
+            // Incompressible `name` data padded to four bytes.
+            &[1, 2, 3, 0],
+        ]
+        .concat();
+
+        let woff = sfnt_to_woff(&sfnt);
+        assert_eq!(font_format(&woff), FontFormat::Woff);
+        assert!(woff.len() < 44 + 2 * 20 + 64);
+        assert_eq!(decode_woff(woff).unwrap(), sfnt);
+
+        // Other formats are passed through.
+        assert_eq!(decode_woff(sfnt.clone()).unwrap(), sfnt);
+
+        // Truncated tables are rejected.
+        let mut woff = sfnt_to_woff(&sfnt);
+        woff.truncate(woff.len() - 4);
+        assert!(decode_woff(woff).is_err());
+    }
+
+    #[test]
+    fn latin_lookup_detection() {
+        #[rustfmt::skip]
+        let gsub = |script: &[u8; 4]| -> Vec<u8> {
+            [
+                // Header with script list, feature list and lookup list offsets.
+                &[0, 1, 0, 0, 0, 10, 0, 30, 0, 44][..],
+                // Script list with a single script at offset 8.
+                &[0, 1, script[0], script[1], script[2], script[3], 0, 8],
+                // Script with a default language system at offset 4.
+                &[0, 4, 0, 0],
+                // Language system using feature 0.
+                &[0, 0, 0xFF, 0xFF, 0, 1, 0, 0],
+                // Feature list with `ccmp` at offset 8, using lookup 0.
+                &[0, 1, b'c', b'c', b'm', b'p', 0, 8],
+                &[0, 0, 0, 1, 0, 0],
+                // Empty lookup list.
+                &[0, 0],
+            ]
+            .concat()
+        };
+
+        assert!(has_latin_lookups(&gsub(b"latn")));
+        assert!(has_latin_lookups(&gsub(b"DFLT")));
+        assert!(!has_latin_lookups(&gsub(b"arab")));
+        assert!(has_latin_lookups(&gsub(b"latn")[..36]));
+    }
+}
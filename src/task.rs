@@ -0,0 +1,110 @@
+//! Runtime-agnostic execution of blocking work.
+
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use super::Error;
+
+/// Future resolving to the result of a closure executed on a separate thread.
+///
+/// A panic in the closure resolves the future with `Error::PlatformError`.
+pub struct BlockingTask<T> {
+    state: Arc<Mutex<TaskState<T>>>,
+}
+
+struct TaskState<T> {
+    result: Option<Result<T, Error>>,
+    waker: Option<Waker>,
+}
+
+impl<T: Send + 'static> BlockingTask<T> {
+    /// Run `f` on a new thread.
+    pub fn spawn<F>(f: F) -> Self
+    where
+        F: FnOnce() -> Result<T, Error> + Send + 'static,
+    {
+        let state = Arc::new(Mutex::new(TaskState { result: None, waker: None }));
+
+        let task_state = Arc::clone(&state);
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(f)).unwrap_or_else(|payload| {
+                Err(Error::PlatformError(format!("task panicked: {}", panic_message(&payload))))
+            });
+
+            let mut state = task_state.lock().unwrap_or_else(PoisonError::into_inner);
+            state.result = Some(result);
+            if let Some(waker) = state.waker.take() {
+                waker.wake();
+            }
+        });
+
+        Self { state }
+    }
+}
+
+impl<T> Future for BlockingTask<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+/// Message of a panic payload, if it has one.
+fn panic_message(payload: &Box<dyn Any + Send>) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload.downcast_ref::<String>().map_or("unknown error", String::as_str),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Wake;
+
+    use super::*;
+
+    /// Waker unparking the thread polling the future.
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn panicking_task_resolves() {
+        assert_eq!(block_on(BlockingTask::spawn(|| Ok(3))).unwrap(), 3);
+
+        let task = BlockingTask::<()>::spawn(|| panic!("no fonts"));
+        match block_on(task) {
+            Err(Error::PlatformError(message)) => assert!(message.contains("no fonts")),
+            result => panic!("unexpected result: {:?}", result),
+        }
+    }
+}